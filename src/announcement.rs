@@ -0,0 +1,106 @@
+//! An announcement administrators set for the whole instance, such as a
+//! maintenance window or a policy note. Clients get it from `/api/v1/meta` and
+//! record when the user acknowledged it, so it's shown once.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{app::AppUser, App, Result};
+
+const CURRENT: &[u8] = b"current";
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+	#[default]
+	Info,
+	Warning,
+}
+
+#[derive(Deserialize)]
+pub struct NewAnnouncement {
+	pub message: String,
+	#[serde(default)]
+	pub level: Level,
+	/// Hidden afterwards, e.g. once the maintenance is over
+	pub expires: Option<DateTime<Utc>>,
+}
+
+impl NewAnnouncement {
+	/// Replaces the current announcement; being new, it's shown to users who
+	/// acknowledged the previous one too
+	pub fn insert(self, state: &App, admin: &AppUser) -> Result<Announcement> {
+		let announcement = Announcement {
+			id: admin.db.generate_id()?,
+			message: self.message,
+			level: self.level,
+			created: Utc::now(),
+			expires: self.expires,
+		};
+		state
+			.announcements
+			.insert(CURRENT, bincode::serialize(&announcement)?)?;
+
+		Ok(announcement)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Announcement {
+	pub id: u64,
+	pub message: String,
+	pub level: Level,
+	pub created: DateTime<Utc>,
+	pub expires: Option<DateTime<Utc>>,
+}
+
+/// The announcement along with whether the user acknowledged it
+#[derive(Serialize)]
+pub struct UserAnnouncement {
+	#[serde(flatten)]
+	pub announcement: Announcement,
+	pub acknowledged: bool,
+}
+
+impl Announcement {
+	/// The announcement, unless there is none or it expired
+	pub fn current(state: &App) -> Result<Option<Announcement>> {
+		let announcement = state
+			.announcements
+			.get(CURRENT)?
+			.map(|bytes| bincode::deserialize::<Announcement>(&bytes))
+			.transpose()?;
+		Ok(announcement.filter(|announcement| {
+			announcement
+				.expires
+				.is_none_or(|expires| expires > Utc::now())
+		}))
+	}
+
+	pub fn clear(state: &App) -> Result<()> {
+		state.announcements.remove(CURRENT)?;
+		Ok(())
+	}
+
+	pub fn for_user(state: &App, app: &AppUser) -> Result<Option<UserAnnouncement>> {
+		let acknowledged = app
+			.meta
+			.get(AppUser::META_ANNOUNCEMENT_ACK)?
+			.map(|bytes| bincode::deserialize::<u64>(&bytes))
+			.transpose()?;
+		Ok(Self::current(state)?.map(|announcement| UserAnnouncement {
+			acknowledged: acknowledged == Some(announcement.id),
+			announcement,
+		}))
+	}
+
+	/// Records that the user saw the announcement; acknowledging an outdated one
+	/// does nothing, so a client can't hide one it never showed
+	pub fn acknowledge(state: &App, app: &AppUser, id: u64) -> Result<()> {
+		if Self::current(state)?.is_some_and(|announcement| announcement.id == id) {
+			app.meta
+				.insert(AppUser::META_ANNOUNCEMENT_ACK, bincode::serialize(&id)?)?;
+		}
+		Ok(())
+	}
+}
@@ -1,30 +1,247 @@
-use std::collections::{BTreeMap, BTreeSet};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::db::Article;
-use crate::err::Result;
+use sled::transaction::TransactionError;
+use sled::Transactional;
+use url::Url;
+
+use crate::blob::BlobStore;
+use crate::crypt;
+use crate::db::{
+	Article, ArticleId, ArticleOrderBy, CapabilityToken, Feed, IndexedArticle, IndexedFields,
+	Order, User,
+};
+use crate::dns::{CachingResolver, DnsConfig};
+use crate::err::{Error, FetchError, Result};
+use crate::fetch::{self, FetchCache, Refreshes, SizeLimits};
+use crate::history::RefreshReport;
+use crate::invite;
+use crate::migrate;
+use crate::query::{Clause, SearchQuery};
+use crate::replica;
+use crate::retention::Retention;
+use crate::scheduler;
+use crate::search::{Hit, SearchIndex};
+use crate::sharing::{Blogroll, Subscription};
+use crate::sync::SyncRemote;
+use crate::team::SharedFeeds;
+use crate::telegram;
 
 pub struct Config {
 	pub db_path: PathBuf,
+	/// Directory of the blob store
+	pub blobs_path: PathBuf,
+	/// Directory of the search index
+	pub search_path: PathBuf,
+	pub fetch_cache_ttl: Duration,
+	pub limits: SizeLimits,
+	pub bcrypt_cost: u32,
+	pub dns: DnsConfig,
+	pub http: HttpConfig,
+}
+
+/// Size of a tree, from its live entries
+#[derive(Serialize)]
+pub struct TreeUsage {
+	pub name: String,
+	pub entries: usize,
+	/// Keys and values, without sled's own overhead
+	pub bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct StorageUsage {
+	/// Including space not yet reclaimed, which compaction frees
+	pub size_on_disk: u64,
+	/// Sum of the trees' live data
+	pub live_bytes: u64,
+	/// Largest first
+	pub trees: Vec<TreeUsage>,
+	/// Whether the database is compacted on the next start
+	pub compaction_scheduled: bool,
+}
+
+/// Connection settings of the client feeds are fetched with; `None` keeps
+/// reqwest's default
+pub struct HttpConfig {
+	pub pool_max_idle_per_host: Option<usize>,
+	pub pool_idle_timeout: Option<Duration>,
+	/// Negotiate HTTP/2 where servers offer it, some CDNs misbehave with it
+	pub http2: bool,
+	pub tcp_keepalive: Option<Duration>,
+	/// PEM files with root certificates trusted in addition to the built-in ones,
+	/// e.g. of a private CA
+	pub extra_root_certs: Vec<PathBuf>,
 }
 
 pub struct App {
 	db: sled::Db,
+	db_path: PathBuf,
 	pub users: sled::Tree,
+	pub tokens: sled::Tree,
+	pub blogrolls: sled::Tree,
+	/// Usernames of chats linked to the Telegram bot, by chat id
+	pub telegram_chats: sled::Tree,
+	/// Pending codes for linking a chat, by code
+	pub telegram_links: sled::Tree,
+	/// The instance announcement, if one is set
+	pub announcements: sled::Tree,
+	/// Registration invites, by code
+	pub invites: sled::Tree,
+	/// Blocked hosts and feed urls, by target
+	pub blocklist: sled::Tree,
+	/// Feeds indexed once for all subscribers, by url
+	pub shared_feeds: sled::Tree,
+	bodies: sled::Tree,
+	body_refs: sled::Tree,
+	blobs: BlobStore,
+	pub search_index: SearchIndex,
 	client: reqwest::Client,
+	/// Only for feeds with `accept_invalid_certs`
+	insecure_client: reqwest::Client,
+	fetch_cache: FetchCache,
+	limits: SizeLimits,
+	pub refreshes: Refreshes,
+	pub bcrypt_cost: u32,
+}
+
+/// Refreshes per user looked at for erroring feeds
+const ERROR_WINDOW: usize = 50;
+const TOP_ERRORING: usize = 10;
+
+#[derive(Serialize)]
+pub struct SchedulerStatus {
+	/// Refreshes running right now
+	pub refreshes_in_flight: usize,
+	/// Minutes between scheduled refreshes of feeds without their own
+	/// interval, None if disabled
+	pub refresh_interval: Option<u32>,
+	/// Whether this instance replicates a primary rather than running its own
+	/// schedules
+	pub replica: bool,
+	pub sync_remotes: usize,
+	/// Remotes whose last sync failed
+	pub sync_remotes_failing: usize,
+	pub last_sync: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct ErroringFeed {
+	pub username: String,
+	pub feed_id: u64,
+	pub feed_name: String,
+	/// Failed fetches among the user's recent refreshes
+	pub errors: usize,
+	/// None if the feed recovered since
+	pub last_error: Option<FetchError>,
+}
+
+#[derive(Serialize)]
+pub struct Overview {
+	pub users: usize,
+	pub admins: usize,
+	pub feeds: usize,
+	pub articles: usize,
+	/// Distinct article bodies, shared between users
+	pub article_bodies: usize,
+	pub size_on_disk: u64,
+	pub compaction_scheduled: bool,
+	pub scheduler: SchedulerStatus,
+	pub top_erroring_feeds: Vec<ErroringFeed>,
 }
 
 impl App {
 	const TREE_USERS: &str = "users";
+	const TREE_TOKENS: &str = "tokens";
+	const TREE_BLOGROLLS: &str = "blogrolls";
+	const TREE_TELEGRAM_CHATS: &str = "telegram_chats";
+	const TREE_TELEGRAM_LINKS: &str = "telegram_links";
+	const TREE_ANNOUNCEMENTS: &str = "announcements";
+	const TREE_INVITES: &str = "invites";
+	const TREE_BLOCKLIST: &str = "blocklist";
+	const TREE_SHARED_FEEDS: &str = "shared_feeds";
+	const TREE_BODIES: &str = "bodies";
+	const TREE_BODY_REFS: &str = "body_refs";
+	const TREE_BLOB_REFS: &str = "blob_refs";
 	const TREE_FEEDS: &str = "feeds";
 	const TREE_ARTICLES: &str = "articles";
+	const TREE_ARTICLE_KEYS: &str = "article_keys";
 	const TREE_INDEX: &str = "index";
+	const TREE_META: &str = "meta";
+	const TREE_DELETED_FEEDS: &str = "deleted_feeds";
+	const TREE_ARTICLE_REVISIONS: &str = "article_revisions";
+	const TREE_NOTIFY_TARGETS: &str = "notify_targets";
+	const TREE_SUBSCRIPTIONS: &str = "subscriptions";
+	const TREE_READ: &str = "read";
+	const TREE_STARRED: &str = "starred";
+	const TREE_POSITIONS: &str = "positions";
+	const TREE_TAGS: &str = "tags";
+	const TREE_ARTICLE_TAGS: &str = "article_tags";
+	const TREE_STATE_CLOCK: &str = "state_clock";
+	const TREE_STATE_CHANGES: &str = "state_changes";
+	const TREE_DEVICES: &str = "devices";
+	const TREE_SNAPSHOTS: &str = "snapshots";
+	const TREE_ARTICLE_SNAPSHOTS: &str = "article_snapshots";
+	const TREE_LINK_CHECKS: &str = "link_checks";
+	const TREE_FEED_READS: &str = "feed_reads";
+	const TREE_REFRESH_HISTORY: &str = "refresh_history";
+	const TREE_SYNC_REMOTES: &str = "sync_remotes";
+	const TREE_SYNC_STATE: &str = "sync_state";
+
+	/// Sealed when encryption is enabled, to tell whether the key is right
+	const ENCRYPTION_CHECK: &'static [u8] = b"encryption_check";
+
+	/// Marks the database at `db_path` for compaction on the next start
+	fn compaction_marker(db_path: &Path) -> PathBuf {
+		db_path.with_extension("compact")
+	}
+
+	/// Rewrites the database with only its live data, as sled reclaims space
+	/// left by overwritten and removed entries slowly. The copy is made next to
+	/// the database and swapped in once complete, so a failure leaves the
+	/// original in place.
+	fn compact(db_path: &Path) -> Result<()> {
+		let copy_path = db_path.with_extension("compacting");
+		let old_path = db_path.with_extension("old");
+		for path in [&copy_path, &old_path] {
+			if path.exists() {
+				std::fs::remove_dir_all(path)?;
+			}
+		}
+
+		let db = sled::open(db_path)?;
+		let size_before = db.size_on_disk()?;
+		let copy = sled::open(&copy_path)?;
+		copy.import(db.export());
+		copy.flush()?;
+		let size_after = copy.size_on_disk()?;
+		drop((db, copy));
+
+		std::fs::rename(db_path, &old_path)?;
+		std::fs::rename(&copy_path, db_path)?;
+		std::fs::remove_dir_all(&old_path)?;
+
+		log::info!(
+			"compacted database from {} to {} bytes",
+			size_before,
+			size_after
+		);
+		Ok(())
+	}
 
 	pub fn new(cfg: &Config) -> Result<Self> {
+		// done before opening, so nothing writes to the database meanwhile
+		let marker = Self::compaction_marker(&cfg.db_path);
+		if marker.exists() {
+			Self::compact(&cfg.db_path)?;
+			std::fs::remove_file(marker)?;
+		}
+
 		let db = sled::Config::default()
 			.path(&cfg.db_path)
 			.flush_every_ms(Some(1000));
@@ -38,13 +255,488 @@ impl App {
 
 		let db = db.open()?;
 		let users = db.open_tree(Self::TREE_USERS)?;
+		let tokens = db.open_tree(Self::TREE_TOKENS)?;
+		let blogrolls = db.open_tree(Self::TREE_BLOGROLLS)?;
+		let telegram_chats = db.open_tree(Self::TREE_TELEGRAM_CHATS)?;
+		let telegram_links = db.open_tree(Self::TREE_TELEGRAM_LINKS)?;
+		let announcements = db.open_tree(Self::TREE_ANNOUNCEMENTS)?;
+		let invites = db.open_tree(Self::TREE_INVITES)?;
+		let blocklist = db.open_tree(Self::TREE_BLOCKLIST)?;
+		let shared_feeds = db.open_tree(Self::TREE_SHARED_FEEDS)?;
+		let bodies = db.open_tree(Self::TREE_BODIES)?;
+		let body_refs = db.open_tree(Self::TREE_BODY_REFS)?;
+		let blobs = BlobStore::open(cfg.blobs_path.clone(), db.open_tree(Self::TREE_BLOB_REFS)?)?;
+		let search_index = SearchIndex::open(&cfg.search_path)?;
+
+		let mut root_certs = vec![];
+		for path in &cfg.http.extra_root_certs {
+			root_certs.extend(reqwest::Certificate::from_pem_bundle(&std::fs::read(
+				path,
+			)?)?);
+		}
+
+		let resolver = Arc::new(CachingResolver::new(&cfg.dns)?);
+		let client_builder = || {
+			let mut client = reqwest::ClientBuilder::new()
+				.timeout(Duration::from_secs(20))
+				.connect_timeout(Duration::from_secs(10))
+				.dns_resolver(resolver.clone())
+				.tcp_keepalive(cfg.http.tcp_keepalive)
+				.redirect(fetch::redirect_policy());
+			if let Some(max_idle) = cfg.http.pool_max_idle_per_host {
+				client = client.pool_max_idle_per_host(max_idle);
+			}
+			if let Some(idle_timeout) = cfg.http.pool_idle_timeout {
+				client = client.pool_idle_timeout(idle_timeout);
+			}
+			if !cfg.http.http2 {
+				client = client.http1_only();
+			}
+			for cert in &root_certs {
+				client = client.add_root_certificate(cert.clone());
+			}
+			client
+		};
+		let client = client_builder().build()?;
+		let insecure_client = client_builder().danger_accept_invalid_certs(true).build()?;
+
+		let app = Self {
+			db,
+			db_path: cfg.db_path.clone(),
+			users,
+			tokens,
+			blogrolls,
+			telegram_chats,
+			telegram_links,
+			announcements,
+			invites,
+			blocklist,
+			shared_feeds,
+			bodies,
+			body_refs,
+			blobs,
+			search_index,
+			client,
+			insecure_client,
+			fetch_cache: FetchCache::new(cfg.fetch_cache_ttl, cfg.limits.max_feed_size),
+			limits: cfg.limits,
+			refreshes: Refreshes::default(),
+			bcrypt_cost: cfg.bcrypt_cost,
+		};
+		// records must decode before anything else reads them
+		migrate::upgrade(&app, &app.db)?;
+		app.check_encryption()?;
+
+		Ok(app)
+	}
+
+	/// Refuses to go on with a missing or wrong key, and encrypts existing data
+	/// once encryption is first enabled
+	fn check_encryption(&self) -> Result<()> {
+		match self.db.get(Self::ENCRYPTION_CHECK)? {
+			Some(check) if crypt::enabled() => {
+				crypt::open(&check)?;
+			}
+			Some(_) => {
+				return Err(Error::Encryption(
+					"database is encrypted, but no key is configured".into(),
+				))
+			}
+			None if crypt::enabled() => {
+				self.encrypt_existing()?;
+				self.db.insert(
+					Self::ENCRYPTION_CHECK,
+					crypt::seal(Self::ENCRYPTION_CHECK.to_vec()),
+				)?;
+				// the plain values linger in the log until it's rewritten
+				self.schedule_compaction()?;
+			}
+			None => {}
+		}
+
+		Ok(())
+	}
+
+	/// Seals values written before encryption was enabled, and moves tokens
+	/// under their hashed keys. Values already sealed are skipped, so it resumes
+	/// where an interrupted run stopped.
+	pub fn encrypt_existing(&self) -> Result<()> {
+		fn seal_tree(tree: &sled::Tree, prefix: &[u8]) -> Result<usize> {
+			let mut sealed = 0;
+			for item in tree.scan_prefix(prefix) {
+				let (key, value) = item?;
+				if !crypt::is_sealed(&value) {
+					tree.insert(key, crypt::seal(value.to_vec()))?;
+					sealed += 1;
+				}
+			}
+			Ok(sealed)
+		}
+
+		log::info!("encrypting existing data");
+		let mut sealed = seal_tree(&self.users, b"")? + seal_tree(&self.bodies, b"")?;
+
+		for item in self.tokens.iter() {
+			let (key, value) = item?;
+			if !crypt::is_sealed(&value) {
+				let token: CapabilityToken = crypt::decode(&value)?;
+				self.tokens.remove(key)?;
+				self.tokens.insert(
+					CapabilityToken::key_of(&token.token),
+					crypt::encode(&token)?,
+				)?;
+				sealed += 1;
+			}
+		}
+
+		for user in User::get_all(self)? {
+			let app = self.open_user(&user.username)?;
+			sealed += seal_tree(&app.articles, b"")?
+				+ seal_tree(&app.feeds, b"")?
+				+ seal_tree(&app.sync_remotes, b"")?
+				+ seal_tree(&app.notify_targets, b"")?;
+		}
+
+		log::info!(
+			"encrypted {} values, plain copies are dropped by a compaction on the next restart",
+			sealed
+		);
+		Ok(())
+	}
+
+	/// Names of all trees belonging to the user
+	pub fn user_trees(&self, username: &str) -> Vec<sled::IVec> {
+		let prefix = format!("{}/", username);
+		self.db
+			.tree_names()
+			.into_iter()
+			.filter(|name| name.starts_with(prefix.as_bytes()))
+			.collect()
+	}
+
+	/// Deletes a user and all of their data. The user record, tokens and
+	/// blogrolls are removed in one transaction, so the account is gone at once;
+	/// per-user trees are dropped afterwards, and any left behind by a failure
+	/// show up in [`App::orphan_trees`].
+	pub fn delete_user(&self, username: &str) -> Result<()> {
+		User::get_user(self, username)?.ok_or(Error::UsernameNotFound)?;
+
+		// article bodies are shared, so references must be released first
+		Article::release_all(&self.open_user(username)?)?;
+
+		let token_keys: Vec<Vec<u8>> = CapabilityToken::get_all(self, username)?
+			.into_iter()
+			.map(|token| CapabilityToken::key_of(&token.token))
+			.collect();
+		let blogroll_keys: Vec<String> = Blogroll::get_all(self)?
+			.into_iter()
+			.filter(|blogroll| blogroll.owner == username)
+			.map(|blogroll| blogroll.key())
+			.collect();
+
+		(&self.users, &self.tokens, &self.blogrolls).transaction(
+			|(users, tokens, blogrolls)| {
+				users.remove(username.as_bytes())?;
+				for token in &token_keys {
+					tokens.remove(token.as_slice())?;
+				}
+				for blogroll in &blogroll_keys {
+					blogrolls.remove(blogroll.as_bytes())?;
+				}
+				Ok(())
+			},
+		)?;
+
+		telegram::delete_user(self, username)?;
+		self.search_index.remove_user(username);
+		self.search_index.commit()?;
+
+		for tree in self.user_trees(username) {
+			self.db.drop_tree(tree)?;
+		}
+
+		Ok(())
+	}
+
+	/// Renames a user. The new name is reserved first, so nobody registers it
+	/// while the trees are copied under it; then the user record is swapped in one
+	/// transaction that also revokes the user's tokens. A failure in between
+	/// drops the copies and the reservation again.
+	pub fn rename_user(&self, username: &str, new_username: &str) -> Result<()> {
+		if !invite::valid_username(new_username) {
+			return Err(Error::InvalidUsername);
+		}
+		let mut user = User::get_user(self, username)?.ok_or(Error::UsernameNotFound)?;
+		user.username = new_username.to_owned();
+		let user_bytes = crypt::encode(&user)?;
+
+		// reserved under the renamed record, which the swap keeps
+		self.users
+			.compare_and_swap(
+				new_username.as_bytes(),
+				None as Option<&[u8]>,
+				Some(user_bytes.as_slice()),
+			)?
+			.map_err(|_| Error::UsernameTaken)?;
+
+		let old_trees = self.user_trees(username);
+		let has_blogrolls = match self.swap_user(username, new_username, &old_trees, &user_bytes) {
+			Ok(has_blogrolls) => has_blogrolls,
+			Err(e) => {
+				for name in self.user_trees(new_username) {
+					self.db.drop_tree(name)?;
+				}
+				let _ = self.users.compare_and_swap(
+					new_username.as_bytes(),
+					Some(user_bytes.as_slice()),
+					None as Option<&[u8]>,
+				)?;
+				return Err(e);
+			}
+		};
+
+		// re-link other users' subscriptions to the renamed owner's blogrolls
+		if has_blogrolls {
+			for name in self.db.tree_names() {
+				if !name.ends_with(format!("/{}", Self::TREE_SUBSCRIPTIONS).as_bytes()) {
+					continue;
+				}
+
+				let tree = self.db.open_tree(name)?;
+				for item in tree.iter() {
+					let (key, bytes) = item?;
+					let mut subscription: Subscription = bincode::deserialize(&bytes)?;
+					if subscription.owner == username {
+						subscription.owner = new_username.to_owned();
+						tree.remove(key)?;
+						tree.insert(
+							Blogroll::key_of(new_username, &subscription.folder).as_bytes(),
+							bincode::serialize(&subscription)?,
+						)?;
+					}
+				}
+			}
+		}
+
+		telegram::rename_user(self, username, new_username)?;
+
+		for name in old_trees {
+			self.db.drop_tree(name)?;
+		}
+
+		// documents are owned by username, so the index is built anew
+		self.search_index.remove_user(username);
+		self.open_user(new_username)?.create_search_index()?;
+
+		Ok(())
+	}
+
+	/// Copies the trees of a user being renamed under the reserved new name, and
+	/// swaps the user record, see [`App::rename_user`]. Returns whether the user
+	/// owns blogrolls, which others' subscriptions link to.
+	fn swap_user(
+		&self,
+		username: &str,
+		new_username: &str,
+		old_trees: &[sled::IVec],
+		user_bytes: &[u8],
+	) -> Result<bool> {
+		for name in old_trees {
+			let suffix = &name[username.len() + 1..];
+			let new_name = [format!("{}/", new_username).as_bytes(), suffix].concat();
+
+			let old_tree = self.db.open_tree(name)?;
+			let new_tree = self.db.open_tree(new_name)?;
+			for item in old_tree.iter() {
+				let (key, value) = item?;
+				new_tree.insert(key, value)?;
+			}
+		}
+
+		let token_keys: Vec<Vec<u8>> = CapabilityToken::get_all(self, username)?
+			.into_iter()
+			.map(|token| CapabilityToken::key_of(&token.token))
+			.collect();
+		let renamed_blogrolls = Blogroll::get_all(self)?
+			.into_iter()
+			.filter(|blogroll| blogroll.owner == username)
+			.map(|blogroll| {
+				let renamed = Blogroll {
+					owner: new_username.to_owned(),
+					folder: blogroll.folder.clone(),
+				};
+				Ok((blogroll.key(), renamed.key(), bincode::serialize(&renamed)?))
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		(&self.users, &self.tokens, &self.blogrolls)
+			.transaction(|(users, tokens, blogrolls)| {
+				// the reservation may have been lifted, e.g. by deleting the user
+				if users.get(new_username.as_bytes())?.as_deref() != Some(user_bytes) {
+					return sled::transaction::abort(Error::UsernameTaken);
+				}
+				users.remove(username.as_bytes())?;
+				for token in &token_keys {
+					tokens.remove(token.as_slice())?;
+				}
+				for (old_key, new_key, blogroll) in &renamed_blogrolls {
+					blogrolls.remove(old_key.as_bytes())?;
+					blogrolls.insert(new_key.as_bytes(), blogroll.as_slice())?;
+				}
+				Ok(())
+			})
+			.map_err(|e| match e {
+				TransactionError::Abort(e) => e,
+				TransactionError::Storage(e) => e.into(),
+			})?;
 
-		let client = reqwest::ClientBuilder::new()
-			.timeout(Duration::from_secs(20))
-			.connect_timeout(Duration::from_secs(10))
-			.build()?;
+		Ok(!renamed_blogrolls.is_empty())
+	}
+
+	/// Per-user trees whose user no longer exists
+	pub fn orphan_trees(&self) -> Result<Vec<String>> {
+		let mut orphans = vec![];
+		for name in self.db.tree_names() {
+			let name = String::from_utf8_lossy(&name).into_owned();
+			if let Some((username, _)) = name.split_once('/') {
+				if !self.users.contains_key(username.as_bytes())? {
+					orphans.push(name);
+				}
+			}
+		}
+
+		Ok(orphans)
+	}
+
+	pub fn drop_orphan_trees(&self) -> Result<Vec<String>> {
+		let orphans = self.orphan_trees()?;
+		for name in &orphans {
+			self.db.drop_tree(name.as_bytes())?;
+		}
+
+		Ok(orphans)
+	}
+
+	pub fn storage_usage(&self) -> Result<StorageUsage> {
+		let mut trees = vec![];
+		for name in self.db.tree_names() {
+			let tree = self.db.open_tree(&name)?;
+			let mut usage = TreeUsage {
+				name: String::from_utf8_lossy(&name).into_owned(),
+				entries: 0,
+				bytes: 0,
+			};
+			for item in tree.iter() {
+				let (key, value) = item?;
+				usage.entries += 1;
+				usage.bytes += (key.len() + value.len()) as u64;
+			}
+			trees.push(usage);
+		}
+		trees.sort_by_key(|tree| std::cmp::Reverse(tree.bytes));
+
+		Ok(StorageUsage {
+			size_on_disk: self.db.size_on_disk()?,
+			live_bytes: trees.iter().map(|tree| tree.bytes).sum(),
+			trees,
+			compaction_scheduled: Self::compaction_marker(&self.db_path).exists(),
+		})
+	}
+
+	/// Walks every user's trees, so it's run off the async runtime
+	pub fn overview(&self) -> Result<Overview> {
+		let users = User::get_all(self)?;
+		let mut overview = Overview {
+			users: users.len(),
+			admins: users.iter().filter(|user| user.admin).count(),
+			feeds: 0,
+			articles: 0,
+			article_bodies: self.bodies.len(),
+			size_on_disk: self.db.size_on_disk()?,
+			compaction_scheduled: Self::compaction_marker(&self.db_path).exists(),
+			scheduler: SchedulerStatus {
+				refreshes_in_flight: self.refreshes.in_flight(),
+				refresh_interval: scheduler::default_interval(),
+				replica: replica::enabled(),
+				sync_remotes: 0,
+				sync_remotes_failing: 0,
+				last_sync: None,
+			},
+			top_erroring_feeds: vec![],
+		};
 
-		Ok(Self { db, users, client })
+		for user in users {
+			let app = self.open_user(&user.username)?;
+			let feeds = Feed::get_all(&app)?;
+			overview.feeds += feeds.len();
+			overview.articles += app.articles.len();
+
+			for remote in SyncRemote::get_all(&app)? {
+				let scheduler = &mut overview.scheduler;
+				scheduler.sync_remotes += 1;
+				scheduler.sync_remotes_failing += remote.last_error.is_some() as usize;
+				scheduler.last_sync = scheduler.last_sync.max(remote.last_sync);
+			}
+
+			let mut errors = HashMap::<u64, ErroringFeed>::new();
+			for report in RefreshReport::get_latest(&app, ERROR_WINDOW)? {
+				for error in report.errors {
+					errors
+						.entry(error.feed_id)
+						.or_insert_with(|| ErroringFeed {
+							username: user.username.clone(),
+							feed_id: error.feed_id,
+							feed_name: error.feed_name,
+							errors: 0,
+							last_error: None,
+						})
+						.errors += 1;
+				}
+			}
+			// feeds removed since don't matter anymore
+			for feed in feeds {
+				if let Some(mut erroring) = errors.remove(&feed.id) {
+					erroring.feed_name = feed.name;
+					erroring.last_error = feed.last_error;
+					overview.top_erroring_feeds.push(erroring);
+				}
+			}
+		}
+
+		overview
+			.top_erroring_feeds
+			.sort_by_key(|erroring| std::cmp::Reverse(erroring.errors));
+		overview.top_erroring_feeds.truncate(TOP_ERRORING);
+
+		Ok(overview)
+	}
+
+	/// Schedules compaction for the next start, as the database can't be
+	/// swapped out while in use
+	pub fn schedule_compaction(&self) -> Result<()> {
+		self.db.flush()?;
+		std::fs::write(Self::compaction_marker(&self.db_path), [])?;
+		Ok(())
+	}
+
+	/// Rebuilds the search indexes of users whose index is of an older format or
+	/// corrupted, e.g. after an upgrade or a crash
+	pub fn repair_search_indexes(&self) -> Result<()> {
+		for user in User::get_all(self)? {
+			let app = self.open_user(&user.username)?;
+			if !app.search_index_ok()? {
+				log::info!("rebuilding search index of {}", user.username);
+				app.create_search_index()?;
+			}
+		}
+		Ok(())
+	}
+
+	/// The user's meta tree, without opening the rest of the user's trees
+	pub fn user_meta(&self, username: &str) -> Result<sled::Tree> {
+		Ok(self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_META))?)
 	}
 
 	pub fn open_user(&self, username: &str) -> Result<AppUser> {
@@ -55,85 +747,630 @@ impl App {
 		let articles = self
 			.db
 			.open_tree(format!("{}/{}", username, Self::TREE_ARTICLES))?;
+		let article_keys =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_ARTICLE_KEYS))?;
 		let index = self
 			.db
 			.open_tree(format!("{}/{}", username, Self::TREE_INDEX))?;
 
+		let meta = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_META))?;
+
+		let deleted_feeds =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_DELETED_FEEDS))?;
+
+		let article_revisions =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_ARTICLE_REVISIONS))?;
+
+		let notify_targets =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_NOTIFY_TARGETS))?;
+
+		let subscriptions =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_SUBSCRIPTIONS))?;
+
+		let read = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_READ))?;
+
+		let starred = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_STARRED))?;
+
+		let positions = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_POSITIONS))?;
+
+		let tags = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_TAGS))?;
+
+		let article_tags =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_ARTICLE_TAGS))?;
+
+		let state_clock = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_STATE_CLOCK))?;
+
+		let state_changes =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_STATE_CHANGES))?;
+
+		let devices = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_DEVICES))?;
+
+		let snapshots = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_SNAPSHOTS))?;
+
+		let article_snapshots =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_ARTICLE_SNAPSHOTS))?;
+
+		let link_checks = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_LINK_CHECKS))?;
+
+		let feed_reads = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_FEED_READS))?;
+
+		let refresh_history =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_REFRESH_HISTORY))?;
+
+		let sync_remotes =
+			self.db
+				.open_tree(format!("{}/{}", username, Self::TREE_SYNC_REMOTES))?;
+
+		let sync_state = self
+			.db
+			.open_tree(format!("{}/{}", username, Self::TREE_SYNC_STATE))?;
+
+		// settings saved before a field was added no longer decode, don't lock the
+		// user out over them
+		let settings = meta
+			.get(AppUser::META_SETTINGS)?
+			.and_then(|bytes| {
+				bincode::deserialize(&bytes)
+					.map_err(|e| log::warn!("resetting settings of {}: {}", username, e))
+					.ok()
+			})
+			.unwrap_or_default();
+
 		Ok(AppUser {
+			username: username.to_owned(),
+			settings,
 			db,
 			feeds,
 			articles,
+			article_keys,
 			index,
+			meta,
+			deleted_feeds,
+			article_revisions,
+			notify_targets,
+			subscriptions,
+			read,
+			starred,
+			positions,
+			tags,
+			article_tags,
+			state_clock,
+			state_changes,
+			devices,
+			snapshots,
+			article_snapshots,
+			link_checks,
+			feed_reads,
+			refresh_history,
+			sync_remotes,
+			sync_state,
+			blocklist: self.blocklist.clone(),
+			shared_feeds: self.shared_feeds.clone(),
+			bodies: self.bodies.clone(),
+			body_refs: self.body_refs.clone(),
+			blobs: self.blobs.clone(),
+			search_index: self.search_index.clone(),
 			client: self.client.clone(),
+			insecure_client: self.insecure_client.clone(),
+			fetch_cache: self.fetch_cache.clone(),
+			limits: self.limits,
 		})
 	}
 }
 
+/// Per-user preferences, loaded along with the user for every request
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct UserSettings {
+	/// IANA time zone name, e.g. "Europe/Bucharest"
+	pub timezone: Option<String>,
+	/// Article ordering used when a listing does not specify one
+	pub order_by: Option<ArticleOrderBy>,
+	pub order: Option<Order>,
+	/// Page size used when a paginated listing does not specify one
+	pub page_size: Option<usize>,
+	/// Whether listings and searches only have unread articles when they don't
+	/// specify it
+	pub unread_only: Option<bool>,
+	/// Let the user's subscriptions count towards suggestions for other users
+	pub share_subscriptions: bool,
+	/// Parts of articles searched in
+	pub indexed_fields: IndexedFields,
+	/// Which articles pruning would remove
+	pub retention: Retention,
+}
+
+/// Change counter of some of the user's data, for caching what's derived from it
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+pub struct Revision {
+	pub value: u64,
+	pub changed: DateTime<Utc>,
+}
+
+/// Limits set by administrators, rather than by the user like settings
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Quota {
+	pub max_feeds: Option<usize>,
+}
+
 #[derive(Serialize)]
 pub struct Status {
 	last_new_article: DateTime<Utc>,
 	total_articles: u32,
+	unread: usize,
+	/// Unread articles per feed id, for feeds with any
+	unread_feeds: BTreeMap<u64, usize>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	new_since: Option<NewSince>,
 }
 
+/// Articles first seen after a point in time, so clients can poll cheaply and
+/// only sync when something changed
+#[derive(Serialize)]
+pub struct NewSince {
+	since: DateTime<Utc>,
+	new_articles: usize,
+	/// New articles per feed id
+	feeds: BTreeMap<u64, usize>,
+}
+
+#[derive(Clone)]
 pub struct AppUser {
+	pub username: String,
+	pub settings: UserSettings,
 	pub db: sled::Db,
 	pub feeds: sled::Tree,
 	pub articles: sled::Tree,
+	pub article_keys: sled::Tree,
+	/// Version of the search index, and articles queued for indexing
 	pub index: sled::Tree,
+	pub meta: sled::Tree,
+	/// Feeds revision at which feeds were deleted, by feed id
+	pub deleted_feeds: sled::Tree,
+	/// Articles revision at which articles were last added, changed or removed,
+	/// by article id, see [`Article::changed_since`]
+	pub article_revisions: sled::Tree,
+	pub notify_targets: sled::Tree,
+	pub subscriptions: sled::Tree,
+	/// Entry keys of read articles
+	pub read: sled::Tree,
+	/// Entry keys of starred articles
+	pub starred: sled::Tree,
+	/// Read positions of articles, by entry key
+	pub positions: sled::Tree,
+	/// Tagged articles, by tag and entry key, see [`crate::tag`]
+	pub tags: sled::Tree,
+	/// Tags of articles, by entry key
+	pub article_tags: sled::Tree,
+	/// When read and starred state last changed, by flag and entry key
+	pub state_clock: sled::Tree,
+	/// Log of state changes for devices to sync, by sequence number
+	pub state_changes: sled::Tree,
+	/// Devices syncing state, by id
+	pub devices: sled::Tree,
+	/// Last seen content of page watch feeds, by feed id
+	pub snapshots: sled::Tree,
+	/// Blob of the archived page of articles, by entry key
+	pub article_snapshots: sled::Tree,
+	/// Last link check of starred articles, by entry key
+	pub link_checks: sled::Tree,
+	/// How much of each feed the user read, by feed id
+	pub feed_reads: sled::Tree,
+	/// Summaries of past refreshes, by id
+	pub refresh_history: sled::Tree,
+	/// Other readers to sync with, by id
+	pub sync_remotes: sled::Tree,
+	/// State agreed on at the last sync, by remote id
+	pub sync_state: sled::Tree,
+	/// The instance's, see [`crate::blocklist`]
+	pub blocklist: sled::Tree,
+	/// The instance's, see [`crate::team`]
+	pub shared_feeds: sled::Tree,
+	pub bodies: sled::Tree,
+	pub body_refs: sled::Tree,
+	pub blobs: BlobStore,
+	/// The instance's, see [`crate::search`]
+	pub search_index: SearchIndex,
 	pub client: reqwest::Client,
+	insecure_client: reqwest::Client,
+	pub fetch_cache: FetchCache,
+	pub limits: SizeLimits,
 }
 
 impl AppUser {
-	pub fn status(&self) -> Result<Status> {
-		let mut status = Status {
-			last_new_article: DateTime::<Utc>::MIN_UTC,
-			total_articles: 0,
-		};
+	/// Format of the index, bumped whenever it changes
+	const INDEX_VERSION: u32 = 3;
+	const INDEX_VERSION_KEY: &'static [u8] = b"__article_search_index_version";
+	/// Articles to index on the next update, see [`AppUser::queue_for_index`]
+	const INDEX_PENDING_PREFIX: &'static [u8] = b"__article_search_index_pending/";
+	const META_FEEDS_REVISION: &'static [u8] = b"feeds_revision";
+	const META_ARTICLES_REVISION: &'static [u8] = b"articles_revision";
+	const META_STARRED_REVISION: &'static [u8] = b"starred_revision";
+	const META_READ_REVISION: &'static [u8] = b"read_revision";
+	const META_SETTINGS: &'static [u8] = b"settings";
+	pub const META_SCRAPER_PRESETS: &'static [u8] = b"scraper_presets";
+	pub const META_REPLICA: &'static [u8] = b"replica";
+	pub const META_ANNOUNCEMENT_ACK: &'static [u8] = b"announcement_ack";
+	pub const META_TEMPLATES: &'static [u8] = b"templates";
+	pub const META_MUTES: &'static [u8] = b"mutes";
+	pub const META_QUOTA: &'static [u8] = b"quota";
+	pub const DEFAULT_PAGE_SIZE: usize = 50;
+	pub const MAX_PAGE_SIZE: usize = 500;
+	/// Matches a search goes through at most, the most relevant ones
+	pub const MAX_SEARCH_HITS: usize = 1000;
 
-		for article in Article::iter(self) {
-			let article = article?;
-			status.total_articles += 1;
-			status.last_new_article = status.last_new_article.max(article.published);
+	/// Page size of a listing, falling back to the user's default
+	pub fn page_size(&self, requested: Option<usize>) -> usize {
+		requested
+			.or(self.settings.page_size)
+			.unwrap_or(Self::DEFAULT_PAGE_SIZE)
+			.clamp(1, Self::MAX_PAGE_SIZE)
+	}
+
+	/// Client to fetch the feed and its pages with
+	pub fn client_for(&self, feed: &Feed) -> &reqwest::Client {
+		if feed.accept_invalid_certs {
+			log::warn!(
+				"fetching feed {} of {} WITHOUT certificate validation",
+				feed.url,
+				self.username
+			);
+			&self.insecure_client
 		}
+		else {
+			&self.client
+		}
+	}
 
-		Ok(status)
+	pub fn save_settings(&mut self, settings: UserSettings) -> Result<()> {
+		self.meta
+			.insert(Self::META_SETTINGS, bincode::serialize(&settings)?)?;
+		self.settings = settings;
+		Ok(())
 	}
 
-	pub fn search(&self, term: &str) -> Result<Vec<String>> {
-		// reconstruct search index from sled
-		let b_tree: BTreeMap<String, BTreeSet<String>> = self
-			.index
-			.get(b"__article_search_index")?
+	pub fn quota(&self) -> Result<Quota> {
+		Ok(self
+			.meta
+			.get(Self::META_QUOTA)?
 			.map(|bytes| bincode::deserialize(&bytes))
 			.transpose()?
+			.unwrap_or_default())
+	}
+
+	pub fn set_quota(&self, quota: &Quota) -> Result<()> {
+		self.meta
+			.insert(Self::META_QUOTA, bincode::serialize(quota)?)?;
+		Ok(())
+	}
+
+	pub fn feeds_revision(&self) -> Result<u64> {
+		Ok(self
+			.meta
+			.get(Self::META_FEEDS_REVISION)?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()?
+			.unwrap_or_default())
+	}
+
+	/// Atomically increments the feeds revision, returning the new value
+	pub fn bump_feeds_revision(&self) -> Result<u64> {
+		let bytes = self
+			.meta
+			.update_and_fetch(Self::META_FEEDS_REVISION, |old| {
+				let rev: u64 = old
+					.and_then(|bytes| bincode::deserialize(bytes).ok())
+					.unwrap_or_default();
+				bincode::serialize(&(rev + 1)).ok()
+			})?
 			.unwrap_or_default();
 
-		// hackly replace search index b_tree_map
-		let mut search_index = indicium::simple::SearchIndexBuilder::default().build();
-		*search_index = b_tree;
+		Ok(bincode::deserialize(&bytes)?)
+	}
+
+	fn revision(&self, key: &[u8]) -> Result<Revision> {
+		Ok(self
+			.meta
+			.get(key)?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()?
+			.unwrap_or_default())
+	}
+
+	fn bump_revision(&self, key: &[u8]) -> Result<()> {
+		self.meta.update_and_fetch(key, |old| {
+			let rev: Revision = old
+				.and_then(|bytes| bincode::deserialize(bytes).ok())
+				.unwrap_or_default();
+			bincode::serialize(&Revision {
+				value: rev.value + 1,
+				changed: Utc::now(),
+			})
+			.ok()
+		})?;
+		Ok(())
+	}
+
+	/// Changes whenever an article is added, changed or removed
+	pub fn articles_revision(&self) -> Result<Revision> {
+		self.revision(Self::META_ARTICLES_REVISION)
+	}
+
+	/// Increments the articles revision for the articles that were added or
+	/// changed, or removed if flagged so, and records it as the one they last
+	/// changed at. Both happen in one transaction, so whoever reads the revision
+	/// before [the changes](Article::changed_since) misses none up to it.
+	pub fn bump_articles_revision(&self, articles: &[(ArticleId, bool)]) -> Result<()> {
+		(&self.meta, &self.article_revisions).transaction(|(meta, revisions)| {
+			let rev: Revision = meta
+				.get(Self::META_ARTICLES_REVISION)?
+				.and_then(|bytes| bincode::deserialize(&bytes).ok())
+				.unwrap_or_default();
+			let rev = Revision {
+				value: rev.value + 1,
+				changed: Utc::now(),
+			};
+			meta.insert(
+				Self::META_ARTICLES_REVISION,
+				bincode::serialize(&rev).expect("revisions serialize"),
+			)?;
+			for (id, removed) in articles {
+				let value = [&rev.value.to_be_bytes()[..], &[*removed as u8]].concat();
+				revisions.insert(id.as_bytes(), value)?;
+			}
+			Ok(())
+		})?;
+		Ok(())
+	}
+
+	/// Changes whenever an article is starred or unstarred
+	pub fn starred_revision(&self) -> Result<Revision> {
+		self.revision(Self::META_STARRED_REVISION)
+	}
+
+	pub fn bump_starred_revision(&self) -> Result<()> {
+		self.bump_revision(Self::META_STARRED_REVISION)
+	}
+
+	/// Changes whenever an article is marked read or unread
+	pub fn read_revision(&self) -> Result<Revision> {
+		self.revision(Self::META_READ_REVISION)
+	}
+
+	pub fn bump_read_revision(&self) -> Result<()> {
+		self.bump_revision(Self::META_READ_REVISION)
+	}
+
+	pub fn status(&self, since: Option<DateTime<Utc>>) -> Result<Status> {
+		// articles are keyed newest-first, so the first key holds the latest publish time
+		let last_new_article = self
+			.articles
+			.first()?
+			.map(|(key, _)| ArticleId::from_bytes(&key))
+			.transpose()?
+			.map(|id| id.published())
+			.unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+		let new_since = since
+			.map(|since| {
+				let feeds = Article::count_seen_since(self, since)?;
+				Ok::<_, Error>(NewSince {
+					since,
+					new_articles: feeds.values().sum(),
+					feeds,
+				})
+			})
+			.transpose()?;
+
+		let unread_feeds = Article::count_unread(self)?;
+
+		Ok(Status {
+			last_new_article,
+			total_articles: self.articles.len() as u32,
+			unread: unread_feeds.values().sum(),
+			unread_feeds,
+			new_since,
+		})
+	}
+
+	/// Ids of the `limit` articles matching best, best first
+	pub fn search(&self, query: &SearchQuery, limit: usize) -> Result<Vec<ArticleId>> {
+		self.search_clauses(&query.clauses, Some(limit))
+	}
+
+	/// Ids of all articles matching any of the query's exclusions. A query of
+	/// exclusions alone filters a listing by them, rather than be capped like a
+	/// search.
+	pub fn search_excluded(&self, query: &SearchQuery) -> Result<HashSet<ArticleId>> {
+		let mut excluded = HashSet::new();
+		for clause in query.clauses.iter().filter(|clause| clause.exclude) {
+			let clause = Clause {
+				exclude: false,
+				..clause.clone()
+			};
+			excluded.extend(self.search_clauses(&[clause], None)?);
+		}
+		Ok(excluded)
+	}
 
-		// search results
-		Ok(search_index
-			.search(term)
+	/// Ids of the articles matching the clauses, see [`SearchIndex::search`]
+	fn search_clauses(&self, clauses: &[Clause], limit: Option<usize>) -> Result<Vec<ArticleId>> {
+		let hits =
+			self.search_index
+				.search(&self.username, &self.shared_feed_urls()?, clauses, limit)?;
+
+		// articles of shared feeds are the user's own too, unless removed
+		let mut seen = HashSet::new();
+		let mut ids = vec![];
+		for hit in hits {
+			let id = match hit {
+				Hit::Own(id) => id,
+				Hit::Shared(composite) => match Article::current_id(self, &composite)? {
+					Some(id) => id,
+					None => continue,
+				},
+			};
+			if seen.insert(id) {
+				ids.push(id);
+			}
+		}
+		Ok(ids)
+	}
+
+	/// Urls of the user's feeds that are [shared](crate::team), by feed id
+	fn shared_feed_urls(&self) -> Result<HashMap<u64, Url>> {
+		let shared = SharedFeeds::new(&self.shared_feeds)?;
+		Ok(Feed::get_all(self)?
 			.into_iter()
-			.map(ToOwned::to_owned)
+			.filter(|feed| shared.contains(&feed.url))
+			.map(|feed| (feed.id, feed.url))
 			.collect())
 	}
 
+	/// Stages indexing the article, in the shared index if its feed is shared.
+	/// Shared feeds are indexed whole, whatever the user's indexed fields.
+	fn index_article(&self, article: &Article, shared: &HashMap<u64, Url>) -> Result<()> {
+		match shared.get(&article.feed_id) {
+			Some(url) => {
+				self.search_index.remove(&self.username, &article.id);
+				self.search_index
+					.add_shared(url, &IndexedArticle(article, IndexedFields::Content))
+			}
+			None => self.search_index.add(
+				&self.username,
+				&IndexedArticle(article, self.settings.indexed_fields),
+			),
+		}
+	}
+
+	/// Whether the index was built in the current format; if not, it needs to be
+	/// rebuilt
+	pub fn search_index_ok(&self) -> Result<bool> {
+		let version = self
+			.index
+			.get(Self::INDEX_VERSION_KEY)?
+			.and_then(|bytes| bytes.as_ref().try_into().ok())
+			.map(u32::from_be_bytes);
+		Ok(version == Some(Self::INDEX_VERSION))
+	}
+
+	/// Rebuilds the search index from all articles, e.g. after the indexed
+	/// fields changed. Refreshes only [update](Self::update_search_index) it.
 	pub fn create_search_index(&self) -> Result<()> {
-		// create index
-		let mut search_index = indicium::simple::SearchIndexBuilder::default().build();
+		// drop the queue, as well as the shards of the sled index tantivy replaced;
+		// the version goes first, so an interrupted rebuild is redone
+		self.index.remove(Self::INDEX_VERSION_KEY)?;
+		self.index.clear()?;
+
+		self.search_index.remove_user(&self.username);
+		let shared = self.shared_feed_urls()?;
 		for article in Article::iter(self) {
-			let article = article?;
-			search_index.insert(&article.id, &article);
+			self.index_article(&article?, &shared)?;
 		}
+		self.search_index.commit()?;
 
-		// manually serialize search index into db
-		self.index.insert(
-			b"__article_search_index",
-			bincode::serialize(&*search_index)?,
-		)?;
+		self.index
+			.insert(Self::INDEX_VERSION_KEY, &Self::INDEX_VERSION.to_be_bytes())?;
+		Ok(())
+	}
 
+	/// Queues an article to be indexed again on the next update, after it was
+	/// added, changed or removed. Every queueing stores a new value, so an update
+	/// running meanwhile can tell it apart from the one it indexed.
+	pub fn queue_for_index(&self, id: &ArticleId) -> Result<()> {
+		let mut key = Self::INDEX_PENDING_PREFIX.to_vec();
+		key.extend_from_slice(id.as_bytes());
+		self.index
+			.insert(key, &self.db.generate_id()?.to_be_bytes())?;
 		Ok(())
 	}
+
+	/// Queues all articles of the feed to be indexed again, returning how many
+	pub fn queue_feed_for_index(&self, feed_id: u64) -> Result<usize> {
+		let mut queued = 0;
+		for key in self
+			.article_keys
+			.scan_prefix(feed_id.to_be_bytes())
+			.values()
+		{
+			self.queue_for_index(&ArticleId::from_bytes(&key?)?)?;
+			queued += 1;
+		}
+		Ok(queued)
+	}
+
+	/// Indexes the articles queued since the last update, dropping the ones no
+	/// longer stored
+	pub fn update_search_index(&self) -> Result<()> {
+		let mut queued = vec![];
+		for item in self.index.scan_prefix(Self::INDEX_PENDING_PREFIX) {
+			let (key, value) = item?;
+			let id = ArticleId::from_bytes(&key[Self::INDEX_PENDING_PREFIX.len()..])?;
+			queued.push((id, key, value));
+		}
+		if queued.is_empty() {
+			return Ok(());
+		}
+
+		// articles of shared feeds stay in the shared index until the feed is no
+		// longer shared, other subscribers may still have them
+		let shared = self.shared_feed_urls()?;
+		for (id, _, _) in &queued {
+			// an article moved to another id is queued under that one too
+			match Article::get_id(self, id)? {
+				Some(article) if article.id == *id => self.index_article(&article, &shared)?,
+				_ => self.search_index.remove(&self.username, id),
+			}
+		}
+		self.search_index.commit()?;
+
+		// unless queued again meanwhile
+		for (_, key, value) in queued {
+			let _ = self
+				.index
+				.compare_and_swap(key, Some(value), None as Option<&[u8]>)?;
+		}
+		Ok(())
+	}
+
+	/// Drops removed articles from the search index
+	pub fn remove_from_search_index(&self, ids: &BTreeSet<ArticleId>) -> Result<()> {
+		if ids.is_empty() {
+			return Ok(());
+		}
+
+		for id in ids {
+			self.search_index.remove(&self.username, id);
+		}
+		self.search_index.commit()
+	}
 }
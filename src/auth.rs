@@ -0,0 +1,149 @@
+//! How API requests are authenticated, by the backend chosen with
+//! `AUTH_BACKEND`:
+//!
+//! - `local`, the default, checks HTTP Basic credentials against the users'
+//!   password hashes
+//! - `proxy` takes the username from a header set by a reverse proxy that
+//!   authenticated the user, e.g. against an OIDC provider or LDAP. The header
+//!   is only trusted from the proxy's addresses, as anyone else can set it.
+//! - `ldap`, with the `ldap` feature, checks HTTP Basic credentials against a
+//!   directory server, see [`crate::ldap`]
+//!
+//! Except with `ldap`, users must exist already. Capability tokens are checked by the
+//! routes taking them, whatever the backend.
+
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock};
+
+use axum::http::{HeaderMap, HeaderName};
+use base64::{
+	alphabet,
+	engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+	Engine,
+};
+use ipnet::IpNet;
+
+use crate::db::User;
+use crate::{App, Error, Result};
+
+static BACKEND: OnceLock<Box<dyn AuthBackend>> = OnceLock::new();
+
+/// What a backend gets to know of a request
+pub struct AuthRequest<'a> {
+	pub headers: &'a HeaderMap,
+	/// Where the request came from, the proxy if behind one
+	pub peer: Option<IpAddr>,
+	/// See [`crate::network::ClientIp`]
+	pub client_ip: Option<IpAddr>,
+}
+
+pub trait AuthBackend: Send + Sync {
+	/// Listed among the server's auth methods
+	fn method(&self) -> &'static str;
+
+	/// The user the request is made by, called on a blocking thread. Failures
+	/// are reported as [`Error::UsernameNotFound`] or
+	/// [`Error::PasswordIncorrect`], which don't tell which part was wrong.
+	fn authenticate(&self, app: &Arc<App>, request: &AuthRequest) -> Result<User>;
+}
+
+pub fn configure(backend: Box<dyn AuthBackend>) {
+	let _ = BACKEND.set(backend);
+}
+
+pub fn backend() -> &'static dyn AuthBackend {
+	BACKEND.get().map_or(&LocalBackend, AsRef::as_ref)
+}
+
+/// The username and password of HTTP Basic authentication
+pub fn basic_credentials(headers: &HeaderMap) -> Result<(String, String)> {
+	// clients differ on whether they pad, so accept both
+	const BASIC_ENGINE: GeneralPurpose = GeneralPurpose::new(
+		&alphabet::STANDARD,
+		GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+	);
+
+	let payload = headers
+		.get(axum::http::header::AUTHORIZATION)
+		.and_then(|header| header.to_str().ok())
+		.and_then(|auth| auth.trim().strip_prefix("Basic "))
+		.ok_or(Error::UsernameNotFound)?;
+	let decoded_bytes = BASIC_ENGINE
+		.decode(payload.trim())
+		.map_err(|_| Error::UsernameNotFound)?;
+	let decoded = String::from_utf8(decoded_bytes).map_err(|_| Error::UsernameNotFound)?;
+
+	// only the username is delimited, passwords may contain colons
+	let (username, password) = decoded.split_once(':').ok_or(Error::UsernameNotFound)?;
+	Ok((username.to_owned(), password.to_owned()))
+}
+
+/// HTTP Basic credentials, checked against the users' password hashes
+pub struct LocalBackend;
+
+impl AuthBackend for LocalBackend {
+	fn method(&self) -> &'static str {
+		"basic"
+	}
+
+	fn authenticate(&self, app: &Arc<App>, request: &AuthRequest) -> Result<User> {
+		let (username, password) = basic_credentials(request.headers)?;
+
+		let user = User::try_login(app, &username, &password).inspect_err(|_| {
+			log::warn!(
+				"failed login of {} from {}",
+				username,
+				request
+					.client_ip
+					.map_or("unknown address".into(), |ip| ip.to_string())
+			)
+		})?;
+
+		// upgrade hashes from a previously configured cost in the background
+		if user.needs_rehash(app) {
+			let app = app.clone();
+			tokio::task::spawn_blocking(move || {
+				User::rehash(&app, &username, &password)
+					.unwrap_or_else(|e| log::warn!("could not rehash password: {}", e))
+			});
+		}
+
+		Ok(user)
+	}
+}
+
+/// The username in a header set by a trusted reverse proxy
+pub struct ProxyBackend {
+	pub header: HeaderName,
+	/// Networks of the proxies the header is trusted from
+	pub proxies: Vec<IpNet>,
+}
+
+impl AuthBackend for ProxyBackend {
+	fn method(&self) -> &'static str {
+		"proxy"
+	}
+
+	fn authenticate(&self, app: &Arc<App>, request: &AuthRequest) -> Result<User> {
+		let trusted = request
+			.peer
+			.is_some_and(|peer| self.proxies.iter().any(|proxy| proxy.contains(&peer)));
+		let username = request
+			.headers
+			.get(&self.header)
+			.and_then(|header| header.to_str().ok())
+			.map(str::trim)
+			.filter(|username| !username.is_empty())
+			.ok_or(Error::UsernameNotFound)?;
+		if !trusted {
+			log::warn!(
+				"ignored {} header of untrusted {:?}",
+				self.header,
+				request.peer
+			);
+			return Err(Error::UsernameNotFound);
+		}
+
+		User::get_user(app, username)?.ok_or(Error::UsernameNotFound)
+	}
+}
@@ -0,0 +1,97 @@
+//! Content-addressed storage for large data, e.g. archived article pages, kept
+//! as files under the data directory rather than in sled values. Files are named
+//! by the SHA-256 of their content, so identical data is stored once, and are
+//! reference counted, so a file is removed once nothing uses it anymore.
+
+use std::fmt::Write;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+
+pub type BlobHash = [u8; 32];
+
+#[derive(Clone)]
+pub struct BlobStore {
+	root: PathBuf,
+	/// Reference counts, by hash
+	refs: sled::Tree,
+	/// Serializes taking and dropping references, so a file being removed isn't
+	/// referenced again meanwhile
+	lock: Arc<Mutex<()>>,
+}
+
+impl BlobStore {
+	pub fn open(root: PathBuf, refs: sled::Tree) -> Result<Self> {
+		std::fs::create_dir_all(&root)?;
+		Ok(Self {
+			root,
+			refs,
+			lock: Arc::default(),
+		})
+	}
+
+	/// Files are spread over directories by the first byte of their hash
+	fn path(&self, hash: &BlobHash) -> PathBuf {
+		let mut name = String::with_capacity(64);
+		for byte in hash {
+			let _ = write!(name, "{:02x}", byte);
+		}
+		self.root.join(&name[..2]).join(&name[2..])
+	}
+
+	fn refs(&self, hash: &BlobHash) -> Result<u64> {
+		Ok(self
+			.refs
+			.get(hash)?
+			.map(|refs| u64::from_be_bytes(refs.as_ref().try_into().unwrap_or_default()))
+			.unwrap_or(0))
+	}
+
+	/// Stores the data if needed, and takes a reference to it
+	pub fn put(&self, data: &[u8]) -> Result<BlobHash> {
+		let hash: BlobHash = Sha256::digest(data).into();
+		let path = self.path(&hash);
+
+		let _lock = self.lock.lock().unwrap();
+		if !path.exists() {
+			// written aside first, so a crash never leaves a truncated blob
+			let dir = path.parent().expect("blob paths have a parent");
+			std::fs::create_dir_all(dir)?;
+			let tmp = path.with_extension("tmp");
+			std::fs::write(&tmp, data)?;
+			std::fs::rename(&tmp, &path)?;
+		}
+		let refs = self.refs(&hash)?;
+		self.refs.insert(hash, &(refs + 1).to_be_bytes())?;
+
+		Ok(hash)
+	}
+
+	pub fn get(&self, hash: &BlobHash) -> Result<Option<Vec<u8>>> {
+		match std::fs::read(self.path(hash)) {
+			Ok(data) => Ok(Some(data)),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Drops a reference to a blob, removing it once unreferenced
+	pub fn release(&self, hash: &BlobHash) -> Result<()> {
+		let _lock = self.lock.lock().unwrap();
+		let refs = self.refs(hash)?;
+		if refs > 1 {
+			self.refs.insert(hash, &(refs - 1).to_be_bytes())?;
+			return Ok(());
+		}
+
+		self.refs.remove(hash)?;
+		match std::fs::remove_file(self.path(hash)) {
+			Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+			_ => Ok(()),
+		}
+	}
+}
@@ -0,0 +1,171 @@
+//! Hosts and feed urls blocked instance-wide by administrators, e.g. over abuse,
+//! legal requests or feeds eating up bandwidth. Blocked feeds can't be added or
+//! imported; feeds users already subscribed to are disabled, and no longer
+//! fetched until the block is lifted.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	db::{normalize_url, Feed, User},
+	App, Error, Result,
+};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockTarget {
+	/// The host along with its subdomains
+	Host(String),
+	/// A single feed, compared once normalized
+	Url(Url),
+}
+
+impl BlockTarget {
+	fn normalize(self) -> Result<Self> {
+		match self {
+			BlockTarget::Host(host) => {
+				let host = host.trim().trim_end_matches('.').to_lowercase();
+				if host.is_empty() {
+					return Err(Error::EmptyField("host"));
+				}
+				Ok(BlockTarget::Host(host))
+			}
+			BlockTarget::Url(url) => Ok(BlockTarget::Url(normalize_url(&url)?)),
+		}
+	}
+
+	fn key(&self) -> String {
+		match self {
+			BlockTarget::Host(host) => format!("host:{}", host),
+			BlockTarget::Url(url) => format!("url:{}", url),
+		}
+	}
+
+	/// Whether it covers `url`, which must be normalized
+	fn matches(&self, url: &Url) -> bool {
+		match self {
+			BlockTarget::Host(host) => url.host_str().is_some_and(|url_host| {
+				url_host == host
+					|| url_host
+						.strip_suffix(host.as_str())
+						.is_some_and(|subdomain| subdomain.ends_with('.'))
+			}),
+			BlockTarget::Url(blocked) => blocked == url,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+pub struct NewBlock {
+	pub target: BlockTarget,
+	/// Shown to users whose feeds it blocks
+	pub reason: Option<String>,
+}
+
+impl NewBlock {
+	/// Adds the block, replacing one of the same target, and disables the feeds
+	/// it covers
+	pub fn insert(self, app: &App, created_by: &str) -> Result<BlocklistUpdate> {
+		let block = Block {
+			target: self.target.normalize()?,
+			reason: self.reason,
+			created_by: created_by.to_owned(),
+			created: Utc::now(),
+		};
+		app.blocklist
+			.insert(block.target.key(), bincode::serialize(&block)?)?;
+
+		let update = apply(app)?;
+		log::info!(
+			"{} blocked {:?}, disabling {} feeds",
+			created_by,
+			block.target,
+			update.disabled_feeds
+		);
+		Ok(update)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Block {
+	pub target: BlockTarget,
+	pub reason: Option<String>,
+	pub created_by: String,
+	pub created: DateTime<Utc>,
+}
+
+impl Block {
+	pub fn get_all(app: &App) -> Result<Vec<Block>> {
+		Blocklist::new(&app.blocklist).map(|blocklist| blocklist.0)
+	}
+
+	/// Lifts the block, enabling the feeds no other block covers
+	pub fn remove(app: &App, target: BlockTarget) -> Result<BlocklistUpdate> {
+		app.blocklist
+			.remove(target.normalize()?.key())?
+			.ok_or(Error::NotFound("block".into()))?;
+		apply(app)
+	}
+}
+
+/// The blocks of the instance, for checking feeds against
+pub struct Blocklist(Vec<Block>);
+
+impl Blocklist {
+	pub fn new(blocklist: &sled::Tree) -> Result<Self> {
+		blocklist
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect::<Result<_>>()
+			.map(Self)
+	}
+
+	/// Whether any block covers `url`, which must be normalized
+	pub fn blocks(&self, url: &Url) -> bool {
+		self.0.iter().any(|block| block.target.matches(url))
+	}
+
+	/// Fails if any block covers `url`, which must be normalized
+	pub fn check(&self, url: &Url) -> Result<()> {
+		match self.0.iter().find(|block| block.target.matches(url)) {
+			Some(block) => Err(Error::FeedBlocked(url.to_string(), block.reason.clone())),
+			None => Ok(()),
+		}
+	}
+}
+
+#[derive(Serialize, Default)]
+pub struct BlocklistUpdate {
+	/// Feeds of all users newly covered by a block
+	pub disabled_feeds: usize,
+	/// Feeds of all users no longer covered by any block
+	pub enabled_feeds: usize,
+}
+
+/// Disables the feeds of all users the blocklist covers, and enables the ones
+/// it no longer does
+fn apply(app: &App) -> Result<BlocklistUpdate> {
+	let blocklist = Blocklist::new(&app.blocklist)?;
+	let mut update = BlocklistUpdate::default();
+	for user in User::get_all(app)? {
+		let user_app = app.open_user(&user.username)?;
+		for mut feed in Feed::get_all(&user_app)? {
+			let blocked = blocklist.blocks(&feed.url);
+			if blocked == feed.blocked {
+				continue;
+			}
+			match blocked {
+				true => update.disabled_feeds += 1,
+				false => update.enabled_feeds += 1,
+			}
+			feed.blocked = blocked;
+			feed.insert(&user_app)?;
+		}
+	}
+
+	Ok(update)
+}
@@ -0,0 +1,97 @@
+//! Clustering of near-duplicate articles in listings, e.g. the same story
+//! carried by several outlets, into a single entry listing the others as
+//! alternates. Articles are duplicates if their bodies hash the same, or if
+//! their titles share most of their words and they were published close
+//! together.
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::db::Article;
+
+/// Titles only match articles published at most this far apart
+const WINDOW_HOURS: i64 = 48;
+/// Share of the words of two titles they must have in common
+const MIN_SIMILARITY: f64 = 0.6;
+/// Shorter titles only match titles with the same words
+const MIN_WORDS: usize = 3;
+/// Longest suffix stripped as the outlet's name, in words
+const MAX_SUFFIX_WORDS: usize = 4;
+
+pub struct Cluster {
+	/// The first of the articles in the listing's order
+	pub article: Article,
+	pub alternates: Vec<Article>,
+}
+
+struct Key {
+	words: BTreeSet<String>,
+	/// None if the article has neither a summary nor content
+	body: Option<[u8; 32]>,
+	published: DateTime<Utc>,
+}
+
+impl Key {
+	fn new(article: &Article) -> Self {
+		let has_body = !article.summary.is_empty() || !article.content.is_empty();
+		Self {
+			words: title_words(&article.title),
+			body: has_body.then(|| article.body_hash()),
+			published: article.published,
+		}
+	}
+
+	fn matches(&self, other: &Key) -> bool {
+		if self.body.is_some() && self.body == other.body {
+			return true;
+		}
+		if (self.published - other.published).abs() > Duration::hours(WINDOW_HOURS) {
+			return false;
+		}
+
+		let common = self.words.intersection(&other.words).count();
+		let union = self.words.len() + other.words.len() - common;
+		match self.words.len().min(other.words.len()) >= MIN_WORDS {
+			true => common as f64 / union as f64 >= MIN_SIMILARITY,
+			false => !self.words.is_empty() && self.words == other.words,
+		}
+	}
+}
+
+/// Lowercased words of the title, without the outlet's name outlets often
+/// append, e.g. "Story - Outlet"
+fn title_words(title: &str) -> BTreeSet<String> {
+	let title = [" - ", " | ", " — "]
+		.into_iter()
+		.filter_map(|separator| title.rsplit_once(separator))
+		.find(|(_, suffix)| suffix.split_whitespace().count() <= MAX_SUFFIX_WORDS)
+		.map(|(story, _)| story)
+		.unwrap_or(title);
+
+	title
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|word| !word.is_empty())
+		.map(str::to_lowercase)
+		.collect()
+}
+
+/// Groups the articles into clusters, keeping the order of their first articles
+pub fn cluster(articles: Vec<Article>) -> Vec<Cluster> {
+	let mut clusters: Vec<(Key, Cluster)> = vec![];
+	for article in articles {
+		let key = Key::new(&article);
+		match clusters.iter_mut().find(|(other, _)| key.matches(other)) {
+			Some((_, cluster)) => cluster.alternates.push(article),
+			None => clusters.push((
+				key,
+				Cluster {
+					article,
+					alternates: vec![],
+				},
+			)),
+		}
+	}
+
+	clusters.into_iter().map(|(_, cluster)| cluster).collect()
+}
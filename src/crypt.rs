@@ -0,0 +1,157 @@
+//! Optional encryption at rest of the sensitive values in the database: users,
+//! tokens, articles, as well as feeds, sync remotes and notification targets,
+//! which hold credentials for other services. Values are sealed with
+//! ChaCha20-Poly1305 under a key from the environment, a file, or the OS
+//! keyring, and opened transparently as they're read. Values written before encryption was enabled are read as they
+//! are and sealed by [`App::encrypt_existing`](crate::App::encrypt_existing).
+//! The search index is kept by tantivy outside of the database, and isn't
+//! encrypted.
+//!
+//! Keys aren't encrypted, they're needed for lookups and ordering. Tokens are
+//! secrets themselves, so they're stored under a hash instead, see
+//! [`CapabilityToken::key_of`](crate::db::CapabilityToken::key_of).
+
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Error, Result};
+
+/// Marks sealed values; plain bincode values never start with it, as they'd
+/// have to start with an absurdly long string or sequence
+const MAGIC: &[u8] = b"NRE1";
+const KEY_LEN: usize = 32;
+
+static KEY: OnceLock<LessSafeKey> = OnceLock::new();
+
+/// Where the key comes from
+pub enum KeySource<'a> {
+	/// Base64 encoded
+	Value(&'a str),
+	/// A file with the base64 encoded key
+	File(&'a Path),
+	/// An entry of the OS keyring, holding the base64 encoded key
+	#[cfg(feature = "keyring")]
+	Keyring { service: &'a str, user: &'a str },
+}
+
+/// Enables encryption. Must be called before the database is opened, i.e. at
+/// startup.
+pub fn configure(source: KeySource) -> anyhow::Result<()> {
+	let encoded = match source {
+		KeySource::Value(value) => value.to_owned(),
+		KeySource::File(path) => std::fs::read_to_string(path)
+			.with_context(|| format!("could not read key file {}", path.display()))?,
+		#[cfg(feature = "keyring")]
+		KeySource::Keyring { service, user } => keyring::Entry::new(service, user)?
+			.get_password()
+			.with_context(|| format!("could not get key {} of {} from keyring", user, service))?,
+	};
+
+	let bytes = BASE64
+		.decode(encoded.trim())
+		.context("encryption key is not valid base64")?;
+	if bytes.len() != KEY_LEN {
+		anyhow::bail!("encryption key must be {} bytes", KEY_LEN);
+	}
+	let key = UnboundKey::new(&CHACHA20_POLY1305, &bytes)
+		.map_err(|_| anyhow::anyhow!("invalid encryption key"))?;
+
+	KEY.set(LessSafeKey::new(key))
+		.map_err(|_| anyhow::anyhow!("encryption was already configured"))
+}
+
+pub fn enabled() -> bool {
+	KEY.get().is_some()
+}
+
+pub fn is_sealed(bytes: &[u8]) -> bool {
+	bytes.starts_with(MAGIC)
+}
+
+/// Encrypts a value, if encryption is enabled
+pub fn seal(mut bytes: Vec<u8>) -> Vec<u8> {
+	let Some(key) = KEY.get()
+	else {
+		return bytes;
+	};
+
+	let mut nonce = [0u8; NONCE_LEN];
+	rand::Rng::fill(&mut rand::thread_rng(), &mut nonce);
+	key.seal_in_place_append_tag(
+		Nonce::assume_unique_for_key(nonce),
+		Aad::empty(),
+		&mut bytes,
+	)
+	.expect("values are far smaller than the cipher's limit");
+
+	[MAGIC, &nonce, &bytes].concat()
+}
+
+/// Decrypts a value if it was sealed, plain values are returned as they are
+pub fn open(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+	let Some(sealed) = bytes.strip_prefix(MAGIC)
+	else {
+		return Ok(Cow::Borrowed(bytes));
+	};
+	let key = KEY
+		.get()
+		.ok_or_else(|| Error::Encryption("value is encrypted, but no key is configured".into()))?;
+	if sealed.len() < NONCE_LEN {
+		return Err(Error::Encryption("value is truncated".into()));
+	}
+
+	let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+	let nonce = Nonce::try_assume_unique_for_key(nonce).expect("nonce has the right length");
+	let mut buffer = ciphertext.to_vec();
+	let plain = key
+		.open_in_place(nonce, Aad::empty(), &mut buffer)
+		.map_err(|_| Error::Encryption("value does not decrypt, is the key right?".into()))?
+		.len();
+	buffer.truncate(plain);
+
+	Ok(Cow::Owned(buffer))
+}
+
+/// Serializes and seals a value
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+	Ok(seal(bincode::serialize(value)?))
+}
+
+/// Opens and deserializes a value
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+	Ok(bincode::deserialize(&open(bytes)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the key is set once per process, so everything that needs it is in one test
+	#[test]
+	fn sealed_values_open_and_plain_ones_pass_through() {
+		let plain = bincode::serialize("plain").unwrap();
+		assert_eq!(open(&plain).unwrap(), plain);
+
+		configure(KeySource::Value(&BASE64.encode([7; KEY_LEN]))).unwrap();
+		let sealed = seal(plain.clone());
+		assert!(is_sealed(&sealed));
+		assert_ne!(sealed[MAGIC.len()..], plain);
+		assert_eq!(open(&sealed).unwrap(), plain);
+		assert_eq!(open(&plain).unwrap(), plain);
+		assert_eq!(
+			decode::<String>(&encode("value").unwrap()).unwrap(),
+			"value"
+		);
+
+		let mut tampered = sealed;
+		*tampered.last_mut().unwrap() ^= 1;
+		assert!(matches!(open(&tampered), Err(Error::Encryption(_))));
+		assert!(matches!(open(MAGIC), Err(Error::Encryption(_))));
+	}
+}
@@ -1,47 +1,108 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::IpAddr;
+
+use base64::Engine;
 use chrono::{DateTime, Utc};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::transaction::TransactionError;
+use sled::Transactional;
 use url::Url;
 
-use crate::{app::AppUser, App, Error, Result};
+use crate::{
+	app::{AppUser, Quota},
+	blob::BlobHash,
+	blocklist::Blocklist,
+	crypt,
+	download::Downloader,
+	err::FetchError,
+	fetch::{CertPins, HttpValidators},
+	insights::FeedReads,
+	mute::Mutes,
+	scheduler::FetchSchedule,
+	scrape::ScraperConfig,
+	source::SourceRequest,
+	tag,
+	watch::{self, PageWatch},
+	App, Error, Result,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct NewUser {
 	pub username: String,
 	pub password: String,
+	pub admin: bool,
+	/// Written along with the user, who can't act without it meanwhile
+	pub quota: Option<Quota>,
 }
 
 impl NewUser {
 	pub fn insert(self, app: &App) -> Result<User> {
+		// fails early, before the costly hash
 		if app.users.contains_key(self.username.as_bytes())? {
 			return Err(Error::UsernameTaken);
 		}
 
-		let pass_hash = bcrypt::hash(self.password.as_bytes(), 10)?;
+		let pass_hash = bcrypt::hash(self.password.as_bytes(), app.bcrypt_cost)?;
 		let user = User {
 			username: self.username,
 			pass_hash,
+			admin: self.admin,
+			last_login: None,
+			last_token: None,
+			last_user_agent: None,
+			last_ip: None,
 		};
 
-		app.users
-			.insert(user.username.as_bytes(), bincode::serialize(&user)?)?;
+		let user_bytes = crypt::encode(&user)?;
+		let quota = self.quota.as_ref().map(bincode::serialize).transpose()?;
+		let meta = app.user_meta(&user.username)?;
+
+		// another registration may have taken the name while hashing
+		(&app.users, &meta)
+			.transaction(|(users, meta)| {
+				if users.get(user.username.as_bytes())?.is_some() {
+					return sled::transaction::abort(Error::UsernameTaken);
+				}
+				users.insert(user.username.as_bytes(), user_bytes.as_slice())?;
+				if let Some(quota) = &quota {
+					meta.insert(AppUser::META_QUOTA, quota.as_slice())?;
+				}
+				Ok(())
+			})
+			.map_err(|e| match e {
+				TransactionError::Abort(e) => e,
+				TransactionError::Storage(e) => e.into(),
+			})?;
 
 		Ok(user)
 	}
 }
 
+/// How long a login is recorded for when nothing about it changes
+const LOGIN_RECORD_MINUTES: i64 = 1;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
 	pub username: String,
 	pub pass_hash: String,
+	pub admin: bool,
+
+	pub last_login: Option<DateTime<Utc>>,
+	/// Label of the capability token last used
+	pub last_token: Option<String>,
+	pub last_user_agent: Option<String>,
+	/// Address the user last logged in from
+	pub last_ip: Option<IpAddr>,
 }
 
 impl User {
-	fn get_user(db: &App, username: &str) -> Result<Option<User>> {
+	pub fn get_user(db: &App, username: &str) -> Result<Option<User>> {
 		db.users
 			.get(username.as_bytes())?
-			.map(|bytes| bincode::deserialize(&bytes))
+			.map(|bytes| crypt::decode(&bytes))
 			.transpose()
-			.map_err(Into::into)
 	}
 
 	pub fn try_login(db: &App, username: &str, password: &str) -> Result<User> {
@@ -54,28 +115,359 @@ impl User {
 
 		Ok(user)
 	}
+
+	pub fn get_all(db: &App) -> Result<Vec<User>> {
+		db.users
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| crypt::decode(&v))
+			})
+			.collect()
+	}
+
+	fn update(db: &App, username: &str, f: impl FnOnce(&mut User)) -> Result<()> {
+		let mut user = Self::get_user(db, username)?.ok_or(Error::UsernameNotFound)?;
+		f(&mut user);
+		db.users
+			.insert(user.username.as_bytes(), crypt::encode(&user)?)?;
+		Ok(())
+	}
+
+	/// Whether the password was hashed with a different cost than configured
+	pub fn needs_rehash(&self, db: &App) -> bool {
+		self.pass_hash
+			.parse::<bcrypt::HashParts>()
+			.map(|parts| parts.get_cost() != db.bcrypt_cost)
+			.unwrap_or(false)
+	}
+
+	/// Rehashes the password with the configured cost; the caller must have
+	/// verified the password already
+	pub fn rehash(db: &App, username: &str, password: &str) -> Result<()> {
+		let pass_hash = bcrypt::hash(password.as_bytes(), db.bcrypt_cost)?;
+		Self::update(db, username, |user| user.pass_hash = pass_hash)
+	}
+
+	/// Records a login of the user as authenticated. Every request logs in, so
+	/// the record is only written when it changes, or at most once a minute.
+	pub fn record_login(
+		db: &App,
+		user: &User,
+		user_agent: Option<&str>,
+		ip: Option<IpAddr>,
+	) -> Result<()> {
+		let recent = user.last_login.is_some_and(|last| {
+			Utc::now() - last < chrono::Duration::minutes(LOGIN_RECORD_MINUTES)
+		});
+		if recent && user.last_user_agent.as_deref() == user_agent && user.last_ip == ip {
+			return Ok(());
+		}
+
+		Self::update(db, &user.username, |user| {
+			user.last_login = Some(Utc::now());
+			user.last_user_agent = user_agent.map(ToOwned::to_owned);
+			user.last_ip = ip;
+		})
+	}
+
+	/// Records the token as the last one used, unless it already is
+	pub fn record_token_use(db: &App, token: &CapabilityToken) -> Result<()> {
+		let user = Self::get_user(db, &token.username)?.ok_or(Error::UsernameNotFound)?;
+		if user.last_token.as_ref() == Some(&token.label) {
+			return Ok(());
+		}
+
+		Self::update(db, &token.username, |user| {
+			user.last_token = Some(token.label.clone());
+		})
+	}
+
+	/// Fails unless the user exists and is an admin
+	pub fn require_admin(db: &App, username: &str) -> Result<User> {
+		Self::get_user(db, username)?
+			.filter(|user| user.admin)
+			.ok_or(Error::Forbidden)
+	}
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct ScraperConfig {}
+/// Public view of a user, without credentials
+#[derive(Serialize)]
+pub struct Account {
+	pub username: String,
+	pub admin: bool,
+	pub last_login: Option<DateTime<Utc>>,
+	pub last_token: Option<String>,
+	pub last_user_agent: Option<String>,
+	pub last_ip: Option<IpAddr>,
+}
+
+impl From<User> for Account {
+	fn from(user: User) -> Self {
+		Self {
+			username: user.username,
+			admin: user.admin,
+			last_login: user.last_login,
+			last_token: user.last_token,
+			last_user_agent: user.last_user_agent,
+			last_ip: user.last_ip,
+		}
+	}
+}
+
+/// What a capability token grants access to
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum TokenScope {
+	/// Read access to the user's republished Atom feed
+	Feed,
+	/// Triggering refreshes of the user's feeds, for inbound webhooks
+	Refresh,
+	/// Read access to an Atom feed of the user's starred articles
+	Starred,
+}
+
+#[derive(Deserialize)]
+pub struct NewToken {
+	pub label: String,
+	pub scope: TokenScope,
+}
+
+impl NewToken {
+	pub fn insert(self, app: &App, username: &str) -> Result<CapabilityToken> {
+		let mut bytes = [0u8; 24];
+		rand::Rng::fill(&mut rand::thread_rng(), &mut bytes);
+
+		let token = CapabilityToken {
+			token: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+			username: username.to_owned(),
+			label: self.label,
+			scope: self.scope,
+			created: Utc::now(),
+		};
+
+		app.tokens.insert(
+			CapabilityToken::key_of(&token.token),
+			crypt::encode(&token)?,
+		)?;
+
+		Ok(token)
+	}
+}
+
+/// Secret embedded in URLs that are accessed without logging in, each
+/// independently labeled and revocable
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CapabilityToken {
+	pub token: String,
+	pub username: String,
+	pub label: String,
+	pub scope: TokenScope,
+	pub created: DateTime<Utc>,
+}
+
+impl CapabilityToken {
+	/// Key a token is stored under: the token itself, or its hash when stored
+	/// values are encrypted, as keys aren't
+	pub fn key_of(token: &str) -> Vec<u8> {
+		if crypt::enabled() {
+			[b"sha256:".as_slice(), &Sha256::digest(token.as_bytes())].concat()
+		}
+		else {
+			token.as_bytes().to_vec()
+		}
+	}
+
+	pub fn get_all(app: &App, username: &str) -> Result<Vec<CapabilityToken>> {
+		app.tokens
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| crypt::decode::<CapabilityToken>(&v))
+			})
+			.filter_ok(|token| token.username == username)
+			.collect()
+	}
+
+	/// Resolves a token, which must have been issued for the given scope
+	pub fn authorize(app: &App, token: &str, scope: TokenScope) -> Result<CapabilityToken> {
+		app.tokens
+			.get(Self::key_of(token))?
+			.map(|bytes| crypt::decode::<CapabilityToken>(&bytes))
+			.transpose()?
+			.filter(|token| token.scope == scope)
+			.ok_or(Error::NotFound("token".into()))
+	}
+
+	pub fn revoke(app: &App, username: &str, token: &str) -> Result<()> {
+		let found = app
+			.tokens
+			.get(Self::key_of(token))?
+			.map(|bytes| crypt::decode::<CapabilityToken>(&bytes))
+			.transpose()?
+			.filter(|token| token.username == username)
+			.ok_or(Error::NotFound("token".into()))?;
+
+		app.tokens.remove(Self::key_of(&found.token))?;
+		Ok(())
+	}
+}
+
+/// Controls what `fetch_feed` stores as article content
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentMode {
+	/// Content as provided by the feed
+	#[default]
+	FeedProvided,
+	/// No content is stored, only the summary
+	SummaryOnly,
+	/// Main content extracted from the article page, by the feed's scraper or
+	/// else [`readability`](crate::readability). This is what the
+	/// `fetch_full_content` flag turns on; there is no separate one, see
+	/// [`with_full_content`](Self::with_full_content).
+	ScrapedFullText,
+	/// Full snapshot of the article page
+	ArchivedSnapshot,
+}
+
+impl ContentMode {
+	/// The mode a `fetch_full_content` flag switches this one to
+	pub fn with_full_content(self, fetch_full_content: bool) -> ContentMode {
+		match fetch_full_content {
+			true => ContentMode::ScrapedFullText,
+			false if self == ContentMode::ScrapedFullText => ContentMode::FeedProvided,
+			false => self,
+		}
+	}
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct NewFeed {
 	pub url: url::Url,
 	pub name: Option<String>,
+	pub category: Option<String>,
 	pub scraper: Option<ScraperConfig>,
+	pub content_mode: Option<ContentMode>,
+	/// Sets `content_mode` to `scraped_full_text`, unless that is set
+	pub fetch_full_content: Option<bool>,
+	#[serde(default)]
+	pub auto_read: bool,
+	pub hide_after_days: Option<u32>,
+	#[serde(default)]
+	pub accept_invalid_certs: bool,
+	pub downloader: Option<Downloader>,
+	pub watch: Option<PageWatch>,
+	pub request: Option<SourceRequest>,
+	pub refresh_interval: Option<u32>,
+	pub fetch_schedule: Option<FetchSchedule>,
+	#[serde(default)]
+	pub priority: i32,
+}
+
+/// Canonical form of a feed url: `feed://` and `feed:` urls are resolved to
+/// what they wrap, hosts are lowercased, default ports and fragments dropped.
+/// The `url` crate already does most of this for http(s), but not for other
+/// schemes.
+pub fn normalize_url(url: &Url) -> Result<Url> {
+	let mut url = match url.scheme() {
+		"feed" => {
+			let wrapped = &url.as_str()["feed:".len()..];
+			match wrapped.starts_with("//") {
+				true => Url::parse(&format!("http:{}", wrapped))?,
+				false => Url::parse(wrapped)?,
+			}
+		}
+		_ => url.clone(),
+	};
+
+	url.set_fragment(None);
+	if let Some(host) = url.host_str().map(str::to_lowercase) {
+		url.set_host(Some(&host))?;
+	}
+	let default_port = match url.scheme() {
+		"gemini" => Some(1965),
+		_ => None,
+	};
+	if url.port().is_some() && url.port() == default_port {
+		// only fails for urls without a host, which have no port either
+		let _ = url.set_port(None);
+	}
+
+	Ok(url)
 }
 
 impl NewFeed {
+	/// A plain feed, as added by an import
+	pub fn new(url: Url, name: Option<String>, category: Option<String>) -> Self {
+		NewFeed {
+			url,
+			name,
+			category,
+			scraper: None,
+			content_mode: None,
+			fetch_full_content: None,
+			auto_read: false,
+			hide_after_days: None,
+			accept_invalid_certs: false,
+			downloader: None,
+			watch: None,
+			request: None,
+			refresh_interval: None,
+			fetch_schedule: None,
+			priority: 0,
+		}
+	}
+
 	pub async fn insert(self, app: &AppUser) -> Result<()> {
+		if let Some(watch) = &self.watch {
+			watch.parse_selector()?;
+		}
+		if let Some(scraper) = &self.scraper {
+			scraper.validate(app)?;
+		}
+		if let Some(max_feeds) = app.quota()?.max_feeds {
+			if app.feeds.len() >= max_feeds {
+				return Err(Error::QuotaExceeded("feeds"));
+			}
+		}
+		let url = normalize_url(&self.url)?;
+		Blocklist::new(&app.blocklist)?.check(&url)?;
+		if Feed::get_all(app)?.iter().any(|feed| feed.url == url) {
+			return Err(Error::FeedExists(url.to_string()));
+		}
+
 		Feed {
+			revision: 0,
 			id: app.db.generate_id()?,
-			url: self.url,
+			url,
+			original_url: self.url.to_string(),
 			name: self.name.unwrap_or_default(),
+			category: self.category,
 			scraper: self.scraper,
+			content_mode: self
+				.content_mode
+				.or(self
+					.fetch_full_content
+					.map(|full| ContentMode::default().with_full_content(full)))
+				.unwrap_or_default(),
+			auto_read: self.auto_read,
+			hide_after_days: self.hide_after_days,
+			accept_invalid_certs: self.accept_invalid_certs,
+			downloader: self.downloader,
+			watch: self.watch,
+			request: self.request,
+			refresh_interval: self.refresh_interval,
+			fetch_schedule: self.fetch_schedule,
+			priority: self.priority,
+			blocked: false,
+			cert_pins: CertPins::new(),
 
+			subscribed: Utc::now(),
 			last_fetch_time: DateTime::<Utc>::MIN_UTC,
 			last_error: None,
+			meta: FeedMeta::default(),
 		}
 		.insert(app)?;
 
@@ -83,185 +475,1711 @@ impl NewFeed {
 	}
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct PatchFeed {
-	pub id: u64,
+	/// Only needed on the deprecated `PATCH /api/v1/feeds`, resource routes take
+	/// the id from the path
+	pub id: Option<u64>,
 	pub url: Option<url::Url>,
 	pub name: Option<String>,
+	#[serde(default, deserialize_with = "present")]
+	pub category: Option<Option<String>>,
+	#[serde(default, deserialize_with = "present")]
 	pub scraper: Option<Option<ScraperConfig>>,
+	pub content_mode: Option<ContentMode>,
+	/// Switches `content_mode` to or from `scraped_full_text`, unless that is set
+	pub fetch_full_content: Option<bool>,
+	pub auto_read: Option<bool>,
+	#[serde(default, deserialize_with = "present")]
+	pub hide_after_days: Option<Option<u32>>,
+	pub accept_invalid_certs: Option<bool>,
+	#[serde(default, deserialize_with = "present")]
+	pub downloader: Option<Option<Downloader>>,
+	#[serde(default, deserialize_with = "present")]
+	pub watch: Option<Option<PageWatch>>,
+	#[serde(default, deserialize_with = "present")]
+	pub request: Option<Option<SourceRequest>>,
+	#[serde(default, deserialize_with = "present")]
+	pub refresh_interval: Option<Option<u32>>,
+	#[serde(default, deserialize_with = "present")]
+	pub fetch_schedule: Option<Option<FetchSchedule>>,
+	pub priority: Option<i32>,
+}
+
+/// Tells an explicit `null`, which clears a field, from an absent one
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+	T: Deserialize<'de>,
+	D: serde::Deserializer<'de>,
+{
+	T::deserialize(deserializer).map(Some)
 }
 
 impl PatchFeed {
 	pub fn apply(self, app: &AppUser) -> Result<()> {
-		let mut feed = Feed::get_id(app, self.id)?.ok_or(Error::NotFound("feed".into()))?;
+		let id = self.id.ok_or(Error::NotFound("feed".into()))?;
+		let mut feed = Feed::get_id(app, id)?.ok_or(Error::NotFound("feed".into()))?;
 
 		if let Some(url) = self.url {
-			feed.url = url;
+			let normalized = normalize_url(&url)?;
+			Blocklist::new(&app.blocklist)?.check(&normalized)?;
+			let feeds = Feed::get_all(app)?;
+			if feeds.iter().any(|other| other.id != id && other.url == normalized) {
+				return Err(Error::FeedExists(normalized.to_string()));
+			}
+			feed.url = normalized;
+			feed.original_url = url.to_string();
+			feed.meta.validators = HttpValidators::default();
+			feed.meta.redirected_to = None;
+			feed.meta.redirect_permanent = false;
+			watch::reset(app, id)?;
 		}
 		if let Some(name) = self.name {
 			feed.name = name;
 		}
+		if let Some(category) = self.category {
+			feed.category = category;
+		}
+		// a different extraction or request may yield different articles, so the
+		// next fetch is not answered with a 304 for an unchanged feed
 		if let Some(scraper) = self.scraper {
+			if let Some(scraper) = &scraper {
+				scraper.validate(app)?;
+			}
 			feed.scraper = scraper;
+			feed.meta.validators = HttpValidators::default();
+		}
+		let content_mode = self.content_mode.or(self
+			.fetch_full_content
+			.map(|full| feed.content_mode.with_full_content(full)));
+		if let Some(content_mode) = content_mode {
+			feed.content_mode = content_mode;
+			feed.meta.validators = HttpValidators::default();
+		}
+		if let Some(auto_read) = self.auto_read {
+			feed.auto_read = auto_read;
+		}
+		if let Some(hide_after_days) = self.hide_after_days {
+			feed.hide_after_days = hide_after_days;
+		}
+		if let Some(accept_invalid_certs) = self.accept_invalid_certs {
+			feed.accept_invalid_certs = accept_invalid_certs;
+		}
+		if let Some(downloader) = self.downloader {
+			feed.downloader = downloader;
+		}
+		if let Some(watch) = self.watch {
+			if let Some(watch) = &watch {
+				watch.parse_selector()?;
+			}
+			feed.watch = watch;
+			watch::reset(app, id)?;
+		}
+		if let Some(request) = self.request {
+			feed.request = request;
+			feed.meta.validators = HttpValidators::default();
+		}
+		if let Some(refresh_interval) = self.refresh_interval {
+			feed.refresh_interval = refresh_interval;
+		}
+		if let Some(fetch_schedule) = self.fetch_schedule {
+			feed.fetch_schedule = fetch_schedule;
+		}
+		if let Some(priority) = self.priority {
+			feed.priority = priority;
 		}
 
-		Ok(())
+		feed.insert(app)
 	}
 }
 
+/// Metadata the feed declares about itself, refreshed on every fetch
+#[derive(Serialize, Deserialize, Default)]
+pub struct FeedMeta {
+	pub title: Option<String>,
+	pub description: Option<String>,
+	pub site_url: Option<String>,
+	pub icon_url: Option<String>,
+	/// Declared update period in minutes, from `ttl` or `sy:updatePeriod`
+	pub update_period: Option<u32>,
+	/// Where the url redirected to on the last fetch
+	pub redirected_to: Option<Url>,
+	/// Whether it was redirected there permanently, with a 301 or 308, and not
+	/// just for now
+	pub redirect_permanent: bool,
+	/// Came with the last fetched version, to make the next fetch conditional
+	pub validators: HttpValidators,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Feed {
 	pub id: u64,
+	/// Normalized, see [`normalize_url`]
 	pub url: url::Url,
+	/// As submitted
+	pub original_url: String,
 	pub name: String,
+	pub category: Option<String>,
 	pub scraper: Option<ScraperConfig>,
+	pub content_mode: ContentMode,
+	/// New articles arrive already marked read, for feeds that should not
+	/// inflate unread counts
+	pub auto_read: bool,
+	/// Articles older than this drop out of default listings, but stay stored
+	/// and searchable
+	pub hide_after_days: Option<u32>,
+	/// Skips TLS certificate validation, for internal services with self-signed
+	/// certificates. Prefer adding the CA to `EXTRA_ROOT_CERTS`.
+	pub accept_invalid_certs: bool,
+	/// Receives the enclosures of new articles
+	pub downloader: Option<Downloader>,
+	/// Makes this a page watch feed: `url` is a plain web page, and changes to
+	/// the selected part of it become articles
+	pub watch: Option<PageWatch>,
+	/// How `url` is requested, if not with a plain GET
+	pub request: Option<SourceRequest>,
+	/// Minutes between scheduled refreshes, instead of the instance's default
+	pub refresh_interval: Option<u32>,
+	/// Restricts scheduled refreshes to when the source is known to update
+	pub fetch_schedule: Option<FetchSchedule>,
+	/// Feeds with a higher priority are fetched first in a refresh
+	pub priority: i32,
+	/// Covered by the instance's [blocklist](crate::blocklist), and not fetched
+	pub blocked: bool,
+	/// Certificates of the gemini hosts the feed is fetched from, pinned on
+	/// first use. With `accept_invalid_certs`, pinned anew on every fetch.
+	pub cert_pins: CertPins,
 
+	pub subscribed: DateTime<Utc>,
 	pub last_fetch_time: DateTime<Utc>,
-	pub last_error: Option<String>,
+	pub last_error: Option<FetchError>,
+	pub meta: FeedMeta,
+
+	/// Feeds revision at which this feed was last modified
+	pub revision: u64,
 }
 
 impl Feed {
-	pub fn insert(&self, app: &AppUser) -> Result<()> {
+	/// The feed as shown to its owner, without the secrets it fetches or sends
+	/// enclosures with
+	pub fn redacted(mut self) -> Feed {
+		if let Some(oauth) = self
+			.request
+			.as_mut()
+			.and_then(|request| request.oauth.as_mut())
+		{
+			oauth.redact();
+		}
+		if let Some(downloader) = &mut self.downloader {
+			downloader.password = None;
+		}
+		self
+	}
+
+	/// The feed as shown to other users, e.g. subscribers of a blogroll, without
+	/// how the owner fetches it or where its enclosures go
+	pub fn public(mut self) -> Feed {
+		self.request = None;
+		self.downloader = None;
+		self
+	}
+
+	pub fn insert(&mut self, app: &AppUser) -> Result<()> {
+		self.revision = app.bump_feeds_revision()?;
 		app.feeds
-			.insert(bincode::serialize(&self.id)?, bincode::serialize(&self)?)?;
+			.insert(bincode::serialize(&self.id)?, crypt::encode(&self)?)?;
 		Ok(())
 	}
 
 	pub fn get_id(app: &AppUser, id: u64) -> Result<Option<Feed>> {
-		let maybe_feed = app.feeds.get(bincode::serialize(&id)?)?;
+		app.feeds
+			.get(bincode::serialize(&id)?)?
+			.map(|bytes| crypt::decode(&bytes))
+			.transpose()
+	}
 
-		let feed = if let Some(feed) = maybe_feed {
-			bincode::deserialize(&feed)?
-		}
-		else {
-			None
-		};
+	/// Removes the feed along with all of its articles
+	pub fn remove(app: &AppUser, id: u64) -> Result<()> {
+		app.feeds
+			.remove(bincode::serialize(&id)?)?
+			.ok_or(Error::NotFound("feed".into()))?;
+		// kept for clients syncing feeds since a revision, see [`Feed::deleted_since`]
+		let revision = app.bump_feeds_revision()?;
+		app.deleted_feeds
+			.insert(id.to_be_bytes(), &revision.to_be_bytes())?;
+		watch::reset(app, id)?;
+		FeedReads::remove(app, id)?;
 
-		Ok(feed)
+		let removed = Article::remove_feed(app, id)?;
+		app.remove_from_search_index(&removed)
 	}
 
 	pub fn get_all(app: &AppUser) -> Result<Vec<Feed>> {
 		app.feeds
 			.iter()
-			.map(|item| {
-				item.map_err(Error::from)
-					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
-			})
+			.map(|item| item.map_err(Error::from).and_then(|(_, v)| crypt::decode(&v)))
 			.collect()
 	}
+
+	/// Ids of the feeds deleted after the feeds revision `since`
+	pub fn deleted_since(app: &AppUser, since: u64) -> Result<Vec<u64>> {
+		let mut deleted = vec![];
+		for item in app.deleted_feeds.iter() {
+			let (id, revision) = item?;
+			let revision = u64::from_be_bytes(revision.as_ref().try_into().unwrap_or_default());
+			if revision > since {
+				deleted.push(u64::from_be_bytes(
+					id.as_ref().try_into().unwrap_or_default(),
+				));
+			}
+		}
+		Ok(deleted)
+	}
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Article {
-	pub id: String,
-	pub feed_id: u64,
-	pub published: DateTime<Utc>,
-	pub url: Option<String>,
-	pub title: String,
-	pub summary: String,
-	pub content: String,
+/// Display windows of the user's feeds, see [`Feed::hide_after_days`], and
+/// the user's muted keywords
+pub struct Visibility {
+	cutoffs: HashMap<u64, DateTime<Utc>>,
+	mutes: Mutes,
 }
 
-impl Article {
-	pub fn get_id(app: &AppUser, id: &str) -> Result<Option<Article>> {
-		let maybe = app.articles.get(bincode::serialize(&id)?)?;
+impl Visibility {
+	pub fn new(app: &AppUser) -> Result<Self> {
+		let now = Utc::now();
+		let cutoffs = Feed::get_all(app)?
+			.into_iter()
+			.filter_map(|feed| {
+				let days = feed.hide_after_days?;
+				Some((feed.id, now - chrono::Duration::days(days as i64)))
+			})
+			.collect();
+
+		Ok(Self {
+			cutoffs,
+			mutes: Mutes::new(app)?,
+		})
+	}
+
+	pub fn is_visible(&self, article: &Article) -> bool {
+		self.cutoffs
+			.get(&article.feed_id)
+			.is_none_or(|cutoff| article.published >= *cutoff)
+			&& !self.mutes.mutes(article)
+	}
+}
+
+/// Identifies an article by its feed and a hash of the feed-provided entry id.
+///
+/// The byte encoding is `inverted publish time ++ feed id ++ entry hash`, all big
+/// endian, so that sled iterates articles newest-first. Since the publish time is
+/// part of the key, `article_keys` maps `feed id ++ entry hash` to the current key.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ArticleId([u8; 24]);
+
+impl ArticleId {
+	pub fn new(published: DateTime<Utc>, feed_id: u64, entry_id: &str) -> Self {
+		// flip the sign bit for an order-preserving unsigned value, then invert it
+		let time = !((published.timestamp_millis() as u64) ^ (1 << 63));
+
+		let mut bytes = [0; 24];
+		bytes[..8].copy_from_slice(&time.to_be_bytes());
+		bytes[8..16].copy_from_slice(&feed_id.to_be_bytes());
+		bytes[16..].copy_from_slice(&fnv1a(entry_id.as_bytes()).to_be_bytes());
+		Self(bytes)
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+		bytes
+			.try_into()
+			.map(Self)
+			.map_err(|_| Error::InvalidArticleId)
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.0
+	}
+
+	pub fn published(&self) -> DateTime<Utc> {
+		let time = u64::from_be_bytes(self.0[..8].try_into().unwrap());
+		let millis = (!time ^ (1 << 63)) as i64;
+		DateTime::from_timestamp_millis(millis).unwrap_or(DateTime::<Utc>::MIN_UTC)
+	}
+
+	/// Key into `article_keys`, stable across changes of the publish time
+	pub fn entry_key(&self) -> &[u8] {
+		&self.0[8..]
+	}
 
-		let article = if let Some(feed) = maybe {
-			bincode::deserialize(&feed)?
+	pub fn composite(&self) -> CompositeId {
+		CompositeId {
+			feed_id: u64::from_be_bytes(self.0[8..16].try_into().unwrap()),
+			entry_hash: u64::from_be_bytes(self.0[16..].try_into().unwrap()),
 		}
-		else {
-			None
-		};
+	}
 
-		Ok(article)
+	fn entry_key_of(feed_id: u64, entry_id: &str) -> [u8; 16] {
+		let mut bytes = [0; 16];
+		bytes[..8].copy_from_slice(&feed_id.to_be_bytes());
+		bytes[8..].copy_from_slice(&fnv1a(entry_id.as_bytes()).to_be_bytes());
+		bytes
 	}
+}
 
-	pub fn insert(&self, app: &AppUser) -> Result<()> {
-		app.articles
-			.insert(self.id.as_bytes(), bincode::serialize(self)?)
-			.map(|_| ())
-			.map_err(Error::from)
+/// Identifies an article by its feed and entry, so unlike [`ArticleId`] it
+/// survives changes of the publish time. Formatted as `{feed_id}-{entry_hash}`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct CompositeId {
+	pub feed_id: u64,
+	entry_hash: u64,
+}
+
+impl CompositeId {
+	pub fn new(feed_id: u64, entry_hash: u64) -> Self {
+		Self {
+			feed_id,
+			entry_hash,
+		}
 	}
 
-	pub fn iter(app: &AppUser) -> impl Iterator<Item = Result<Article>> {
-		app.articles.iter().map(|item| {
-			item.map_err(Error::from)
-				.and_then(|(_, v)| bincode::deserialize::<Article>(&v).map_err(Error::from))
-		})
+	pub fn entry_hash(&self) -> u64 {
+		self.entry_hash
 	}
 
-	pub fn get_all(app: &AppUser) -> Result<Vec<Article>> {
-		Article::iter(app).collect()
+	fn entry_key(&self) -> [u8; 16] {
+		let mut bytes = [0; 16];
+		bytes[..8].copy_from_slice(&self.feed_id.to_be_bytes());
+		bytes[8..].copy_from_slice(&self.entry_hash.to_be_bytes());
+		bytes
 	}
 }
 
-impl indicium::simple::Indexable for Article {
-	fn strings(&self) -> Vec<String> {
-		return vec![
-			self.title.clone(),
-			self.summary.clone(),
-			self.content.clone(),
-		];
+impl std::fmt::Display for CompositeId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}-{:016x}", self.feed_id, self.entry_hash)
 	}
 }
 
-#[non_exhaustive]
-pub enum ImportOpts {
-	Opml(opml::OPML),
+impl std::str::FromStr for CompositeId {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		let (feed_id, entry_hash) = s.split_once('-').ok_or(Error::InvalidArticleId)?;
+		if entry_hash.len() != 16 {
+			return Err(Error::InvalidArticleId);
+		}
+
+		Ok(Self {
+			feed_id: feed_id.parse().map_err(|_| Error::InvalidArticleId)?,
+			entry_hash: u64::from_str_radix(entry_hash, 16).map_err(|_| Error::InvalidArticleId)?,
+		})
+	}
 }
 
-pub async fn import(app: &AppUser, opts: ImportOpts) -> Result<()> {
-	match opts {
-		ImportOpts::Opml(opml) => {
-			fn walk_outlines(outline: opml::Outline, collector: &mut Vec<opml::Outline>) {
-				for outline in &outline.outlines {
-					walk_outlines(outline.clone(), collector);
-				}
+impl Serialize for CompositeId {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
 
-				collector.push(outline);
-			}
+impl<'de> Deserialize<'de> for CompositeId {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		s.parse().map_err(serde::de::Error::custom)
+	}
+}
 
-			let mut vec = Vec::new();
-			for outline in opml.body.outlines {
-				walk_outlines(outline, &mut vec);
-			}
+/// 64-bit FNV-1a; entry hashes end up in keys, so they must be stable across builds
+fn fnv1a(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+		(hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+	})
+}
 
-			let is_feed = |o: &opml::Outline| o.xml_url.is_some();
+impl std::fmt::Display for ArticleId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for byte in self.0 {
+			write!(f, "{:02x}", byte)?;
+		}
+		Ok(())
+	}
+}
 
-			for outline in vec {
-				if is_feed(&outline) {
-					NewFeed {
-						url: Url::parse(&outline.xml_url.unwrap_or_default())?,
-						name: Some(outline.text),
-						scraper: None,
-					}
-					.insert(app)
-					.await?;
-				}
-			}
+impl std::str::FromStr for ArticleId {
+	type Err = Error;
 
-			Ok(())
+	fn from_str(s: &str) -> Result<Self> {
+		if s.len() != 48 || !s.is_ascii() {
+			return Err(Error::InvalidArticleId);
+		}
+
+		let mut bytes = [0; 24];
+		for (i, byte) in bytes.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+				.map_err(|_| Error::InvalidArticleId)?;
 		}
+		Ok(Self(bytes))
 	}
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(tag = "kind")]
+impl Serialize for ArticleId {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for ArticleId {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(serde::de::Error::custom)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 #[serde(rename_all = "snake_case")]
-#[non_exhaustive]
-pub enum ExportOpts {
-	Opml,
+pub enum ArticleOrderBy {
+	Title,
+	Published,
+	FirstSeen,
+	/// How well articles match the search, best first when descending; as
+	/// `Published` without one
+	Relevance,
 }
 
-pub fn export(app: &AppUser, opts: ExportOpts) -> Result<String> {
-	match opts {
-		ExportOpts::Opml => {
-			let mut opml = opml::OPML::default();
-			for feed in Feed::get_all(app)? {
-				opml.add_feed(&feed.name, &feed.url.to_string());
-			}
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+	Asc,
+	Desc,
+}
 
-			opml.to_string().map_err(Error::from)
+#[derive(Serialize, Deserialize)]
+pub struct Article {
+	pub id: ArticleId,
+	pub feed_id: u64,
+	/// As claimed by the feed
+	pub published: DateTime<Utc>,
+	/// When this server first saw the article; unlike `published`, feeds can't
+	/// backdate it
+	pub first_seen: DateTime<Utc>,
+	pub url: Option<String>,
+	pub title: String,
+	pub summary: String,
+	pub content: String,
+	pub authors: Vec<String>,
+	pub categories: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StateFlag {
+	Read,
+	Starred,
+}
+
+impl StateFlag {
+	fn tree(self, app: &AppUser) -> &sled::Tree {
+		match self {
+			StateFlag::Read => &app.read,
+			StateFlag::Starred => &app.starred,
 		}
 	}
+
+	/// Key into `state_clock`, the flag's discriminant followed by the entry key
+	fn clock_key(self, id: &ArticleId) -> [u8; 17] {
+		let mut key = [0; 17];
+		key[0] = self as u8;
+		key[1..].copy_from_slice(id.entry_key());
+		key
+	}
+}
+
+/// A change of a flag, as logged for devices to sync
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StateChange {
+	/// Position in the log
+	pub seq: u64,
+	pub id: ArticleId,
+	pub flag: StateFlag,
+	pub value: bool,
+	pub at: DateTime<Utc>,
+	/// Device the change was synced from
+	pub device: Option<u64>,
+}
+
+impl StateChange {
+	/// Changes logged after `seq`, oldest first
+	pub fn since(app: &AppUser, seq: u64) -> Result<Vec<StateChange>> {
+		app.state_changes
+			.range((seq + 1).to_be_bytes()..)
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect()
+	}
+
+	/// Drops changes up to and including `seq`, once every device has them
+	pub fn prune(app: &AppUser, seq: u64) -> Result<()> {
+		for key in app.state_changes.range(..=seq.to_be_bytes()).keys() {
+			app.state_changes.remove(key?)?;
+		}
+		Ok(())
+	}
+
+	/// The last position in the log
+	pub fn last_seq(app: &AppUser) -> Result<u64> {
+		Ok(app
+			.state_changes
+			.last()?
+			.map(|(key, _)| u64::from_be_bytes(key.as_ref().try_into().unwrap()))
+			.unwrap_or_default())
+	}
+}
+
+/// How far the user got reading an article, to resume on another device
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ReadPosition {
+	/// From 0 at the start to 1 at the end
+	pub progress: f64,
+	pub updated: DateTime<Utc>,
+}
+
+/// Per-user state of an article
+#[derive(Serialize)]
+pub struct ArticleState {
+	pub read: bool,
+	pub starred: bool,
+	pub position: Option<ReadPosition>,
+	pub tags: Vec<String>,
+}
+
+impl ArticleState {
+	pub fn get(app: &AppUser, id: &ArticleId) -> Result<Self> {
+		Ok(ArticleState {
+			read: Article::is_read(app, id)?,
+			starred: Article::is_starred(app, id)?,
+			position: Article::get_position(app, id)?,
+			tags: tag::get(app, id)?,
+		})
+	}
+}
+
+#[derive(Deserialize)]
+pub struct PatchArticleState {
+	pub read: Option<bool>,
+	pub starred: Option<bool>,
+	/// `null` clears it
+	#[serde(default, deserialize_with = "present")]
+	pub position: Option<Option<f64>>,
+	/// Replaces the article's tags
+	pub tags: Option<Vec<String>>,
+}
+
+impl PatchArticleState {
+	pub fn apply(self, app: &AppUser, id: &ArticleId) -> Result<ArticleState> {
+		if let Some(read) = self.read {
+			Article::set_read(app, id, read)?;
+			if read {
+				FeedReads::record(app, id)?;
+			}
+		}
+		if let Some(starred) = self.starred {
+			Article::set_starred(app, id, starred)?;
+		}
+		if let Some(position) = self.position {
+			Article::set_position(app, id, position)?;
+		}
+		if let Some(tags) = self.tags {
+			tag::set(app, id, tags)?;
+		}
+
+		ArticleState::get(app, id)
+	}
+}
+
+/// Article as listed, without the content, which can be large; clients get it
+/// per article when opening one
+#[derive(Serialize)]
+pub struct ListedArticle {
+	pub id: ArticleId,
+	pub feed_id: u64,
+	pub published: DateTime<Utc>,
+	pub first_seen: DateTime<Utc>,
+	pub url: Option<String>,
+	pub title: String,
+	pub summary: String,
+	pub authors: Vec<String>,
+	pub categories: Vec<String>,
+}
+
+impl From<Article> for ListedArticle {
+	fn from(article: Article) -> Self {
+		Self {
+			id: article.id,
+			feed_id: article.feed_id,
+			published: article.published,
+			first_seen: article.first_seen,
+			url: article.url,
+			title: article.title,
+			summary: article.summary,
+			authors: article.authors,
+			categories: article.categories,
+		}
+	}
+}
+
+/// Article as stored in the user's tree. The body lives in the instance-wide
+/// body store, so identical bodies are stored once however many users
+/// subscribe to the feed.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct StoredArticle {
+	id: ArticleId,
+	feed_id: u64,
+	published: DateTime<Utc>,
+	first_seen: DateTime<Utc>,
+	url: Option<String>,
+	title: String,
+	authors: Vec<String>,
+	categories: Vec<String>,
+	body: BodyHash,
+}
+
+type BodyHash = [u8; 32];
+
+fn body_hash(summary: &str, content: &str) -> BodyHash {
+	let mut hasher = Sha256::new();
+	hasher.update(summary.as_bytes());
+	hasher.update([0]);
+	hasher.update(content.as_bytes());
+	hasher.finalize().into()
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArticleBody {
+	summary: String,
+	content: String,
+}
+
+impl ArticleBody {
+	fn hash(&self) -> BodyHash {
+		body_hash(&self.summary, &self.content)
+	}
+
+	fn get(app: &AppUser, hash: &BodyHash) -> Result<ArticleBody> {
+		app.bodies
+			.get(hash)?
+			.map(|bytes| crypt::decode(&bytes))
+			.transpose()?
+			.ok_or(Error::NotFound("article body".into()))
+	}
+
+	/// Stores the body if needed, and takes a reference to it
+	fn retain(&self, app: &AppUser) -> Result<BodyHash> {
+		let hash = self.hash();
+		let body = crypt::encode(self)?;
+
+		(&app.bodies, &app.body_refs).transaction(|(bodies, body_refs)| {
+			let refs = match body_refs.get(hash)? {
+				Some(refs) => u64::from_be_bytes(refs.as_ref().try_into().unwrap_or_default()),
+				None => 0,
+			};
+			if refs == 0 {
+				bodies.insert(&hash, body.as_slice())?;
+			}
+			body_refs.insert(&hash, &(refs + 1).to_be_bytes())?;
+			Ok(())
+		})?;
+
+		Ok(hash)
+	}
+
+	/// Drops a reference to a body, removing it once unreferenced
+	fn release(app: &AppUser, hash: &BodyHash) -> Result<()> {
+		(&app.bodies, &app.body_refs).transaction(|(bodies, body_refs)| {
+			let refs = match body_refs.get(hash)? {
+				Some(refs) => u64::from_be_bytes(refs.as_ref().try_into().unwrap_or_default()),
+				None => 0,
+			};
+			if refs <= 1 {
+				bodies.remove(hash)?;
+				body_refs.remove(hash)?;
+			}
+			else {
+				body_refs.insert(hash, &(refs - 1).to_be_bytes())?;
+			}
+			Ok(())
+		})?;
+
+		Ok(())
+	}
+}
+
+impl Article {
+	/// Storage the articles take up, along with the bodies no other article uses
+	pub fn stored_bytes(app: &AppUser, ids: &[ArticleId]) -> Result<u64> {
+		let mut bytes = 0;
+		let mut released = HashMap::<BodyHash, u64>::new();
+		for id in ids {
+			let Some(value) = app.articles.get(id.as_bytes())?
+			else {
+				continue;
+			};
+			bytes += (id.as_bytes().len() + value.len()) as u64;
+			let stored: StoredArticle = crypt::decode(&value)?;
+			*released.entry(stored.body).or_default() += 1;
+		}
+
+		for (hash, count) in released {
+			let refs = app
+				.body_refs
+				.get(hash)?
+				.map(|refs| u64::from_be_bytes(refs.as_ref().try_into().unwrap_or_default()))
+				.unwrap_or(0);
+			if count >= refs {
+				if let Some(body) = app.bodies.get(hash)? {
+					bytes += (hash.len() + body.len()) as u64;
+				}
+			}
+		}
+
+		Ok(bytes)
+	}
+}
+
+impl StoredArticle {
+	fn get(app: &AppUser, key: &[u8]) -> Result<Option<StoredArticle>> {
+		app.articles
+			.get(key)?
+			.map(|bytes| crypt::decode(&bytes))
+			.transpose()
+	}
+
+	fn into_article(self, app: &AppUser) -> Result<Article> {
+		let body = ArticleBody::get(app, &self.body)?;
+
+		Ok(Article {
+			id: self.id,
+			feed_id: self.feed_id,
+			published: self.published,
+			first_seen: self.first_seen,
+			url: self.url,
+			title: self.title,
+			summary: body.summary,
+			content: body.content,
+			authors: self.authors,
+			categories: self.categories,
+		})
+	}
+}
+
+impl Article {
+	/// Looks up an article by its id, or by the id it had before its feed changed
+	/// the publish time
+	pub fn get_id(app: &AppUser, id: &ArticleId) -> Result<Option<Article>> {
+		let stored = match StoredArticle::get(app, id.as_bytes())? {
+			Some(stored) => Some(stored),
+			None => match Self::resolve(app, id)? {
+				Some(current) if current != *id => StoredArticle::get(app, current.as_bytes())?,
+				_ => None,
+			},
+		};
+		stored.map(|stored| stored.into_article(app)).transpose()
+	}
+
+	/// The id the article is stored under now. Ids start with the publish time,
+	/// which feeds may change, but ids handed out before still find the article
+	/// through its entry key.
+	pub fn resolve(app: &AppUser, id: &ArticleId) -> Result<Option<ArticleId>> {
+		app.article_keys
+			.get(id.entry_key())?
+			.map(|key| ArticleId::from_bytes(&key))
+			.transpose()
+	}
+
+	/// Articles added or changed, and removed, after the articles revision
+	/// `since`. Ids an article moved away from count as removed.
+	pub fn changed_since(app: &AppUser, since: u64) -> Result<(Vec<ArticleId>, Vec<ArticleId>)> {
+		let mut changed = vec![];
+		let mut removed = vec![];
+		for item in app.article_revisions.iter() {
+			let (id, value) = item?;
+			let revision = value
+				.get(..8)
+				.and_then(|bytes| bytes.try_into().ok())
+				.map(u64::from_be_bytes)
+				.unwrap_or_default();
+			if revision <= since {
+				continue;
+			}
+
+			let id = ArticleId::from_bytes(&id)?;
+			match value.get(8) {
+				Some(1) => removed.push(id),
+				_ => changed.push(id),
+			}
+		}
+		Ok((changed, removed))
+	}
+
+	/// Whether the article exists, under this id or a previous one
+	pub fn exists(app: &AppUser, id: &ArticleId) -> Result<bool> {
+		Ok(app.article_keys.contains_key(id.entry_key())?)
+	}
+
+	/// Whether a stored value is an article in the current format
+	pub fn decodes(bytes: &[u8]) -> bool {
+		crypt::decode::<StoredArticle>(bytes).is_ok()
+	}
+
+	/// Looks up an article by the feed-provided entry id, whatever its current key is
+	pub fn get_entry(app: &AppUser, feed_id: u64, entry_id: &str) -> Result<Option<Article>> {
+		let key = app
+			.article_keys
+			.get(ArticleId::entry_key_of(feed_id, entry_id))?;
+
+		match key {
+			Some(key) => Self::get_id(app, &ArticleId::from_bytes(&key)?),
+			None => Ok(None),
+		}
+	}
+
+	pub fn get_composite(app: &AppUser, id: &CompositeId) -> Result<Option<Article>> {
+		match Self::current_id(app, id)? {
+			Some(id) => Self::get_id(app, &id),
+			None => Ok(None),
+		}
+	}
+
+	/// Id of the article currently stored for the feed entry, if any
+	pub fn current_id(app: &AppUser, id: &CompositeId) -> Result<Option<ArticleId>> {
+		app.article_keys
+			.get(id.entry_key())?
+			.map(|key| ArticleId::from_bytes(&key))
+			.transpose()
+	}
+
+	pub fn insert(&self, app: &AppUser) -> Result<()> {
+		let body = ArticleBody {
+			summary: self.summary.clone(),
+			content: self.content.clone(),
+		};
+		let stored = StoredArticle {
+			id: self.id,
+			feed_id: self.feed_id,
+			published: self.published,
+			first_seen: self.first_seen,
+			url: self.url.clone(),
+			title: self.title.clone(),
+			authors: self.authors.clone(),
+			categories: self.categories.clone(),
+			body: body.retain(app)?,
+		};
+
+		// the publish time may have changed, in which case the old key is dropped
+		let prev_key = app
+			.article_keys
+			.insert(self.id.entry_key(), self.id.as_bytes())?;
+		let prev = match prev_key {
+			Some(prev_key) if prev_key != self.id.as_bytes() => app
+				.articles
+				.remove(prev_key)?
+				.map(|bytes| crypt::decode::<StoredArticle>(&bytes))
+				.transpose()?,
+			_ => None,
+		};
+
+		let replaced = app
+			.articles
+			.insert(self.id.as_bytes(), crypt::encode(&stored)?)?
+			.map(|bytes| crypt::decode::<StoredArticle>(&bytes))
+			.transpose()?;
+
+		// feeds are refetched whole, so most inserts change nothing
+		if prev.is_some() || replaced.as_ref() != Some(&stored) {
+			let mut changed = vec![(self.id, false)];
+			changed.extend(prev.as_ref().map(|prev| (prev.id, true)));
+			app.bump_articles_revision(&changed)?;
+			app.queue_for_index(&self.id)?;
+		}
+		if let Some(prev) = &prev {
+			app.queue_for_index(&prev.id)?;
+		}
+		for prev in prev.into_iter().chain(replaced) {
+			ArticleBody::release(app, &prev.body)?;
+		}
+
+		Ok(())
+	}
+
+	/// Hash of the summary and content, which the body is stored under
+	pub fn body_hash(&self) -> [u8; 32] {
+		body_hash(&self.summary, &self.content)
+	}
+
+	/// Iterates articles newest-first
+	pub fn iter(app: &AppUser) -> impl DoubleEndedIterator<Item = Result<Article>> + '_ {
+		app.articles.iter().map(move |item| Self::decode(app, item))
+	}
+
+	/// Iterates articles newest-first, starting right after the given one
+	pub fn iter_after<'a>(
+		app: &'a AppUser,
+		after: &ArticleId,
+	) -> impl DoubleEndedIterator<Item = Result<Article>> + 'a {
+		let start = std::ops::Bound::Excluded(after.as_bytes().to_vec());
+		app.articles
+			.range((start, std::ops::Bound::Unbounded))
+			.map(move |item| Self::decode(app, item))
+	}
+
+	/// Iterates articles newest-first, starting right after `after` and only of
+	/// the feed `feed_id`, if given. Other feeds' articles are skipped by their
+	/// key, without loading them.
+	pub fn iter_from<'a>(
+		app: &'a AppUser,
+		after: Option<&ArticleId>,
+		feed_id: Option<u64>,
+	) -> impl Iterator<Item = Result<Article>> + 'a {
+		let start = match after {
+			Some(after) => std::ops::Bound::Excluded(after.as_bytes().to_vec()),
+			None => std::ops::Bound::Unbounded,
+		};
+		app.articles
+			.range((start, std::ops::Bound::Unbounded))
+			.filter(move |item| match (feed_id, item) {
+				(Some(feed_id), Ok((key, _))) => {
+					ArticleId::from_bytes(key).map_or(true, |id| id.composite().feed_id == feed_id)
+				}
+				_ => true,
+			})
+			.map(move |item| Self::decode(app, item))
+	}
+
+	fn decode(app: &AppUser, item: sled::Result<(sled::IVec, sled::IVec)>) -> Result<Article> {
+		item.map_err(Error::from)
+			.and_then(|(_, v)| crypt::decode::<StoredArticle>(&v))
+			.and_then(|stored| stored.into_article(app))
+	}
+
+	pub fn remove(app: &AppUser, id: &ArticleId) -> Result<()> {
+		let id = Self::resolve(app, id)?.ok_or(Error::NotFound("article".into()))?;
+		Self::remove_unindexed(app, &id)?;
+		app.remove_from_search_index(&BTreeSet::from([id]))
+	}
+
+	/// Removes the article, leaving the search index to the caller
+	fn remove_unindexed(app: &AppUser, id: &ArticleId) -> Result<()> {
+		let stored = app
+			.articles
+			.remove(id.as_bytes())?
+			.map(|bytes| crypt::decode::<StoredArticle>(&bytes))
+			.transpose()?
+			.ok_or(Error::NotFound("article".into()))?;
+
+		// only drop the entry mapping if it still points to this article
+		let removed = app
+			.article_keys
+			.compare_and_swap(id.entry_key(), Some(id.as_bytes()), None as Option<&[u8]>)?
+			.is_ok();
+		if removed {
+			app.read.remove(id.entry_key())?;
+			app.starred.remove(id.entry_key())?;
+			app.positions.remove(id.entry_key())?;
+			tag::remove(app, id)?;
+			app.link_checks.remove(id.entry_key())?;
+			if let Some(hash) = app.article_snapshots.remove(id.entry_key())? {
+				app.blobs.release(&Self::blob_hash(&hash)?)?;
+			}
+			for flag in [StateFlag::Read, StateFlag::Starred] {
+				app.state_clock.remove(flag.clock_key(id))?;
+			}
+		}
+		app.bump_articles_revision(&[(*id, true)])?;
+
+		ArticleBody::release(app, &stored.body)
+	}
+
+	fn blob_hash(bytes: &[u8]) -> Result<BlobHash> {
+		bytes
+			.try_into()
+			.map_err(|_| Error::NotFound("article snapshot".into()))
+	}
+
+	/// Stores the archived page of the article in the blob store. Kept per entry,
+	/// like read state.
+	pub fn set_snapshot(app: &AppUser, id: &ArticleId, page: &[u8]) -> Result<()> {
+		let hash = app.blobs.put(page)?;
+		if let Some(prev) = app.article_snapshots.insert(id.entry_key(), &hash)? {
+			app.blobs.release(&Self::blob_hash(&prev)?)?;
+		}
+		Ok(())
+	}
+
+	pub fn get_snapshot(app: &AppUser, id: &ArticleId) -> Result<Option<Vec<u8>>> {
+		match app.article_snapshots.get(id.entry_key())? {
+			Some(hash) => app.blobs.get(&Self::blob_hash(&hash)?),
+			None => Ok(None),
+		}
+	}
+
+	/// Read state is kept per entry, so it survives changes of the publish time
+	pub fn set_read(app: &AppUser, id: &ArticleId, read: bool) -> Result<()> {
+		Self::set_flag(app, id, StateFlag::Read, read, Utc::now(), None).map(|_| ())
+	}
+
+	pub fn is_read(app: &AppUser, id: &ArticleId) -> Result<bool> {
+		Ok(app.read.contains_key(id.entry_key())?)
+	}
+
+	/// Marks the feed's articles read, returning how many were unread
+	pub fn mark_feed_read(app: &AppUser, feed_id: u64) -> Result<usize> {
+		let mut marked = 0;
+		for item in app.article_keys.scan_prefix(feed_id.to_be_bytes()) {
+			let (entry_key, key) = item?;
+			if app.read.contains_key(&entry_key)? {
+				continue;
+			}
+			Self::set_read(app, &ArticleId::from_bytes(&key)?, true)?;
+			marked += 1;
+		}
+
+		Ok(marked)
+	}
+
+	/// Unread articles per feed, for feeds with any. Entry keys start with the
+	/// feed id, so no article needs decoding.
+	pub fn count_unread(app: &AppUser) -> Result<BTreeMap<u64, usize>> {
+		let mut counts = BTreeMap::new();
+		for item in app.article_keys.iter() {
+			let (entry_key, _) = item?;
+			if app.read.contains_key(&entry_key)? {
+				continue;
+			}
+			let feed_id = entry_key
+				.get(..8)
+				.and_then(|bytes| bytes.try_into().ok())
+				.map(u64::from_be_bytes)
+				.ok_or(Error::InvalidArticleId)?;
+			*counts.entry(feed_id).or_default() += 1;
+		}
+
+		Ok(counts)
+	}
+
+	pub fn set_starred(app: &AppUser, id: &ArticleId, starred: bool) -> Result<()> {
+		Self::set_flag(app, id, StateFlag::Starred, starred, Utc::now(), None).map(|_| ())
+	}
+
+	pub fn is_starred(app: &AppUser, id: &ArticleId) -> Result<bool> {
+		Ok(app.starred.contains_key(id.entry_key())?)
+	}
+
+	/// Sets a flag as of `at`, unless it was changed later than that; the latest
+	/// change wins. Changes are logged for devices to sync, along with the device
+	/// they came from, if any. Returns whether the flag was set.
+	pub fn set_flag(
+		app: &AppUser,
+		id: &ArticleId,
+		flag: StateFlag,
+		value: bool,
+		at: DateTime<Utc>,
+		device: Option<u64>,
+	) -> Result<bool> {
+		if !Self::exists(app, id)? {
+			return Err(Error::NotFound("article".into()));
+		}
+
+		let clock_key = flag.clock_key(id);
+		if let Some(changed) = app.state_clock.get(clock_key)? {
+			let changed: DateTime<Utc> = bincode::deserialize(&changed)?;
+			if changed > at {
+				return Ok(false);
+			}
+		}
+
+		let tree = flag.tree(app);
+		let changed = match value {
+			true => tree.insert(id.entry_key(), &[])?.is_none(),
+			false => tree.remove(id.entry_key())?.is_some(),
+		};
+		if changed {
+			app.state_clock
+				.insert(clock_key, bincode::serialize(&at)?)?;
+			match flag {
+				StateFlag::Read => app.bump_read_revision()?,
+				StateFlag::Starred => app.bump_starred_revision()?,
+			}
+			// nobody to sync the log to otherwise
+			if !app.devices.is_empty() {
+				let change = StateChange {
+					seq: app.db.generate_id()?,
+					id: *id,
+					flag,
+					value,
+					at,
+					device,
+				};
+				app.state_changes
+					.insert(change.seq.to_be_bytes(), bincode::serialize(&change)?)?;
+			}
+		}
+		Ok(true)
+	}
+
+	pub fn get_flag(app: &AppUser, id: &ArticleId, flag: StateFlag) -> Result<bool> {
+		Ok(flag.tree(app).contains_key(id.entry_key())?)
+	}
+
+	/// When the flag was last changed, if ever
+	pub fn flag_changed(
+		app: &AppUser,
+		id: &ArticleId,
+		flag: StateFlag,
+	) -> Result<Option<DateTime<Utc>>> {
+		app.state_clock
+			.get(flag.clock_key(id))?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()
+			.map_err(Into::into)
+	}
+
+	pub fn set_position(app: &AppUser, id: &ArticleId, progress: Option<f64>) -> Result<()> {
+		if !Self::exists(app, id)? {
+			return Err(Error::NotFound("article".into()));
+		}
+
+		match progress {
+			Some(progress) => {
+				let position = ReadPosition {
+					progress: progress.clamp(0.0, 1.0),
+					updated: Utc::now(),
+				};
+				app.positions
+					.insert(id.entry_key(), bincode::serialize(&position)?)?;
+			}
+			None => {
+				app.positions.remove(id.entry_key())?;
+			}
+		}
+		Ok(())
+	}
+
+	pub fn get_position(app: &AppUser, id: &ArticleId) -> Result<Option<ReadPosition>> {
+		app.positions
+			.get(id.entry_key())?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()
+			.map_err(Into::into)
+	}
+
+	/// The starred articles, in no particular order
+	pub fn get_starred(app: &AppUser) -> Result<Vec<Article>> {
+		let mut articles = vec![];
+		for item in app.starred.iter() {
+			let (entry_key, _) = item?;
+			if let Some(key) = app.article_keys.get(entry_key)? {
+				articles.extend(Self::get_id(app, &ArticleId::from_bytes(&key)?)?);
+			}
+		}
+		Ok(articles)
+	}
+
+	/// Counts the articles first seen after `since` per feed, without loading bodies
+	pub fn count_seen_since(app: &AppUser, since: DateTime<Utc>) -> Result<BTreeMap<u64, usize>> {
+		let mut counts = BTreeMap::new();
+		for item in app.articles.iter() {
+			let (_, bytes) = item?;
+			let stored: StoredArticle = crypt::decode(&bytes)?;
+			if stored.first_seen > since {
+				*counts.entry(stored.feed_id).or_default() += 1;
+			}
+		}
+
+		Ok(counts)
+	}
+
+	/// The articles first seen after `since`, newest-first, loading only their bodies
+	pub fn get_seen_since(app: &AppUser, since: DateTime<Utc>) -> Result<Vec<Article>> {
+		let mut articles = vec![];
+		for item in app.articles.iter() {
+			let (_, bytes) = item?;
+			let stored: StoredArticle = crypt::decode(&bytes)?;
+			if stored.first_seen > since {
+				articles.push(stored.into_article(app)?);
+			}
+		}
+
+		Ok(articles)
+	}
+
+	/// Publish time of each feed's newest article, read off the keys alone
+	pub fn latest_per_feed(app: &AppUser) -> Result<HashMap<u64, DateTime<Utc>>> {
+		let mut latest = HashMap::new();
+		for key in app.articles.iter().keys() {
+			let id = ArticleId::from_bytes(&key?)?;
+			// newest-first, so the first one seen is the newest
+			latest
+				.entry(id.composite().feed_id)
+				.or_insert_with(|| id.published());
+		}
+
+		Ok(latest)
+	}
+
+	/// Pages through the articles matching `filter` newest-first, starting after `cursor`
+	pub fn page(
+		app: &AppUser,
+		cursor: Option<&ArticleId>,
+		limit: usize,
+		mut filter: impl FnMut(&Article) -> Result<bool>,
+	) -> Result<Page<Article>> {
+		let iter: Box<dyn Iterator<Item = _>> = match cursor {
+			Some(cursor) => Box::new(Article::iter_after(app, cursor)),
+			None => Box::new(Article::iter(app)),
+		};
+
+		let mut items = vec![];
+		for article in iter {
+			let article = article?;
+			if !filter(&article)? {
+				continue;
+			}
+
+			// one more than requested tells whether there is a next page
+			if items.len() == limit {
+				return Ok(Page {
+					next_cursor: items.last().map(|article: &Article| article.id),
+					items,
+				});
+			}
+
+			items.push(article);
+		}
+
+		Ok(Page {
+			items,
+			next_cursor: None,
+		})
+	}
+
+	pub fn feed_has_articles(app: &AppUser, feed_id: u64) -> Result<bool> {
+		Ok(app
+			.article_keys
+			.scan_prefix(feed_id.to_be_bytes())
+			.next()
+			.transpose()?
+			.is_some())
+	}
+
+	/// Removes the feed's articles, returning their ids. Entry keys start with
+	/// the feed id, so only the feed's articles are looked at.
+	pub fn remove_feed(app: &AppUser, feed_id: u64) -> Result<BTreeSet<ArticleId>> {
+		let mut removed = BTreeSet::new();
+		for item in app.article_keys.scan_prefix(feed_id.to_be_bytes()) {
+			let (_, key) = item?;
+			let id = ArticleId::from_bytes(&key)?;
+			Self::remove_unindexed(app, &id)?;
+			removed.insert(id);
+		}
+
+		Ok(removed)
+	}
+
+	/// Releases the bodies and snapshots referenced by all of the user's
+	/// articles, before the user's trees are dropped
+	pub fn release_all(app: &AppUser) -> Result<()> {
+		for item in app.articles.iter() {
+			let (key, bytes) = item?;
+			let stored: StoredArticle = crypt::decode(&bytes)?;
+			ArticleBody::release(app, &stored.body)?;
+			app.articles.remove(key)?;
+		}
+		for hash in app.article_snapshots.iter().values() {
+			app.blobs.release(&Self::blob_hash(&hash?)?)?;
+		}
+
+		Ok(())
+	}
+}
+
+/// One page of a listing; pass `next_cursor` as the cursor to get the next one
+#[derive(Serialize, Deserialize)]
+pub struct Page<T> {
+	pub items: Vec<T>,
+	/// Absent on the last page
+	pub next_cursor: Option<ArticleId>,
+}
+
+impl<T> Page<T> {
+	pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+		Page {
+			items: self.items.into_iter().map(f).collect(),
+			next_cursor: self.next_cursor,
+		}
+	}
+
+	/// Maps the items as a whole, e.g. to group them
+	pub fn try_map_items<U>(self, f: impl FnOnce(Vec<T>) -> Result<Vec<U>>) -> Result<Page<U>> {
+		Ok(Page {
+			items: f(self.items)?,
+			next_cursor: self.next_cursor,
+		})
+	}
+}
+
+/// A folder, i.e. the feeds sharing a category. Nested folders are categories
+/// named by their path, folder names joined by `/`, as in `Work/Rust`.
+#[derive(Serialize)]
+pub struct Category {
+	pub name: String,
+	pub unread: usize,
+	pub feeds: Vec<ListedFeed>,
+}
+
+/// Feed as listed, with its unread count for badges
+#[derive(Serialize)]
+pub struct ListedFeed {
+	#[serde(flatten)]
+	pub feed: Feed,
+	pub unread: usize,
+	/// Whether `content_mode` is `scraped_full_text`, for clients of the flag
+	/// that was folded into it
+	pub fetch_full_content: bool,
+}
+
+impl ListedFeed {
+	pub fn with_unread(feeds: Vec<Feed>, unread: &BTreeMap<u64, usize>) -> Vec<ListedFeed> {
+		feeds
+			.into_iter()
+			.map(|feed| ListedFeed {
+				unread: unread.get(&feed.id).copied().unwrap_or(0),
+				fetch_full_content: feed.content_mode == ContentMode::ScrapedFullText,
+				feed: feed.redacted(),
+			})
+			.collect()
+	}
+}
+
+impl Category {
+	pub fn get_all(app: &AppUser) -> Result<Vec<Category>> {
+		let unread = Article::count_unread(app)?;
+
+		let mut categories = BTreeMap::<String, Vec<ListedFeed>>::new();
+		for feed in ListedFeed::with_unread(Feed::get_all(app)?, &unread) {
+			if let Some(name) = feed.feed.category.clone() {
+				categories.entry(name).or_default().push(feed);
+			}
+		}
+
+		Ok(categories
+			.into_iter()
+			.map(|(name, feeds)| Category {
+				name,
+				unread: feeds.iter().map(|feed| feed.unread).sum(),
+				feeds,
+			})
+			.collect())
+	}
+
+	/// Whether the category is the folder `name`, or a folder within it
+	pub fn is_in(category: &str, name: &str) -> bool {
+		category
+			.strip_prefix(name)
+			.is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+	}
+
+	/// Ids of the feeds in the category, or in folders within it
+	pub fn feed_ids(app: &AppUser, name: &str) -> Result<BTreeSet<u64>> {
+		let feed_ids: BTreeSet<u64> = Feed::get_all(app)?
+			.into_iter()
+			.filter(|feed| {
+				feed.category
+					.as_deref()
+					.is_some_and(|category| Self::is_in(category, name))
+			})
+			.map(|feed| feed.id)
+			.collect();
+
+		if feed_ids.is_empty() {
+			return Err(Error::NotFound("category".into()));
+		}
+		Ok(feed_ids)
+	}
+}
+
+/// Which parts of articles are searchable; indexing less keeps the index
+/// small and quick to rebuild
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexedFields {
+	Title,
+	/// The title, summary, authors and categories
+	Summary,
+	/// Everything, content included
+	#[default]
+	Content,
+}
+
+/// An article as indexed, with only the fields to index
+pub struct IndexedArticle<'a>(pub &'a Article, pub IndexedFields);
+
+impl IndexedArticle<'_> {
+	/// Text of the fields other than the title, markup left out
+	pub fn body(&self) -> String {
+		let IndexedArticle(article, fields) = self;
+		if *fields == IndexedFields::Title {
+			return String::new();
+		}
+
+		let mut html = vec![&article.summary];
+		if *fields == IndexedFields::Content {
+			html.push(&article.content);
+		}
+		html.into_iter()
+			.flat_map(|html| {
+				scraper::Html::parse_fragment(html)
+					.root_element()
+					.text()
+					.map(str::to_owned)
+					.collect::<Vec<_>>()
+			})
+			.chain(article.authors.iter().cloned())
+			.chain(article.categories.iter().cloned())
+			.join(" ")
+	}
+}
+
+#[non_exhaustive]
+pub enum ImportOpts {
+	Opml(opml::OPML),
+}
+
+/// Outcome of an import, per feed
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+	pub imported: Vec<String>,
+	/// Already subscribed to, or listed more than once
+	pub duplicates: Vec<String>,
+	pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Serialize)]
+pub struct ImportFailure {
+	pub url: String,
+	/// See [`Error::code`]
+	pub code: &'static str,
+	pub reason: String,
+}
+
+/// Imports the feeds of an OPML file. Feeds within a folder are put in a
+/// category of that name, or of the folder's path for nested folders, see
+/// [`Category`]. Feeds already subscribed to are not added again, but moved to
+/// the folder they're in.
+/// A feed that can't be imported doesn't stop the rest from being imported.
+pub async fn import(app: &AppUser, opts: ImportOpts) -> Result<ImportReport> {
+	match opts {
+		ImportOpts::Opml(opml) => {
+			fn walk_outlines(
+				outline: opml::Outline,
+				folder: Option<&str>,
+				collector: &mut Vec<(opml::Outline, Option<String>)>,
+			) {
+				if outline.xml_url.is_some() {
+					collector.push((outline, folder.map(str::to_owned)));
+					return;
+				}
+
+				// unnamed folders don't nest
+				let name = outline.title.as_deref().unwrap_or(&outline.text);
+				let folder = match (folder, name.is_empty()) {
+					(folder, true) => folder.map(str::to_owned),
+					(Some(parent), false) => Some(format!("{}/{}", parent, name)),
+					(None, false) => Some(name.to_owned()),
+				};
+				for child in &outline.outlines {
+					walk_outlines(child.clone(), folder.as_deref(), collector);
+				}
+			}
+
+			let mut vec = Vec::new();
+			for outline in opml.body.outlines {
+				walk_outlines(outline, None, &mut vec);
+			}
+
+			let mut existing: HashMap<Url, Feed> = Feed::get_all(app)?
+				.into_iter()
+				.map(|feed| (feed.url.clone(), feed))
+				.collect();
+			let mut imported = HashSet::new();
+			let mut report = ImportReport::default();
+			for (outline, category) in vec {
+				let xml_url = outline.xml_url.unwrap_or_default();
+				let result: Result<bool> = async {
+					let url = Url::parse(&xml_url)?;
+					let normalized = normalize_url(&url)?;
+					if !imported.insert(normalized.clone()) {
+						return Ok(false);
+					}
+
+					match existing.get_mut(&normalized) {
+						Some(feed) => {
+							if feed.category != category {
+								feed.category = category;
+								feed.insert(app)?;
+							}
+							Ok(false)
+						}
+						None => {
+							NewFeed::new(url, Some(outline.text), category)
+								.insert(app)
+								.await?;
+							Ok(true)
+						}
+					}
+				}
+				.await;
+
+				match result {
+					Ok(true) => report.imported.push(xml_url),
+					Ok(false) => report.duplicates.push(xml_url),
+					Err(e) => report.failed.push(ImportFailure {
+						url: xml_url,
+						code: e.code(),
+						reason: e.to_string(),
+					}),
+				}
+			}
+
+			Ok(report)
+		}
+	}
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ExportOpts {
+	Opml,
+}
+
+/// The outlines of the folder at the path, see [`Category`], adding the
+/// folders missing
+fn folder_outlines<'a>(
+	mut outlines: &'a mut Vec<opml::Outline>,
+	path: &str,
+) -> &'a mut Vec<opml::Outline> {
+	for name in path.split('/') {
+		let index = match outlines
+			.iter()
+			.position(|outline| outline.xml_url.is_none() && outline.text == name)
+		{
+			Some(index) => index,
+			None => {
+				outlines.push(opml::Outline {
+					text: name.to_owned(),
+					title: Some(name.to_owned()),
+					..opml::Outline::default()
+				});
+				outlines.len() - 1
+			}
+		};
+		outlines = &mut outlines[index].outlines;
+	}
+	outlines
+}
+
+/// Exports the feeds as OPML, with a folder per category, nested as their paths
+/// are. Feeds are listed by the url they were added with, so importing the
+/// export reproduces them.
+pub fn export(app: &AppUser, opts: ExportOpts) -> Result<String> {
+	match opts {
+		ExportOpts::Opml => {
+			let mut opml = opml::OPML::default();
+			let mut folders: BTreeMap<String, Vec<opml::Outline>> = BTreeMap::new();
+			for feed in Feed::get_all(app)? {
+				let outline = opml::Outline {
+					text: feed.name.clone(),
+					title: Some(feed.meta.title.unwrap_or(feed.name)),
+					r#type: Some("rss".into()),
+					xml_url: Some(feed.original_url),
+					html_url: feed.meta.site_url,
+					..opml::Outline::default()
+				};
+				match feed.category {
+					Some(category) => folders.entry(category).or_default().push(outline),
+					None => opml.body.outlines.push(outline),
+				}
+			}
+			for (category, outlines) in folders {
+				folder_outlines(&mut opml.body.outlines, &category).extend(outlines);
+			}
+
+			opml.to_string().map_err(Error::from)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::TimeZone;
+
+	use super::*;
+
+	#[test]
+	fn article_ids_sort_newest_first() {
+		let day = |year, day| Utc.with_ymd_and_hms(year, 1, day, 12, 0, 0).unwrap();
+		let mut ids = [
+			ArticleId::new(day(1969, 1), 1, "a"),
+			ArticleId::new(day(2024, 1), 2, "a"),
+			ArticleId::new(day(2024, 2), 1, "a"),
+			ArticleId::new(day(2024, 1), 1, "a"),
+		];
+		ids.sort();
+		let sorted: Vec<_> = ids
+			.iter()
+			.map(|id| (id.published(), id.composite().feed_id))
+			.collect();
+		// articles published at the same time go by feed
+		assert_eq!(
+			sorted,
+			[
+				(day(2024, 2), 1),
+				(day(2024, 1), 1),
+				(day(2024, 1), 2),
+				(day(1969, 1), 1),
+			]
+		);
+
+		let id = ids[0];
+		assert_eq!(ArticleId::from_bytes(id.as_bytes()).unwrap(), id);
+		assert!(ArticleId::from_bytes(&id.as_bytes()[1..]).is_err());
+	}
+
+	#[test]
+	fn urls_are_normalized() {
+		let normalized = |url: &str| {
+			normalize_url(&Url::parse(url).unwrap())
+				.unwrap()
+				.to_string()
+		};
+		assert_eq!(
+			normalized("feed://Example.COM/feed.xml#top"),
+			"http://example.com/feed.xml"
+		);
+		assert_eq!(
+			normalized("feed:https://example.com/feed.xml"),
+			"https://example.com/feed.xml"
+		);
+		assert_eq!(
+			normalized("gemini://example.com:1965/feed.gmi"),
+			"gemini://example.com/feed.gmi"
+		);
+		assert_eq!(
+			normalized("http://example.com:8080/feed.xml"),
+			"http://example.com:8080/feed.xml"
+		);
+	}
 }
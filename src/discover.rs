@@ -0,0 +1,149 @@
+//! Feed suggestions for filling a new reader: a curated catalog bundled as OPML,
+//! grouped by topic, and what other users of the instance subscribe to. The
+//! latter only counts users who opted in through their settings.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	db::{normalize_url, Feed, User},
+	App, Result,
+};
+
+/// Feeds subscribed to by fewer opted-in users are not suggested, so
+/// suggestions can't be traced back to a single user
+const MIN_SUBSCRIBERS: usize = 2;
+const MAX_POPULAR: usize = 20;
+
+#[derive(Serialize, Clone)]
+pub struct CatalogFeed {
+	pub topic: String,
+	pub title: String,
+	pub url: Url,
+	pub site_url: Option<String>,
+	/// Whether the user already subscribes to it
+	pub subscribed: bool,
+}
+
+#[derive(Serialize)]
+pub struct PopularFeed {
+	pub title: String,
+	pub url: Url,
+	pub subscribers: usize,
+}
+
+#[derive(Serialize)]
+pub struct Discover {
+	pub topics: Vec<String>,
+	pub catalog: Vec<CatalogFeed>,
+	/// Subscribed to by users who share feeds with this user
+	pub popular: Vec<PopularFeed>,
+}
+
+/// The bundled catalog, from `discover_catalog.opml`: top-level outlines are
+/// topics, the feeds within them belong to that topic
+fn catalog() -> &'static [CatalogFeed] {
+	static CATALOG: OnceLock<Vec<CatalogFeed>> = OnceLock::new();
+	CATALOG.get_or_init(|| {
+		let opml = opml::OPML::from_str(include_str!("discover_catalog.opml"))
+			.expect("bundled catalog is valid");
+		opml.body
+			.outlines
+			.into_iter()
+			.flat_map(|topic| {
+				let name = topic.text.to_lowercase();
+				topic.outlines.into_iter().filter_map(move |feed| {
+					Some(CatalogFeed {
+						topic: name.clone(),
+						title: feed.text,
+						url: normalize_url(&Url::parse(&feed.xml_url?).ok()?).ok()?,
+						site_url: feed.html_url,
+						subscribed: false,
+					})
+				})
+			})
+			.collect()
+	})
+}
+
+/// Suggestions for the user, optionally only those on one topic. Opted-in
+/// users' feeds count towards a topic if they're in a category of that name.
+pub fn discover(app: &App, user: &AppUser, topic: Option<&str>) -> Result<Discover> {
+	let topic = topic.map(str::to_lowercase);
+	let own: BTreeSet<Url> = Feed::get_all(user)?
+		.into_iter()
+		.map(|feed| feed.url)
+		.collect();
+
+	let topics = catalog()
+		.iter()
+		.map(|feed| feed.topic.clone())
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.collect();
+	let catalog = catalog()
+		.iter()
+		.filter(|feed| topic.as_ref().is_none_or(|topic| feed.topic == *topic))
+		.map(|feed| CatalogFeed {
+			subscribed: own.contains(&feed.url),
+			..feed.clone()
+		})
+		.collect();
+
+	// subscriptions of other opted-in users with something in common with this
+	// one, or of all of them for a user without feeds yet
+	let mut counts: BTreeMap<Url, (String, usize)> = BTreeMap::new();
+	for other in User::get_all(app)? {
+		if other.username == user.username {
+			continue;
+		}
+		let other = app.open_user(&other.username)?;
+		if !other.settings.share_subscriptions {
+			continue;
+		}
+
+		let feeds = Feed::get_all(&other)?;
+		if !own.is_empty() && !feeds.iter().any(|feed| own.contains(&feed.url)) {
+			continue;
+		}
+
+		let feeds: BTreeMap<Url, Feed> = feeds
+			.into_iter()
+			.filter(|feed| !own.contains(&feed.url))
+			.filter(|feed| {
+				topic.as_ref().is_none_or(|topic| {
+					feed.category
+						.as_ref()
+						.is_some_and(|category| category.to_lowercase() == *topic)
+				})
+			})
+			.map(|feed| (feed.url.clone(), feed))
+			.collect();
+		for (url, feed) in feeds {
+			let title = feed.meta.title.unwrap_or(feed.name);
+			counts.entry(url).or_insert((title, 0)).1 += 1;
+		}
+	}
+
+	let mut popular: Vec<PopularFeed> = counts
+		.into_iter()
+		.filter(|(_, (_, subscribers))| *subscribers >= MIN_SUBSCRIBERS)
+		.map(|(url, (title, subscribers))| PopularFeed {
+			title,
+			url,
+			subscribers,
+		})
+		.collect();
+	popular.sort_by_key(|feed| std::cmp::Reverse(feed.subscribers));
+	popular.truncate(MAX_POPULAR);
+
+	Ok(Discover {
+		topics,
+		catalog,
+		popular,
+	})
+}
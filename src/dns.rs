@@ -0,0 +1,101 @@
+//! Caching DNS resolution for the HTTP client, so refreshing hundreds of feeds
+//! on a few hosts doesn't look the same names up over and over
+
+use std::{
+	net::{IpAddr, SocketAddr},
+	sync::Arc,
+};
+
+use hickory_resolver::{
+	config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+	TokioAsyncResolver,
+};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+use crate::Result;
+
+/// Where lookups that miss the cache are sent
+#[derive(Clone, Debug)]
+pub enum Upstream {
+	/// The resolvers configured in the operating system
+	System,
+	Cloudflare,
+	Google,
+	Quad9,
+	/// DNS over HTTPS
+	CloudflareHttps,
+	GoogleHttps,
+	Quad9Https,
+	/// Plain DNS servers on port 53
+	Servers(Vec<IpAddr>),
+}
+
+impl std::str::FromStr for Upstream {
+	type Err = std::net::AddrParseError;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		Ok(match s {
+			"system" => Upstream::System,
+			"cloudflare" => Upstream::Cloudflare,
+			"google" => Upstream::Google,
+			"quad9" => Upstream::Quad9,
+			"cloudflare-https" => Upstream::CloudflareHttps,
+			"google-https" => Upstream::GoogleHttps,
+			"quad9-https" => Upstream::Quad9Https,
+			servers => Upstream::Servers(
+				servers
+					.split(',')
+					.map(|ip| ip.trim().parse())
+					.collect::<std::result::Result<_, _>>()?,
+			),
+		})
+	}
+}
+
+pub struct DnsConfig {
+	pub upstream: Upstream,
+	/// Number of lookups kept, each for as long as its record's TTL
+	pub cache_size: usize,
+}
+
+pub struct CachingResolver(Arc<TokioAsyncResolver>);
+
+impl CachingResolver {
+	pub fn new(cfg: &DnsConfig) -> Result<Self> {
+		let (config, mut opts) = match &cfg.upstream {
+			Upstream::System => hickory_resolver::system_conf::read_system_conf()?,
+			Upstream::Cloudflare => (ResolverConfig::cloudflare(), ResolverOpts::default()),
+			Upstream::Google => (ResolverConfig::google(), ResolverOpts::default()),
+			Upstream::Quad9 => (ResolverConfig::quad9(), ResolverOpts::default()),
+			Upstream::CloudflareHttps => {
+				(ResolverConfig::cloudflare_https(), ResolverOpts::default())
+			}
+			Upstream::GoogleHttps => (ResolverConfig::google_https(), ResolverOpts::default()),
+			Upstream::Quad9Https => (ResolverConfig::quad9_https(), ResolverOpts::default()),
+			Upstream::Servers(ips) => (
+				ResolverConfig::from_parts(
+					None,
+					vec![],
+					NameServerConfigGroup::from_ips_clear(ips, 53, true),
+				),
+				ResolverOpts::default(),
+			),
+		};
+		opts.cache_size = cfg.cache_size;
+
+		Ok(Self(Arc::new(TokioAsyncResolver::tokio(config, opts))))
+	}
+}
+
+impl Resolve for CachingResolver {
+	fn resolve(&self, name: Name) -> Resolving {
+		let resolver = self.0.clone();
+		Box::pin(async move {
+			let lookup = resolver.lookup_ip(name.as_str()).await?;
+			// reqwest fills in the port
+			let addrs: Addrs = Box::new(lookup.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+			Ok(addrs)
+		})
+	}
+}
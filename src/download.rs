@@ -0,0 +1,197 @@
+//! Hands enclosures of new articles to a downloader, e.g. to fetch podcast
+//! episodes or add torrents from a torrent RSS feed
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	db::{ArticleId, Feed},
+	Error, Result,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloaderKind {
+	/// POSTs each enclosure as JSON
+	Webhook,
+	/// Adds each enclosure through the qBittorrent Web API, `url` being the
+	/// Web UI address
+	Qbittorrent,
+	/// Adds each enclosure through the Transmission RPC, `url` being the RPC
+	/// endpoint, usually `/transmission/rpc`
+	Transmission,
+}
+
+/// Where a feed's new enclosures are sent
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Downloader {
+	pub kind: DownloaderKind,
+	pub url: Url,
+	pub username: Option<String>,
+	pub password: Option<String>,
+	/// qBittorrent category torrents are added to
+	pub category: Option<String>,
+	/// Only enclosures whose MIME type starts with one of these are sent;
+	/// all of them if empty
+	#[serde(default)]
+	pub mime_types: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Enclosure {
+	pub url: String,
+	pub mime_type: Option<String>,
+	/// In bytes, as claimed by the feed
+	pub size: Option<u64>,
+	pub feed_id: u64,
+	pub feed_name: String,
+	pub article_id: ArticleId,
+	pub article_title: String,
+}
+
+/// Collects the enclosures of an entry: RSS `<enclosure>`s and MediaRSS
+/// content, which feed_rs both reports as media, and Atom enclosure links
+pub fn entry_enclosures(
+	entry: &feed_rs::model::Entry,
+) -> Vec<(String, Option<String>, Option<u64>)> {
+	let media = entry
+		.media
+		.iter()
+		.flat_map(|media| &media.content)
+		.filter_map(|content| {
+			let url = content.url.as_ref()?;
+			Some((
+				url.to_string(),
+				content.content_type.as_ref().map(|mime| mime.to_string()),
+				content.size,
+			))
+		});
+	let links = entry
+		.links
+		.iter()
+		.filter(|link| link.rel.as_deref() == Some("enclosure"))
+		.map(|link| (link.href.clone(), link.media_type.clone(), link.length));
+
+	let mut enclosures: Vec<(String, Option<String>, Option<u64>)> = vec![];
+	for enclosure in media.chain(links) {
+		if !enclosures.iter().any(|(url, _, _)| *url == enclosure.0) {
+			enclosures.push(enclosure);
+		}
+	}
+	enclosures
+}
+
+impl Downloader {
+	fn accepts(&self, enclosure: &Enclosure) -> bool {
+		self.mime_types.is_empty()
+			|| enclosure.mime_type.as_deref().is_some_and(|mime| {
+				self.mime_types
+					.iter()
+					.any(|accepted| mime.starts_with(accepted.as_str()))
+			})
+	}
+
+	async fn send(&self, client: &reqwest::Client, enclosure: &Enclosure) -> Result<()> {
+		match self.kind {
+			DownloaderKind::Webhook => {
+				let request = client.post(self.url.clone()).json(enclosure);
+				self.authenticate(request)
+					.send()
+					.await?
+					.error_for_status()?;
+			}
+			DownloaderKind::Qbittorrent => {
+				let login = client
+					.post(self.url.join("api/v2/auth/login")?)
+					.form(&[
+						("username", self.username.as_deref().unwrap_or_default()),
+						("password", self.password.as_deref().unwrap_or_default()),
+					])
+					.send()
+					.await?
+					.error_for_status()?;
+				let cookie = login
+					.headers()
+					.get(reqwest::header::SET_COOKIE)
+					.and_then(|cookie| cookie.to_str().ok())
+					.and_then(|cookie| cookie.split(';').next())
+					.map(str::to_owned)
+					.ok_or_else(|| Error::Download("qBittorrent login failed".into()))?;
+
+				let mut form = reqwest::multipart::Form::new().text("urls", enclosure.url.clone());
+				if let Some(category) = &self.category {
+					form = form.text("category", category.clone());
+				}
+				client
+					.post(self.url.join("api/v2/torrents/add")?)
+					.header(reqwest::header::COOKIE, cookie)
+					.multipart(form)
+					.send()
+					.await?
+					.error_for_status()?;
+			}
+			DownloaderKind::Transmission => {
+				let body = serde_json::json!({
+					"method": "torrent-add",
+					"arguments": { "filename": enclosure.url },
+				});
+				let response = self
+					.authenticate(client.post(self.url.clone()).json(&body))
+					.send()
+					.await?;
+
+				// the first request only tells the session id to use, as CSRF protection
+				let response = match response.headers().get("X-Transmission-Session-Id") {
+					Some(session_id) if response.status() == reqwest::StatusCode::CONFLICT => {
+						self.authenticate(client.post(self.url.clone()).json(&body))
+							.header("X-Transmission-Session-Id", session_id.clone())
+							.send()
+							.await?
+					}
+					_ => response,
+				};
+
+				let result: serde_json::Value = response.error_for_status()?.json().await?;
+				match result["result"].as_str() {
+					Some("success") => {}
+					other => {
+						return Err(Error::Download(format!(
+							"transmission answered {}",
+							other.unwrap_or("nothing")
+						)))
+					}
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn authenticate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+		match &self.username {
+			Some(username) => request.basic_auth(username, self.password.as_ref()),
+			None => request,
+		}
+	}
+}
+
+/// Sends the enclosures to the feed's downloader. Failures are only logged,
+/// they should not fail the refresh.
+pub async fn send_enclosures(app: &AppUser, feed: &Feed, enclosures: Vec<Enclosure>) {
+	let Some(downloader) = &feed.downloader
+	else {
+		return;
+	};
+
+	for enclosure in enclosures.iter().filter(|e| downloader.accepts(e)) {
+		if let Err(e) = downloader.send(&app.client, enclosure).await {
+			log::warn!(
+				"could not hand {} of feed {} to its downloader: {}",
+				enclosure.url,
+				feed.id,
+				e
+			);
+		}
+	}
+}
@@ -1,5 +1,6 @@
-use axum::http::StatusCode;
+use axum::http::{header, StatusCode};
 use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -18,6 +19,42 @@ pub enum Error {
 	#[error("password incorrect")]
 	PasswordIncorrect,
 
+	#[error("insufficient permissions")]
+	Forbidden,
+
+	#[error("this instance is a read-only replica")]
+	ReadOnly,
+
+	#[error("quota of {0} exceeded")]
+	QuotaExceeded(&'static str),
+
+	#[error(
+		"{0} is blocked on this instance{}",
+		.1.as_ref().map(|reason| format!(": {}", reason)).unwrap_or_default()
+	)]
+	FeedBlocked(String, Option<String>),
+
+	#[error("already subscribed to {0}")]
+	FeedExists(String),
+
+	#[error("usernames must be 1 to 64 characters, without slashes or colons")]
+	InvalidUsername,
+
+	#[error("invite is invalid or used up")]
+	InvalidInvite,
+
+	#[error("too many attempts, try again later")]
+	RateLimited,
+
+	#[error("invalid article id")]
+	InvalidArticleId,
+
+	#[error("invalid search query: {0}")]
+	InvalidQuery(String),
+
+	#[error("invalid tag: {0}")]
+	InvalidTag(String),
+
 	#[error("{0} was not found")]
 	NotFound(String),
 
@@ -27,6 +64,9 @@ pub enum Error {
 	#[error("database error: {0}")]
 	Sled(#[from] sled::Error),
 
+	#[error("search index error: {0}")]
+	Search(#[from] tantivy::TantivyError),
+
 	#[error("serialization error: {0}")]
 	Encode(#[from] bincode::Error),
 
@@ -36,6 +76,10 @@ pub enum Error {
 	#[error("error while parsing feed: {0}")]
 	FeedRS(#[from] feed_rs::parser::ParseFeedError),
 
+	/// Malformed XML, at a line and column of the body
+	#[error("error while parsing feed: {0}, at line {1}, column {2}")]
+	FeedXml(feed_rs::parser::ParseFeedError, usize, usize),
+
 	#[error("error while parsing base64 string: {0}")]
 	Base64(#[from] base64::DecodeError),
 
@@ -51,8 +95,235 @@ pub enum Error {
 	#[error("string not utf8: {0}")]
 	Utf8(#[from] std::string::FromUtf8Error),
 
+	#[error("invalid css selector: {0}")]
+	Selector(String),
+
+	#[error("unknown field: {0}")]
+	UnknownField(String),
+
+	#[error("{0} must not be empty")]
+	EmptyField(&'static str),
+
+	#[error("invalid template: {0}")]
+	Template(String),
+
+	#[error("{0} is larger than the limit of {1} bytes")]
+	TooLarge(String, usize),
+
+	#[error("{0} kept timing out, skipped for the rest of the refresh")]
+	CircuitOpen(String),
+
+	#[error("downloader error: {0}")]
+	Download(String),
+
+	#[error("sync error: {0}")]
+	Sync(String),
+
+	#[error("email error: {0}")]
+	Email(String),
+
+	#[error("invalid email address: {0}")]
+	InvalidAddress(String),
+
+	#[error("encryption error: {0}")]
+	Encryption(String),
+
+	#[error("database format error: {0}")]
+	Schema(String),
+
+	#[error("telegram error: {0}")]
+	Telegram(String),
+
+	#[error("gemini error: {0}")]
+	Gemini(String),
+
+	#[error("ldap error: {0}")]
+	Ldap(String),
+
+	#[error("tls error: {0}")]
+	Tls(String),
+
+	#[error("dns resolver error: {0}")]
+	Resolve(#[from] hickory_resolver::error::ResolveError),
+
 	#[error("transaction error: {0}")]
 	Transaction(#[from] sled::transaction::TransactionError),
+
+	/// An error shared between all waiters of a coalesced operation
+	#[error("{0}")]
+	Shared(std::sync::Arc<Error>),
+}
+
+impl Error {
+	pub fn status(&self) -> StatusCode {
+		match self {
+			Error::UsernameTaken
+			| Error::FeedExists(_)
+			| Error::InvalidArticleId
+			| Error::InvalidQuery(_)
+			| Error::InvalidTag(_)
+			| Error::InvalidAddress(_)
+			| Error::Selector(_)
+			| Error::UnknownField(_)
+			| Error::Template(_)
+			| Error::EmptyField(_)
+			| Error::InvalidUsername
+			| Error::InvalidInvite => StatusCode::BAD_REQUEST,
+			Error::UsernameNotFound | Error::PasswordIncorrect => StatusCode::UNAUTHORIZED,
+			Error::Forbidden
+			| Error::ReadOnly
+			| Error::QuotaExceeded(_)
+			| Error::FeedBlocked(..) => StatusCode::FORBIDDEN,
+			Error::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+			Error::NotFound(_) => StatusCode::NOT_FOUND,
+			Error::Shared(e) => e.status(),
+			_ => StatusCode::INTERNAL_SERVER_ERROR,
+		}
+	}
+
+	/// Whether the same operation may succeed when retried a bit later
+	pub fn is_transient(&self) -> bool {
+		match self {
+			Error::Reqwest(e) => {
+				e.is_timeout()
+					|| e.is_connect()
+					|| e.is_body() || e.status().is_some_and(|status| {
+					status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+				})
+			}
+			Error::Io(_) => true,
+			Error::Shared(e) => e.is_transient(),
+			_ => false,
+		}
+	}
+
+	/// Whether the operation gave up waiting on the other end
+	pub fn is_timeout(&self) -> bool {
+		match self {
+			Error::Reqwest(e) => e.is_timeout(),
+			Error::Shared(e) => e.is_timeout(),
+			_ => false,
+		}
+	}
+
+	/// Stable, machine-readable error code for structured error responses
+	pub fn code(&self) -> &'static str {
+		match self {
+			Error::NoRootDir => "no_root_dir",
+			Error::UsernameTaken => "username_taken",
+			// don't reveal which of the two was wrong
+			Error::UsernameNotFound | Error::PasswordIncorrect => "unauthorized",
+			Error::Forbidden => "forbidden",
+			Error::ReadOnly => "read_only",
+			Error::QuotaExceeded(_) => "quota_exceeded",
+			Error::FeedBlocked(..) => "feed_blocked",
+			Error::FeedExists(_) => "feed_exists",
+			Error::InvalidUsername => "invalid_username",
+			Error::InvalidInvite => "invalid_invite",
+			Error::RateLimited => "rate_limited",
+			Error::InvalidArticleId => "invalid_article_id",
+			Error::InvalidQuery(_) => "invalid_query",
+			Error::InvalidTag(_) => "invalid_tag",
+			Error::InvalidAddress(_) => "invalid_address",
+			Error::NotFound(_) => "not_found",
+			Error::Reqwest(_) => "http_client",
+			Error::FeedRS(_) | Error::FeedXml(..) => "feed_parse",
+			Error::Opml(_) => "opml",
+			Error::Url(_) => "invalid_url",
+			Error::Selector(_) => "invalid_selector",
+			Error::UnknownField(_) => "unknown_field",
+			Error::Template(_) => "invalid_template",
+			Error::EmptyField(_) => "empty_field",
+			Error::Shared(e) => e.code(),
+			_ => "internal",
+		}
+	}
+}
+
+/// Class of a failed fetch, for grouping and reacting to feed errors
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchErrorKind {
+	/// The server responded with an error status
+	Http,
+	Timeout,
+	/// The host could not be resolved or connected to
+	Connect,
+	/// The TLS handshake failed, e.g. over an invalid certificate
+	Tls,
+	/// The response isn't a feed, or a malformed one
+	Parse,
+	TooLarge,
+	Other,
+}
+
+/// Why a feed's last fetch failed, as stored on the feed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchError {
+	pub kind: FetchErrorKind,
+	/// For http errors
+	pub status: Option<u16>,
+	/// For parse errors, where the parser reports it
+	pub line: Option<usize>,
+	pub column: Option<usize>,
+	pub message: String,
+}
+
+/// Whether the TLS layer failed somewhere down the error's sources. Which TLS
+/// backend fails depends on the client, so they are told apart by message; the
+/// error itself is skipped, as its message has the url.
+fn is_tls(e: &(dyn std::error::Error + 'static)) -> bool {
+	let mut source = e.source();
+	while let Some(e) = source {
+		let message = e.to_string().to_lowercase();
+		if ["tls", "ssl", "certificate", "handshake"]
+			.iter()
+			.any(|word| message.contains(word))
+		{
+			return true;
+		}
+		source = e.source();
+	}
+	false
+}
+
+impl FetchError {
+	pub fn new(e: &Error) -> Self {
+		let mut error = FetchError {
+			kind: FetchErrorKind::Other,
+			status: None,
+			line: None,
+			column: None,
+			message: e.to_string(),
+		};
+		error.kind = match e {
+			Error::Shared(e) => return Self::new(e),
+			Error::Reqwest(e) if e.is_timeout() => FetchErrorKind::Timeout,
+			Error::Reqwest(e) if e.status().is_some() => {
+				error.status = e.status().map(|status| status.as_u16());
+				FetchErrorKind::Http
+			}
+			Error::Reqwest(e) if is_tls(e) => FetchErrorKind::Tls,
+			Error::Reqwest(e) if e.is_connect() => FetchErrorKind::Connect,
+			Error::Tls(_) => FetchErrorKind::Tls,
+			Error::Resolve(_) => FetchErrorKind::Connect,
+			Error::CircuitOpen(_) => FetchErrorKind::Timeout,
+			Error::TooLarge(..) => FetchErrorKind::TooLarge,
+			Error::FeedRS(feed_rs::parser::ParseFeedError::JsonSerde(e)) => {
+				error.line = Some(e.line());
+				error.column = Some(e.column());
+				FetchErrorKind::Parse
+			}
+			Error::FeedXml(_, line, column) => {
+				error.line = Some(*line);
+				error.column = Some(*column);
+				FetchErrorKind::Parse
+			}
+			Error::FeedRS(_) | Error::Utf8(_) => FetchErrorKind::Parse,
+			_ => FetchErrorKind::Other,
+		};
+		error
+	}
 }
 
 impl IntoResponse for Error {
@@ -61,9 +332,36 @@ impl IntoResponse for Error {
 			Error::UsernameTaken => {
 				(StatusCode::BAD_REQUEST, "Username already taken").into_response()
 			}
-			Error::UsernameNotFound | Error::PasswordIncorrect => {
-				(StatusCode::UNAUTHORIZED, "Username or password incorrect").into_response()
+			Error::Forbidden => (StatusCode::FORBIDDEN, "Forbidden").into_response(),
+			Error::ReadOnly | Error::QuotaExceeded(_) | Error::FeedBlocked(..) => {
+				(StatusCode::FORBIDDEN, format!("{}", self)).into_response()
+			}
+			Error::InvalidUsername | Error::InvalidInvite | Error::FeedExists(_) => {
+				(StatusCode::BAD_REQUEST, format!("{}", self)).into_response()
+			}
+			Error::RateLimited => {
+				(StatusCode::TOO_MANY_REQUESTS, format!("{}", self)).into_response()
+			}
+			Error::NotFound(_) => (StatusCode::NOT_FOUND, format!("{}", self)).into_response(),
+			Error::InvalidArticleId => {
+				(StatusCode::BAD_REQUEST, "Invalid article id").into_response()
 			}
+			Error::Selector(_)
+			| Error::InvalidQuery(_)
+			| Error::InvalidTag(_)
+			| Error::UnknownField(_)
+			| Error::Template(_)
+			| Error::EmptyField(_) => (StatusCode::BAD_REQUEST, format!("{}", self)).into_response(),
+			Error::UsernameNotFound | Error::PasswordIncorrect => (
+				StatusCode::UNAUTHORIZED,
+				[(
+					header::WWW_AUTHENTICATE,
+					r#"Basic realm="NanoRSS", charset="UTF-8""#,
+				)],
+				"Username or password incorrect",
+			)
+				.into_response(),
+			Error::Shared(e) => (e.status(), format!("{}", e)).into_response(),
 			_ => (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", self)).into_response(),
 		}
 	}
@@ -1,81 +1,1241 @@
-use chrono::Utc;
-use futures::stream::TryStreamExt;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
+use futures::{
+	future::{BoxFuture, FutureExt, Shared},
+	stream::{StreamExt, TryStreamExt},
+};
+use reqwest::header::{self, HeaderMap};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[cfg(feature = "gemini")]
+use crate::gemini;
 use crate::{
 	app::AppUser,
-	db::{Article, Feed},
-	err::Result,
-	Error,
+	db::{Article, ArticleId, ContentMode, Feed, FeedMeta},
+	download::{self, Enclosure},
+	err::{FetchError, Result},
+	history::{FeedError, RefreshReport},
+	metrics, notify,
+	quirks::SiteQuirk,
+	readability,
+	scrape::Scraped,
+	source::SourceRequest,
+	watch, Error,
 };
 
-// TODO: implement scraper
-pub async fn fetch_feed(app: &AppUser, feed: &Feed) -> Result<()> {
-	let response = app
-		.client
-		.get(feed.url.clone())
-		.send()
-		.await?
-		.error_for_status()?
-		.bytes()
-		.await?;
+/// Reads the syndication module's `sy:updatePeriod`/`sy:updateFrequency`, which
+/// feed_rs does not expose, and converts them to minutes
+fn sy_update_period(body: &[u8]) -> Option<u32> {
+	let body = String::from_utf8_lossy(body);
+	let element = |name: &str| {
+		let start = body.find(&format!("{}>", name))? + name.len() + 1;
+		let end = start + body[start..].find('<')?;
+		Some(body[start..end].trim().to_owned())
+	};
+
+	let period = match element("updatePeriod")?.as_str() {
+		"hourly" => 60,
+		"daily" => 60 * 24,
+		"weekly" => 60 * 24 * 7,
+		"monthly" => 60 * 24 * 30,
+		"yearly" => 60 * 24 * 365,
+		_ => return None,
+	};
+	let frequency = element("updateFrequency")
+		.and_then(|f| f.parse::<u32>().ok())
+		.filter(|f| *f > 0)
+		.unwrap_or(1);
+
+	Some(period / frequency)
+}
+
+/// Caps protecting the instance from pathological feeds, in bytes
+#[derive(Clone, Copy, Debug)]
+pub struct SizeLimits {
+	/// Feed downloads are aborted past this
+	pub max_feed_size: usize,
+	/// Page downloads, of scraped articles, watched pages and the like, are
+	/// aborted past this
+	pub max_page_size: usize,
+	/// Article content is truncated to this
+	pub max_content_size: usize,
+}
+
+/// Appended to content that was cut off at the size limit
+const TRUNCATED_MARKER: &str = "<p>[content truncated]</p>";
+
+/// Cuts content off at `max` bytes, on a character boundary, marking it as such
+fn truncate_content(content: &mut String, max: usize) {
+	if content.len() <= max {
+		return;
+	}
+	let mut end = max;
+	while !content.is_char_boundary(end) {
+		end -= 1;
+	}
+	content.truncate(end);
+	content.push_str(TRUNCATED_MARKER);
+}
+
+/// The `ETag` and `Last-Modified` of a fetched resource, sent back as
+/// `If-None-Match` and `If-Modified-Since` when fetching it again, so unchanged
+/// feeds are answered with an empty 304
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+pub struct HttpValidators {
+	pub etag: Option<String>,
+	pub last_modified: Option<String>,
+}
+
+impl HttpValidators {
+	fn from_headers(headers: &HeaderMap) -> Self {
+		let header = |name| {
+			headers
+				.get(name)
+				.and_then(|value| value.to_str().ok())
+				.map(str::to_owned)
+		};
+		Self {
+			etag: header(header::ETAG),
+			last_modified: header(header::LAST_MODIFIED),
+		}
+	}
+
+	fn is_empty(&self) -> bool {
+		self.etag.is_none() && self.last_modified.is_none()
+	}
+
+	/// Whether both were sent along the same version of a resource
+	fn matches(&self, other: &HttpValidators) -> bool {
+		!self.is_empty() && self == other
+	}
+
+	fn apply(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+		if let Some(etag) = &self.etag {
+			request = request.header(header::IF_NONE_MATCH, etag);
+		}
+		if let Some(last_modified) = &self.last_modified {
+			request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+		}
+		request
+	}
+}
+
+/// SHA-256 fingerprints of the certificates gemini hosts presented, by host
+pub type CertPins = BTreeMap<String, String>;
+
+/// Redirects followed per request, as reqwest does by default
+const MAX_REDIRECTS: usize = 10;
+
+tokio::task_local! {
+	/// Whether every redirect the request in scope followed was permanent
+	static PERMANENT_REDIRECTS: Cell<bool>;
+}
+
+/// Redirect policy of the app's clients: reqwest's default one, that also
+/// notes for [`fetch_resource`] whether the redirects followed were permanent.
+/// The policy runs in the task awaiting the response, so it sees the task local.
+pub fn redirect_policy() -> reqwest::redirect::Policy {
+	reqwest::redirect::Policy::custom(|attempt| {
+		if !matches!(
+			attempt.status(),
+			reqwest::StatusCode::MOVED_PERMANENTLY | reqwest::StatusCode::PERMANENT_REDIRECT
+		) {
+			// outside of fetch_resource nobody asks
+			let _ = PERMANENT_REDIRECTS.try_with(|permanent| permanent.set(false));
+		}
+		if attempt.previous().len() >= MAX_REDIRECTS {
+			attempt.error("too many redirects")
+		}
+		else {
+			attempt.follow()
+		}
+	})
+}
+
+// only gemfeeds need the mime type
+#[cfg_attr(not(feature = "gemini"), allow(dead_code))]
+struct Resource {
+	/// After following redirects
+	url: Url,
+	/// Whether `url` differs from the one requested
+	redirected: bool,
+	/// Whether every redirect followed was permanent, so `url` can replace the
+	/// one requested
+	permanent: bool,
+	mime: Option<String>,
+	body: Vec<u8>,
+	validators: HttpValidators,
+	/// Whether the server answered 304 to the validators sent, leaving `body`
+	/// empty
+	not_modified: bool,
+}
+
+/// Fetches the resource at `url`, dispatching on its scheme. HTTP requests get
+/// the workarounds of the [site quirks registry](SiteQuirk), and are made
+/// conditional on `validators` if given. Gemini hosts are checked against the
+/// `pins` of the feed fetched for, if any.
+/// Bodies larger than `max_size` are aborted, failing the fetch.
+#[cfg_attr(not(feature = "gemini"), allow(unused_variables))]
+async fn fetch_resource(
+	client: &reqwest::Client,
+	url: &Url,
+	request: Option<&SourceRequest>,
+	validators: Option<&HttpValidators>,
+	pins: Option<&mut CertPins>,
+	max_size: usize,
+) -> Result<Resource> {
+	match url.scheme() {
+		#[cfg(feature = "gemini")]
+		"gemini" => {
+			if request.is_some() {
+				return Err(Error::Gemini("requests can't be customized".into()));
+			}
+
+			// without a feed there's nothing to pin to, so this is a first use
+			let mut unpinned = CertPins::new();
+			let response = gemini::fetch(url, pins.unwrap_or(&mut unpinned), max_size).await?;
+			Ok(Resource {
+				redirected: response.url != *url,
+				permanent: response.permanent,
+				url: response.url,
+				mime: Some(response.mime),
+				body: response.body,
+				// gemini has no conditional requests
+				validators: HttpValidators::default(),
+				not_modified: false,
+			})
+		}
+		_ => {
+			let quirk = SiteQuirk::for_url(url);
+			let url = match quirk {
+				Some(quirk) => quirk.apply_url(url)?,
+				None => url.clone(),
+			};
+			let request = match request {
+				Some(request) => request.build(client, &url),
+				None => client.get(url),
+			};
+			let request = match validators {
+				Some(validators) => validators.apply(request),
+				None => request,
+			};
+			let request = match quirk {
+				Some(quirk) => quirk.apply_headers(request),
+				None => request,
+			}
+			.build()?;
+			// compared to what was requested, so quirks aren't mistaken for redirects
+			let requested = request.url().clone();
+			let (response, permanent) = PERMANENT_REDIRECTS
+				.scope(Cell::new(true), async {
+					let response = client.execute(request).await;
+					(response, PERMANENT_REDIRECTS.with(Cell::get))
+				})
+				.await;
+			let mut response = response?.error_for_status()?;
+			let mime = response
+				.headers()
+				.get(reqwest::header::CONTENT_TYPE)
+				.and_then(|value| value.to_str().ok())
+				.map(str::to_owned);
+			let url = response.url().clone();
+			let validators = HttpValidators::from_headers(response.headers());
+			if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+				return Ok(Resource {
+					redirected: url != requested,
+					permanent,
+					url,
+					mime,
+					body: vec![],
+					validators,
+					not_modified: true,
+				});
+			}
+
+			// the length is only a hint, the body is checked as it comes in
+			let too_large = || Error::TooLarge(url.to_string(), max_size);
+			if response
+				.content_length()
+				.is_some_and(|length| length > max_size as u64)
+			{
+				return Err(too_large());
+			}
+			let mut body = vec![];
+			while let Some(chunk) = response.chunk().await? {
+				if body.len() + chunk.len() > max_size {
+					return Err(too_large());
+				}
+				body.extend_from_slice(&chunk);
+			}
+
+			Ok(Resource {
+				redirected: url != requested,
+				permanent,
+				url,
+				mime,
+				body,
+				validators,
+				not_modified: false,
+			})
+		}
+	}
+}
+
+/// Fetches a page, for the feed with the `pins` if any, see [`fetch_resource`]
+pub async fn fetch_page(
+	client: &reqwest::Client,
+	url: &str,
+	pins: Option<&mut CertPins>,
+	max_size: usize,
+) -> Result<String> {
+	let resource = fetch_resource(client, &Url::parse(url)?, None, None, pins, max_size).await?;
+	Ok(decode_page(resource.mime.as_deref(), &resource.body))
+}
+
+/// Decodes a page by the charset its `Content-Type` names, or its byte order
+/// mark, UTF-8 otherwise. Malformed sequences are replaced, pages are only
+/// ever read for their text.
+fn decode_page(mime: Option<&str>, body: &[u8]) -> String {
+	let encoding = mime
+		.into_iter()
+		.flat_map(|mime| mime.split(';').skip(1))
+		.filter_map(|param| param.split_once('='))
+		.find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+		.and_then(|(_, charset)| {
+			encoding_rs::Encoding::for_label(charset.trim().trim_matches('"').as_bytes())
+		})
+		.unwrap_or(encoding_rs::UTF_8);
+	encoding.decode(body).0.into_owned()
+}
+
+/// Naive main content extraction, for pages readability finds no article on:
+/// the first `<article>` element, or else `<body>`
+fn extract_main_content(page: &str) -> &str {
+	let lower = page.to_ascii_lowercase();
+	let element = |name: &str| {
+		let open = lower.find(&format!("<{}", name))?;
+		let start = open + lower[open..].find('>')? + 1;
+		let end = start + lower[start..].find(&format!("</{}", name))?;
+		Some(&page[start..end])
+	};
+
+	element("article")
+		.or_else(|| element("body"))
+		.unwrap_or(page)
+}
+
+/// A fetched and parsed feed, as shared between users through the [`FetchCache`]
+#[derive(Clone)]
+pub struct ParsedFeed {
+	pub feed: feed_rs::model::Feed,
+	pub update_period: Option<u32>,
+	/// Where the feed was found, if redirected
+	pub redirected_to: Option<Url>,
+	/// Whether every redirect there was permanent
+	pub redirect_permanent: bool,
+	/// Size of the fetched body, in bytes
+	pub size: usize,
+	pub validators: HttpValidators,
+}
+
+/// Instance-wide cache of parsed feeds, so a feed several users subscribe to is
+/// only fetched and parsed once per TTL
+#[derive(Clone)]
+pub struct FetchCache {
+	ttl: Duration,
+	max_size: usize,
+	entries: Arc<std::sync::Mutex<HashMap<Url, CacheEntry>>>,
+}
+
+type CacheEntry = Arc<tokio::sync::Mutex<Option<CachedFeed>>>;
+
+struct CachedFeed {
+	fetched: Instant,
+	parsed: ParsedFeed,
+}
+
+impl FetchCache {
+	pub fn new(ttl: Duration, max_size: usize) -> Self {
+		Self {
+			ttl,
+			max_size,
+			entries: Default::default(),
+		}
+	}
+
+	/// Returns the cached feed if still fresh, fetching it otherwise
+	pub async fn get(&self, client: &reqwest::Client, url: &Url) -> Result<ParsedFeed> {
+		let parsed = self
+			.get_modified(client, url, &HttpValidators::default())
+			.await?;
+		Ok(parsed.expect("unconditional fetches are always modified"))
+	}
+
+	/// Like [`get`](Self::get), but None if the feed is still the version
+	/// `validators` were sent along with, whether cached or answered with a 304.
+	/// Concurrent calls for the same url wait for the in-flight fetch instead of
+	/// repeating it.
+	pub async fn get_modified(
+		&self,
+		client: &reqwest::Client,
+		url: &Url,
+		validators: &HttpValidators,
+	) -> Result<Option<ParsedFeed>> {
+		let entry = {
+			let mut entries = self.entries.lock().unwrap();
+
+			// drop stale entries nobody is waiting on
+			let ttl = self.ttl;
+			entries.retain(|_, entry| match entry.try_lock() {
+				Ok(cached) => cached.as_ref().is_some_and(|c| c.fetched.elapsed() < ttl),
+				Err(_) => true,
+			});
+
+			entries.entry(url.clone()).or_default().clone()
+		};
+
+		let mut cached = entry.lock().await;
+		if let Some(cached) = cached.as_ref().filter(|c| c.fetched.elapsed() < self.ttl) {
+			return Ok(
+				Some(cached.parsed.clone()).filter(|parsed| !parsed.validators.matches(validators))
+			);
+		}
+
+		// a 304 only says the caller's version is current, so there is nothing to
+		// cache for other subscribers
+		let Some(parsed) =
+			fetch_parsed(client, url, None, Some(validators), None, self.max_size).await?
+		else {
+			return Ok(None);
+		};
+		*cached = Some(CachedFeed {
+			fetched: Instant::now(),
+			parsed: parsed.clone(),
+		});
+
+		Ok(Some(parsed))
+	}
+}
+
+/// Fetches and parses a feed, None if it was answered with a 304
+async fn fetch_parsed(
+	client: &reqwest::Client,
+	url: &Url,
+	request: Option<&SourceRequest>,
+	validators: Option<&HttpValidators>,
+	pins: Option<&mut CertPins>,
+	max_size: usize,
+) -> Result<Option<ParsedFeed>> {
+	let resource = fetch_resource(client, url, request, validators, pins, max_size).await?;
+	if resource.not_modified {
+		return Ok(None);
+	}
+
+	// gemlogs commonly publish gemfeeds rather than Atom
+	#[cfg(feature = "gemini")]
+	let response = match resource.mime {
+		Some(mime) if mime.starts_with("text/gemini") => {
+			gemini::gemfeed_to_atom(&resource.url, &String::from_utf8_lossy(&resource.body))?
+		}
+		_ => resource.body,
+	};
+	#[cfg(not(feature = "gemini"))]
+	let response = resource.body;
 
 	// NOTE: this might appear redundant, but Rust couldn't figure out the types otherwise
 	let response_byteslice: &[u8] = &response;
-	let parsed = feed_rs::parser::parse_with_uri(response_byteslice, Some(feed.url.as_str()))?;
+	let feed = parse_feed(url, response_byteslice)?;
+
+	Ok(Some(ParsedFeed {
+		update_period: feed.ttl.or_else(|| sy_update_period(response_byteslice)),
+		feed,
+		redirect_permanent: resource.redirected && resource.permanent,
+		redirected_to: resource.redirected.then_some(resource.url),
+		size: response_byteslice.len(),
+		validators: resource.validators,
+	}))
+}
+
+/// Parses a feed, locating malformed XML in the body
+fn parse_feed(url: &Url, body: &[u8]) -> Result<feed_rs::model::Feed> {
+	feed_rs::parser::Builder::new()
+		.base_uri(Some(url.as_str()))
+		.build()
+		.parse(body)
+		.map_err(|e| match e {
+			feed_rs::parser::ParseFeedError::XmlReader(_) => {
+				let ended_early = e.to_string().contains("Unexpected EOF");
+				match xml_error_position(body, ended_early) {
+					Some((line, column)) => Error::FeedXml(e, line, column),
+					None => e.into(),
+				}
+			}
+			e => e.into(),
+		})
+}
+
+/// Line and column, from 1, where reading the body as XML fails, if it does.
+/// feed-rs doesn't report where, so it's read again the way feed-rs reads it;
+/// a document that `ended_early` fails at its end.
+fn xml_error_position(body: &[u8], ended_early: bool) -> Option<(usize, usize)> {
+	let mut reader = quick_xml::NsReader::from_reader(body);
+	reader
+		.expand_empty_elements(true)
+		.trim_markup_names_in_closing_tags(true)
+		.trim_text(false);
+	let mut buf = vec![];
+	let offset = loop {
+		match reader.read_event_into(&mut buf) {
+			Ok(quick_xml::events::Event::Eof) if ended_early => break body.len(),
+			Ok(quick_xml::events::Event::Eof) => return None,
+			Ok(_) => buf.clear(),
+			Err(_) => break reader.buffer_position().min(body.len()),
+		}
+	};
+
+	let before = &body[..offset];
+	let line_start = before
+		.iter()
+		.rposition(|byte| *byte == b'\n')
+		.map_or(0, |newline| newline + 1);
+	let line = before.iter().filter(|byte| **byte == b'\n').count() + 1;
+	let column = String::from_utf8_lossy(&before[line_start..]).chars().count() + 1;
+	Some((line, column))
+}
+
+/// Outcome of fetching a feed
+pub struct Fetched {
+	/// Articles not seen before
+	pub new_articles: Vec<ArticleId>,
+	/// Size of what was fetched, or served from the fetch cache
+	pub bytes: usize,
+}
+
+impl Fetched {
+	pub fn nothing_new(bytes: usize) -> Self {
+		Self {
+			new_articles: vec![],
+			bytes,
+		}
+	}
+}
+
+/// Fetches a feed requested its own way, authorizing the request if the feed
+/// uses OAuth, or checking the certificates pinned for it. None if unchanged
+/// since the last fetch.
+async fn fetch_own(app: &AppUser, feed: &mut Feed) -> Result<Option<ParsedFeed>> {
+	if feed.accept_invalid_certs {
+		feed.cert_pins.clear();
+	}
+	if let Some(oauth) = feed
+		.request
+		.as_mut()
+		.and_then(|request| request.oauth.as_mut())
+	{
+		oauth.access_token(&app.client).await?;
+	}
+	let client = app.client_for(feed);
+	let max_size = app.limits.max_feed_size;
+	let validators = Some(&feed.meta.validators);
+	let result = fetch_parsed(
+		client,
+		&feed.url,
+		feed.request.as_ref(),
+		validators,
+		Some(&mut feed.cert_pins),
+		max_size,
+	)
+	.await;
+
+	let oauth = feed
+		.request
+		.as_mut()
+		.and_then(|request| request.oauth.as_mut());
+	match (result, oauth) {
+		(Err(Error::Reqwest(e)), Some(oauth))
+			if e.status() == Some(reqwest::StatusCode::UNAUTHORIZED) =>
+		{
+			// the token may have been revoked before it expired
+			oauth.invalidate();
+			oauth.access_token(&app.client).await?;
+			let validators = Some(&feed.meta.validators);
+			fetch_parsed(
+				client,
+				&feed.url,
+				feed.request.as_ref(),
+				validators,
+				Some(&mut feed.cert_pins),
+				max_size,
+			)
+			.await
+		}
+		(result, _) => result,
+	}
+}
+
+/// Fetches a feed and stores its articles
+pub async fn fetch_feed(app: &AppUser, feed: &mut Feed) -> Result<Fetched> {
+	if let Some(watch) = feed.watch.clone() {
+		return watch::fetch_watched(app, feed, &watch).await;
+	}
+
+	let parsed = if feed.accept_invalid_certs
+		|| feed.request.is_some()
+		|| feed.url.scheme() == "gemini"
+	{
+		// not shared: other subscribers of the url may validate certificates,
+		// have pinned others, or request it differently
+		fetch_own(app, feed).await?
+	}
+	else {
+		app.fetch_cache
+			.get_modified(&app.client, &feed.url, &feed.meta.validators)
+			.await?
+	};
+	// unchanged since the last fetch, its articles are stored already
+	let Some(ParsedFeed {
+		feed: parsed,
+		update_period,
+		redirected_to,
+		redirect_permanent,
+		size,
+		validators,
+	}) = parsed
+	else {
+		return Ok(Fetched::nothing_new(0));
+	};
+
+	// update what the feed says about itself. The validators are only kept once
+	// all entries are stored, or the next fetch would skip those that weren't.
+	feed.meta = FeedMeta {
+		title: parsed.title.map(|text| text.content),
+		description: parsed.description.map(|text| text.content),
+		site_url: parsed
+			.links
+			.iter()
+			.find(|link| link.rel.as_deref() == Some("alternate"))
+			.or_else(|| {
+				parsed
+					.links
+					.iter()
+					.find(|link| link.rel.as_deref() != Some("self"))
+			})
+			.map(|link| link.href.clone()),
+		icon_url: parsed.icon.or(parsed.logo).map(|image| image.uri),
+		update_period,
+		redirected_to,
+		redirect_permanent,
+		validators: HttpValidators::default(),
+	};
+
+	let selectors = feed
+		.scraper
+		.as_ref()
+		.map(|scraper| scraper.resolve(app))
+		.transpose()?;
+
+	// subscribing should not queue up the whole back catalogue
+	let download = feed.downloader.is_some() && Article::feed_has_articles(app, feed.id)?;
+	let mut enclosures = vec![];
 
 	// insert new stuff
 	let utc_now = Utc::now();
+	let mut new_articles = vec![];
 	for entry in parsed.entries {
 		// NOTE: we might be getting an error here because the scema does not parse anymore
-		let prev_article = match Article::get_id(app, &entry.id) {
+		let prev_article = match Article::get_entry(app, feed.id, &entry.id) {
 			Ok(a) => a,
 			Err(e) => {
 				log::warn!("could not get article from db: {}", e);
 				None
 			}
 		};
+		let url = entry
+			.content
+			.as_ref()
+			.and_then(|content| content.src.as_ref().map(|link| link.href.clone()))
+			.or_else(|| entry.links.first().map(|link| link.href.clone()));
+
+		// pages are only scraped once, when the article first shows up
+		let scraped = match (&selectors, &prev_article, &url) {
+			(Some(selectors), Some(prev_article), _) => Scraped::kept(prev_article, selectors),
+			(Some(selectors), None, Some(url)) => {
+				match fetch_page(
+					app.client_for(feed),
+					url,
+					Some(&mut feed.cert_pins),
+					app.limits.max_page_size,
+				)
+				.await
+				{
+					Ok(page) => selectors.scrape(&page),
+					Err(e) => {
+						log::warn!("could not fetch article page {}: {}", url, e);
+						Scraped::default()
+					}
+				}
+			}
+			_ => Scraped::default(),
+		};
+
+		let published = scraped
+			.date
+			.or(entry.published)
+			.or_else(|| prev_article.as_ref().map(|article| article.published))
+			.unwrap_or(utc_now);
+		let entry_enclosures = match download {
+			true => download::entry_enclosures(&entry),
+			false => vec![],
+		};
+		let feed_content = entry
+			.content
+			.map(|content| content.body.unwrap_or_default())
+			.unwrap_or_default();
+
+		let first_seen = prev_article
+			.as_ref()
+			.map(|article| article.first_seen)
+			.unwrap_or(utc_now);
+
+		let id = ArticleId::new(published, feed.id, &entry.id);
+		let is_new = prev_article.is_none();
+		if is_new {
+			new_articles.push(id);
+		}
+
+		let title = scraped
+			.title
+			.or(entry.title.map(|text| text.content))
+			.unwrap_or_default();
+		if is_new && download {
+			for (url, mime_type, size) in entry_enclosures {
+				enclosures.push(Enclosure {
+					url,
+					mime_type,
+					size,
+					feed_id: feed.id,
+					feed_name: feed.name.clone(),
+					article_id: id,
+					article_title: title.clone(),
+				});
+			}
+		}
+
+		// archived pages go to the blob store, the article keeps the feed's content
+		let mut snapshot = None;
+		let mut content = match (&feed.content_mode, prev_article, &url, scraped.content) {
+			(_, _, _, Some(scraped)) => scraped,
+			(ContentMode::FeedProvided, _, _, _) | (_, _, None, _) => feed_content,
+			(ContentMode::SummaryOnly, _, _, _) => String::new(),
+			// pages are only downloaded once, when the article first shows up
+			(_, Some(prev_article), _, _) => prev_article.content,
+			(mode, None, Some(url), _) => {
+				match fetch_page(
+					app.client_for(feed),
+					url,
+					Some(&mut feed.cert_pins),
+					app.limits.max_page_size,
+				)
+				.await
+				{
+					Ok(page) if matches!(mode, ContentMode::ScrapedFullText) => {
+						readability::extract(&page)
+							.unwrap_or_else(|| extract_main_content(&page).to_owned())
+					}
+					Ok(page) => {
+						snapshot = Some(page);
+						feed_content
+					}
+					Err(e) => {
+						log::warn!("could not fetch article page {}: {}", url, e);
+						feed_content
+					}
+				}
+			}
+		};
+		truncate_content(&mut content, app.limits.max_content_size);
+
 		Article {
-			id: entry.id,
+			id,
 			feed_id: feed.id,
-			url: entry
-				.content
-				.as_ref()
-				.and_then(|content| content.src.as_ref().map(|link| link.href.clone()))
-				.or_else(|| entry.links.first().map(|link| link.href.clone())),
-			title: entry.title.map(|text| text.content).unwrap_or_default(),
+			url,
+			title,
 			summary: entry.summary.map(|text| text.content).unwrap_or_default(),
-			published: entry
-				.published
-				.or_else(|| prev_article.map(|article| article.published))
-				.unwrap_or(utc_now),
-			content: entry
-				.content
-				.map(|content| content.body.unwrap_or_default())
-				.unwrap_or_default(),
+			published,
+			first_seen,
+			content,
+			authors: entry
+				.authors
+				.into_iter()
+				.map(|person| person.name)
+				.collect(),
+			categories: entry
+				.categories
+				.into_iter()
+				.map(|category| category.label.unwrap_or(category.term))
+				.collect(),
 		}
 		.insert(app)?;
+
+		if let Some(page) = snapshot {
+			Article::set_snapshot(app, &id, page.as_bytes())?;
+		}
+		if is_new && feed.auto_read {
+			Article::set_read(app, &id, true)?;
+		}
+	}
+
+	feed.meta.validators = validators;
+	download::send_enclosures(app, feed, enclosures).await;
+
+	Ok(Fetched {
+		new_articles,
+		bytes: size,
+	})
+}
+
+/// How often a transiently failing feed is retried within one refresh
+const FETCH_RETRIES: u32 = 2;
+/// Delay before the first retry, doubled for every further one
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+
+/// Consecutive timeouts after which a host is skipped for the rest of a refresh
+const BREAKER_THRESHOLD: u32 = 3;
+
+/// Tracks timeouts per host during a refresh, so one dead host fails fast
+/// instead of tying up fetches until each of its feeds times out
+#[derive(Default)]
+struct HostBreaker {
+	timeouts: std::sync::Mutex<HashMap<String, u32>>,
+}
+
+impl HostBreaker {
+	fn host(url: &Url) -> String {
+		url.host_str().unwrap_or(url.as_str()).to_owned()
+	}
+
+	/// Fails if the host's circuit is open
+	fn check(&self, url: &Url) -> Result<()> {
+		let host = Self::host(url);
+		match self.timeouts.lock().unwrap().get(&host) {
+			Some(timeouts) if *timeouts >= BREAKER_THRESHOLD => Err(Error::CircuitOpen(host)),
+			_ => Ok(()),
+		}
+	}
+
+	fn record<T>(&self, url: &Url, result: &Result<T>) {
+		let mut timeouts = self.timeouts.lock().unwrap();
+		match result {
+			Ok(_) => {
+				timeouts.remove(&Self::host(url));
+			}
+			Err(e) if e.is_timeout() => *timeouts.entry(Self::host(url)).or_default() += 1,
+			Err(_) => (),
+		}
+	}
+}
+
+enum Refreshed {
+	/// Fetched or failed for good, with the feed's id
+	Done(u64, Fetched, Option<FeedError>),
+	/// Failed transiently, to be retried later in the cycle
+	Retry(Box<Feed>, bool),
+}
+
+/// Fetches a feed and records the outcome on it. Only owned feeds are saved,
+/// shared ones belong to another user.
+async fn refresh_feed(
+	app: &AppUser,
+	breaker: &HostBreaker,
+	mut feed: Feed,
+	owned: bool,
+	attempt: u32,
+) -> Result<Refreshed> {
+	// no point in waiting to fail fast
+	if attempt > 0 && breaker.check(&feed.url).is_ok() {
+		// jittered, so retries don't hit the same hosts all at once
+		let backoff = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+		let jitter = RETRY_BASE_DELAY.mul_f64(rand::random::<f64>());
+		tokio::time::sleep(backoff + jitter).await;
+	}
+
+	let result = match breaker.check(&feed.url) {
+		Ok(()) => fetch_feed(app, &mut feed).await,
+		Err(e) => Err(e),
+	};
+	breaker.record(&feed.url, &result);
+
+	feed.last_fetch_time = Utc::now();
+	let (fetched, error) = match result {
+		Ok(fetched) => {
+			feed.last_error = None;
+			(fetched, None)
+		}
+		Err(e) if e.is_transient() && attempt < FETCH_RETRIES => {
+			log::info!("retrying {} after transient error: {}", feed.url, e);
+			return Ok(Refreshed::Retry(Box::new(feed), owned));
+		}
+		Err(e) => {
+			// whatever was fetched may not be stored, so it's fetched in full next time
+			feed.meta.validators = HttpValidators::default();
+			feed.last_error = Some(FetchError::new(&e));
+			let error = FeedError {
+				feed_id: feed.id,
+				feed_name: feed.name.clone(),
+				error: format!("{}", e),
+			};
+			(Fetched::nothing_new(0), Some(error))
+		}
+	};
+
+	if owned {
+		feed.insert(app)?;
+	}
+
+	Ok(Refreshed::Done(feed.id, fetched, error))
+}
+
+/// Progress of a running refresh
+#[derive(Serialize, Clone, Debug)]
+pub struct RefreshProgress {
+	pub started: DateTime<Utc>,
+	/// Feeds in the refresh
+	pub feeds: usize,
+	/// Feeds fetched so far, including failed ones
+	pub done: usize,
+	pub new_articles: usize,
+	pub errors: usize,
+	/// Feeds fetched so far, whose articles clients can show already
+	pub done_feeds: Vec<u64>,
+}
+
+/// Progress of the refreshes running, by user
+static PROGRESS: Mutex<BTreeMap<String, RefreshProgress>> = Mutex::new(BTreeMap::new());
+
+/// Progress of the user's running refresh, if any
+pub fn progress(username: &str) -> Option<RefreshProgress> {
+	PROGRESS.lock().unwrap().get(username).cloned()
+}
+
+/// Drops the user's progress when the refresh ends, however it ends
+struct ProgressGuard<'a>(&'a str);
+
+impl ProgressGuard<'_> {
+	fn start(username: &str, progress: RefreshProgress) -> ProgressGuard<'_> {
+		PROGRESS
+			.lock()
+			.unwrap()
+			.insert(username.to_owned(), progress);
+		ProgressGuard(username)
+	}
+
+	fn update(&self, f: impl FnOnce(&mut RefreshProgress)) {
+		if let Some(progress) = PROGRESS.lock().unwrap().get_mut(self.0) {
+			f(progress);
+		}
+	}
+}
+
+impl Drop for ProgressGuard<'_> {
+	fn drop(&mut self) {
+		PROGRESS.lock().unwrap().remove(self.0);
+	}
+}
+
+/// Fetches feeds concurrently, highest priority first, retrying transient
+/// failures later in the same run, and returns the ids of new articles along
+/// with a report of the run. See [`refresh_feed`] about `owned`.
+async fn refresh_feeds(
+	app: &AppUser,
+	mut feeds: Vec<(Feed, bool)>,
+) -> Result<(Vec<ArticleId>, RefreshReport)> {
+	// blocked by the instance, there's nothing to fetch
+	feeds.retain(|(feed, _)| !feed.blocked);
+
+	let started = Utc::now();
+	let timer = Instant::now();
+	let mut report = RefreshReport {
+		started,
+		duration_ms: 0,
+		feeds: feeds.len(),
+		new_articles: 0,
+		bytes: 0,
+		errors: vec![],
+	};
+	let progress = ProgressGuard::start(
+		&app.username,
+		RefreshProgress {
+			started,
+			feeds: feeds.len(),
+			done: 0,
+			new_articles: 0,
+			errors: 0,
+			done_feeds: vec![],
+		},
+	);
+
+	// fetches start in order, so favorites' articles are there first
+	feeds.sort_by_key(|(feed, _)| std::cmp::Reverse(feed.priority));
+
+	let breaker = HostBreaker::default();
+	let mut new_articles = vec![];
+	let mut pending = feeds;
+	for attempt in 0..=FETCH_RETRIES {
+		if pending.is_empty() {
+			break;
+		}
+
+		let mut results = futures::stream::iter(std::mem::take(&mut pending))
+			.map(|(feed, owned)| refresh_feed(app, &breaker, feed, owned, attempt))
+			.buffer_unordered(32);
+		while let Some(result) = results.try_next().await? {
+			match result {
+				Refreshed::Done(feed_id, fetched, error) => {
+					progress.update(|progress| {
+						progress.done += 1;
+						progress.new_articles += fetched.new_articles.len();
+						progress.errors += error.is_some() as usize;
+						progress.done_feeds.push(feed_id);
+					});
+					report.bytes += fetched.bytes;
+					report.errors.extend(error);
+					new_articles.extend(fetched.new_articles);
+				}
+				Refreshed::Retry(feed, owned) => pending.push((*feed, owned)),
+			}
+		}
+	}
+
+	report.duration_ms = timer.elapsed().as_millis() as u64;
+	report.new_articles = new_articles.len();
+	Ok((new_articles, report))
+}
+
+/// Records the refresh, updates the search index and sends notifications
+/// after feeds were fetched
+async fn finish_refresh(
+	app: &AppUser,
+	new_articles: &[ArticleId],
+	report: RefreshReport,
+) -> Result<()> {
+	metrics::record_refresh(&app.username, &report);
+	if let Err(e) = report.insert(app) {
+		log::warn!("could not record refresh of {}: {}", app.username, e);
+	}
+
+	app.update_search_index()?;
+
+	// a failing notification target should not fail the refresh
+	if let Err(e) = notify::notify_new_articles(app, new_articles).await {
+		log::warn!("could not send notifications: {}", e);
 	}
 
 	Ok(())
 }
 
-pub async fn fetch_all_feeds(app: &AppUser) -> Result<()> {
-	// do these concurrently
-	futures::stream::iter(Feed::get_all(&app)?.into_iter().map(Ok))
-		.try_for_each_concurrent(32, |mut feed| async move {
-			let result = fetch_feed(app, &feed).await;
+/// Fetches the user's own feeds, as well as `shared_feeds` from subscribed
+/// blogrolls. The latter belong to another user, so their records are not updated.
+pub async fn fetch_all_feeds(app: &AppUser, shared_feeds: Vec<Feed>) -> Result<()> {
+	fetch_feeds(app, Feed::get_all(app)?, shared_feeds).await
+}
+
+/// Fetches some of the user's own feeds, e.g. those due for a scheduled
+/// refresh, see [`fetch_all_feeds`] about `shared_feeds`
+pub async fn fetch_feeds(app: &AppUser, feeds: Vec<Feed>, shared_feeds: Vec<Feed>) -> Result<()> {
+	let feeds = feeds
+		.into_iter()
+		.map(|feed| (feed, true))
+		.chain(shared_feeds.into_iter().map(|mut feed| {
+			// the owner's downloader and credentials are theirs alone
+			feed.downloader = None;
+			if let Some(request) = &mut feed.request {
+				request.oauth = None;
+			}
+			(feed, false)
+		}))
+		.collect();
+
+	let (new_articles, report) = refresh_feeds(app, feeds).await?;
+	finish_refresh(app, &new_articles, report).await
+}
+
+/// Fetches one of the user's own feeds
+pub async fn fetch_one_feed(app: &AppUser, id: u64) -> Result<()> {
+	let feed = Feed::get_id(app, id)?.ok_or(Error::NotFound("feed".into()))?;
 
-			feed.last_fetch_time = Utc::now();
-			feed.last_error = result.err().map(|e| format!("{}", e));
+	let (new_articles, report) = refresh_feeds(app, vec![(feed, true)]).await?;
+	finish_refresh(app, &new_articles, report).await
+}
 
-			feed.insert(app)?;
+type RefreshResult = std::result::Result<(), Arc<Error>>;
+type RunningRefresh = Shared<BoxFuture<'static, RefreshResult>>;
+type InFlight = Arc<Mutex<HashMap<String, RunningRefresh>>>;
+
+/// Coalesces refreshes of the same user, so that the scheduler and a manual
+/// refresh firing together share one run instead of racing on the feed records
+#[derive(Default)]
+pub struct Refreshes {
+	in_flight: InFlight,
+}
+
+/// Removes a refresh from those in flight once it's done, or has panicked
+struct Finished {
+	in_flight: InFlight,
+	key: String,
+}
+
+impl Drop for Finished {
+	fn drop(&mut self) {
+		self.in_flight.lock().unwrap().remove(&self.key);
+	}
+}
 
-			Ok::<_, Error>(())
+impl Refreshes {
+	/// Number of refreshes running
+	pub fn in_flight(&self) -> usize {
+		self.in_flight.lock().unwrap().len()
+	}
+
+	/// Refreshes the user's feeds, or joins the refresh already in progress
+	/// and returns its result. A refresh of only some feeds is waited for first,
+	/// as it doesn't fetch the rest.
+	pub async fn run(&self, app: AppUser, shared_feeds: Vec<Feed>) -> Result<()> {
+		let running_some = self.running(&format!("{}/some", app.username));
+		if let Some(refresh) = running_some {
+			// its failure is the failure of some feeds, which are fetched again
+			let _ = refresh.await;
+		}
+
+		let key = app.username.clone();
+		self.join(key, async move {
+			fetch_all_feeds(&app, shared_feeds).await.map_err(Arc::new)
 		})
-		.await?;
+		.await
+	}
 
-	// create search index
-	app.create_search_index()?;
+	/// Refreshes some of the user's feeds, or joins a refresh of all of them or
+	/// of some already in progress
+	pub async fn run_feeds(
+		&self,
+		app: AppUser,
+		feeds: Vec<Feed>,
+		shared_feeds: Vec<Feed>,
+	) -> Result<()> {
+		if let Some(refresh) = self.running(&app.username) {
+			return refresh.await.map_err(Error::Shared);
+		}
 
-	Ok(())
+		// a separate key, so a refresh of all feeds doesn't join this one
+		let key = format!("{}/some", app.username);
+		self.join(key, async move {
+			fetch_feeds(&app, feeds, shared_feeds)
+				.await
+				.map_err(Arc::new)
+		})
+		.await
+	}
+
+	/// Refreshes a single feed, or joins a refresh of all of the user's feeds
+	/// or of this feed already in progress
+	pub async fn run_one(&self, app: AppUser, id: u64) -> Result<()> {
+		if let Some(refresh) = self.running(&app.username) {
+			return refresh.await.map_err(Error::Shared);
+		}
+
+		let key = format!("{}/{}", app.username, id);
+		self.join(key, async move {
+			fetch_one_feed(&app, id).await.map_err(Arc::new)
+		})
+		.await
+	}
+
+	fn running(&self, key: &str) -> Option<RunningRefresh> {
+		self.in_flight.lock().unwrap().get(key).cloned()
+	}
+
+	/// Joins the refresh under `key`, or starts `run` as one. It runs as a task of
+	/// its own, so it finishes even if all waiters are dropped, e.g. as their
+	/// clients disconnected.
+	async fn join(
+		&self,
+		key: String,
+		run: impl std::future::Future<Output = RefreshResult> + Send + 'static,
+	) -> Result<()> {
+		let refresh = {
+			let mut in_flight = self.in_flight.lock().unwrap();
+			match in_flight.get(&key) {
+				Some(refresh) => refresh.clone(),
+				None => {
+					// the task can only finish once it's in flight, as that takes the lock
+					let finished = Finished {
+						in_flight: self.in_flight.clone(),
+						key: key.clone(),
+					};
+					let task = tokio::spawn(async move {
+						let _finished = finished;
+						run.await
+					});
+					let refresh = async move { task.await.expect("refresh panicked") }
+						.boxed()
+						.shared();
+					in_flight.insert(key, refresh.clone());
+					refresh
+				}
+			}
+		};
+
+		refresh.await.map_err(Error::Shared)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::err::FetchErrorKind;
+
+	#[test]
+	fn pages_are_decoded_by_their_charset() {
+		let latin1 = b"caf\xe9";
+		assert_eq!(
+			decode_page(Some("text/html; charset=ISO-8859-1"), latin1),
+			"caf\u{e9}"
+		);
+		assert_eq!(
+			decode_page(Some("text/html;Charset=\"windows-1252\""), latin1),
+			"caf\u{e9}"
+		);
+		assert_eq!(
+			decode_page(Some("text/html"), "caf\u{e9}".as_bytes()),
+			"caf\u{e9}"
+		);
+		assert_eq!(decode_page(None, latin1), "caf\u{fffd}");
+	}
+
+	#[test]
+	fn malformed_feeds_are_located() {
+		let url = Url::parse("https://example.com/feed.xml").unwrap();
+		let located = |body: &str| {
+			let error = FetchError::new(&parse_feed(&url, body.as_bytes()).unwrap_err());
+			assert_eq!(error.kind, FetchErrorKind::Parse);
+			(error.line, error.column)
+		};
+
+		// at the name of the mismatched end tag
+		let mismatched = concat!(
+			"<?xml version=\"1.0\"?>\n",
+			"<rss version=\"2.0\">\n",
+			"<channel>\n",
+			"\t<title>Blog</titel>\n",
+			"</channel>\n",
+			"</rss>\n",
+		);
+		assert_eq!(located(mismatched), (Some(4), Some(15)));
+		let truncated = "<rss version=\"2.0\">\n<channel>\n<title>Blog</title>\n";
+		assert_eq!(located(truncated), (Some(4), Some(1)));
+	}
 }
@@ -0,0 +1,268 @@
+//! Minimal client for the Gemini protocol, so gemlogs can be subscribed to
+//! alongside HTTP feeds. Both Atom feeds and gemfeeds, i.e. gemtext pages listing
+//! dated links, are supported.
+//!
+//! Capsules overwhelmingly use self-signed certificates, so they are trusted on
+//! first use, as interactive clients do: the fingerprint of the certificate a
+//! host first presents is pinned, and fetches fail once it presents another.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use atom_syndication::{Entry, Feed as AtomFeed, Link, Text};
+use chrono::{NaiveDate, TimeZone, Utc};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use url::Url;
+
+use crate::{fetch::CertPins, Error, Result};
+
+const DEFAULT_PORT: u16 = 1965;
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+pub struct Response {
+	/// After following redirects, relative links resolve against it
+	pub url: Url,
+	/// Whether every redirect followed was permanent
+	pub permanent: bool,
+	pub mime: String,
+	pub body: Vec<u8>,
+}
+
+/// Requests the url, following redirects, checking hosts against `pins` and
+/// pinning those not seen before. Responses larger than `max_size` fail the
+/// request.
+pub async fn fetch(url: &Url, pins: &mut CertPins, max_size: usize) -> Result<Response> {
+	let mut url = url.clone();
+	let mut permanent = true;
+	for _ in 0..=MAX_REDIRECTS {
+		let (status, meta, body) = tokio::time::timeout(TIMEOUT, request(&url, pins, max_size))
+			.await
+			.map_err(|_| Error::Gemini(format!("{} timed out", url)))??;
+
+		match status / 10 {
+			2 => {
+				return Ok(Response {
+					url,
+					permanent,
+					mime: meta,
+					body,
+				})
+			}
+			3 => {
+				// 31 is a permanent redirect, 30 a temporary one
+				permanent &= status == 31;
+				url = url.join(&meta)?;
+			}
+			_ => {
+				return Err(Error::Gemini(format!(
+					"{} answered {} {}",
+					url, status, meta
+				)))
+			}
+		}
+	}
+
+	Err(Error::Gemini(format!("too many redirects for {}", url)))
+}
+
+/// Accepts the certificate pinned for the host, or any if none is, noting the
+/// fingerprint of the one presented. The handshake is still verified against
+/// the certificate's key.
+struct PinVerifier {
+	pinned: Option<String>,
+	presented: Mutex<Option<String>>,
+}
+
+fn fingerprint(certificate: &rustls::Certificate) -> String {
+	Sha256::digest(&certificate.0)
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect()
+}
+
+impl ServerCertVerifier for PinVerifier {
+	fn verify_server_cert(
+		&self,
+		end_entity: &rustls::Certificate,
+		_intermediates: &[rustls::Certificate],
+		_server_name: &rustls::ServerName,
+		_scts: &mut dyn Iterator<Item = &[u8]>,
+		_ocsp_response: &[u8],
+		_now: SystemTime,
+	) -> std::result::Result<ServerCertVerified, rustls::Error> {
+		let presented = fingerprint(end_entity);
+		if self
+			.pinned
+			.as_ref()
+			.is_some_and(|pinned| *pinned != presented)
+		{
+			return Err(rustls::Error::General(format!(
+				"certificate changed since it was pinned, to one of fingerprint {}",
+				presented
+			)));
+		}
+		*self.presented.lock().unwrap() = Some(presented);
+		Ok(ServerCertVerified::assertion())
+	}
+}
+
+async fn request(
+	url: &Url,
+	pins: &mut CertPins,
+	max_size: usize,
+) -> Result<(u8, String, Vec<u8>)> {
+	let host = url
+		.host_str()
+		.ok_or_else(|| Error::Gemini(format!("{} has no host", url)))?;
+	let port = url.port().unwrap_or(DEFAULT_PORT);
+	let name = rustls::ServerName::try_from(host)
+		.map_err(|_| Error::Gemini(format!("invalid host name {}", host)))?;
+
+	let verifier = Arc::new(PinVerifier {
+		pinned: pins.get(host).cloned(),
+		presented: Mutex::new(None),
+	});
+	let config = rustls::ClientConfig::builder()
+		.with_safe_defaults()
+		.with_custom_certificate_verifier(verifier.clone())
+		.with_no_client_auth();
+	let stream = tokio::net::TcpStream::connect((host, port)).await?;
+	let mut stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+		.connect(name, stream)
+		.await
+		.map_err(|e| match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+			Some(e) => Error::Tls(format!("{}: {}", host, e)),
+			None => e.into(),
+		})?;
+	if let Some(presented) = verifier.presented.lock().unwrap().take() {
+		pins.entry(host.to_owned()).or_insert(presented);
+	}
+
+	stream.write_all(format!("{}\r\n", url).as_bytes()).await?;
+
+	// one byte past the limit tells a response of exactly the limit from a
+	// larger one; the header counts, it's at most a line
+	let mut response = vec![];
+	stream
+		.take(max_size as u64 + 1)
+		.read_to_end(&mut response)
+		.await?;
+	if response.len() > max_size {
+		return Err(Error::TooLarge(url.to_string(), max_size));
+	}
+
+	let header_end = response
+		.windows(2)
+		.position(|window| window == b"\r\n")
+		.ok_or_else(|| Error::Gemini(format!("malformed response from {}", url)))?;
+	let header = String::from_utf8_lossy(&response[..header_end]).into_owned();
+	let (status, meta) = header.split_once(' ').unwrap_or((&header, ""));
+	let status = status
+		.parse()
+		.map_err(|_| Error::Gemini(format!("malformed status from {}", url)))?;
+
+	Ok((
+		status,
+		meta.trim().to_owned(),
+		response.split_off(header_end + 2),
+	))
+}
+
+/// Converts a gemfeed to Atom: the first heading is the title, and every link
+/// line whose label starts with a `YYYY-MM-DD` date is an entry
+pub fn gemfeed_to_atom(url: &Url, gemtext: &str) -> Result<Vec<u8>> {
+	let mut title = None;
+	let mut entries = vec![];
+	for line in gemtext.lines() {
+		if let Some(heading) = line.strip_prefix("# ") {
+			title.get_or_insert_with(|| heading.trim().to_owned());
+			continue;
+		}
+
+		let Some(link) = line.strip_prefix("=>")
+		else {
+			continue;
+		};
+		let Some((target, label)) = link.trim().split_once(char::is_whitespace)
+		else {
+			continue;
+		};
+		let label = label.trim();
+		let Some(date) = label
+			.get(..10)
+			.and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+		else {
+			continue;
+		};
+
+		let href = url.join(target)?.to_string();
+		let published = Utc
+			.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+			.fixed_offset();
+		let entry_title = label[10..].trim_start_matches([' ', '-', ':']).trim();
+		entries.push(Entry {
+			title: Text::plain(entry_title),
+			id: href.clone(),
+			updated: published,
+			published: Some(published),
+			links: vec![Link {
+				href,
+				..Default::default()
+			}],
+			..Default::default()
+		});
+	}
+
+	let updated = entries
+		.iter()
+		.map(|entry| entry.updated)
+		.max()
+		.unwrap_or_else(|| Utc::now().fixed_offset());
+
+	let feed = AtomFeed {
+		title: Text::plain(title.unwrap_or_else(|| url.to_string())),
+		id: url.to_string(),
+		updated,
+		links: vec![Link {
+			href: url.to_string(),
+			rel: "alternate".into(),
+			..Default::default()
+		}],
+		entries,
+		..Default::default()
+	};
+
+	Ok(feed.to_string().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_the_pinned_certificate_is_accepted() {
+		let verify = |pinned: Option<String>, certificate: &[u8]| {
+			let verifier = PinVerifier {
+				pinned,
+				presented: Mutex::new(None),
+			};
+			let result = verifier.verify_server_cert(
+				&rustls::Certificate(certificate.to_vec()),
+				&[],
+				&rustls::ServerName::try_from("example.org").unwrap(),
+				&mut std::iter::empty(),
+				&[],
+				SystemTime::now(),
+			);
+			result.map(|_| verifier.presented.into_inner().unwrap())
+		};
+
+		let first = verify(None, b"first").unwrap().unwrap();
+		assert_eq!(first, fingerprint(&rustls::Certificate(b"first".to_vec())));
+		assert!(verify(Some(first.clone()), b"first").is_ok());
+		assert!(verify(Some(first), b"second").is_err());
+	}
+}
@@ -0,0 +1,179 @@
+//! Health report of a user's feeds, flagging those that fail to fetch, moved,
+//! or stopped publishing, with a replacement url where one can be found. Only
+//! replacements known to work are suggested, so they can be applied in bulk.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use scraper::{Html, Selector};
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	db::{normalize_url, Article, Feed, PatchFeed},
+	err::{FetchError, FetchErrorKind},
+	fetch, Error, Result,
+};
+
+/// Feeds without new articles for this long are stale
+const STALE_AFTER_DAYS: i64 = 90;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+	Ok,
+	/// The last fetch failed
+	Erroring,
+	/// Fetched fine, but nothing new was published in a long time
+	Stale,
+	/// Fetched fine, but from another url. Only permanent redirects suggest it
+	/// as a replacement.
+	Redirected,
+}
+
+#[derive(Serialize)]
+pub struct FeedHealth {
+	pub id: u64,
+	pub name: String,
+	pub url: Url,
+	pub health: Health,
+	pub last_fetch_time: DateTime<Utc>,
+	pub last_error: Option<FetchError>,
+	pub latest_article: Option<DateTime<Utc>>,
+	/// Url to replace the current one with: where it redirects to, or a feed
+	/// advertised by the site of an erroring one
+	pub suggested_url: Option<Url>,
+	/// Whether the suggested url was applied
+	pub applied: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct FeedsReport {
+	pub ok: Vec<FeedHealth>,
+	pub erroring: Vec<FeedHealth>,
+	/// Erroring feeds per class of error
+	pub error_kinds: BTreeMap<FetchErrorKind, usize>,
+	pub stale: Vec<FeedHealth>,
+	pub redirected: Vec<FeedHealth>,
+}
+
+/// Feed urls a page advertises through `<link rel="alternate">`
+fn advertised_feeds(page: &str, base: &Url) -> Vec<Url> {
+	let selector = Selector::parse(
+		r#"link[rel~="alternate"][type="application/rss+xml"], link[rel~="alternate"][type="application/atom+xml"]"#,
+	)
+	.expect("selector is valid");
+	Html::parse_document(page)
+		.select(&selector)
+		.filter_map(|link| base.join(link.value().attr("href")?).ok())
+		.collect()
+}
+
+/// A working feed advertised by the site of a feed that fails to fetch
+async fn discover_replacement(app: &AppUser, feed: &Feed) -> Option<Url> {
+	let site = feed
+		.meta
+		.site_url
+		.as_ref()
+		.and_then(|url| Url::parse(url).ok())
+		.or_else(|| feed.url.join("/").ok())?;
+	let page = fetch::fetch_page(
+		app.client_for(feed),
+		site.as_str(),
+		None,
+		app.limits.max_page_size,
+	)
+	.await
+	.ok()?;
+
+	for candidate in advertised_feeds(&page, &site) {
+		let Ok(candidate) = normalize_url(&candidate)
+		else {
+			continue;
+		};
+		if candidate == feed.url {
+			continue;
+		}
+		if app
+			.fetch_cache
+			.get(app.client_for(feed), &candidate)
+			.await
+			.is_ok()
+		{
+			return Some(candidate);
+		}
+	}
+
+	None
+}
+
+/// Checks the user's feeds, replacing the urls of those with a suggested one
+/// if `apply` is set
+pub async fn report(app: &AppUser, apply: bool) -> Result<FeedsReport> {
+	let latest = Article::latest_per_feed(app)?;
+	let stale_before = Utc::now() - Duration::days(STALE_AFTER_DAYS);
+
+	let mut report = FeedsReport::default();
+	for mut feed in Feed::get_all(app)? {
+		let latest_article = latest.get(&feed.id).copied();
+		let (health, suggested_url) = match (&feed.last_error, &feed.meta.redirected_to) {
+			// page watches are plain pages, not advertised by their site
+			(Some(_), _) if feed.watch.is_some() => (Health::Erroring, None),
+			(Some(_), _) => (Health::Erroring, discover_replacement(app, &feed).await),
+			(None, Some(redirected_to)) => (
+				Health::Redirected,
+				Some(redirected_to)
+					.filter(|_| feed.meta.redirect_permanent)
+					.and_then(|url| normalize_url(url).ok()),
+			),
+			(None, None) if latest_article.is_some_and(|latest| latest < stale_before) => {
+				(Health::Stale, None)
+			}
+			(None, None) => (Health::Ok, None),
+		};
+
+		let applied = match (&suggested_url, apply) {
+			(Some(url), true) => {
+				let patch = PatchFeed {
+					id: Some(feed.id),
+					url: Some(url.clone()),
+					..Default::default()
+				};
+				match patch.apply(app) {
+					Ok(()) => true,
+					// left for the user to look into, like any other suggestion
+					Err(Error::FeedBlocked(..)) => false,
+					Err(e) => return Err(e),
+				}
+			}
+			_ => false,
+		};
+		if applied {
+			feed = Feed::get_id(app, feed.id)?.ok_or(Error::NotFound("feed".into()))?;
+		}
+
+		let entry = FeedHealth {
+			id: feed.id,
+			name: feed.name,
+			url: feed.url,
+			health,
+			last_fetch_time: feed.last_fetch_time,
+			last_error: feed.last_error,
+			latest_article,
+			suggested_url,
+			applied,
+		};
+		if let Some(error) = &entry.last_error {
+			*report.error_kinds.entry(error.kind).or_default() += 1;
+		}
+		match health {
+			Health::Ok => report.ok.push(entry),
+			Health::Erroring => report.erroring.push(entry),
+			Health::Stale => report.stale.push(entry),
+			Health::Redirected => report.redirected.push(entry),
+		}
+	}
+
+	Ok(report)
+}
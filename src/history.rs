@@ -0,0 +1,59 @@
+//! Summaries of past refreshes, kept so trends like refreshes slowing down or
+//! feeds failing more often can be spotted. Only the most recent ones are kept.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{app::AppUser, Error, Result};
+
+/// Older reports are dropped beyond this many
+const MAX_REPORTS: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeedError {
+	pub feed_id: u64,
+	pub feed_name: String,
+	pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RefreshReport {
+	pub started: DateTime<Utc>,
+	pub duration_ms: u64,
+	/// Feeds fetched, including failed ones
+	pub feeds: usize,
+	pub new_articles: usize,
+	/// Size of the fetched feeds, including those served from the fetch cache
+	pub bytes: usize,
+	pub errors: Vec<FeedError>,
+}
+
+impl RefreshReport {
+	pub fn insert(&self, app: &AppUser) -> Result<()> {
+		app.refresh_history.insert(
+			app.db.generate_id()?.to_be_bytes(),
+			bincode::serialize(self)?,
+		)?;
+
+		while app.refresh_history.len() > MAX_REPORTS {
+			if app.refresh_history.pop_min()?.is_none() {
+				break;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The latest reports, newest first
+	pub fn get_latest(app: &AppUser, limit: usize) -> Result<Vec<RefreshReport>> {
+		app.refresh_history
+			.iter()
+			.rev()
+			.take(limit)
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect()
+	}
+}
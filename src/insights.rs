@@ -0,0 +1,103 @@
+//! How much the user actually reads of each feed, to suggest unsubscribing from
+//! the ones they stopped reading. Only articles the user marks read themselves
+//! count; feeds marked read wholesale, or arriving read, don't. Feeds get a
+//! trial period as long as the window before they're suggested.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, Feed},
+	Result,
+};
+
+const DEFAULT_IDLE_DAYS: u32 = 90;
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct FeedReads {
+	/// Articles of the feed the user read
+	pub reads: u64,
+	pub last_read: Option<DateTime<Utc>>,
+}
+
+impl FeedReads {
+	pub fn get(app: &AppUser, feed_id: u64) -> Result<FeedReads> {
+		Ok(app
+			.feed_reads
+			.get(feed_id.to_be_bytes())?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()?
+			.unwrap_or_default())
+	}
+
+	/// Counts a read of the article, as the user marked it read
+	pub fn record(app: &AppUser, id: &ArticleId) -> Result<()> {
+		let feed_id = id.composite().feed_id;
+		let mut reads = Self::get(app, feed_id)?;
+		reads.reads += 1;
+		reads.last_read = Some(Utc::now());
+		app.feed_reads
+			.insert(feed_id.to_be_bytes(), bincode::serialize(&reads)?)?;
+		Ok(())
+	}
+
+	pub fn remove(app: &AppUser, feed_id: u64) -> Result<()> {
+		app.feed_reads.remove(feed_id.to_be_bytes())?;
+		Ok(())
+	}
+}
+
+#[derive(Deserialize, Default)]
+pub struct InsightsRequest {
+	/// Feeds not read for this long are suggested, 90 if not set
+	pub days: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Insights {
+	pub since: DateTime<Utc>,
+	/// Feeds subscribed to before `since` and not read since, the longest unread
+	/// first
+	pub idle_feeds: Vec<IdleFeed>,
+}
+
+#[derive(Serialize)]
+pub struct IdleFeed {
+	pub feed_id: u64,
+	pub name: String,
+	pub subscribed: DateTime<Utc>,
+	/// Articles that arrived since `since`
+	pub new_articles: usize,
+	#[serde(flatten)]
+	pub reads: FeedReads,
+}
+
+impl Insights {
+	pub fn new(app: &AppUser, request: &InsightsRequest) -> Result<Insights> {
+		let days = request.days.unwrap_or(DEFAULT_IDLE_DAYS).max(1);
+		let since = Utc::now() - Duration::days(days as i64);
+		let new_articles = Article::count_seen_since(app, since)?;
+
+		let mut idle_feeds = vec![];
+		for feed in Feed::get_all(app)? {
+			let reads = FeedReads::get(app, feed.id)?;
+			let idle = feed.subscribed <= since
+				&& reads.last_read.is_none_or(|last_read| last_read < since);
+			if !idle {
+				continue;
+			}
+			idle_feeds.push(IdleFeed {
+				feed_id: feed.id,
+				name: feed.name,
+				subscribed: feed.subscribed,
+				new_articles: new_articles.get(&feed.id).copied().unwrap_or_default(),
+				reads,
+			});
+		}
+		// never read sorts first
+		idle_feeds.sort_by_key(|feed| (feed.reads.last_read, feed.subscribed));
+
+		Ok(Insights { since, idle_feeds })
+	}
+}
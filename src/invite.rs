@@ -0,0 +1,260 @@
+//! Self-registration, so small communities can onboard users without the
+//! operator creating every account. Registration is closed by default; it can
+//! require an invite code generated by an administrator, or be open to anyone.
+//! Invites carry the quota of the users registering with them. Attempts are
+//! rate limited per client address.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::Quota,
+	db::{NewUser, User},
+	App, Error, Result,
+};
+
+const MAX_USERNAME_LEN: usize = 64;
+const RATE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+static CONFIG: OnceLock<RegistrationConfig> = OnceLock::new();
+static ATTEMPTS: Mutex<Option<HashMap<IpAddr, Vec<Instant>>>> = Mutex::new(None);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+	#[default]
+	Closed,
+	Invite,
+	Open,
+}
+
+impl std::str::FromStr for RegistrationMode {
+	type Err = anyhow::Error;
+
+	fn from_str(mode: &str) -> anyhow::Result<Self> {
+		match mode {
+			"closed" => Ok(Self::Closed),
+			"invite" => Ok(Self::Invite),
+			"open" => Ok(Self::Open),
+			_ => anyhow::bail!("unknown registration mode {}", mode),
+		}
+	}
+}
+
+#[derive(Default, Debug)]
+pub struct RegistrationConfig {
+	pub mode: RegistrationMode,
+	/// Attempts per client address and hour
+	pub rate_limit: usize,
+	/// Of users registering without an invite
+	pub open_quota: Quota,
+}
+
+pub fn configure(config: RegistrationConfig) {
+	let _ = CONFIG.set(config);
+}
+
+pub fn mode() -> RegistrationMode {
+	CONFIG.get().map(|config| config.mode).unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+pub struct NewInvite {
+	/// Users that can register with it, 1 if not set
+	pub uses: Option<u32>,
+	pub expires_in_days: Option<u32>,
+	#[serde(default)]
+	pub quota: Quota,
+}
+
+impl NewInvite {
+	pub fn insert(self, app: &App, created_by: &str) -> Result<Invite> {
+		let mut bytes = [0u8; 12];
+		rand::Rng::fill(&mut rand::thread_rng(), &mut bytes);
+
+		let invite = Invite {
+			code: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+			created_by: created_by.to_owned(),
+			created: Utc::now(),
+			expires: self
+				.expires_in_days
+				.map(|days| Utc::now() + chrono::Duration::days(days as i64)),
+			uses_left: self.uses.unwrap_or(1).max(1),
+			quota: self.quota,
+		};
+		app.invites
+			.insert(invite.code.as_bytes(), bincode::serialize(&invite)?)?;
+
+		Ok(invite)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Invite {
+	pub code: String,
+	pub created_by: String,
+	pub created: DateTime<Utc>,
+	pub expires: Option<DateTime<Utc>>,
+	pub uses_left: u32,
+	/// Given to the users registering with it
+	pub quota: Quota,
+}
+
+impl Invite {
+	pub fn get_all(app: &App) -> Result<Vec<Invite>> {
+		app.invites
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect()
+	}
+
+	pub fn remove(app: &App, code: &str) -> Result<()> {
+		app.invites
+			.remove(code.as_bytes())?
+			.ok_or(Error::NotFound("invite".into()))?;
+		Ok(())
+	}
+
+	fn is_valid(&self) -> bool {
+		self.uses_left > 0 && self.expires.is_none_or(|expires| expires > Utc::now())
+	}
+
+	/// Takes one use of the invite. A used up invite is kept until
+	/// [settled](Invite::settle), so its last use can still be given back.
+	fn redeem(app: &App, code: &str) -> Result<Invite> {
+		let mut redeemed = None;
+		app.invites.fetch_and_update(code.as_bytes(), |bytes| {
+			redeemed = None;
+			let bytes = bytes?;
+			let Ok(mut invite) = bincode::deserialize::<Invite>(bytes)
+			else {
+				return Some(bytes.to_vec());
+			};
+			if !invite.is_valid() {
+				return Some(bytes.to_vec());
+			}
+
+			invite.uses_left -= 1;
+			let updated = bincode::serialize(&invite).ok();
+			redeemed = Some(invite);
+			updated
+		})?;
+
+		redeemed.ok_or(Error::InvalidInvite)
+	}
+
+	/// Gives back a use taken for a registration that failed, unless the invite
+	/// was removed meanwhile
+	fn restore(app: &App, code: &str) -> Result<()> {
+		// other registrations may have redeemed it meanwhile, so only the count is bumped
+		app.invites.fetch_and_update(code.as_bytes(), |bytes| {
+			let bytes = bytes?;
+			let Ok(mut invite) = bincode::deserialize::<Invite>(bytes)
+			else {
+				return Some(bytes.to_vec());
+			};
+			invite.uses_left += 1;
+			bincode::serialize(&invite).ok()
+		})?;
+		Ok(())
+	}
+
+	/// Drops the invite once a registration took its last use for good
+	fn settle(app: &App, code: &str) -> Result<()> {
+		app.invites.fetch_and_update(code.as_bytes(), |bytes| {
+			let bytes = bytes?;
+			match bincode::deserialize::<Invite>(bytes) {
+				Ok(invite) if invite.uses_left == 0 => None,
+				_ => Some(bytes.to_vec()),
+			}
+		})?;
+		Ok(())
+	}
+}
+
+/// Counts an attempt of the client, failing if it made too many recently
+fn check_rate(ip: IpAddr, limit: usize) -> Result<()> {
+	let now = Instant::now();
+	let mut attempts = ATTEMPTS.lock().unwrap();
+	let attempts = attempts.get_or_insert_with(HashMap::new);
+	attempts.retain(|_, times| {
+		times.retain(|time| now.duration_since(*time) < RATE_WINDOW);
+		!times.is_empty()
+	});
+
+	let times = attempts.entry(ip).or_default();
+	if times.len() >= limit {
+		return Err(Error::RateLimited);
+	}
+	times.push(now);
+	Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct Registration {
+	pub username: String,
+	pub password: String,
+	pub invite: Option<String>,
+}
+
+/// Whether the name can be given to a new user
+pub fn valid_username(username: &str) -> bool {
+	// the name prefixes the user's trees, and basic auth splits at the colon
+	!username.is_empty()
+		&& username.len() <= MAX_USERNAME_LEN
+		&& !username.contains(['/', ':'])
+		&& !username.chars().any(char::is_control)
+}
+
+impl Registration {
+	pub fn register(self, app: &App, ip: IpAddr) -> Result<User> {
+		let Some(config) = CONFIG
+			.get()
+			.filter(|config| config.mode != RegistrationMode::Closed)
+		else {
+			return Err(Error::Forbidden);
+		};
+		check_rate(ip, config.rate_limit)?;
+		if !valid_username(&self.username) {
+			return Err(Error::InvalidUsername);
+		}
+
+		// an invite is taken even if registration is open, for its quota
+		let invite = match (&self.invite, config.mode) {
+			(Some(code), _) => Some(Invite::redeem(app, code)?),
+			(None, RegistrationMode::Open) => None,
+			(None, _) => return Err(Error::InvalidInvite),
+		};
+		let quota = invite
+			.as_ref()
+			.map(|invite| invite.quota.clone())
+			.unwrap_or_else(|| config.open_quota.clone());
+
+		let new_user = NewUser {
+			username: self.username,
+			password: self.password,
+			admin: false,
+			quota: Some(quota),
+		};
+		let registered = new_user.insert(app);
+		if let Some(invite) = invite {
+			match registered {
+				Ok(_) => Invite::settle(app, &invite.code)?,
+				Err(_) => Invite::restore(app, &invite.code)?,
+			}
+		}
+		let user = registered?;
+
+		log::info!("{} registered from {}", user.username, ip);
+		Ok(user)
+	}
+}
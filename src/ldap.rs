@@ -0,0 +1,203 @@
+//! Logging in against an LDAP or Active Directory server, with
+//! `AUTH_BACKEND=ldap`. Clients keep sending HTTP Basic credentials, which are
+//! checked by binding as the user: either at a DN made from the username, or
+//! at the DN a search with a service account finds. A filter on the user's entry
+//! can limit access, e.g. to members of a group.
+//!
+//! Users logging in for the first time get a local user record, with a random
+//! password so they can only log in through the directory. As directories match
+//! names regardless of case, usernames are lowercased.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use ldap3::{dn_escape, ldap_escape, LdapConn, LdapConnSettings, LdapError, Scope, SearchEntry};
+use sha2::{Digest, Sha256};
+
+use crate::auth::{basic_credentials, AuthBackend, AuthRequest};
+use crate::db::{NewUser, User};
+use crate::{invite, App, Error, Result};
+
+/// How long a successful login is trusted without asking the server again;
+/// clients send credentials with every request
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Result code of a bind with wrong credentials
+const INVALID_CREDENTIALS: u32 = 49;
+
+pub struct LdapConfig {
+	/// `ldap://` or `ldaps://` url of the server
+	pub url: String,
+	/// Upgrade `ldap://` connections with StartTLS
+	pub starttls: bool,
+	pub bind: LdapBind,
+	/// Filter the user's entry must match to log in, e.g.
+	/// `(memberOf=cn=nanorss,ou=groups,dc=example,dc=org)`
+	pub group_filter: Option<String>,
+}
+
+pub enum LdapBind {
+	/// Binds at the DN with `{username}` replaced, e.g.
+	/// `uid={username},ou=people,dc=example,dc=org`
+	Direct { dn: String },
+	/// Binds at the entry a search for `{username}` in `filter` finds, e.g.
+	/// `(sAMAccountName={username})` for Active Directory
+	Search {
+		bind_dn: String,
+		bind_password: String,
+		base_dn: String,
+		filter: String,
+	},
+}
+
+pub struct LdapBackend {
+	config: LdapConfig,
+	/// Users by when they logged in and a salted hash of their password
+	cache: Mutex<HashMap<String, (Instant, Vec<u8>)>>,
+	salt: [u8; 16],
+}
+
+fn ldap_error(e: LdapError) -> Error {
+	Error::Ldap(e.to_string())
+}
+
+impl LdapBackend {
+	pub fn new(config: LdapConfig) -> Self {
+		Self {
+			config,
+			cache: Mutex::new(HashMap::new()),
+			salt: rand::random(),
+		}
+	}
+
+	fn password_hash(&self, password: &str) -> Vec<u8> {
+		let mut hasher = Sha256::new();
+		hasher.update(self.salt);
+		hasher.update(password.as_bytes());
+		hasher.finalize().to_vec()
+	}
+
+	/// Checks the credentials with the server, and whether the user may log in
+	fn check(&self, username: &str, password: &str) -> Result<()> {
+		let settings = LdapConnSettings::new()
+			.set_conn_timeout(TIMEOUT)
+			.set_starttls(self.config.starttls);
+		let mut ldap = LdapConn::with_settings(settings, &self.config.url).map_err(ldap_error)?;
+		ldap.with_timeout(TIMEOUT);
+
+		let dn = match &self.config.bind {
+			LdapBind::Direct { dn } => dn.replace("{username}", &dn_escape(username)),
+			LdapBind::Search {
+				bind_dn,
+				bind_password,
+				base_dn,
+				filter,
+			} => {
+				ldap.simple_bind(bind_dn, bind_password)
+					.and_then(|result| result.success())
+					.map_err(ldap_error)?;
+				let filter = filter.replace("{username}", &ldap_escape(username));
+				let (entries, _) = ldap
+					.search(base_dn, Scope::Subtree, &filter, vec!["1.1"])
+					.and_then(|result| result.success())
+					.map_err(ldap_error)?;
+				// an ambiguous filter must not let in whoever comes first
+				match <[_; 1]>::try_from(entries) {
+					Ok([entry]) => SearchEntry::construct(entry).dn,
+					Err(_) => return Err(Error::UsernameNotFound),
+				}
+			}
+		};
+
+		match ldap
+			.simple_bind(&dn, password)
+			.and_then(|result| result.success())
+		{
+			Ok(_) => {}
+			Err(LdapError::LdapResult { result }) if result.rc == INVALID_CREDENTIALS => {
+				return Err(Error::PasswordIncorrect)
+			}
+			Err(e) => return Err(ldap_error(e)),
+		}
+
+		if let Some(group_filter) = &self.config.group_filter {
+			let (entries, _) = ldap
+				.search(&dn, Scope::Base, group_filter, vec!["1.1"])
+				.and_then(|result| result.success())
+				.map_err(ldap_error)?;
+			if entries.is_empty() {
+				log::warn!("{} is not allowed by the ldap group filter", username);
+				return Err(Error::UsernameNotFound);
+			}
+		}
+
+		let _ = ldap.unbind();
+		Ok(())
+	}
+
+	/// The user's local record, created on their first login
+	fn provision(app: &App, username: &str) -> Result<User> {
+		if let Some(user) = User::get_user(app, username)? {
+			return Ok(user);
+		}
+		if !invite::valid_username(username) {
+			return Err(Error::InvalidUsername);
+		}
+
+		let mut password = [0u8; 24];
+		rand::Rng::fill(&mut rand::thread_rng(), &mut password);
+		let user = NewUser {
+			username: username.to_owned(),
+			password: base64::engine::general_purpose::STANDARD.encode(password),
+			admin: false,
+			quota: None,
+		}
+		.insert(app)?;
+		log::info!("created user {} on their first ldap login", username);
+		Ok(user)
+	}
+}
+
+impl AuthBackend for LdapBackend {
+	fn method(&self) -> &'static str {
+		// clients send the same credentials as with local users
+		"basic"
+	}
+
+	fn authenticate(&self, app: &Arc<App>, request: &AuthRequest) -> Result<User> {
+		let (username, password) = basic_credentials(request.headers)?;
+		let username = username.to_lowercase();
+		// an empty password binds anonymously, which servers let succeed
+		if password.is_empty() {
+			return Err(Error::PasswordIncorrect);
+		}
+
+		let hash = self.password_hash(&password);
+		let cached = self
+			.cache
+			.lock()
+			.unwrap()
+			.get(&username)
+			.is_some_and(|(logged_in, cached)| logged_in.elapsed() < CACHE_TTL && *cached == hash);
+		if !cached {
+			self.check(&username, &password).inspect_err(|e| {
+				log::warn!(
+					"failed ldap login of {} from {}: {}",
+					username,
+					request
+						.client_ip
+						.map_or("unknown address".into(), |ip| ip.to_string()),
+					e
+				)
+			})?;
+			self.cache
+				.lock()
+				.unwrap()
+				.insert(username.clone(), (Instant::now(), hash));
+		}
+
+		Self::provision(app, &username)
+	}
+}
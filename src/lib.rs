@@ -0,0 +1,2378 @@
+#![forbid(unsafe_code)]
+
+mod announcement;
+mod app;
+mod auth;
+mod blob;
+mod blocklist;
+mod cluster;
+mod crypt;
+mod db;
+mod discover;
+mod dns;
+mod download;
+mod err;
+mod fetch;
+#[cfg(feature = "gemini")]
+mod gemini;
+mod health;
+mod history;
+mod insights;
+mod invite;
+#[cfg(feature = "ldap")]
+mod ldap;
+mod linkcheck;
+mod metrics;
+mod migrate;
+mod mute;
+mod network;
+mod notify;
+mod oauth;
+mod publish;
+mod query;
+mod quirks;
+mod readability;
+mod replica;
+mod retention;
+mod scheduler;
+mod scrape;
+mod search;
+mod sharing;
+mod smtp;
+mod source;
+mod stats;
+mod summary;
+mod sync;
+mod tag;
+mod team;
+mod telegram;
+mod template;
+#[cfg(feature = "test-util")]
+pub mod testing;
+mod v2;
+mod watch;
+
+use std::{
+	collections::{BTreeSet, HashMap, HashSet},
+	net::SocketAddr,
+	path::PathBuf,
+	sync::Arc,
+	time::Duration,
+};
+
+use announcement::{Announcement, NewAnnouncement, UserAnnouncement};
+use app::{App, AppUser, Status, UserSettings};
+use auth::AuthRequest;
+use axum::{
+	extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State},
+	handler::Handler,
+	http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+	middleware::{map_response, Next},
+	response::{IntoResponse, Response},
+	routing::{any, get, patch, post, put},
+	Extension, Json, Router,
+};
+use blocklist::{Block, BlockTarget, BlocklistUpdate, NewBlock};
+use chrono::{DateTime, Utc};
+use db::{
+	Account, Article, ArticleId, ArticleOrderBy, ArticleState, CapabilityToken, Category,
+	ExportOpts, Feed, ListedArticle, ListedFeed, NewFeed, NewToken, NewUser, Order, Page,
+	PatchArticleState, PatchFeed, TokenScope, User, Visibility,
+};
+pub use err::{Error, Result};
+use insights::{FeedReads, Insights, InsightsRequest};
+use itertools::{Either, Itertools};
+use linkcheck::LinkReport;
+use mute::{Mute, Mutes, NewMute};
+use network::{ClientIp, NetworkConfig};
+use notify::{NewNotifyTarget, NotifyTarget};
+use query::SearchQuery;
+use retention::PrunePreview;
+use scrape::ScraperPreset;
+
+use serde::{Deserialize, Serialize};
+use sharing::{Blogroll, Subscription};
+use stats::{ReadingStats, StatsRequest};
+use summary::{Summary, SummaryRequest};
+use sync::{Device, DeviceSync, NewDevice, NewSyncRemote, SyncRemote};
+use tag::TagFilter;
+use team::{SharedFeed, SharedFeedRequest, SharedFeedUpdate};
+use template::Templates;
+use tokio::sync::Semaphore;
+use tower_http::cors::CorsLayer;
+
+type AppState = Arc<App>;
+
+/// Limit on request bodies, OPML imports being the largest
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// Authenticates the request with the configured [`auth`] backend, attaching
+/// the user's [`AppUser`]
+async fn auth<B>(
+	State(state): State<AppState>,
+	mut req: Request<B>,
+	next: Next<B>,
+) -> Result<Response, Error> {
+	let client_ip = req.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip);
+	let peer = req
+		.extensions()
+		.get::<ConnectInfo<SocketAddr>>()
+		.map(|ConnectInfo(peer)| peer.ip());
+	// backends hash passwords or wait on directory servers
+	let (app, headers) = (state.clone(), req.headers().clone());
+	let user = tokio::task::spawn_blocking(move || {
+		auth::backend().authenticate(
+			&app,
+			&AuthRequest {
+				headers: &headers,
+				peer,
+				client_ip,
+			},
+		)
+	})
+	.await
+	.expect("authentication panicked")?;
+
+	let user_agent = req
+		.headers()
+		.get(header::USER_AGENT)
+		.and_then(|header| header.to_str().ok());
+	if let Err(e) = User::record_login(&state, &user, user_agent, client_ip) {
+		log::warn!("could not record login of {}: {}", user.username, e);
+	}
+
+	req.extensions_mut()
+		.insert(state.open_user(&user.username)?);
+	Ok(next.run(req).await)
+}
+
+/// Like `auth`, but reports failures as structured errors
+async fn auth_v2<B>(
+	state: State<AppState>,
+	req: Request<B>,
+	next: Next<B>,
+) -> Result<Response, v2::ApiError> {
+	auth(state, req, next).await.map_err(v2::ApiError)
+}
+
+/// Bounds the number of requests handled at once. Requests beyond that wait for
+/// a slot up to `queue_timeout`, then get shed with a 503.
+#[derive(Clone)]
+struct ConcurrencyLimit {
+	permits: Arc<Semaphore>,
+	queue_timeout: Duration,
+}
+
+async fn limit_concurrency<B>(
+	State(limit): State<ConcurrencyLimit>,
+	req: Request<B>,
+	next: Next<B>,
+) -> Response {
+	let permit = tokio::time::timeout(limit.queue_timeout, limit.permits.acquire_owned()).await;
+
+	match permit {
+		Ok(Ok(_permit)) => next.run(req).await,
+		_ => (
+			StatusCode::SERVICE_UNAVAILABLE,
+			[(
+				header::RETRY_AFTER,
+				limit.queue_timeout.as_secs().max(1).to_string(),
+			)],
+			"Server overloaded",
+		)
+			.into_response(),
+	}
+}
+
+/// Runs the server, or a maintenance command, as configured by the
+/// environment
+pub async fn run() -> anyhow::Result<()> {
+	// get environment, crash if missing
+	let addr = dotenvy::var("ADDRESS").unwrap_or("0.0.0.0".into());
+	let port = dotenvy::var("PORT").unwrap_or("8888".into());
+	let root = dotenvy::var("DATA_PATH")
+		.ok()
+		.map(PathBuf::from)
+		.or_else(|| {
+			dirs::data_dir().map(|mut p| {
+				p.push("nanorss");
+				p
+			})
+		})
+		.ok_or(Error::NoRootDir)?;
+
+	// maintenance commands run instead of the server
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	match args.first().map(String::as_str) {
+		#[cfg(feature = "redb")]
+		Some("migrate") => return migrate::run(&root.join("db.sled"), &args[1..]),
+		Some(command) => anyhow::bail!("unknown command {}", command),
+		None => (),
+	}
+
+	let fetch_cache_ttl = dotenvy::var("FETCH_CACHE_TTL")
+		.ok()
+		.and_then(|ttl| ttl.parse().ok())
+		.unwrap_or(300);
+	let bcrypt_cost = dotenvy::var("BCRYPT_COST")
+		.ok()
+		.and_then(|cost| cost.parse().ok())
+		.unwrap_or(10);
+	let max_concurrent_requests = dotenvy::var("MAX_CONCURRENT_REQUESTS")
+		.ok()
+		.and_then(|max| max.parse().ok())
+		.unwrap_or(64);
+	let queue_timeout = dotenvy::var("QUEUE_TIMEOUT")
+		.ok()
+		.and_then(|timeout| timeout.parse().ok())
+		.unwrap_or(5);
+	let limits = fetch::SizeLimits {
+		max_feed_size: dotenvy::var("MAX_FEED_SIZE_MB")
+			.ok()
+			.and_then(|size| size.parse::<usize>().ok())
+			.unwrap_or(20)
+			* 1024 * 1024,
+		max_page_size: dotenvy::var("MAX_PAGE_SIZE_MB")
+			.ok()
+			.and_then(|size| size.parse::<usize>().ok())
+			.unwrap_or(5)
+			* 1024 * 1024,
+		max_content_size: dotenvy::var("MAX_ARTICLE_CONTENT_KB")
+			.ok()
+			.and_then(|size| size.parse::<usize>().ok())
+			.unwrap_or(1024)
+			* 1024,
+	};
+	let dns_upstream = dotenvy::var("DNS_RESOLVER")
+		.ok()
+		.map(|upstream| upstream.parse())
+		.transpose()?
+		.unwrap_or(dns::Upstream::System);
+	let dns_cache_size = dotenvy::var("DNS_CACHE_SIZE")
+		.ok()
+		.and_then(|size| size.parse().ok())
+		.unwrap_or(1024);
+	let http = app::HttpConfig {
+		pool_max_idle_per_host: dotenvy::var("HTTP_POOL_MAX_IDLE_PER_HOST")
+			.ok()
+			.and_then(|max| max.parse().ok()),
+		pool_idle_timeout: dotenvy::var("HTTP_POOL_IDLE_TIMEOUT")
+			.ok()
+			.and_then(|timeout| timeout.parse().ok())
+			.map(Duration::from_secs),
+		http2: dotenvy::var("HTTP2")
+			.ok()
+			.and_then(|http2| http2.parse().ok())
+			.unwrap_or(true),
+		tcp_keepalive: dotenvy::var("HTTP_TCP_KEEPALIVE")
+			.ok()
+			.and_then(|keepalive| keepalive.parse().ok())
+			.map(Duration::from_secs),
+		extra_root_certs: dotenvy::var("EXTRA_ROOT_CERTS")
+			.map(|paths| paths.split(',').map(PathBuf::from).collect())
+			.unwrap_or_default(),
+	};
+	if let Ok(path) = dotenvy::var("SITE_QUIRKS") {
+		quirks::load(path.as_ref())?;
+	}
+	if let Ok(key) = dotenvy::var("ENCRYPTION_KEY") {
+		crypt::configure(crypt::KeySource::Value(&key))?;
+	}
+	else if let Ok(path) = dotenvy::var("ENCRYPTION_KEY_FILE") {
+		crypt::configure(crypt::KeySource::File(path.as_ref()))?;
+	}
+	#[cfg(feature = "keyring")]
+	if !crypt::enabled() && dotenvy::var("ENCRYPTION_KEYRING").is_ok_and(|keyring| keyring == "1") {
+		crypt::configure(crypt::KeySource::Keyring {
+			service: "nanorss",
+			user: "encryption-key",
+		})?;
+	}
+	let network = NetworkConfig {
+		allow: network::parse_networks(&dotenvy::var("ALLOWED_NETWORKS").unwrap_or_default())?,
+		deny: network::parse_networks(&dotenvy::var("DENIED_NETWORKS").unwrap_or_default())?,
+		trusted_proxies: network::parse_networks(
+			&dotenvy::var("TRUSTED_PROXIES").unwrap_or_default(),
+		)?,
+	};
+	auth::configure(
+		match dotenvy::var("AUTH_BACKEND").as_deref().unwrap_or("local") {
+			"local" => Box::new(auth::LocalBackend),
+			"proxy" => {
+				let proxies = match dotenvy::var("AUTH_PROXY_NETWORKS") {
+					Ok(list) => network::parse_networks(&list)?,
+					Err(_) => network.trusted_proxies.clone(),
+				};
+				if proxies.is_empty() {
+					anyhow::bail!("proxy auth needs AUTH_PROXY_NETWORKS or TRUSTED_PROXIES");
+				}
+				Box::new(auth::ProxyBackend {
+					header: dotenvy::var("AUTH_PROXY_HEADER")
+						.as_deref()
+						.unwrap_or("remote-user")
+						.parse()?,
+					proxies,
+				})
+			}
+			#[cfg(feature = "ldap")]
+			"ldap" => Box::new(ldap::LdapBackend::new(ldap::LdapConfig {
+				url: dotenvy::var("LDAP_URL")?,
+				starttls: dotenvy::var("LDAP_STARTTLS")
+					.ok()
+					.and_then(|starttls| starttls.parse().ok())
+					.unwrap_or(false),
+				bind: match dotenvy::var("LDAP_USER_DN") {
+					Ok(dn) => ldap::LdapBind::Direct { dn },
+					Err(_) => ldap::LdapBind::Search {
+						bind_dn: dotenvy::var("LDAP_BIND_DN")?,
+						bind_password: dotenvy::var("LDAP_BIND_PASSWORD")?,
+						base_dn: dotenvy::var("LDAP_BASE_DN")?,
+						filter: dotenvy::var("LDAP_USER_FILTER")
+							.unwrap_or_else(|_| "(uid={username})".into()),
+					},
+				},
+				group_filter: dotenvy::var("LDAP_GROUP_FILTER").ok(),
+			})),
+			backend => anyhow::bail!("unknown auth backend {}", backend),
+		},
+	);
+	if let Ok(token) = dotenvy::var("TELEGRAM_BOT_TOKEN") {
+		telegram::configure(token, dotenvy::var("TELEGRAM_API_URL").ok());
+	}
+	invite::configure(invite::RegistrationConfig {
+		mode: dotenvy::var("REGISTRATION")
+			.ok()
+			.map(|mode| mode.parse())
+			.transpose()?
+			.unwrap_or_default(),
+		rate_limit: dotenvy::var("REGISTRATION_RATE_LIMIT")
+			.ok()
+			.and_then(|limit| limit.parse().ok())
+			.unwrap_or(5),
+		open_quota: app::Quota {
+			max_feeds: dotenvy::var("REGISTRATION_MAX_FEEDS")
+				.ok()
+				.and_then(|max| max.parse().ok()),
+		},
+	});
+	metrics::configure(metrics::MetricsConfig {
+		per_user: dotenvy::var("METRICS_PER_USER")
+			.ok()
+			.and_then(|per_user| per_user.parse().ok())
+			.unwrap_or(false),
+		max_users: dotenvy::var("METRICS_MAX_USER_LABELS")
+			.ok()
+			.and_then(|max| max.parse().ok())
+			.unwrap_or(20),
+	});
+	scheduler::configure(scheduler::SchedulerConfig {
+		default_interval: dotenvy::var("REFRESH_INTERVAL")
+			.ok()
+			.and_then(|interval| interval.parse().ok())
+			.unwrap_or(60),
+	});
+	linkcheck::configure(linkcheck::LinkCheckConfig {
+		interval_hours: dotenvy::var("LINK_CHECK_INTERVAL")
+			.ok()
+			.and_then(|interval| interval.parse().ok())
+			.unwrap_or(0),
+		wayback_api: dotenvy::var("WAYBACK_API")
+			.ok()
+			.and_then(|url| url.parse().ok())
+			.unwrap_or_else(|| {
+				"https://archive.org/wayback/available"
+					.parse()
+					.expect("url is valid")
+			}),
+	});
+	if let Ok(primary) = dotenvy::var("REPLICATE_FROM") {
+		replica::configure(replica::ReplicaConfig {
+			primary: primary.parse()?,
+			username: dotenvy::var("REPLICA_USERNAME")?,
+			password: dotenvy::var("REPLICA_PASSWORD")?,
+			interval: Duration::from_secs(
+				dotenvy::var("REPLICA_INTERVAL")
+					.ok()
+					.and_then(|interval| interval.parse().ok())
+					.unwrap_or(60),
+			),
+		});
+	}
+	let username = dotenvy::var("USERNAME");
+	let password = dotenvy::var("PASSWORD");
+
+	// init logger
+	env_logger::init();
+
+	// init and seed db
+	let cfg = app::Config {
+		db_path: root.join("db.sled"),
+		blobs_path: root.join("blobs"),
+		search_path: root.join("search"),
+		fetch_cache_ttl: Duration::from_secs(fetch_cache_ttl),
+		limits,
+		bcrypt_cost,
+		dns: dns::DnsConfig {
+			upstream: dns_upstream,
+			cache_size: dns_cache_size,
+		},
+		http,
+	};
+	let app = App::new(&cfg)?;
+
+	match (username, password) {
+		(Ok(username), Ok(password)) => {
+			// the user configured by the operator administers the instance
+			let new_user = NewUser {
+				username,
+				password,
+				admin: true,
+				quota: None,
+			};
+			new_user
+				.insert(&app)
+				.map(|u| log::info!("created user {}", u.username))
+				.unwrap_or_else(|e| log::warn!("could not create user: {}", e));
+		}
+		(Err(_), Err(_)) => (),
+		(Err(_), _) | (_, Err(_)) => {
+			log::error!("both USER and PASSWD need to be set to create a user")
+		}
+	}
+
+	// init routes
+	let state = Arc::new(app);
+
+	// a replica only takes changes from its primary
+	if replica::enabled() {
+		tokio::spawn(replica::run(state.clone()));
+	}
+	else {
+		tokio::spawn(scheduler::run(state.clone()));
+		tokio::spawn(sync::run_scheduler(state.clone()));
+		tokio::spawn(telegram::run(state.clone()));
+		tokio::spawn(linkcheck::run(state.clone()));
+	}
+
+	// searches fail on an outdated or corrupted index, rebuild those in the background
+	let repair_state = state.clone();
+	tokio::task::spawn_blocking(move || {
+		repair_state
+			.repair_search_indexes()
+			.unwrap_or_else(|e| log::error!("could not check search indexes: {}", e))
+	});
+
+	let router = router(
+		state,
+		ServerConfig {
+			network,
+			max_concurrent_requests,
+			queue_timeout: Duration::from_secs(queue_timeout),
+		},
+	);
+
+	let addr = SocketAddr::new(addr.parse().unwrap(), port.parse().unwrap());
+	axum::Server::bind(&addr)
+		.serve(router.into_make_service_with_connect_info::<SocketAddr>())
+		.await
+		.unwrap();
+
+	Ok(())
+}
+
+/// Settings of the HTTP layer, see [`router`]
+struct ServerConfig {
+	network: NetworkConfig,
+	max_concurrent_requests: usize,
+	queue_timeout: Duration,
+}
+
+/// The API, with the middleware authenticating and limiting requests.
+/// Connections need their [`ConnectInfo`].
+fn router(state: AppState, config: ServerConfig) -> Router {
+	let router = Router::new()
+		.route("/api/v1/status", any(get_status))
+		.route("/api/v1/summary", get(get_summary))
+		.route("/api/v1/insights", get(get_insights))
+		.route("/api/v1/stats/reading", get(get_reading_stats))
+		.route("/api/v1/prune/preview", get(get_prune_preview))
+		.route("/api/v1/import", post(import))
+		.route("/api/v1/export", post(export))
+		.route(
+			"/api/v1/feeds",
+			get(get_feeds)
+				.post(post_feed)
+				.patch(patch_feed.layer(map_response(deprecated))),
+		)
+		.route("/api/v1/feeds/report", get(get_feeds_report))
+		.route(
+			"/api/v1/feeds/:id",
+			get(get_feed).patch(patch_feed_id).delete(delete_feed),
+		)
+		.route("/api/v1/feeds/:id/articles", get(get_feed_articles))
+		.route("/api/v1/feeds/:id/read", post(post_feed_read))
+		.route("/api/v1/articles", get(get_articles))
+		.route(
+			"/api/v1/articles/:id",
+			get(get_article)
+				.patch(patch_article_state)
+				.delete(delete_article),
+		)
+		.route("/api/v1/articles/batch", post(post_articles_batch))
+		.route("/api/v1/articles/changes", get(get_article_changes))
+		.route("/api/v1/articles/:id/content", get(get_article_content))
+		.route("/api/v1/articles/:id/snapshot", get(get_article_snapshot))
+		.route("/api/v1/articles/:id/state", patch(patch_article_state))
+		.route(
+			"/api/v1/articles/:id/read",
+			put(put_article_read).delete(delete_article_read),
+		)
+		.route(
+			"/api/v1/articles/:id/star",
+			put(put_article_star).delete(delete_article_star),
+		)
+		.route("/api/v1/streams/:stream/articles", get(get_stream_articles))
+		.route("/api/v1/links", get(get_links))
+		.route("/api/v1/categories", get(get_categories))
+		.route("/api/v1/tags", get(get_tags))
+		.route(
+			"/api/v1/categories/:name/articles",
+			get(get_category_articles),
+		)
+		.route(
+			"/api/v1/mutes",
+			get(get_mutes).post(post_mute).delete(delete_mute),
+		)
+		.route(
+			"/api/v1/scraper/presets",
+			get(get_scraper_presets)
+				.post(post_scraper_preset)
+				.delete(delete_scraper_preset),
+		)
+		.route("/api/v1/discover", get(get_discover))
+		.route("/api/v1/search", post(search))
+		.route("/api/v1/refresh", post(refresh))
+		.route("/api/v1/refresh/history", get(get_refresh_history))
+		.route("/api/v1/refresh/progress", get(get_refresh_progress))
+		.route(
+			"/api/v1/notifications/targets",
+			get(get_notify_targets)
+				.post(post_notify_target)
+				.delete(delete_notify_target),
+		)
+		.route(
+			"/api/v1/notifications/templates",
+			get(get_notify_templates).put(put_notify_templates),
+		)
+		.route("/api/v1/telegram/link", post(post_telegram_link))
+		.route(
+			"/api/v1/sync/remotes",
+			get(get_sync_remotes)
+				.post(post_sync_remote)
+				.delete(delete_sync_remote),
+		)
+		.route("/api/v1/sync", post(run_sync))
+		.route(
+			"/api/v1/devices",
+			get(get_devices).post(post_device).delete(delete_device),
+		)
+		.route("/api/v1/devices/:id/sync", post(sync_device))
+		.route(
+			"/api/v1/tokens",
+			get(get_tokens).post(post_token).delete(delete_token),
+		)
+		.route(
+			"/api/v1/blogrolls",
+			get(get_blogrolls)
+				.post(post_blogroll)
+				.delete(delete_blogroll),
+		)
+		.route(
+			"/api/v1/blogrolls/subscriptions",
+			get(get_subscriptions)
+				.post(post_subscription)
+				.delete(delete_subscription),
+		)
+		.route("/api/v1/account", get(get_account).delete(delete_account))
+		.route(
+			"/api/v1/account/settings",
+			get(get_settings).put(put_settings),
+		)
+		.route(
+			"/api/v1/admin/users",
+			get(admin_get_users).delete(admin_delete_user),
+		)
+		.route("/api/v1/admin/users/rename", post(admin_rename_user))
+		.route("/api/v1/admin/index/rebuild", post(admin_rebuild_index))
+		.route(
+			"/api/v1/admin/orphans",
+			get(get_orphan_trees).delete(delete_orphan_trees),
+		)
+		.route("/api/v1/admin/storage", get(get_storage_usage))
+		.route("/api/v1/admin/compact", post(post_compact))
+		.route("/api/v1/admin/metrics", get(get_metrics))
+		.route("/api/v1/admin/overview", get(get_overview))
+		.route(
+			"/api/v1/admin/announcement",
+			put(put_announcement).delete(delete_announcement),
+		)
+		.route(
+			"/api/v1/admin/invites",
+			get(get_invites).post(post_invite).delete(delete_invite),
+		)
+		.route("/api/v1/admin/users/quota", put(put_user_quota))
+		.route(
+			"/api/v1/admin/blocklist",
+			get(get_blocklist).post(post_block).delete(delete_block),
+		)
+		.route(
+			"/api/v1/admin/shared_feeds",
+			get(get_shared_feeds)
+				.post(post_shared_feed)
+				.delete(delete_shared_feed),
+		)
+		.route("/api/v1/announcement", get(get_announcement))
+		.route("/api/v1/announcement/ack", post(ack_announcement))
+		.route_layer(axum::middleware::from_fn_with_state(state.clone(), auth))
+		.nest(
+			"/api/v2",
+			v2::routes().route_layer(axum::middleware::from_fn_with_state(state.clone(), auth_v2)),
+		)
+		.route("/api/version", get(get_version))
+		.route("/api/v1/meta", get(get_meta))
+		.route("/api/v1/register", post(register))
+		// capability token routes, no login required
+		.route("/api/v1/publish/:token", get(get_published_feed))
+		.route("/api/v1/publish/:token/starred", get(get_starred_feed))
+		.route("/api/v1/hooks/refresh/:token", post(hook_refresh))
+		.with_state(state);
+	let router = match replica::enabled() {
+		true => router.layer(axum::middleware::from_fn(replica::read_only)),
+		false => router,
+	};
+	router
+		.layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+		.layer(axum::middleware::from_fn_with_state(
+			ConcurrencyLimit {
+				permits: Arc::new(Semaphore::new(config.max_concurrent_requests)),
+				queue_timeout: config.queue_timeout,
+			},
+			limit_concurrency,
+		))
+		.layer(CorsLayer::permissive())
+		.layer(axum::middleware::from_fn_with_state(
+			Arc::new(config.network),
+			network::filter_clients,
+		))
+}
+
+#[derive(Deserialize)]
+struct StatusRequest {
+	/// Also report what was first seen after this
+	since: Option<DateTime<Utc>>,
+}
+
+#[axum_macros::debug_handler]
+async fn get_status(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<StatusRequest>,
+) -> Result<Json<Status>> {
+	app.status(query.since).map(Json)
+}
+
+async fn get_summary(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<SummaryRequest>,
+) -> Result<Json<Summary>> {
+	tokio::task::spawn_blocking(move || Summary::new(&app, &query))
+		.await
+		.expect("summary panicked")
+		.map(Json)
+}
+
+/// Feeds the user stopped reading, as suggestions to unsubscribe from
+async fn get_insights(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<InsightsRequest>,
+) -> Result<Json<Insights>> {
+	tokio::task::spawn_blocking(move || Insights::new(&app, &query))
+		.await
+		.expect("insights panicked")
+		.map(Json)
+}
+
+async fn get_reading_stats(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<StatsRequest>,
+) -> Result<Json<ReadingStats>> {
+	tokio::task::spawn_blocking(move || ReadingStats::new(&app, &query))
+		.await
+		.expect("reading stats panicked")
+		.map(Json)
+}
+
+/// What the retention policy would prune, without pruning anything
+async fn get_prune_preview(Extension(app): Extension<AppUser>) -> Result<Json<PrunePreview>> {
+	tokio::task::spawn_blocking(move || app.settings.retention.preview(&app))
+		.await
+		.expect("prune preview panicked")
+		.map(Json)
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+	server: &'static str,
+	current: &'static str,
+	versions: &'static [&'static str],
+	capabilities: &'static [&'static str],
+}
+
+async fn get_version() -> Json<VersionInfo> {
+	Json(VersionInfo {
+		server: env!("CARGO_PKG_VERSION"),
+		current: v2::VERSIONS[v2::VERSIONS.len() - 1],
+		versions: v2::VERSIONS,
+		capabilities: v2::CAPABILITIES,
+	})
+}
+
+#[derive(Serialize)]
+struct ServerMeta {
+	version: &'static str,
+	features: Features,
+	limits: Limits,
+	auth_methods: Vec<&'static str>,
+	announcement: Option<Announcement>,
+	registration: invite::RegistrationMode,
+}
+
+#[derive(Serialize)]
+struct Features {
+	websub: bool,
+	scraping: bool,
+	notifications: bool,
+	compat_apis: &'static [&'static str],
+}
+
+#[derive(Serialize)]
+struct Limits {
+	max_body_size: usize,
+	default_page_size: usize,
+	max_page_size: usize,
+}
+
+async fn get_meta(State(state): State<AppState>) -> Result<Json<ServerMeta>> {
+	Ok(Json(ServerMeta {
+		version: env!("CARGO_PKG_VERSION"),
+		features: Features {
+			websub: false,
+			scraping: true,
+			notifications: true,
+			compat_apis: &[],
+		},
+		limits: Limits {
+			max_body_size: MAX_BODY_SIZE,
+			default_page_size: AppUser::DEFAULT_PAGE_SIZE,
+			max_page_size: AppUser::MAX_PAGE_SIZE,
+		},
+		auth_methods: vec![auth::backend().method(), "capability_token"],
+		announcement: Announcement::current(&state)?,
+		registration: invite::mode(),
+	}))
+}
+
+#[derive(Deserialize)]
+struct FeedsRequest {
+	since_revision: Option<u64>,
+}
+
+/// Changes to the feeds since a revision
+#[derive(Serialize)]
+struct FeedsDelta {
+	/// To pass as `since_revision` next time
+	revision: u64,
+	/// Feeds added or changed
+	feeds: Vec<ListedFeed>,
+	/// Ids of feeds deleted
+	deleted: Vec<u64>,
+}
+
+async fn get_feeds(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<FeedsRequest>,
+	headers: HeaderMap,
+) -> Result<Response> {
+	// the revisions are read before the feeds, so a concurrent change yields a stale
+	// etag at worst, never a missed update; unread counts change with the articles
+	// and their read state
+	let revision = app.feeds_revision()?;
+	let etag = format!(
+		"\"feeds-{}-{}-{}\"",
+		revision,
+		app.articles_revision()?.value,
+		app.read_revision()?.value
+	);
+
+	let not_modified = headers
+		.get(header::IF_NONE_MATCH)
+		.and_then(|value| value.to_str().ok())
+		.map(|value| value.split(',').any(|tag| tag.trim() == etag))
+		.unwrap_or(false);
+	if not_modified {
+		return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+	}
+
+	let since = query.since_revision.unwrap_or(0);
+	let feeds: Vec<Feed> = Feed::get_all(&app)?
+		.into_iter()
+		.filter(|feed| feed.revision > since)
+		.collect();
+	let feeds = ListedFeed::with_unread(feeds, &Article::count_unread(&app)?);
+
+	// a delta has to tell which feeds are gone as well
+	match query.since_revision {
+		Some(since) => {
+			let delta = FeedsDelta {
+				revision,
+				feeds,
+				deleted: Feed::deleted_since(&app, since)?,
+			};
+			Ok(([(header::ETAG, etag)], Json(delta)).into_response())
+		}
+		None => Ok(([(header::ETAG, etag)], Json(feeds)).into_response()),
+	}
+}
+
+#[derive(Serialize)]
+struct MarkedRead {
+	marked: usize,
+}
+
+/// Marks all articles of the feed read
+async fn post_feed_read(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<u64>,
+) -> Result<Json<MarkedRead>> {
+	Feed::get_id(&app, id)?.ok_or(Error::NotFound("feed".into()))?;
+	let marked = tokio::task::spawn_blocking(move || Article::mark_feed_read(&app, id))
+		.await
+		.expect("marking read panicked")?;
+	Ok(Json(MarkedRead { marked }))
+}
+
+async fn post_feed(
+	Extension(app): Extension<AppUser>,
+	Json(new_feed): Json<NewFeed>,
+) -> Result<()> {
+	new_feed.insert(&app).await.map(|_| ())
+}
+
+/// Marks responses of routes kept only for compatibility
+async fn deprecated<B>(mut response: Response<B>) -> Response<B> {
+	response
+		.headers_mut()
+		.insert("deprecation", HeaderValue::from_static("true"));
+	response
+}
+
+async fn patch_feed(
+	Extension(app): Extension<AppUser>,
+	Json(patch_feed): Json<PatchFeed>,
+) -> Result<()> {
+	patch_feed.apply(&app)
+}
+
+async fn get_feed(Extension(app): Extension<AppUser>, Path(id): Path<u64>) -> Result<Json<Feed>> {
+	Feed::get_id(&app, id)?
+		.map(|feed| Json(feed.redacted()))
+		.ok_or(Error::NotFound("feed".into()))
+}
+
+async fn patch_feed_id(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<u64>,
+	Json(mut patch_feed): Json<PatchFeed>,
+) -> Result<()> {
+	patch_feed.id = Some(id);
+	patch_feed.apply(&app)
+}
+
+/// Removes the feed along with its articles and their search index entries
+async fn delete_feed(Extension(app): Extension<AppUser>, Path(id): Path<u64>) -> Result<()> {
+	tokio::task::spawn_blocking(move || Feed::remove(&app, id))
+		.await
+		.expect("removing feed panicked")
+}
+
+#[derive(Deserialize)]
+struct ListingRequest {
+	/// The user's page size if not set, all articles if neither is
+	limit: Option<usize>,
+	/// Start right after this article, e.g. the last one of the previous page
+	cursor: Option<ArticleId>,
+	/// Include articles outside their feed's display window or muted
+	#[serde(default)]
+	include_hidden: bool,
+	fields: Option<Fields>,
+	/// Group near-duplicate articles, see [`cluster`]
+	#[serde(default)]
+	cluster: bool,
+	/// Only articles not read yet, the user's setting if not set
+	unread_only: Option<bool>,
+}
+
+/// Sparse fieldset: the comma-separated article fields to respond with, out of
+/// those of [`Article`] and `unread`, `starred` and `position`
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+struct Fields(BTreeSet<String>);
+
+impl Fields {
+	const COMPUTED: [&'static str; 4] = ["unread", "starred", "position", "tags"];
+	const STORED: [&'static str; 10] = [
+		"id",
+		"feed_id",
+		"published",
+		"first_seen",
+		"url",
+		"title",
+		"summary",
+		"content",
+		"authors",
+		"categories",
+	];
+}
+
+impl TryFrom<String> for Fields {
+	type Error = Error;
+
+	fn try_from(fields: String) -> Result<Self> {
+		let fields = fields
+			.split(',')
+			.map(str::trim)
+			.filter(|field| !field.is_empty())
+			.map(str::to_owned)
+			.collect::<BTreeSet<_>>();
+		match fields.iter().find(|field| {
+			!Fields::STORED.contains(&field.as_str()) && !Fields::COMPUTED.contains(&field.as_str())
+		}) {
+			Some(unknown) => Err(Error::UnknownField(unknown.clone())),
+			None => Ok(Fields(fields)),
+		}
+	}
+}
+
+/// Renders an article with the requested fields, or else the default ones of
+/// a listing or of a single article
+fn render_article(
+	app: &AppUser,
+	article: Article,
+	fields: Option<&Fields>,
+	listing: bool,
+) -> Result<serde_json::Value> {
+	let Some(Fields(fields)) = fields
+	else {
+		return Ok(match listing {
+			true => serde_json::to_value(ListedArticle::from(article)),
+			false => {
+				let position = Article::get_position(app, &article.id)?;
+				let tags = tag::get(app, &article.id)?;
+				serde_json::to_value(article).map(|mut value| {
+					value["position"] = serde_json::json!(position);
+					value["tags"] = serde_json::json!(tags);
+					value
+				})
+			}
+		}
+		.expect("articles serialize"));
+	};
+
+	let id = article.id;
+	let serde_json::Value::Object(mut object) =
+		serde_json::to_value(article).expect("articles serialize")
+	else {
+		unreachable!("articles serialize to objects");
+	};
+	object.retain(|field, _| fields.contains(field));
+	if fields.contains("unread") {
+		object.insert("unread".into(), (!Article::is_read(app, &id)?).into());
+	}
+	if fields.contains("starred") {
+		object.insert("starred".into(), Article::is_starred(app, &id)?.into());
+	}
+	if fields.contains("position") {
+		let position = Article::get_position(app, &id)?;
+		object.insert("position".into(), serde_json::json!(position));
+	}
+	if fields.contains("tags") {
+		object.insert("tags".into(), serde_json::json!(tag::get(app, &id)?));
+	}
+
+	Ok(serde_json::Value::Object(object))
+}
+
+/// Renders the articles of a listing, or if asked to, clusters of
+/// near-duplicates as their first article with the others under `alternates`
+fn render_listing(
+	app: &AppUser,
+	articles: Vec<Article>,
+	fields: Option<&Fields>,
+	cluster: bool,
+) -> Result<Vec<serde_json::Value>> {
+	if !cluster {
+		return articles
+			.into_iter()
+			.map(|article| render_article(app, article, fields, true))
+			.collect();
+	}
+
+	cluster::cluster(articles)
+		.into_iter()
+		.map(|cluster| {
+			let mut value = render_article(app, cluster.article, fields, true)?;
+			value["alternates"] = cluster
+				.alternates
+				.into_iter()
+				.map(|article| render_article(app, article, fields, true))
+				.collect::<Result<Vec<_>>>()?
+				.into();
+			Ok(value)
+		})
+		.collect()
+}
+
+/// Whether a listing only has unread articles, as requested or set by the user
+fn unread_only(app: &AppUser, requested: Option<bool>) -> bool {
+	requested.or(app.settings.unread_only).unwrap_or(false)
+}
+
+/// Whether the article belongs in a listing of unread articles only, or not
+fn is_listed(app: &AppUser, unread_only: bool, article: &Article) -> Result<bool> {
+	Ok(!unread_only || !Article::is_read(app, &article.id)?)
+}
+
+/// Articles a v1 listing responds with. They used to list all articles, which
+/// clients without a limit still get unless the user set a page size.
+fn listing_limit(app: &AppUser, requested: Option<usize>) -> usize {
+	match requested.or(app.settings.page_size) {
+		Some(limit) => app.page_size(Some(limit)),
+		None => usize::MAX,
+	}
+}
+
+async fn get_feed_articles(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<u64>,
+	Query(query): Query<ListingRequest>,
+) -> Result<Json<Vec<serde_json::Value>>> {
+	Feed::get_id(&app, id)?.ok_or(Error::NotFound("feed".into()))?;
+	let visibility = visibility(&app, query.include_hidden)?;
+	let unread_only = unread_only(&app, query.unread_only);
+
+	let articles = Article::iter_from(&app, query.cursor.as_ref(), Some(id))
+		.filter_ok(|article| is_visible(&visibility, article))
+		.filter_map(|article| {
+			article
+				.and_then(|article| Ok(is_listed(&app, unread_only, &article)?.then_some(article)))
+				.transpose()
+		})
+		.take(listing_limit(&app, query.limit))
+		.collect::<Result<_>>()?;
+	render_listing(&app, articles, query.fields.as_ref(), query.cluster).map(Json)
+}
+
+#[derive(Deserialize)]
+struct ArticleFieldsRequest {
+	fields: Option<Fields>,
+}
+
+async fn get_article(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+	Query(query): Query<ArticleFieldsRequest>,
+) -> Result<Json<serde_json::Value>> {
+	let article = Article::get_id(&app, &id)?.ok_or(Error::NotFound("article".into()))?;
+	render_article(&app, article, query.fields.as_ref(), false).map(Json)
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+	ids: Vec<ArticleId>,
+	fields: Option<Fields>,
+}
+
+/// Articles by id, e.g. those found by a search, in the order asked for.
+/// Ids of articles that no longer exist are skipped.
+async fn post_articles_batch(
+	Extension(app): Extension<AppUser>,
+	Json(request): Json<BatchRequest>,
+) -> Result<Json<Vec<serde_json::Value>>> {
+	let mut articles = vec![];
+	for id in &request.ids {
+		if let Some(article) = Article::get_id(&app, id)? {
+			articles.push(render_article(
+				&app,
+				article,
+				request.fields.as_ref(),
+				true,
+			)?);
+		}
+	}
+	Ok(Json(articles))
+}
+
+#[derive(Deserialize)]
+struct ArticleChangesRequest {
+	since_revision: Option<u64>,
+}
+
+/// Articles changed since a revision, see [`Article::changed_since`]
+#[derive(Serialize)]
+struct ArticleChanges {
+	/// To pass as `since_revision` next time
+	revision: u64,
+	/// Ids of articles added or changed, to fetch e.g. through the batch route
+	changed: Vec<ArticleId>,
+	/// Ids of articles removed
+	removed: Vec<ArticleId>,
+}
+
+/// Changes to the articles since a revision; without one, only the current
+/// revision to start from
+async fn get_article_changes(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<ArticleChangesRequest>,
+) -> Result<Json<ArticleChanges>> {
+	// read before the changes, so a concurrent change is reported twice at worst,
+	// never missed
+	let revision = app.articles_revision()?.value;
+	let (changed, removed) = match query.since_revision {
+		Some(since) => Article::changed_since(&app, since)?,
+		None => (vec![], vec![]),
+	};
+	Ok(Json(ArticleChanges {
+		revision,
+		changed,
+		removed,
+	}))
+}
+
+async fn patch_article_state(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+	Json(patch): Json<PatchArticleState>,
+) -> Result<Json<ArticleState>> {
+	patch.apply(&app, &id).map(Json)
+}
+
+#[derive(Serialize)]
+struct ArticleContent {
+	id: ArticleId,
+	content: String,
+}
+
+async fn get_article_content(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+) -> Result<Json<ArticleContent>> {
+	Article::get_id(&app, &id)?
+		.map(|article| {
+			Json(ArticleContent {
+				id: article.id,
+				content: article.content,
+			})
+		})
+		.ok_or(Error::NotFound("article".into()))
+}
+
+/// The archived page of an article of a feed in `archived_snapshot` mode. It's
+/// the original site's markup, so it is sandboxed rather than run as ours.
+async fn get_article_snapshot(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+) -> Result<Response> {
+	let page =
+		Article::get_snapshot(&app, &id)?.ok_or(Error::NotFound("article snapshot".into()))?;
+	Ok((
+		[
+			(header::CONTENT_TYPE, "text/html; charset=utf-8"),
+			(header::CONTENT_SECURITY_POLICY, "sandbox"),
+			(header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+		],
+		page,
+	)
+		.into_response())
+}
+
+async fn delete_article(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+) -> Result<()> {
+	Article::remove(&app, &id)
+}
+
+async fn put_article_read(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+) -> Result<()> {
+	Article::set_read(&app, &id, true)?;
+	FeedReads::record(&app, &id)
+}
+
+async fn delete_article_read(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+) -> Result<()> {
+	Article::set_read(&app, &id, false)
+}
+
+#[derive(Deserialize)]
+struct LinksRequest {
+	#[serde(default)]
+	dead: bool,
+}
+
+/// Links of starred articles and how their last check went, only dead ones if
+/// `dead` is set
+async fn get_links(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<LinksRequest>,
+) -> Result<Json<Vec<LinkReport>>> {
+	tokio::task::spawn_blocking(move || LinkReport::get_all(&app, query.dead))
+		.await
+		.expect("listing links panicked")
+		.map(Json)
+}
+
+async fn put_article_star(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+) -> Result<()> {
+	Article::set_starred(&app, &id, true)
+}
+
+async fn delete_article_star(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<ArticleId>,
+) -> Result<()> {
+	Article::set_starred(&app, &id, false)
+}
+
+/// Virtual streams, as most reader clients model them
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Stream {
+	All,
+	Unread,
+	Starred,
+}
+
+async fn get_stream_articles(
+	Extension(app): Extension<AppUser>,
+	Path(stream): Path<Stream>,
+	Query(query): Query<PageRequest>,
+) -> Result<Json<Page<serde_json::Value>>> {
+	let visibility = visibility(&app, query.include_hidden)?;
+	Article::page(
+		&app,
+		query.cursor.as_ref(),
+		app.page_size(query.limit),
+		|article| match stream {
+			_ if !is_visible(&visibility, article) => Ok(false),
+			Stream::All => Ok(true),
+			Stream::Unread => Ok(!Article::is_read(&app, &article.id)?),
+			Stream::Starred => Article::is_starred(&app, &article.id),
+		},
+	)?
+	.try_map_items(|articles| render_listing(&app, articles, query.fields.as_ref(), query.cluster))
+	.map(Json)
+}
+
+async fn get_categories(Extension(app): Extension<AppUser>) -> Result<Json<Vec<Category>>> {
+	Category::get_all(&app).map(Json)
+}
+
+async fn get_tags(Extension(app): Extension<AppUser>) -> Result<Json<Vec<tag::TagCount>>> {
+	tag::get_all(&app).map(Json)
+}
+
+#[derive(Deserialize)]
+struct PageRequest {
+	limit: Option<usize>,
+	cursor: Option<ArticleId>,
+	/// Include articles outside their feed's display window or muted
+	#[serde(default)]
+	include_hidden: bool,
+	fields: Option<Fields>,
+	/// Group near-duplicate articles within the page, see [`cluster`]
+	#[serde(default)]
+	cluster: bool,
+	/// Only articles not read yet, the user's setting if not set. Streams
+	/// ignore it, they're picked by name.
+	unread_only: Option<bool>,
+}
+
+/// Display windows to apply to a listing, unless hidden articles were asked for
+fn visibility(app: &AppUser, include_hidden: bool) -> Result<Option<Visibility>> {
+	match include_hidden {
+		true => Ok(None),
+		false => Visibility::new(app).map(Some),
+	}
+}
+
+fn is_visible(visibility: &Option<Visibility>, article: &Article) -> bool {
+	visibility
+		.as_ref()
+		.is_none_or(|visibility| visibility.is_visible(article))
+}
+
+async fn get_category_articles(
+	Extension(app): Extension<AppUser>,
+	Path(name): Path<String>,
+	Query(query): Query<PageRequest>,
+) -> Result<Json<Page<serde_json::Value>>> {
+	let feed_ids = Category::feed_ids(&app, &name)?;
+	let visibility = visibility(&app, query.include_hidden)?;
+	let unread_only = unread_only(&app, query.unread_only);
+	Article::page(
+		&app,
+		query.cursor.as_ref(),
+		app.page_size(query.limit),
+		|article| {
+			Ok(feed_ids.contains(&article.feed_id)
+				&& is_visible(&visibility, article)
+				&& is_listed(&app, unread_only, article)?)
+		},
+	)?
+	.try_map_items(|articles| render_listing(&app, articles, query.fields.as_ref(), query.cluster))
+	.map(Json)
+}
+
+async fn get_mutes(Extension(app): Extension<AppUser>) -> Result<Json<Vec<Mute>>> {
+	Mute::get_all(&app).map(Json)
+}
+
+async fn post_mute(
+	Extension(app): Extension<AppUser>,
+	Json(new_mute): Json<NewMute>,
+) -> Result<Json<Mute>> {
+	new_mute.insert(&app).map(Json)
+}
+
+async fn delete_mute(
+	Extension(app): Extension<AppUser>,
+	Json(DeleteRequest { id }): Json<DeleteRequest>,
+) -> Result<()> {
+	Mute::remove(&app, id)
+}
+
+async fn refresh(State(state): State<AppState>, Extension(app): Extension<AppUser>) -> Result<()> {
+	let shared_feeds = sharing::shared_feeds(&state, &app)?;
+	state.refreshes.run(app, shared_feeds).await
+}
+
+/// Progress of the running refresh, null if none is running
+async fn get_refresh_progress(
+	Extension(app): Extension<AppUser>,
+) -> Json<Option<fetch::RefreshProgress>> {
+	Json(fetch::progress(&app.username))
+}
+
+#[derive(Deserialize)]
+struct RefreshHistoryRequest {
+	limit: Option<usize>,
+}
+
+/// Reports of past refreshes, newest first
+async fn get_refresh_history(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<RefreshHistoryRequest>,
+) -> Result<Json<Vec<history::RefreshReport>>> {
+	history::RefreshReport::get_latest(&app, app.page_size(query.limit)).map(Json)
+}
+
+async fn get_notify_targets(Extension(app): Extension<AppUser>) -> Result<Json<Vec<NotifyTarget>>> {
+	Ok(Json(
+		NotifyTarget::get_all(&app)?
+			.into_iter()
+			.map(NotifyTarget::redacted)
+			.collect(),
+	))
+}
+
+async fn post_notify_target(
+	Extension(app): Extension<AppUser>,
+	Json(new_target): Json<NewNotifyTarget>,
+) -> Result<Json<NotifyTarget>> {
+	new_target
+		.insert(&app)
+		.map(|target| Json(target.redacted()))
+}
+
+#[derive(Deserialize)]
+struct DeleteRequest {
+	id: u64,
+}
+
+async fn delete_notify_target(
+	Extension(app): Extension<AppUser>,
+	Json(DeleteRequest { id }): Json<DeleteRequest>,
+) -> Result<()> {
+	NotifyTarget::remove(&app, id)
+}
+
+async fn get_notify_templates(Extension(app): Extension<AppUser>) -> Result<Json<Templates>> {
+	Templates::get(&app).map(Json)
+}
+
+/// Replaces the templates; unset ones go back to the built-in format
+async fn put_notify_templates(
+	Extension(app): Extension<AppUser>,
+	Json(templates): Json<Templates>,
+) -> Result<Json<Templates>> {
+	templates.save(&app)?;
+	Ok(Json(templates))
+}
+
+/// A code to send the Telegram bot as `/start <code>`, linking the chat
+async fn post_telegram_link(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<telegram::LinkCode>> {
+	telegram::LinkCode::create(&state, &app.username).map(Json)
+}
+
+async fn get_sync_remotes(Extension(app): Extension<AppUser>) -> Result<Json<Vec<SyncRemote>>> {
+	Ok(Json(
+		SyncRemote::get_all(&app)?
+			.into_iter()
+			.map(SyncRemote::redacted)
+			.collect(),
+	))
+}
+
+async fn post_sync_remote(
+	Extension(app): Extension<AppUser>,
+	Json(new_remote): Json<NewSyncRemote>,
+) -> Result<Json<SyncRemote>> {
+	new_remote
+		.insert(&app)
+		.map(|remote| Json(remote.redacted()))
+}
+
+async fn delete_sync_remote(
+	Extension(app): Extension<AppUser>,
+	Json(DeleteRequest { id }): Json<DeleteRequest>,
+) -> Result<()> {
+	SyncRemote::remove(&app, id)
+}
+
+/// Syncs with all remotes right away, rather than waiting for their interval
+async fn run_sync(Extension(app): Extension<AppUser>) -> Result<Json<Vec<SyncRemote>>> {
+	let mut remotes = vec![];
+	for remote in SyncRemote::get_all(&app)? {
+		remotes.push(sync::sync_remote(&app, remote).await?.redacted());
+	}
+	Ok(Json(remotes))
+}
+
+async fn get_devices(Extension(app): Extension<AppUser>) -> Result<Json<Vec<Device>>> {
+	Device::get_all(&app).map(Json)
+}
+
+async fn post_device(
+	Extension(app): Extension<AppUser>,
+	Json(new_device): Json<NewDevice>,
+) -> Result<Json<Device>> {
+	new_device.insert(&app).map(Json)
+}
+
+async fn delete_device(
+	Extension(app): Extension<AppUser>,
+	Json(DeleteRequest { id }): Json<DeleteRequest>,
+) -> Result<()> {
+	Device::remove(&app, id)
+}
+
+async fn sync_device(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<u64>,
+	Json(sync): Json<DeviceSync>,
+) -> Result<Json<sync::DeviceSyncResult>> {
+	Device::sync(&app, id, sync).map(Json)
+}
+
+#[derive(Deserialize)]
+struct FeedsReportRequest {
+	/// Replace feed urls with the suggested ones
+	#[serde(default)]
+	apply: bool,
+}
+
+async fn get_feeds_report(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<FeedsReportRequest>,
+) -> Result<Json<health::FeedsReport>> {
+	// a GET, so it passes the read-only layer of a replica
+	if query.apply && replica::enabled() {
+		return Err(Error::ReadOnly);
+	}
+	health::report(&app, query.apply).await.map(Json)
+}
+
+#[derive(Deserialize)]
+struct DiscoverRequest {
+	topic: Option<String>,
+}
+
+async fn get_discover(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<DiscoverRequest>,
+) -> Result<Json<discover::Discover>> {
+	discover::discover(&state, &app, query.topic.as_deref()).map(Json)
+}
+
+#[derive(Deserialize)]
+struct ScraperPresetsRequest {
+	/// Only list presets meant for this site
+	url: Option<url::Url>,
+}
+
+async fn get_scraper_presets(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<ScraperPresetsRequest>,
+) -> Result<Json<Vec<ScraperPreset>>> {
+	Ok(Json(
+		ScraperPreset::get_all(&app)?
+			.into_iter()
+			.filter(|preset| query.url.as_ref().is_none_or(|url| preset.matches(url)))
+			.collect(),
+	))
+}
+
+async fn post_scraper_preset(
+	Extension(app): Extension<AppUser>,
+	Json(preset): Json<ScraperPreset>,
+) -> Result<()> {
+	preset.insert(&app)
+}
+
+#[derive(Deserialize)]
+struct DeleteScraperPresetRequest {
+	name: String,
+}
+
+async fn delete_scraper_preset(
+	Extension(app): Extension<AppUser>,
+	Json(DeleteScraperPresetRequest { name }): Json<DeleteScraperPresetRequest>,
+) -> Result<()> {
+	ScraperPreset::remove(&app, &name)
+}
+
+async fn get_tokens(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<CapabilityToken>>> {
+	CapabilityToken::get_all(&state, &app.username).map(Json)
+}
+
+async fn post_token(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(new_token): Json<NewToken>,
+) -> Result<Json<CapabilityToken>> {
+	new_token.insert(&state, &app.username).map(Json)
+}
+
+#[derive(Deserialize)]
+struct RevokeRequest {
+	token: String,
+}
+
+async fn delete_token(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(RevokeRequest { token }): Json<RevokeRequest>,
+) -> Result<()> {
+	CapabilityToken::revoke(&state, &app.username, &token)
+}
+
+/// Validators of a republished feed, so readers polling it get cheap 304s
+struct FeedValidators {
+	etag: String,
+	last_modified: DateTime<Utc>,
+}
+
+impl FeedValidators {
+	const HTTP_DATE: &'static str = "%a, %d %b %Y %H:%M:%S GMT";
+
+	/// Whether the reader's copy is current; the etag decides if it sent one
+	fn matches(&self, headers: &HeaderMap) -> bool {
+		if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+			return value
+				.to_str()
+				.map(|value| value.split(',').any(|tag| tag.trim() == self.etag))
+				.unwrap_or(false);
+		}
+		headers
+			.get(header::IF_MODIFIED_SINCE)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+			.is_some_and(|since| since.timestamp() >= self.last_modified.timestamp())
+	}
+
+	fn headers(&self) -> [(header::HeaderName, String); 2] {
+		[
+			(header::ETAG, self.etag.clone()),
+			(
+				header::LAST_MODIFIED,
+				self.last_modified.format(Self::HTTP_DATE).to_string(),
+			),
+		]
+	}
+
+	fn respond(
+		&self,
+		headers: &HeaderMap,
+		render: impl FnOnce() -> Result<String>,
+	) -> Result<Response> {
+		if self.matches(headers) {
+			return Ok((StatusCode::NOT_MODIFIED, self.headers()).into_response());
+		}
+		let feed = render()?;
+		Ok((
+			self.headers(),
+			[(header::CONTENT_TYPE, "application/atom+xml")],
+			feed,
+		)
+			.into_response())
+	}
+}
+
+async fn get_published_feed(
+	State(state): State<AppState>,
+	Path(token): Path<String>,
+	headers: HeaderMap,
+) -> Result<Response> {
+	let token = CapabilityToken::authorize(&state, &token, TokenScope::Feed)?;
+	User::record_token_use(&state, &token)?;
+	let app = state.open_user(&token.username)?;
+
+	// read before rendering, so a concurrent change yields a stale etag at worst
+	let articles = app.articles_revision()?;
+	let validators = FeedValidators {
+		etag: format!("\"published-{}\"", articles.value),
+		last_modified: articles.changed,
+	};
+	validators.respond(&headers, || publish::atom_feed(&app, &token.username))
+}
+
+async fn get_starred_feed(
+	State(state): State<AppState>,
+	Path(token): Path<String>,
+	headers: HeaderMap,
+) -> Result<Response> {
+	let token = CapabilityToken::authorize(&state, &token, TokenScope::Starred)?;
+	User::record_token_use(&state, &token)?;
+	let app = state.open_user(&token.username)?;
+
+	// starred articles can also change or disappear with the articles themselves
+	let articles = app.articles_revision()?;
+	let starred = app.starred_revision()?;
+	let validators = FeedValidators {
+		etag: format!("\"starred-{}-{}\"", articles.value, starred.value),
+		last_modified: articles.changed.max(starred.changed),
+	};
+	validators.respond(&headers, || {
+		publish::starred_atom_feed(&app, &token.username)
+	})
+}
+
+#[derive(Deserialize)]
+struct HookRefreshRequest {
+	/// Refresh only this feed instead of all of them
+	feed_id: Option<u64>,
+}
+
+async fn hook_refresh(
+	State(state): State<AppState>,
+	Path(token): Path<String>,
+	Query(query): Query<HookRefreshRequest>,
+) -> Result<()> {
+	let token = CapabilityToken::authorize(&state, &token, TokenScope::Refresh)?;
+	User::record_token_use(&state, &token)?;
+	let app = state.open_user(&token.username)?;
+
+	match query.feed_id {
+		Some(id) => state.refreshes.run_one(app, id).await,
+		None => {
+			let shared_feeds = sharing::shared_feeds(&state, &app)?;
+			state.refreshes.run(app, shared_feeds).await
+		}
+	}
+}
+
+async fn get_blogrolls(State(state): State<AppState>) -> Result<Json<Vec<Blogroll>>> {
+	Blogroll::get_all(&state).map(Json)
+}
+
+#[derive(Deserialize)]
+struct PublishRequest {
+	folder: String,
+}
+
+async fn post_blogroll(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(PublishRequest { folder }): Json<PublishRequest>,
+) -> Result<Json<Blogroll>> {
+	Blogroll::publish(&state, &app.username, &folder).map(Json)
+}
+
+async fn delete_blogroll(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(PublishRequest { folder }): Json<PublishRequest>,
+) -> Result<()> {
+	Blogroll::unpublish(&state, &app.username, &folder)
+}
+
+#[derive(Serialize)]
+struct SubscriptionResponse {
+	#[serde(flatten)]
+	subscription: Subscription,
+	feeds: Vec<Feed>,
+}
+
+async fn get_subscriptions(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<SubscriptionResponse>>> {
+	let mut subscriptions = vec![];
+	for subscription in Subscription::get_all(&app)? {
+		let feeds = match Blogroll::get(&state, &subscription.owner, &subscription.folder)? {
+			Some(blogroll) => blogroll
+				.feeds(&state)?
+				.into_iter()
+				.map(Feed::public)
+				.collect(),
+			None => vec![],
+		};
+		subscriptions.push(SubscriptionResponse {
+			subscription,
+			feeds,
+		});
+	}
+
+	Ok(Json(subscriptions))
+}
+
+#[derive(Deserialize)]
+struct SubscriptionRequest {
+	owner: String,
+	folder: String,
+}
+
+async fn post_subscription(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(req): Json<SubscriptionRequest>,
+) -> Result<()> {
+	Subscription::subscribe(&state, &app, &req.owner, &req.folder)
+}
+
+async fn delete_subscription(
+	Extension(app): Extension<AppUser>,
+	Json(req): Json<SubscriptionRequest>,
+) -> Result<()> {
+	Subscription::unsubscribe(&app, &req.owner, &req.folder)
+}
+
+async fn get_account(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Account>> {
+	User::get_user(&state, &app.username)?
+		.map(|user| Json(user.into()))
+		.ok_or(Error::UsernameNotFound)
+}
+
+async fn get_settings(Extension(app): Extension<AppUser>) -> Json<UserSettings> {
+	Json(app.settings)
+}
+
+async fn put_settings(
+	Extension(mut app): Extension<AppUser>,
+	Json(settings): Json<UserSettings>,
+) -> Result<Json<UserSettings>> {
+	let reindex = settings.indexed_fields != app.settings.indexed_fields;
+	app.save_settings(settings)?;
+
+	if reindex {
+		let app = app.clone();
+		tokio::task::spawn_blocking(move || app.create_search_index())
+			.await
+			.expect("index rebuild panicked")?;
+	}
+	Ok(Json(app.settings))
+}
+
+async fn admin_get_users(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<Account>>> {
+	User::require_admin(&state, &app.username)?;
+	Ok(Json(
+		User::get_all(&state)?
+			.into_iter()
+			.map(Account::from)
+			.collect(),
+	))
+}
+
+async fn delete_account(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<()> {
+	state.delete_user(&app.username)
+}
+
+#[derive(Deserialize)]
+struct DeleteUserRequest {
+	username: String,
+}
+
+async fn admin_delete_user(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(req): Json<DeleteUserRequest>,
+) -> Result<()> {
+	User::require_admin(&state, &app.username)?;
+	state.delete_user(&req.username)
+}
+
+#[derive(Deserialize)]
+struct RenameUserRequest {
+	username: String,
+	new_username: String,
+}
+
+async fn admin_rename_user(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(req): Json<RenameUserRequest>,
+) -> Result<()> {
+	User::require_admin(&state, &app.username)?;
+	// copies the user's trees and rebuilds their search index
+	tokio::task::spawn_blocking(move || state.rename_user(&req.username, &req.new_username))
+		.await
+		.expect("renaming user panicked")
+}
+
+#[derive(Deserialize)]
+struct RebuildIndexRequest {
+	/// Only this user's index, rather than everyone's
+	username: Option<String>,
+}
+
+/// Rebuilds search indexes, returning whose were rebuilt
+async fn admin_rebuild_index(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<RebuildIndexRequest>,
+) -> Result<Json<Vec<String>>> {
+	User::require_admin(&state, &app.username)?;
+	let usernames = match query.username {
+		Some(username) => {
+			User::get_user(&state, &username)?.ok_or(Error::NotFound("user".into()))?;
+			vec![username]
+		}
+		None => User::get_all(&state)?
+			.into_iter()
+			.map(|user| user.username)
+			.collect(),
+	};
+
+	tokio::task::spawn_blocking(move || {
+		for username in &usernames {
+			state.open_user(username)?.create_search_index()?;
+		}
+		Ok(Json(usernames))
+	})
+	.await
+	.expect("index rebuild panicked")
+}
+
+async fn get_orphan_trees(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<String>>> {
+	User::require_admin(&state, &app.username)?;
+	state.orphan_trees().map(Json)
+}
+
+async fn delete_orphan_trees(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<String>>> {
+	User::require_admin(&state, &app.username)?;
+	state.drop_orphan_trees().map(Json)
+}
+
+async fn get_storage_usage(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<app::StorageUsage>> {
+	User::require_admin(&state, &app.username)?;
+	// walks the whole database
+	tokio::task::spawn_blocking(move || state.storage_usage())
+		.await
+		.expect("storage usage panicked")
+		.map(Json)
+}
+
+async fn register(
+	State(state): State<AppState>,
+	Extension(ClientIp(ip)): Extension<ClientIp>,
+	Json(registration): Json<invite::Registration>,
+) -> Result<Json<Account>> {
+	// hashing the password blocks
+	tokio::task::spawn_blocking(move || registration.register(&state, ip))
+		.await
+		.expect("registration panicked")
+		.map(Account::from)
+		.map(Json)
+}
+
+async fn get_invites(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<invite::Invite>>> {
+	User::require_admin(&state, &app.username)?;
+	invite::Invite::get_all(&state).map(Json)
+}
+
+async fn post_invite(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(new_invite): Json<invite::NewInvite>,
+) -> Result<Json<invite::Invite>> {
+	User::require_admin(&state, &app.username)?;
+	new_invite.insert(&state, &app.username).map(Json)
+}
+
+#[derive(Deserialize)]
+struct DeleteInviteRequest {
+	code: String,
+}
+
+async fn delete_invite(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(DeleteInviteRequest { code }): Json<DeleteInviteRequest>,
+) -> Result<()> {
+	User::require_admin(&state, &app.username)?;
+	invite::Invite::remove(&state, &code)
+}
+
+async fn get_blocklist(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<Block>>> {
+	User::require_admin(&state, &app.username)?;
+	Block::get_all(&state).map(Json)
+}
+
+/// Blocks a host or feed url, disabling the feeds of all users it covers
+async fn post_block(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(new_block): Json<NewBlock>,
+) -> Result<Json<BlocklistUpdate>> {
+	User::require_admin(&state, &app.username)?;
+	tokio::task::spawn_blocking(move || new_block.insert(&state, &app.username))
+		.await
+		.expect("blocking panicked")
+		.map(Json)
+}
+
+async fn delete_block(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(target): Json<BlockTarget>,
+) -> Result<Json<BlocklistUpdate>> {
+	User::require_admin(&state, &app.username)?;
+	tokio::task::spawn_blocking(move || Block::remove(&state, target))
+		.await
+		.expect("unblocking panicked")
+		.map(Json)
+}
+
+async fn get_shared_feeds(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Vec<SharedFeed>>> {
+	User::require_admin(&state, &app.username)?;
+	SharedFeed::get_all(&state).map(Json)
+}
+
+/// Shares a feed, indexing it once for all of its subscribers
+async fn post_shared_feed(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(request): Json<SharedFeedRequest>,
+) -> Result<Json<SharedFeedUpdate>> {
+	User::require_admin(&state, &app.username)?;
+	tokio::task::spawn_blocking(move || request.insert(&state, &app.username))
+		.await
+		.expect("sharing panicked")
+		.map(Json)
+}
+
+async fn delete_shared_feed(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(request): Json<SharedFeedRequest>,
+) -> Result<Json<SharedFeedUpdate>> {
+	User::require_admin(&state, &app.username)?;
+	tokio::task::spawn_blocking(move || request.remove(&state))
+		.await
+		.expect("unsharing panicked")
+		.map(Json)
+}
+
+#[derive(Deserialize)]
+struct QuotaRequest {
+	username: String,
+	quota: app::Quota,
+}
+
+async fn put_user_quota(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(request): Json<QuotaRequest>,
+) -> Result<()> {
+	User::require_admin(&state, &app.username)?;
+	User::get_user(&state, &request.username)?.ok_or(Error::UsernameNotFound)?;
+	state
+		.open_user(&request.username)?
+		.set_quota(&request.quota)
+}
+
+/// The announcement, with whether the user acknowledged it
+async fn get_announcement(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<Option<UserAnnouncement>>> {
+	Announcement::for_user(&state, &app).map(Json)
+}
+
+#[derive(Deserialize)]
+struct AckRequest {
+	id: u64,
+}
+
+async fn ack_announcement(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(AckRequest { id }): Json<AckRequest>,
+) -> Result<()> {
+	Announcement::acknowledge(&state, &app, id)
+}
+
+async fn put_announcement(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+	Json(new_announcement): Json<NewAnnouncement>,
+) -> Result<Json<Announcement>> {
+	User::require_admin(&state, &app.username)?;
+	new_announcement.insert(&state, &app).map(Json)
+}
+
+async fn delete_announcement(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<()> {
+	User::require_admin(&state, &app.username)?;
+	Announcement::clear(&state)
+}
+
+async fn get_overview(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<app::Overview>> {
+	User::require_admin(&state, &app.username)?;
+	tokio::task::spawn_blocking(move || state.overview())
+		.await
+		.expect("overview panicked")
+		.map(Json)
+}
+
+/// Refresh counters for Prometheus to scrape
+async fn get_metrics(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Response> {
+	User::require_admin(&state, &app.username)?;
+	Ok((
+		[(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+		metrics::render(),
+	)
+		.into_response())
+}
+
+/// Compacts the database on the next restart
+async fn post_compact(
+	State(state): State<AppState>,
+	Extension(app): Extension<AppUser>,
+) -> Result<Json<app::StorageUsage>> {
+	User::require_admin(&state, &app.username)?;
+	state.schedule_compaction()?;
+	tokio::task::spawn_blocking(move || state.storage_usage())
+		.await
+		.expect("storage usage panicked")
+		.map(Json)
+}
+
+#[derive(Deserialize)]
+struct ArticlesRequest {
+	feed_id: Option<u64>,
+	/// Only articles of feeds in the folder, or in folders within it
+	category: Option<String>,
+	/// Only articles with the tag
+	tag: Option<String>,
+	/// The user's page size if not set, all articles if neither is
+	limit: Option<usize>,
+	#[serde(default)]
+	offset: usize,
+	/// Start right after this article, e.g. the last one of the previous page
+	cursor: Option<ArticleId>,
+	/// Include articles outside their feed's display window or muted
+	#[serde(default)]
+	include_hidden: bool,
+	fields: Option<Fields>,
+	/// Group near-duplicate articles, see [`cluster`]
+	#[serde(default)]
+	cluster: bool,
+	/// Only articles not read yet, the user's setting if not set
+	unread_only: Option<bool>,
+}
+
+/// Articles newest-first, only loading the ones listed
+async fn get_articles(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<ArticlesRequest>,
+) -> Result<Json<Vec<serde_json::Value>>> {
+	let visibility = visibility(&app, query.include_hidden)?;
+	let category_feeds = query
+		.category
+		.as_deref()
+		.map(|name| Category::feed_ids(&app, name))
+		.transpose()?;
+	let tag_filter = query
+		.tag
+		.map(|tag| TagFilter::new(&app, &[tag], &[]))
+		.transpose()?;
+	let unread_only = unread_only(&app, query.unread_only);
+	let limit = listing_limit(&app, query.limit);
+	let articles = Article::iter_from(&app, query.cursor.as_ref(), query.feed_id)
+		.filter_ok(|article| {
+			category_feeds
+				.as_ref()
+				.is_none_or(|feed_ids| feed_ids.contains(&article.feed_id))
+				&& tag_filter
+					.as_ref()
+					.is_none_or(|filter| filter.matches(&article.id))
+				&& is_visible(&visibility, article)
+		})
+		.filter_map(|article| {
+			article
+				.and_then(|article| Ok(is_listed(&app, unread_only, &article)?.then_some(article)))
+				.transpose()
+		})
+		.skip(query.offset)
+		.take(limit)
+		.collect::<Result<_>>()?;
+	render_listing(&app, articles, query.fields.as_ref(), query.cluster).map(Json)
+}
+
+async fn import(
+	Extension(app): Extension<AppUser>,
+	body: String,
+) -> Result<Json<db::ImportReport>> {
+	let opml = opml::OPML::from_str(&body)?;
+	db::import(&app, db::ImportOpts::Opml(opml)).await.map(Json)
+}
+
+async fn export(
+	Extension(app): Extension<AppUser>,
+	Query(opts): Query<ExportOpts>,
+) -> Result<String> {
+	db::export(&app, opts)
+}
+
+#[derive(Deserialize)]
+struct ArticleRequest {
+	field_id: Option<u64>,
+	author: Option<String>,
+	/// Words and filters, see [`query`]
+	q: Option<String>,
+	/// Only articles first seen after this, e.g. the last visit
+	seen_since: Option<DateTime<Utc>>,
+	order_by: Option<ArticleOrderBy>,
+	order: Option<Order>,
+	/// Include articles matching muted keywords
+	#[serde(default)]
+	include_muted: bool,
+	/// Only articles not read yet, the user's setting if not set
+	unread_only: Option<bool>,
+}
+
+async fn search(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<ArticleRequest>,
+) -> Result<Json<Vec<ArticleId>>> {
+	let search_query: Option<SearchQuery> = query.q.as_deref().map(str::parse).transpose()?;
+	// rank of each match, best first; a query of filters alone matches anything
+	let search_results: Option<HashMap<ArticleId, usize>> = search_query
+		.as_ref()
+		.filter(|search_query| search_query.has_words())
+		.map(|search_query| app.search(search_query, AppUser::MAX_SEARCH_HITS))
+		.transpose()?
+		.map(|ids| {
+			ids.into_iter()
+				.enumerate()
+				.map(|(rank, id)| (id, rank))
+				.collect()
+		});
+	// without words to rank by, exclusions filter all articles instead of a capped
+	// number of matches
+	let excluded: Option<HashSet<ArticleId>> = search_query
+		.as_ref()
+		.filter(|search_query| !search_query.has_words() && !search_query.clauses.is_empty())
+		.map(|search_query| app.search_excluded(search_query))
+		.transpose()?;
+
+	// searches for words are ranked by relevance unless asked otherwise
+	let has_words = search_query.as_ref().is_some_and(SearchQuery::has_words);
+	let order_by = match (query.order_by, has_words) {
+		(Some(order_by), _) => order_by,
+		(None, true) => ArticleOrderBy::Relevance,
+		(None, false) => app.settings.order_by.unwrap_or(ArticleOrderBy::Published),
+	};
+	let order_by = match (order_by, &search_results) {
+		(ArticleOrderBy::Relevance, None) => ArticleOrderBy::Published,
+		(order_by, _) => order_by,
+	};
+	let order = query
+		.order
+		.or(app.settings.order)
+		.unwrap_or(match &order_by {
+			ArticleOrderBy::Title => Order::Asc,
+			ArticleOrderBy::Published | ArticleOrderBy::FirstSeen | ArticleOrderBy::Relevance => {
+				Order::Desc
+			}
+		});
+
+	// articles are stored newest-first, so publish time ordering needs no sorting;
+	// a search only loads its matches, in the same order
+	let newest_first = !matches!((&order_by, &order), (ArticleOrderBy::Published, Order::Asc));
+	let iter = match &search_results {
+		Some(ranks) => {
+			let mut ids: Vec<ArticleId> = ranks.keys().copied().collect();
+			ids.sort_unstable();
+			if !newest_first {
+				ids.reverse();
+			}
+			Either::Left(
+				ids.into_iter()
+					.filter_map(|id| Article::get_id(&app, &id).transpose()),
+			)
+		}
+		None if newest_first => Either::Right(Either::Left(Article::iter(&app))),
+		None => Either::Right(Either::Right(Article::iter(&app).rev())),
+	};
+
+	let tag_filter = search_query
+		.as_ref()
+		.map(|search_query| TagFilter::new(&app, &search_query.tags, &search_query.excluded_tags))
+		.transpose()?;
+
+	let mutes = match query.include_muted {
+		true => Mutes::default(),
+		false => Mutes::new(&app)?,
+	};
+	let unread_only = unread_only(&app, query.unread_only);
+
+	let mut articles = vec![];
+	for article in iter {
+		let article = article?;
+
+		if mutes.mutes(&article) || !is_listed(&app, unread_only, &article)? {
+			continue;
+		}
+
+		if let Some(false) = search_results.as_ref().map(|s| s.contains_key(&article.id)) {
+			continue;
+		}
+
+		if let Some(true) = excluded.as_ref().map(|e| e.contains(&article.id)) {
+			continue;
+		}
+
+		if let Some(false) = search_query.as_ref().map(|q| q.filters(&article)) {
+			continue;
+		}
+
+		if let Some(false) = tag_filter.as_ref().map(|f| f.matches(&article.id)) {
+			continue;
+		}
+
+		if let Some(false) = query.field_id.as_ref().map(|f_id| f_id == &article.feed_id) {
+			continue;
+		}
+
+		if let Some(false) = query.seen_since.map(|since| article.first_seen > since) {
+			continue;
+		}
+
+		if let Some(author) = query.author.as_ref() {
+			if !article
+				.authors
+				.iter()
+				.any(|a| a.eq_ignore_ascii_case(author))
+			{
+				continue;
+			}
+		}
+
+		articles.push(article);
+	}
+
+	let sorted = match order_by {
+		ArticleOrderBy::Title => {
+			articles.sort_by_cached_key(|art| art.title.clone());
+			true
+		}
+		ArticleOrderBy::FirstSeen => {
+			articles.sort_by_key(|art| art.first_seen);
+			true
+		}
+		ArticleOrderBy::Relevance => {
+			let ranks = search_results
+				.as_ref()
+				.expect("relevance requires a search");
+			articles.sort_by_key(|art| std::cmp::Reverse(ranks[&art.id]));
+			true
+		}
+		ArticleOrderBy::Published => false,
+	};
+	if sorted {
+		if let Order::Desc = order {
+			articles.reverse();
+		}
+	}
+
+	Ok(Json(articles.into_iter().map(|art| art.id).collect()))
+}
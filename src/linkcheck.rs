@@ -0,0 +1,248 @@
+//! Link health of starred articles, so a saved library doesn't silently rot.
+//! A periodic job checks the links of starred articles and flags dead ones,
+//! looking up an archived copy on the Wayback Machine for them.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, User},
+	AppState, Result,
+};
+
+/// How often the job looks for links due for a check
+const TICK: Duration = Duration::from_secs(60 * 60);
+/// Links checked at once per user
+const CONCURRENCY: usize = 8;
+
+static CONFIG: OnceLock<LinkCheckConfig> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct LinkCheckConfig {
+	/// Hours between checks of a link; 0 disables checking
+	pub interval_hours: u32,
+	/// Wayback Machine availability API
+	pub wayback_api: Url,
+}
+
+pub fn configure(config: LinkCheckConfig) {
+	let _ = CONFIG.set(config);
+}
+
+fn config() -> Option<&'static LinkCheckConfig> {
+	CONFIG.get().filter(|config| config.interval_hours > 0)
+}
+
+/// Outcome of the last check of an article's link
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LinkCheck {
+	pub checked: DateTime<Utc>,
+	/// Only set when the page is gone for sure: it's not found, or its host
+	/// can't be reached. Server errors and blocked checks may pass.
+	pub dead: bool,
+	pub status: Option<u16>,
+	pub error: Option<String>,
+	/// Archived copy of the page, looked up once it's dead
+	pub archive_url: Option<String>,
+}
+
+impl LinkCheck {
+	pub fn get(app: &AppUser, id: &ArticleId) -> Result<Option<LinkCheck>> {
+		app.link_checks
+			.get(id.entry_key())?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()
+			.map_err(Into::into)
+	}
+
+	fn insert(&self, app: &AppUser, id: &ArticleId) -> Result<()> {
+		app.link_checks
+			.insert(id.entry_key(), bincode::serialize(self)?)?;
+		Ok(())
+	}
+}
+
+/// A starred article's link, along with its last check
+#[derive(Serialize)]
+pub struct LinkReport {
+	pub id: ArticleId,
+	pub title: String,
+	pub url: String,
+	pub check: Option<LinkCheck>,
+}
+
+impl LinkReport {
+	/// Links of the starred articles, only dead ones if `dead_only` is set
+	pub fn get_all(app: &AppUser, dead_only: bool) -> Result<Vec<LinkReport>> {
+		let mut reports = vec![];
+		for article in Article::get_starred(app)? {
+			let Some(url) = article.url
+			else {
+				continue;
+			};
+			let check = LinkCheck::get(app, &article.id)?;
+			if dead_only && !check.as_ref().is_some_and(|check| check.dead) {
+				continue;
+			}
+			reports.push(LinkReport {
+				id: article.id,
+				title: article.title,
+				url,
+				check,
+			});
+		}
+		// ids sort newest-first
+		reports.sort_by_key(|report| report.id);
+
+		Ok(reports)
+	}
+}
+
+#[derive(Deserialize)]
+struct WaybackResponse {
+	archived_snapshots: WaybackSnapshots,
+}
+
+#[derive(Deserialize)]
+struct WaybackSnapshots {
+	closest: Option<WaybackSnapshot>,
+}
+
+#[derive(Deserialize)]
+struct WaybackSnapshot {
+	available: bool,
+	url: String,
+}
+
+/// The closest archived copy of the page, if there is one
+async fn find_archived(client: &reqwest::Client, api: &Url, url: &str) -> Result<Option<String>> {
+	let mut api = api.clone();
+	api.query_pairs_mut().append_pair("url", url);
+	let response: WaybackResponse = client
+		.get(api)
+		.send()
+		.await?
+		.error_for_status()?
+		.json()
+		.await?;
+
+	Ok(response
+		.archived_snapshots
+		.closest
+		.filter(|snapshot| snapshot.available)
+		.map(|snapshot| snapshot.url))
+}
+
+/// Checks the link with a HEAD request, or a GET one for servers that don't
+/// support HEAD; the body is never read
+async fn check(client: &reqwest::Client, url: &str) -> LinkCheck {
+	let mut result = client.head(url).send().await;
+	if let Ok(response) = &result {
+		if matches!(
+			response.status(),
+			StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+		) {
+			result = client.get(url).send().await;
+		}
+	}
+
+	let (dead, status, error) = match result {
+		Ok(response) => {
+			let status = response.status();
+			let dead = matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE);
+			(dead, Some(status.as_u16()), None)
+		}
+		Err(e) => (e.is_connect() && !e.is_timeout(), None, Some(e.to_string())),
+	};
+
+	LinkCheck {
+		checked: Utc::now(),
+		dead,
+		status,
+		error,
+		archive_url: None,
+	}
+}
+
+/// Checks the links of the user's starred articles not checked in the last
+/// `interval_hours`
+async fn check_user(app: &AppUser, config: &LinkCheckConfig) -> Result<()> {
+	let due_before = Utc::now() - chrono::Duration::hours(config.interval_hours as i64);
+	let mut due = vec![];
+	for article in Article::get_starred(app)? {
+		let Some(url) = article.url
+		else {
+			continue;
+		};
+		let prev = LinkCheck::get(app, &article.id)?;
+		if prev.as_ref().is_none_or(|prev| prev.checked < due_before) {
+			due.push((article.id, url, prev));
+		}
+	}
+
+	let mut checks = futures::stream::iter(due)
+		.map(|(id, url, prev)| async move {
+			let mut checked = check(&app.client, &url).await;
+			// archived copies don't go away, so they're looked up once
+			checked.archive_url = prev.and_then(|prev| prev.archive_url);
+			if checked.dead && checked.archive_url.is_none() {
+				checked.archive_url = find_archived(&app.client, &config.wayback_api, &url)
+					.await
+					.unwrap_or_else(|e| {
+						log::warn!("could not look up archived copy of {}: {}", url, e);
+						None
+					});
+			}
+			(id, checked)
+		})
+		.buffer_unordered(CONCURRENCY);
+	while let Some((id, checked)) = checks.next().await {
+		// the article may have been unstarred or removed meanwhile
+		if Article::is_starred(app, &id)? {
+			checked.insert(app, &id)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Periodically checks the links of all users' starred articles, if enabled
+pub async fn run(state: AppState) {
+	let Some(config) = config()
+	else {
+		return;
+	};
+
+	let mut interval = tokio::time::interval(TICK);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+	loop {
+		interval.tick().await;
+
+		let users = match User::get_all(&state) {
+			Ok(users) => users,
+			Err(e) => {
+				log::warn!("could not list users to check links of: {}", e);
+				continue;
+			}
+		};
+		for user in users {
+			let app = match state.open_user(&user.username) {
+				Ok(app) => app,
+				Err(e) => {
+					log::warn!("could not open user {}: {}", user.username, e);
+					continue;
+				}
+			};
+			if let Err(e) = check_user(&app, config).await {
+				log::warn!("link check of {} failed: {}", user.username, e);
+			}
+		}
+	}
+}
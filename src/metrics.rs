@@ -0,0 +1,140 @@
+//! Counters of refresh activity in the Prometheus text format. On instances
+//! with several users, the counters can be labeled per user to see which
+//! account generates load; the number of distinct labels is capped, users past
+//! the cap share the `_other` label, so a large instance can't blow up the
+//! series count.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+
+use crate::history::RefreshReport;
+
+/// Label of users past the cap
+const OTHER_USERS: &str = "_other";
+
+static CONFIG: OnceLock<MetricsConfig> = OnceLock::new();
+static COUNTERS: Mutex<BTreeMap<String, Counters>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug)]
+pub struct MetricsConfig {
+	/// Whether counters are labeled with the user
+	pub per_user: bool,
+	/// Users labeled individually at most
+	pub max_users: usize,
+}
+
+pub fn configure(config: MetricsConfig) {
+	let _ = CONFIG.set(config);
+}
+
+#[derive(Default, Clone, Copy)]
+struct Counters {
+	refreshes: u64,
+	feeds_fetched: u64,
+	articles_ingested: u64,
+	fetch_errors: u64,
+	fetched_bytes: u64,
+}
+
+impl Counters {
+	fn add(&mut self, report: &RefreshReport) {
+		self.refreshes += 1;
+		self.feeds_fetched += report.feeds as u64;
+		self.articles_ingested += report.new_articles as u64;
+		self.fetch_errors += report.errors.len() as u64;
+		self.fetched_bytes += report.bytes as u64;
+	}
+}
+
+/// The label the user's counters go under, empty if not labeled per user
+fn label(counters: &BTreeMap<String, Counters>, username: &str) -> String {
+	match CONFIG.get() {
+		Some(config) if config.per_user => {
+			let labeled = counters.len() - counters.contains_key(OTHER_USERS) as usize;
+			if counters.contains_key(username) || labeled < config.max_users {
+				username.to_owned()
+			}
+			else {
+				OTHER_USERS.to_owned()
+			}
+		}
+		_ => String::new(),
+	}
+}
+
+pub fn record_refresh(username: &str, report: &RefreshReport) {
+	let mut counters = COUNTERS.lock().unwrap();
+	let label = label(&counters, username);
+	counters.entry(label).or_default().add(report);
+}
+
+/// Name, help and value of each counter
+type Metric = (&'static str, &'static str, fn(&Counters) -> u64);
+
+const METRICS: [Metric; 5] = [
+	(
+		"nanorss_refreshes_total",
+		"Refreshes of a user's feeds",
+		|c| c.refreshes,
+	),
+	(
+		"nanorss_feeds_fetched_total",
+		"Feeds fetched, including failed ones",
+		|c| c.feeds_fetched,
+	),
+	(
+		"nanorss_articles_ingested_total",
+		"New articles stored",
+		|c| c.articles_ingested,
+	),
+	(
+		"nanorss_fetch_errors_total",
+		"Feeds that failed to fetch",
+		|c| c.fetch_errors,
+	),
+	(
+		"nanorss_fetched_bytes_total",
+		"Size of the fetched feeds",
+		|c| c.fetched_bytes,
+	),
+];
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// All counters, in the Prometheus text exposition format
+pub fn render() -> String {
+	let counters = COUNTERS.lock().unwrap();
+	let mut out = String::new();
+	for (name, help, value) in METRICS {
+		let _ = writeln!(out, "# HELP {} {}", name, help);
+		let _ = writeln!(out, "# TYPE {} counter", name);
+		if counters.is_empty() {
+			let _ = writeln!(out, "{} 0", name);
+		}
+		for (label, counters) in counters.iter() {
+			match label.is_empty() {
+				true => {
+					let _ = writeln!(out, "{} {}", name, value(counters));
+				}
+				false => {
+					let _ = writeln!(
+						out,
+						"{}{{user=\"{}\"}} {}",
+						name,
+						escape(label),
+						value(counters)
+					);
+				}
+			}
+		}
+	}
+
+	out
+}
@@ -0,0 +1,237 @@
+//! Changes to the database's format. Records are bincode, which is positional,
+//! so a record written before a field was added no longer decodes. The database
+//! carries the version of its format instead, and [`upgrade`] rewrites older
+//! records once on startup. `nanorss migrate` copies the database into another
+//! one, see [`to_redb`].
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::app::{App, AppUser};
+use crate::crypt;
+use crate::db::{self, ArticleId, ContentMode, FeedMeta};
+use crate::err::{FetchError, FetchErrorKind};
+use crate::fetch::CertPins;
+use crate::{Error, Result};
+
+#[cfg(feature = "redb")]
+mod to_redb;
+#[cfg(feature = "redb")]
+pub use to_redb::run;
+
+/// Bumped whenever stored records change, along with a step in [`UPGRADES`]
+const SCHEMA_VERSION: u32 = 1;
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// Upgrades to each version from the one before it
+const UPGRADES: [fn(&App) -> Result<()>; SCHEMA_VERSION as usize] = [v0::upgrade];
+
+/// Brings the database up to the current format, one version at a time. Every
+/// step skips records already upgraded, so an interrupted one resumes on the
+/// next start.
+pub fn upgrade(app: &App, db: &sled::Db) -> Result<()> {
+	let version = match db.get(SCHEMA_VERSION_KEY)? {
+		Some(bytes) => bytes
+			.as_ref()
+			.try_into()
+			.map(u32::from_be_bytes)
+			.map_err(|_| Error::Schema("schema version is malformed".into()))?,
+		// databases from before it was versioned have users, new ones nothing yet
+		None if app.users.is_empty() => SCHEMA_VERSION,
+		None => 0,
+	};
+	if version > SCHEMA_VERSION {
+		return Err(Error::Schema(format!(
+			"database is of version {}, newer than this build's {}",
+			version, SCHEMA_VERSION
+		)));
+	}
+
+	for (from, step) in UPGRADES.iter().enumerate().skip(version as usize) {
+		log::info!("upgrading database from version {}", from);
+		step(app)?;
+		db.insert(SCHEMA_VERSION_KEY, &(from as u32 + 1).to_be_bytes())?;
+	}
+	db.insert(SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_be_bytes())?;
+	db.flush()?;
+
+	Ok(())
+}
+
+/// Users by their key, which unlike their records decodes in any version
+fn usernames(app: &App) -> Result<Vec<String>> {
+	app.users
+		.iter()
+		.keys()
+		.map(|key| Ok(String::from_utf8(key?.to_vec())?))
+		.collect()
+}
+
+/// The format before the database was versioned
+mod v0 {
+	use super::*;
+
+	#[derive(Deserialize)]
+	struct User {
+		username: String,
+		pass_hash: String,
+	}
+
+	/// Scrapers weren't implemented yet
+	#[derive(Deserialize)]
+	struct ScraperConfig {}
+
+	#[derive(Deserialize)]
+	struct Feed {
+		id: u64,
+		url: url::Url,
+		name: String,
+		_scraper: Option<ScraperConfig>,
+		last_fetch_time: DateTime<Utc>,
+		last_error: Option<String>,
+	}
+
+	/// Keyed by the feed-provided entry id
+	#[derive(Deserialize)]
+	struct Article {
+		id: String,
+		feed_id: u64,
+		published: DateTime<Utc>,
+		url: Option<String>,
+		title: String,
+		summary: String,
+		content: String,
+	}
+
+	pub fn upgrade(app: &App) -> Result<()> {
+		upgrade_users(app)?;
+		for username in usernames(app)? {
+			let user = app.open_user(&username)?;
+			let feeds = upgrade_feeds(&user)?;
+			let articles = upgrade_articles(&user)?;
+			log::info!(
+				"upgraded {} feeds and {} articles of {}",
+				feeds,
+				articles,
+				username
+			);
+			// the index was a single value in the user's index tree then
+			user.create_search_index()?;
+		}
+		Ok(())
+	}
+
+	/// Users could only be set up by the operator, through `USERNAME`, so they
+	/// all become administrators
+	fn upgrade_users(app: &App) -> Result<()> {
+		for item in app.users.iter() {
+			let (key, bytes) = item?;
+			if crypt::decode::<db::User>(&bytes).is_ok() {
+				continue;
+			}
+
+			let old: User = bincode::deserialize(&bytes)?;
+			let user = db::User {
+				username: old.username,
+				pass_hash: old.pass_hash,
+				admin: true,
+				last_login: None,
+				last_token: None,
+				last_user_agent: None,
+				last_ip: None,
+			};
+			app.users.insert(key, crypt::encode(&user)?)?;
+		}
+		Ok(())
+	}
+
+	/// Feeds keep their id, which articles refer to. Fields added since get
+	/// their defaults, and the last error its message.
+	fn upgrade_feeds(app: &AppUser) -> Result<usize> {
+		let mut upgraded = 0;
+		for item in app.feeds.iter() {
+			let (_, bytes) = item?;
+			if crypt::decode::<db::Feed>(&bytes).is_ok() {
+				continue;
+			}
+
+			let old: Feed = bincode::deserialize(&bytes)?;
+			db::Feed {
+				id: old.id,
+				url: db::normalize_url(&old.url)?,
+				original_url: old.url.to_string(),
+				name: old.name,
+				category: None,
+				scraper: None,
+				content_mode: ContentMode::default(),
+				auto_read: false,
+				hide_after_days: None,
+				accept_invalid_certs: false,
+				downloader: None,
+				watch: None,
+				request: None,
+				refresh_interval: None,
+				fetch_schedule: None,
+				priority: 0,
+				blocked: false,
+				cert_pins: CertPins::new(),
+				subscribed: Utc::now(),
+				last_fetch_time: old.last_fetch_time,
+				last_error: old.last_error.map(|message| FetchError {
+					kind: FetchErrorKind::Other,
+					status: None,
+					line: None,
+					column: None,
+					message,
+				}),
+				meta: FeedMeta::default(),
+				revision: 0,
+			}
+			.insert(app)?;
+			upgraded += 1;
+		}
+		Ok(upgraded)
+	}
+
+	/// Moves articles under their [`ArticleId`]. When they were first seen wasn't
+	/// recorded, the publish time stands in for it.
+	fn upgrade_articles(app: &AppUser) -> Result<usize> {
+		// upgraded articles are inserted into the same tree
+		let keys = app
+			.articles
+			.iter()
+			.keys()
+			.collect::<sled::Result<Vec<_>>>()?;
+		let mut upgraded = 0;
+		for key in keys {
+			let Some(bytes) = app.articles.get(&key)?
+			else {
+				continue;
+			};
+			if db::Article::decodes(&bytes) {
+				continue;
+			}
+
+			let old: Article = bincode::deserialize(&bytes)?;
+			let id = ArticleId::new(old.published, old.feed_id, &old.id);
+			db::Article {
+				id,
+				feed_id: old.feed_id,
+				published: old.published,
+				first_seen: old.published,
+				url: old.url,
+				title: old.title,
+				summary: old.summary,
+				content: old.content,
+				authors: vec![],
+				categories: vec![],
+			}
+			.insert(app)?;
+			if key != id.as_bytes() {
+				app.articles.remove(key)?;
+			}
+			upgraded += 1;
+		}
+		Ok(upgraded)
+	}
+}
@@ -0,0 +1,111 @@
+//! `nanorss migrate --to redb [--out <path>]`: copies the sled database into
+//! another embedded database, one table per tree with keys and values as they
+//! are, and verifies the copy entry by entry. The server must be stopped, sled
+//! refuses to open a database in use.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use redb::{ReadableTable, ReadableTableMetadata, TableDefinition};
+
+/// Entries written per transaction, so large trees aren't held in memory
+const BATCH_SIZE: usize = 10_000;
+
+struct Options {
+	out: PathBuf,
+}
+
+fn parse_args(db_path: &Path, args: &[String]) -> anyhow::Result<Options> {
+	let mut to = None;
+	let mut out = None;
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--to" => to = args.next().cloned(),
+			"--out" => out = args.next().map(PathBuf::from),
+			_ => bail!("unknown argument {}", arg),
+		}
+	}
+
+	match to.as_deref() {
+		Some("redb") => Ok(Options {
+			out: out.unwrap_or_else(|| db_path.with_extension("redb")),
+		}),
+		Some(backend) => bail!("unsupported backend {}, only redb is", backend),
+		None => bail!("usage: nanorss migrate --to redb [--out <path>]"),
+	}
+}
+
+fn tree_name(name: &[u8]) -> anyhow::Result<&str> {
+	std::str::from_utf8(name).context("tree name is not utf8")
+}
+
+fn copy_tree(tree: &sled::Tree, target: &redb::Database, name: &str) -> anyhow::Result<usize> {
+	let table = TableDefinition::<&[u8], &[u8]>::new(name);
+	let mut entries = tree.iter().peekable();
+	let mut copied = 0;
+
+	// empty trees get a table too
+	loop {
+		let txn = target.begin_write()?;
+		{
+			let mut table = txn.open_table(table)?;
+			for item in entries.by_ref().take(BATCH_SIZE) {
+				let (key, value) = item?;
+				table.insert(key.as_ref(), value.as_ref())?;
+				copied += 1;
+			}
+		}
+		txn.commit()?;
+
+		if entries.peek().is_none() {
+			return Ok(copied);
+		}
+	}
+}
+
+fn verify_tree(tree: &sled::Tree, target: &redb::Database, name: &str) -> anyhow::Result<()> {
+	let txn = target.begin_read()?;
+	let table = txn.open_table(TableDefinition::<&[u8], &[u8]>::new(name))?;
+	if table.len()? != tree.len() as u64 {
+		bail!(
+			"{} has {} entries, copied {}",
+			name,
+			tree.len(),
+			table.len()?
+		);
+	}
+
+	// both iterate in byte order of the keys
+	for (item, copied) in tree.iter().zip(table.iter()?) {
+		let (key, value) = item?;
+		let (copied_key, copied_value) = copied?;
+		if key.as_ref() != copied_key.value() || value.as_ref() != copied_value.value() {
+			bail!("{} differs from its copy", name);
+		}
+	}
+
+	Ok(())
+}
+
+pub fn run(db_path: &Path, args: &[String]) -> anyhow::Result<()> {
+	let options = parse_args(db_path, args)?;
+	if options.out.exists() {
+		bail!("{} already exists", options.out.display());
+	}
+
+	let db = sled::open(db_path)
+		.with_context(|| format!("could not open {}, is nanorss running?", db_path.display()))?;
+	let target = redb::Database::create(&options.out)?;
+
+	for name in db.tree_names() {
+		let name = tree_name(&name)?;
+		let tree = db.open_tree(name)?;
+		let copied = copy_tree(&tree, &target, name)?;
+		verify_tree(&tree, &target, name)?;
+		println!("{}: {} entries", name, copied);
+	}
+
+	println!("migrated to {}", options.out.display());
+	Ok(())
+}
@@ -0,0 +1,117 @@
+//! Keywords and phrases the user muted, optionally for a while, e.g. an
+//! election for two weeks. Mutes apply when listing and searching; articles
+//! are kept, so muted ones reappear once the mute expires or is removed.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{app::AppUser, db::Article, Error, Result};
+
+#[derive(Deserialize)]
+pub struct NewMute {
+	pub keyword: String,
+	/// Muted until removed if not set
+	pub expires_in_days: Option<u32>,
+}
+
+impl NewMute {
+	pub fn insert(self, app: &AppUser) -> Result<Mute> {
+		let keyword = self.keyword.trim().to_lowercase();
+		if keyword.is_empty() {
+			return Err(Error::EmptyField("keyword"));
+		}
+
+		let mute = Mute {
+			id: app.db.generate_id()?,
+			keyword,
+			created: Utc::now(),
+			expires: self
+				.expires_in_days
+				.map(|days| Utc::now() + chrono::Duration::days(days as i64)),
+		};
+		let mut mutes = Mute::get_all(app)?;
+		mutes.push(mute.clone());
+		Mute::save(app, &mutes)?;
+
+		Ok(mute)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mute {
+	pub id: u64,
+	/// Lowercase
+	pub keyword: String,
+	pub created: DateTime<Utc>,
+	pub expires: Option<DateTime<Utc>>,
+}
+
+impl Mute {
+	fn is_active(&self) -> bool {
+		self.expires.is_none_or(|expires| expires > Utc::now())
+	}
+
+	/// The mutes that haven't expired; expired ones are dropped when saving
+	pub fn get_all(app: &AppUser) -> Result<Vec<Mute>> {
+		let mutes: Vec<Mute> = app
+			.meta
+			.get(AppUser::META_MUTES)?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()?
+			.unwrap_or_default();
+		Ok(mutes.into_iter().filter(Mute::is_active).collect())
+	}
+
+	fn save(app: &AppUser, mutes: &[Mute]) -> Result<()> {
+		app.meta
+			.insert(AppUser::META_MUTES, bincode::serialize(mutes)?)?;
+		Ok(())
+	}
+
+	pub fn remove(app: &AppUser, id: u64) -> Result<()> {
+		let mut mutes = Self::get_all(app)?;
+		let len = mutes.len();
+		mutes.retain(|mute| mute.id != id);
+		if mutes.len() == len {
+			return Err(Error::NotFound("mute".into()));
+		}
+		Self::save(app, &mutes)
+	}
+}
+
+/// The user's active mutes, for filtering listings
+#[derive(Default)]
+pub struct Mutes(Vec<String>);
+
+impl Mutes {
+	pub fn new(app: &AppUser) -> Result<Self> {
+		Ok(Self(
+			Mute::get_all(app)?
+				.into_iter()
+				.map(|mute| mute.keyword)
+				.collect(),
+		))
+	}
+
+	/// Whether the title or summary contains a muted keyword as whole words
+	pub fn mutes(&self, article: &Article) -> bool {
+		if self.0.is_empty() {
+			return false;
+		}
+		let title = article.title.to_lowercase();
+		let summary = article.summary.to_lowercase();
+		self.0
+			.iter()
+			.any(|keyword| contains_words(&title, keyword) || contains_words(&summary, keyword))
+	}
+}
+
+/// Whether `text` contains `words` not directly preceded or followed by
+/// another letter or digit, so muting "war" leaves "software" alone
+fn contains_words(text: &str, words: &str) -> bool {
+	text.match_indices(words).any(|(start, _)| {
+		let before = text[..start].chars().next_back();
+		let after = text[start + words.len()..].chars().next();
+		!before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+	})
+}
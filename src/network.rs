@@ -0,0 +1,101 @@
+//! Network-level access control for internet-facing deployments: requests are
+//! only accepted from allowed networks and never from denied ones, judged by the
+//! client address. Behind a reverse proxy that address is taken from
+//! `X-Forwarded-For`, but only if the request came through a trusted proxy, as
+//! anyone else can send that header.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::{
+	extract::{ConnectInfo, State},
+	http::{Request, StatusCode},
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[derive(Default, Debug)]
+pub struct NetworkConfig {
+	/// If any are set, only clients in these networks are let in
+	pub allow: Vec<IpNet>,
+	/// Clients in these networks are turned away, even if allowed
+	pub deny: Vec<IpNet>,
+	/// Proxies whose `X-Forwarded-For` is trusted
+	pub trusted_proxies: Vec<IpNet>,
+}
+
+/// Parses a comma separated list of networks in CIDR notation or single
+/// addresses
+pub fn parse_networks(list: &str) -> anyhow::Result<Vec<IpNet>> {
+	list.split(',')
+		.map(str::trim)
+		.filter(|network| !network.is_empty())
+		.map(|network| {
+			network
+				.parse::<IpNet>()
+				.or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+				.map_err(|_| anyhow::anyhow!("invalid network {}", network))
+		})
+		.collect()
+}
+
+/// Address of the client a request came from, as far as it can be trusted
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+impl NetworkConfig {
+	fn contains(networks: &[IpNet], ip: &IpAddr) -> bool {
+		networks.iter().any(|network| network.contains(ip))
+	}
+
+	/// The client address: the peer, or if that's a trusted proxy, the last
+	/// address it forwarded for that isn't a trusted proxy itself
+	pub fn client_ip<B>(&self, peer: IpAddr, req: &Request<B>) -> IpAddr {
+		if !Self::contains(&self.trusted_proxies, &peer) {
+			return peer;
+		}
+
+		// each proxy appends the address it got the request from
+		let forwarded: Vec<IpAddr> = req
+			.headers()
+			.get_all(FORWARDED_FOR)
+			.iter()
+			.filter_map(|header| header.to_str().ok())
+			.flat_map(|header| header.split(','))
+			.filter_map(|ip| ip.trim().parse().ok())
+			.collect();
+		forwarded
+			.iter()
+			.rev()
+			.find(|ip| !Self::contains(&self.trusted_proxies, ip))
+			.or(forwarded.first())
+			.copied()
+			.unwrap_or(peer)
+	}
+
+	pub fn is_allowed(&self, ip: &IpAddr) -> bool {
+		(self.allow.is_empty() || Self::contains(&self.allow, ip))
+			&& !Self::contains(&self.deny, ip)
+	}
+}
+
+/// Determines the client address, rejecting clients that aren't allowed and
+/// attaching the address to allowed requests as [`ClientIp`]
+pub async fn filter_clients<B>(
+	State(config): State<Arc<NetworkConfig>>,
+	ConnectInfo(peer): ConnectInfo<SocketAddr>,
+	mut req: Request<B>,
+	next: Next<B>,
+) -> Response {
+	let ip = config.client_ip(peer.ip(), &req);
+	if !config.is_allowed(&ip) {
+		log::warn!("rejected request from {} to {}", ip, req.uri().path());
+		return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+	}
+
+	req.extensions_mut().insert(ClientIp(ip));
+	next.run(req).await
+}
@@ -0,0 +1,616 @@
+//! Notifications about new articles, sent to targets the user configures. Each
+//! transport implements [`Notifier`]; supporting another service takes a new
+//! implementation and a [`Transport`] variant for its configuration.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	crypt,
+	db::{Article, ArticleId, Feed},
+	smtp,
+	telegram::TelegramChat,
+	template::{Formatted, Templates},
+	watch, Error, Result,
+};
+
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+/// Delivers notifications through some service
+pub trait Notifier {
+	fn send<'a>(
+		&'a self,
+		app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>>;
+}
+
+/// POSTs each notification as JSON
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+	pub url: Url,
+}
+
+impl Notifier for Webhook {
+	fn send<'a>(
+		&'a self,
+		app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>> {
+		async move {
+			let request = app.client.post(self.url.clone());
+			let request = match &notification.formatted.webhook {
+				Some(body) => request
+					.header(reqwest::header::CONTENT_TYPE, "application/json")
+					.body(body.clone()),
+				None => request.json(notification),
+			};
+			request.send().await?.error_for_status()?;
+			Ok(())
+		}
+		.boxed()
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Email {
+	pub server: smtp::Server,
+	pub from: String,
+	pub to: Vec<String>,
+}
+
+impl Notifier for Email {
+	fn send<'a>(
+		&'a self,
+		_app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>> {
+		async move {
+			let message = smtp::Message {
+				from: &self.from,
+				to: &self.to,
+				subject: &notification.title,
+				body: &notification.text(),
+				html: notification.formatted.html.as_deref(),
+			};
+			smtp::send(&self.server, &message).await
+		}
+		.boxed()
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ntfy {
+	/// Base url of the ntfy server, e.g. `https://ntfy.sh`
+	pub server: Url,
+	pub topic: String,
+	/// Access token, for protected topics
+	pub token: Option<String>,
+}
+
+impl Notifier for Ntfy {
+	fn send<'a>(
+		&'a self,
+		app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>> {
+		async move {
+			let mut request = app
+				.client
+				.post(self.server.join(&self.topic)?)
+				// headers can't carry non-ASCII text, ntfy decodes RFC 2047 words
+				.header("Title", smtp::encode_header(&notification.title))
+				.body(
+					notification
+						.formatted
+						.text
+						.clone()
+						.unwrap_or_else(|| notification.body.clone()),
+				);
+			if let Some(url) = notification.articles.first().and_then(|a| a.url.as_ref()) {
+				request = request.header("Click", url);
+			}
+			if let Some(token) = &self.token {
+				request = request.bearer_auth(token);
+			}
+			request.send().await?.error_for_status()?;
+			Ok(())
+		}
+		.boxed()
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Gotify {
+	/// Base url of the Gotify server
+	pub server: Url,
+	/// Application token
+	pub token: String,
+	pub priority: Option<u8>,
+}
+
+impl Notifier for Gotify {
+	fn send<'a>(
+		&'a self,
+		app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>> {
+		async move {
+			app.client
+				.post(self.server.join("message")?)
+				.header("X-Gotify-Key", &self.token)
+				.json(&serde_json::json!({
+					"title": notification.title,
+					"message": notification.text(),
+					"priority": self.priority.unwrap_or(5),
+				}))
+				.send()
+				.await?
+				.error_for_status()?;
+			Ok(())
+		}
+		.boxed()
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Telegram {
+	pub bot_token: String,
+	pub chat_id: String,
+}
+
+impl Notifier for Telegram {
+	fn send<'a>(
+		&'a self,
+		app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>> {
+		async move {
+			app.client
+				.post(format!(
+					"{}/bot{}/sendMessage",
+					TELEGRAM_API, self.bot_token
+				))
+				.json(&serde_json::json!({
+					"chat_id": self.chat_id,
+					"text": format!("{}\n\n{}", notification.title, notification.text()),
+					"disable_web_page_preview": notification.articles.len() > 1,
+				}))
+				.send()
+				.await?
+				.error_for_status()?;
+			Ok(())
+		}
+		.boxed()
+	}
+}
+
+/// Posts to a room as a user whose access token is given, which must have
+/// joined the room
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Matrix {
+	/// Base url of the homeserver, e.g. `https://matrix.example.org`
+	pub homeserver: Url,
+	pub access_token: String,
+	/// Internal id of the room, e.g. `!abcdef:example.org`
+	pub room_id: String,
+}
+
+impl Notifier for Matrix {
+	fn send<'a>(
+		&'a self,
+		app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>> {
+		async move {
+			// the transaction id only has to be unique per access token
+			let txn_id = format!("nanorss-{}", rand::random::<u64>());
+			let mut url = self.homeserver.clone();
+			url.path_segments_mut()
+				.map_err(|_| Error::Url(url::ParseError::RelativeUrlWithCannotBeABaseBase))?
+				.pop_if_empty()
+				.extend([
+					"_matrix",
+					"client",
+					"v3",
+					"rooms",
+					&self.room_id,
+					"send",
+					"m.room.message",
+					&txn_id,
+				]);
+
+			app.client
+				.put(url)
+				.bearer_auth(&self.access_token)
+				.json(&serde_json::json!({
+					"msgtype": "m.text",
+					"body": format!("{}\n\n{}", notification.title, notification.text()),
+					"format": "org.matrix.custom.html",
+					"formatted_body": notification.html(),
+				}))
+				.send()
+				.await?
+				.error_for_status()?;
+			Ok(())
+		}
+		.boxed()
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Transport {
+	Webhook(Webhook),
+	Email(Email),
+	Ntfy(Ntfy),
+	Gotify(Gotify),
+	Telegram(Telegram),
+	Matrix(Matrix),
+	/// A chat linked to the instance's bot, see [`crate::telegram`]
+	TelegramBot(TelegramChat),
+}
+
+impl Transport {
+	/// Drops the transport's credentials, for showing it through the API;
+	/// required ones are left empty
+	fn redact(&mut self) {
+		match self {
+			Transport::Email(email) => email.server.password = None,
+			Transport::Ntfy(ntfy) => ntfy.token = None,
+			Transport::Gotify(gotify) => gotify.token.clear(),
+			Transport::Telegram(telegram) => telegram.bot_token.clear(),
+			Transport::Matrix(matrix) => matrix.access_token.clear(),
+			Transport::Webhook(_) | Transport::TelegramBot(_) => {}
+		}
+	}
+
+	fn notifier(&self) -> &dyn Notifier {
+		match self {
+			Transport::Webhook(webhook) => webhook,
+			Transport::Email(email) => email,
+			Transport::Ntfy(ntfy) => ntfy,
+			Transport::Gotify(gotify) => gotify,
+			Transport::Telegram(telegram) => telegram,
+			Transport::Matrix(matrix) => matrix,
+			Transport::TelegramBot(chat) => chat,
+		}
+	}
+}
+
+/// Which new articles a target is notified about; unset criteria match all
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct TargetFilter {
+	pub feed_ids: Option<BTreeSet<u64>>,
+	/// Categories of the articles' feeds
+	pub categories: Option<BTreeSet<String>>,
+	/// Any of these must appear in the title, case-insensitively
+	pub keywords: Vec<String>,
+}
+
+impl TargetFilter {
+	fn matches(&self, article: &NotifiedArticle) -> bool {
+		let title = article.title.to_lowercase();
+		self.feed_ids
+			.as_ref()
+			.is_none_or(|feed_ids| feed_ids.contains(&article.feed_id))
+			&& self.categories.as_ref().is_none_or(|categories| {
+				article
+					.category
+					.as_ref()
+					.is_some_and(|category| categories.contains(category))
+			}) && (self.keywords.is_empty()
+			|| self
+				.keywords
+				.iter()
+				.any(|keyword| title.contains(&keyword.to_lowercase())))
+	}
+}
+
+/// How new articles of one refresh cycle are grouped into notifications
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BatchingRules {
+	/// Send one grouped notification per refresh instead of one per article
+	pub digest: bool,
+	/// Maximum number of articles listed in a digest
+	pub max_listed: Option<usize>,
+	/// Minimum number of new articles before anything is sent
+	pub min_articles: usize,
+}
+
+impl Default for BatchingRules {
+	fn default() -> Self {
+		Self {
+			digest: true,
+			max_listed: Some(10),
+			min_articles: 1,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+pub struct NewNotifyTarget {
+	pub transport: Transport,
+	pub batching: Option<BatchingRules>,
+	pub filter: Option<TargetFilter>,
+}
+
+impl NewNotifyTarget {
+	pub fn insert(self, app: &AppUser) -> Result<NotifyTarget> {
+		// chats are only targeted once linked through the bot
+		if let Transport::TelegramBot(_) = self.transport {
+			return Err(Error::Forbidden);
+		}
+		if let Transport::Email(email) = &self.transport {
+			let mut addresses = std::iter::once(&email.from).chain(&email.to);
+			if let Some(address) = addresses.find(|a| !smtp::valid_address(a)) {
+				return Err(Error::InvalidAddress(address.clone()));
+			}
+		}
+
+		let target = NotifyTarget {
+			id: app.db.generate_id()?,
+			transport: self.transport,
+			batching: self.batching.unwrap_or_default(),
+			filter: self.filter.unwrap_or_default(),
+		};
+		target.insert(app)?;
+
+		Ok(target)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NotifyTarget {
+	pub id: u64,
+	pub transport: Transport,
+	pub batching: BatchingRules,
+	pub filter: TargetFilter,
+}
+
+impl NotifyTarget {
+	/// The target as shown through the API, without its credentials
+	pub fn redacted(mut self) -> NotifyTarget {
+		self.transport.redact();
+		self
+	}
+
+	// stored as JSON, bincode can't decode the internally tagged transport
+	pub fn insert(&self, app: &AppUser) -> Result<()> {
+		app.notify_targets.insert(
+			bincode::serialize(&self.id)?,
+			crypt::seal(serde_json::to_vec(self).expect("targets serialize")),
+		)?;
+		Ok(())
+	}
+
+	pub fn remove(app: &AppUser, id: u64) -> Result<()> {
+		app.notify_targets
+			.remove(bincode::serialize(&id)?)?
+			.ok_or(Error::NotFound("notification target".into()))?;
+		Ok(())
+	}
+
+	/// Targets that don't decode, e.g. ones stored in an older format, are skipped
+	pub fn get_all(app: &AppUser) -> Result<Vec<NotifyTarget>> {
+		let mut targets = vec![];
+		for item in app.notify_targets.iter() {
+			let (_, v) = item?;
+			match serde_json::from_slice(&crypt::open(&v)?) {
+				Ok(target) => targets.push(target),
+				Err(e) => log::warn!("skipping notification target of {}: {}", app.username, e),
+			}
+		}
+		Ok(targets)
+	}
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct NotifiedArticle {
+	pub id: ArticleId,
+	pub feed_id: u64,
+	pub feed_name: String,
+	pub category: Option<String>,
+	pub title: String,
+	pub url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Notification {
+	pub title: String,
+	pub body: String,
+	pub articles: Vec<NotifiedArticle>,
+	/// New articles, including ones a digest doesn't list
+	#[serde(skip)]
+	pub total: usize,
+	/// Feeds the new articles are in
+	#[serde(skip)]
+	pub feeds: usize,
+	#[serde(skip)]
+	pub digest: bool,
+	/// Parts rendered from the user's templates, replacing the built-in ones
+	#[serde(skip)]
+	formatted: Formatted,
+}
+
+impl Notification {
+	/// The body followed by the articles' links, for transports that only
+	/// take plain text
+	fn text(&self) -> String {
+		if let Some(text) = &self.formatted.text {
+			return text.clone();
+		}
+		let links = self
+			.articles
+			.iter()
+			.filter_map(|article| article.url.as_deref())
+			.collect::<Vec<_>>();
+		match links.is_empty() {
+			true => self.body.clone(),
+			false => format!("{}\n\n{}", self.body, links.join("\n")),
+		}
+	}
+
+	/// The title in bold above the articles, linked where they have a url, for
+	/// transports that render HTML
+	fn html(&self) -> String {
+		if let Some(html) = &self.formatted.html {
+			return html.clone();
+		}
+		let mut html = format!("<strong>{}</strong>", watch::escape(&self.title));
+		let items = self
+			.articles
+			.iter()
+			.map(|article| {
+				let title = watch::escape(&article.title);
+				let link = match &article.url {
+					Some(url) => format!("<a href=\"{}\">{}</a>", watch::escape(url), title),
+					None => title,
+				};
+				match article.feed_name == self.title {
+					true => link,
+					false => format!("{}: {}", watch::escape(&article.feed_name), link),
+				}
+			})
+			.map(|item| format!("<li>{}</li>", item))
+			.collect::<String>();
+		html.push_str(&format!("<ul>{}</ul>", items));
+		if let Some(more) = self
+			.body
+			.lines()
+			.last()
+			.filter(|line| line.starts_with("..."))
+		{
+			html.push_str(&format!("<p>{}</p>", watch::escape(more)));
+		}
+		html
+	}
+
+	fn single(article: NotifiedArticle) -> Self {
+		Self {
+			title: article.feed_name.clone(),
+			body: article.title.clone(),
+			articles: vec![article],
+			total: 1,
+			feeds: 1,
+			digest: false,
+			formatted: Formatted::default(),
+		}
+	}
+
+	pub fn digest(mut articles: Vec<NotifiedArticle>, max_listed: Option<usize>) -> Self {
+		let total = articles.len();
+		let feeds = articles
+			.iter()
+			.map(|article| article.feed_id)
+			.collect::<BTreeSet<_>>()
+			.len();
+
+		let title = format!(
+			"{} new article{} in {} feed{}",
+			total,
+			if total == 1 { "" } else { "s" },
+			feeds,
+			if feeds == 1 { "" } else { "s" },
+		);
+
+		articles.truncate(max_listed.unwrap_or(total));
+		let mut body = articles
+			.iter()
+			.map(|article| format!("{}: {}", article.feed_name, article.title))
+			.collect::<Vec<_>>()
+			.join("\n");
+		if articles.len() < total {
+			body.push_str(&format!("\n... and {} more", total - articles.len()));
+		}
+
+		Self {
+			title,
+			body,
+			articles,
+			total,
+			feeds,
+			digest: true,
+			formatted: Formatted::default(),
+		}
+	}
+
+	/// Applies the user's templates, keeping the built-in format if they fail
+	fn format(mut self, app: &AppUser, templates: &Templates) -> Self {
+		match templates.render(&self) {
+			Ok(formatted) => {
+				if let Some(title) = &formatted.title {
+					self.title = title.clone();
+				}
+				self.formatted = formatted;
+			}
+			Err(e) => log::warn!("could not apply templates of {}: {}", app.username, e),
+		}
+		self
+	}
+}
+
+/// Sends notifications about the articles that were new in a refresh cycle,
+/// grouping them according to each target's batching rules
+pub async fn notify_new_articles(app: &AppUser, new_articles: &[ArticleId]) -> Result<()> {
+	let targets = NotifyTarget::get_all(app)?;
+	if targets.is_empty() || new_articles.is_empty() {
+		return Ok(());
+	}
+
+	let templates = Templates::get(app)?;
+	let feeds: BTreeMap<u64, Feed> = Feed::get_all(app)?
+		.into_iter()
+		.map(|feed| (feed.id, feed))
+		.collect();
+
+	let mut articles = vec![];
+	for id in new_articles {
+		if let Some(article) = Article::get_id(app, id)? {
+			let feed = feeds.get(&article.feed_id);
+			articles.push(NotifiedArticle {
+				id: article.id,
+				feed_id: article.feed_id,
+				feed_name: feed.map(|feed| feed.name.clone()).unwrap_or_default(),
+				category: feed.and_then(|feed| feed.category.clone()),
+				title: article.title,
+				url: article.url,
+			});
+		}
+	}
+
+	for target in targets {
+		let articles = articles
+			.iter()
+			.filter(|article| target.filter.matches(article))
+			.cloned()
+			.collect::<Vec<_>>();
+		if articles.is_empty() || articles.len() < target.batching.min_articles {
+			continue;
+		}
+
+		let notifications = if target.batching.digest {
+			vec![Notification::digest(articles, target.batching.max_listed)]
+		}
+		else {
+			articles.into_iter().map(Notification::single).collect()
+		};
+
+		for notification in notifications {
+			let notification = notification.format(app, &templates);
+			if let Err(e) = target.transport.notifier().send(app, &notification).await {
+				log::warn!("could not notify target {}: {}", target.id, e);
+			}
+		}
+	}
+
+	Ok(())
+}
@@ -0,0 +1,109 @@
+//! OAuth 2 for feeds of APIs that require it, e.g. private community
+//! platforms. A feed stores its client credentials and, if the user authorized
+//! it, a refresh token; access tokens are obtained from the token endpoint when
+//! missing or expired, and kept on the feed between fetches.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::Result;
+
+/// Access tokens are renewed this long before they expire
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OAuth {
+	pub token_url: Url,
+	pub client_id: String,
+	pub client_secret: Option<String>,
+	/// Uses the refresh token grant if set, client credentials otherwise. Some
+	/// providers rotate it on every use, the new one is kept.
+	pub refresh_token: Option<String>,
+	pub scope: Option<String>,
+
+	#[serde(default)]
+	pub access_token: Option<String>,
+	#[serde(default)]
+	pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+	access_token: String,
+	/// Seconds
+	expires_in: Option<i64>,
+	refresh_token: Option<String>,
+}
+
+impl OAuth {
+	/// Drops the client secret and tokens, for showing the client through the
+	/// API; they're only ever sent to the token endpoint and the feed
+	pub fn redact(&mut self) {
+		self.client_secret = None;
+		self.refresh_token = None;
+		self.invalidate();
+	}
+
+	fn is_valid(&self) -> bool {
+		self.access_token.is_some()
+			&& self
+				.expires
+				.is_none_or(|expires| expires - Duration::seconds(EXPIRY_MARGIN_SECS) > Utc::now())
+	}
+
+	/// Drops the access token, e.g. after the API rejected it
+	pub fn invalidate(&mut self) {
+		self.access_token = None;
+		self.expires = None;
+	}
+
+	/// A valid access token, requesting a new one if needed
+	pub async fn access_token(&mut self, client: &reqwest::Client) -> Result<&str> {
+		if !self.is_valid() {
+			self.request_token(client).await?;
+		}
+		Ok(self.access_token.as_deref().unwrap_or_default())
+	}
+
+	async fn request_token(&mut self, client: &reqwest::Client) -> Result<()> {
+		let mut form = vec![];
+		match &self.refresh_token {
+			Some(refresh_token) => {
+				form.push(("grant_type", "refresh_token"));
+				form.push(("refresh_token", refresh_token));
+			}
+			None => form.push(("grant_type", "client_credentials")),
+		}
+		if let Some(scope) = &self.scope {
+			form.push(("scope", scope));
+		}
+
+		// clients with a secret authenticate with basic auth, which servers must
+		// support; public clients only identify themselves
+		let request = client.post(self.token_url.clone());
+		let request = match &self.client_secret {
+			Some(secret) => request.basic_auth(&self.client_id, Some(secret)),
+			None => {
+				form.push(("client_id", &self.client_id));
+				request
+			}
+		};
+		let response: TokenResponse = request
+			.form(&form)
+			.send()
+			.await?
+			.error_for_status()?
+			.json()
+			.await?;
+
+		self.access_token = Some(response.access_token);
+		self.expires = response
+			.expires_in
+			.map(|expires_in| Utc::now() + Duration::seconds(expires_in));
+		if let Some(refresh_token) = response.refresh_token {
+			self.refresh_token = Some(refresh_token);
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,85 @@
+use atom_syndication::{Content, Entry, Feed as AtomFeed, Link, Person, Text};
+use chrono::{DateTime, Utc};
+
+use crate::{app::AppUser, db::Article, Result};
+
+/// Number of most recent articles included in a republished feed
+const PUBLISHED_ARTICLES: usize = 50;
+
+/// Renders the user's articles, merged across all feeds, as a single Atom feed
+pub fn atom_feed(app: &AppUser, username: &str) -> Result<String> {
+	let mut entries = vec![];
+	for article in Article::iter(app).take(PUBLISHED_ARTICLES) {
+		entries.push(atom_entry(article?));
+	}
+
+	Ok(render(
+		format!("NanoRSS: {}", username),
+		format!("urn:nanorss:{}", username),
+		entries,
+	))
+}
+
+/// Renders the user's starred articles as an Atom feed, most recent first
+pub fn starred_atom_feed(app: &AppUser, username: &str) -> Result<String> {
+	let mut articles = Article::get_starred(app)?;
+	articles.sort_by_key(|article| std::cmp::Reverse(article.published));
+	articles.truncate(PUBLISHED_ARTICLES);
+
+	Ok(render(
+		format!("NanoRSS: {}, starred", username),
+		format!("urn:nanorss:{}:starred", username),
+		articles.into_iter().map(atom_entry).collect(),
+	))
+}
+
+fn render(title: String, id: String, entries: Vec<Entry>) -> String {
+	let updated = entries
+		.iter()
+		.map(|entry| entry.updated)
+		.max()
+		.unwrap_or_else(|| DateTime::<Utc>::MIN_UTC.fixed_offset());
+
+	let feed = AtomFeed {
+		title: Text::plain(title),
+		id,
+		updated,
+		entries,
+		..Default::default()
+	};
+
+	feed.to_string()
+}
+
+fn atom_entry(article: Article) -> Entry {
+	Entry {
+		title: Text::plain(article.title),
+		id: format!("urn:nanorss:article:{}", article.id),
+		updated: article.published.fixed_offset(),
+		published: Some(article.published.fixed_offset()),
+		authors: article
+			.authors
+			.into_iter()
+			.map(|name| Person {
+				name,
+				..Default::default()
+			})
+			.collect(),
+		links: article
+			.url
+			.into_iter()
+			.map(|href| Link {
+				href,
+				..Default::default()
+			})
+			.collect(),
+		summary: Some(Text::html(article.summary)).filter(|text| !text.value.is_empty()),
+		content: Some(Content {
+			value: Some(article.content),
+			content_type: Some("html".into()),
+			..Default::default()
+		})
+		.filter(|content| content.value.as_ref().is_some_and(|v| !v.is_empty())),
+		..Default::default()
+	}
+}
@@ -0,0 +1,259 @@
+//! The search query language. Queries are words, all of which must match:
+//!
+//! - `"quoted words"` match as a phrase
+//! - a trailing `*` matches words starting with it, as in `rust*` or
+//!   `"async run"*`
+//! - `title:` matches the title only, as in `title:rust` or `title:"async rust"`
+//! - `feed:12` only matches articles of the feed, given more than once of any
+//!   of them
+//! - `tag:rust` only matches articles with the tag, given more than once with
+//!   all of them
+//! - `after:2024-01-01` and `before:2024-02-01` only match articles published
+//!   on or after, or before the day, in UTC; full RFC 3339 times work too
+//! - a leading `-` excludes articles matching the rest, as in `-java`,
+//!   `-feed:12` or `-tag:read`
+//!
+//! Anything else with a colon, e.g. a url, is matched as words.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::db::Article;
+use crate::{Error, Result};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SearchField {
+	Title,
+}
+
+/// Words to match, see [`crate::search`]
+#[derive(Clone)]
+pub struct Clause {
+	pub text: String,
+	/// All indexed fields if not set
+	pub field: Option<SearchField>,
+	/// The last word is a prefix
+	pub prefix: bool,
+	pub exclude: bool,
+}
+
+#[derive(Default)]
+pub struct SearchQuery {
+	pub clauses: Vec<Clause>,
+	/// Any of them if not empty
+	pub feeds: Vec<u64>,
+	pub excluded_feeds: Vec<u64>,
+	/// All of them, see [`crate::tag::TagFilter`]
+	pub tags: Vec<String>,
+	pub excluded_tags: Vec<String>,
+	pub after: Option<DateTime<Utc>>,
+	pub before: Option<DateTime<Utc>>,
+}
+
+/// A word or phrase, with its trailing `*` if any
+struct Value {
+	text: String,
+	prefix: bool,
+}
+
+/// Reads the value the iterator is at: a quoted phrase or a bare word
+fn value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Value {
+	let mut text = String::new();
+	if chars.next_if_eq(&'"').is_some() {
+		text.extend(chars.by_ref().take_while(|c| *c != '"'));
+		let prefix = chars.next_if_eq(&'*').is_some();
+		return Value { text, prefix };
+	}
+
+	while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+		text.push(c);
+	}
+	let prefix = text.ends_with('*');
+	if prefix {
+		text.pop();
+	}
+	Value { text, prefix }
+}
+
+fn date(field: &str, text: &str) -> Result<DateTime<Utc>> {
+	if let Ok(day) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+		return Ok(day.and_time(Default::default()).and_utc());
+	}
+	DateTime::parse_from_rfc3339(text)
+		.map(|time| time.with_timezone(&Utc))
+		.map_err(|_| Error::InvalidQuery(format!("{}:{} is not a date", field, text)))
+}
+
+impl FromStr for SearchQuery {
+	type Err = Error;
+
+	fn from_str(query: &str) -> Result<Self> {
+		let mut parsed = SearchQuery::default();
+		let mut chars = query.chars().peekable();
+		loop {
+			while chars.next_if(|c| c.is_whitespace()).is_some() {}
+			if chars.peek().is_none() {
+				return Ok(parsed);
+			}
+
+			let exclude = chars.next_if_eq(&'-').is_some();
+			// a field is letters up to a colon
+			let field: String = chars
+				.clone()
+				.take_while(char::is_ascii_alphabetic)
+				.collect();
+			let field = match chars.clone().nth(field.len()) {
+				Some(':')
+					if ["title", "feed", "tag", "after", "before"].contains(&field.as_str()) =>
+				{
+					chars.nth(field.len());
+					Some(field)
+				}
+				_ => None,
+			};
+
+			let Value { text, prefix } = value(&mut chars);
+			match (field.as_deref(), exclude) {
+				(None | Some("title"), _) => parsed.clauses.push(Clause {
+					text,
+					field: field.map(|_| SearchField::Title),
+					prefix,
+					exclude,
+				}),
+				(Some("feed"), _) => {
+					let feed_id = text.parse().map_err(|_| {
+						Error::InvalidQuery(format!("feed:{} is not a feed id", text))
+					})?;
+					match exclude {
+						true => parsed.excluded_feeds.push(feed_id),
+						false => parsed.feeds.push(feed_id),
+					}
+				}
+				(Some("tag"), _) => {
+					let tag = crate::tag::normalize(&text)
+						.map_err(|e| Error::InvalidQuery(e.to_string()))?;
+					match exclude {
+						true => parsed.excluded_tags.push(tag),
+						false => parsed.tags.push(tag),
+					}
+				}
+				(Some(field), true) => {
+					return Err(Error::InvalidQuery(format!("{}: can't be excluded", field)))
+				}
+				(Some("after"), false) => parsed.after = Some(date("after", &text)?),
+				(Some(_), false) => parsed.before = Some(date("before", &text)?),
+			}
+		}
+	}
+}
+
+impl SearchQuery {
+	/// Whether the article passes the query's feed and date filters; words are
+	/// left to the search index
+	pub fn filters(&self, article: &Article) -> bool {
+		(self.feeds.is_empty() || self.feeds.contains(&article.feed_id))
+			&& !self.excluded_feeds.contains(&article.feed_id)
+			&& self.after.is_none_or(|after| article.published >= after)
+			&& self.before.is_none_or(|before| article.published < before)
+	}
+
+	/// Whether the query has words to rank matches by
+	pub fn has_words(&self) -> bool {
+		self.clauses.iter().any(|clause| !clause.exclude)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(query: &str) -> SearchQuery {
+		query.parse().unwrap()
+	}
+
+	#[test]
+	fn words_phrases_and_prefixes() {
+		let query = parse(r#"rust "async run"* title:"a b"* tok*"#);
+		let clauses: Vec<_> = query
+			.clauses
+			.iter()
+			.map(|clause| {
+				(
+					clause.text.as_str(),
+					clause.field == Some(SearchField::Title),
+					clause.prefix,
+					clause.exclude,
+				)
+			})
+			.collect();
+		assert_eq!(
+			clauses,
+			[
+				("rust", false, false, false),
+				("async run", false, true, false),
+				("a b", true, true, false),
+				("tok", false, true, false),
+			]
+		);
+	}
+
+	#[test]
+	fn unterminated_quotes_run_to_the_end() {
+		let query = parse(r#"title:"async rust"#);
+		assert_eq!(query.clauses.len(), 1);
+		assert_eq!(query.clauses[0].text, "async rust");
+		assert!(!query.clauses[0].prefix);
+	}
+
+	#[test]
+	fn exclusions() {
+		let query = parse("-java -feed:12 feed:3 -tag:Read");
+		assert_eq!(query.clauses.len(), 1);
+		assert!(query.clauses[0].exclude);
+		assert_eq!(query.feeds, [3]);
+		assert_eq!(query.excluded_feeds, [12]);
+		assert_eq!(query.excluded_tags, ["read"]);
+		assert!(!query.has_words());
+
+		assert!(matches!(
+			"-feed:".parse::<SearchQuery>(),
+			Err(Error::InvalidQuery(_))
+		));
+		assert!(matches!(
+			"-after:2024-01-01".parse::<SearchQuery>(),
+			Err(Error::InvalidQuery(_))
+		));
+	}
+
+	#[test]
+	fn a_bare_dash_excludes_nothing() {
+		let query = parse("rust - async");
+		let words: Vec<_> = query
+			.clauses
+			.iter()
+			.map(|clause| (clause.text.as_str(), clause.exclude))
+			.collect();
+		// empty clauses are skipped by the search index
+		assert_eq!(words, [("rust", false), ("", true), ("async", false)]);
+	}
+
+	#[test]
+	fn dates_and_unknown_fields() {
+		let query = parse("after:2024-01-02 before:2024-02-01T12:00:00+02:00 http://a.b/c");
+		assert_eq!(
+			query.after.unwrap().to_rfc3339(),
+			"2024-01-02T00:00:00+00:00"
+		);
+		assert_eq!(
+			query.before.unwrap().to_rfc3339(),
+			"2024-02-01T10:00:00+00:00"
+		);
+		assert_eq!(query.clauses[0].text, "http://a.b/c");
+
+		assert!(matches!(
+			"after:yesterday".parse::<SearchQuery>(),
+			Err(Error::InvalidQuery(_))
+		));
+	}
+}
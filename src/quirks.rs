@@ -0,0 +1,84 @@
+//! Registry of host-specific workarounds applied to every request a feed makes,
+//! so commonly problematic sites work without per-feed configuration. The
+//! registry ships in `site_quirks.json`; operators can replace it with their own
+//! file through `SITE_QUIRKS` without rebuilding.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use serde::Deserialize;
+use url::Url;
+
+use crate::Result;
+
+static REGISTRY: OnceLock<Vec<SiteQuirk>> = OnceLock::new();
+
+/// An entry of the registry. Entries may carry a `note` on why they're needed,
+/// which is only meant for whoever maintains the file.
+#[derive(Deserialize, Debug)]
+pub struct SiteQuirk {
+	/// Subdomains included
+	pub hosts: Vec<String>,
+	/// Required headers, e.g. a User-Agent or a cookie consent cookie
+	#[serde(default)]
+	pub headers: BTreeMap<String, String>,
+	/// Added to the query, e.g. to bypass a cookie consent interstitial
+	#[serde(default)]
+	pub query: BTreeMap<String, String>,
+	/// Host the request is sent to instead
+	pub alternate_host: Option<String>,
+}
+
+/// Replaces the bundled registry with the one in `path`. Must be called before
+/// the first request, i.e. at startup.
+pub fn load(path: &Path) -> anyhow::Result<()> {
+	let bytes = std::fs::read(path)
+		.with_context(|| format!("could not read site quirks file {}", path.display()))?;
+	let quirks = serde_json::from_slice(&bytes)
+		.with_context(|| format!("invalid site quirks file {}", path.display()))?;
+	REGISTRY
+		.set(quirks)
+		.map_err(|_| anyhow::anyhow!("site quirks were already loaded"))
+}
+
+fn registry() -> &'static [SiteQuirk] {
+	REGISTRY.get_or_init(|| {
+		serde_json::from_str(include_str!("site_quirks.json"))
+			.expect("bundled site quirks are valid")
+	})
+}
+
+impl SiteQuirk {
+	pub fn for_url(url: &Url) -> Option<&'static SiteQuirk> {
+		let host = url.host_str()?;
+		registry().iter().find(|quirk| {
+			quirk
+				.hosts
+				.iter()
+				.any(|quirk_host| host == quirk_host || host.ends_with(&format!(".{}", quirk_host)))
+		})
+	}
+
+	pub fn apply_url(&self, url: &Url) -> Result<Url> {
+		let mut url = url.clone();
+		if let Some(host) = &self.alternate_host {
+			url.set_host(Some(host))?;
+		}
+		if !self.query.is_empty() {
+			let mut pairs = url.query_pairs_mut();
+			for (name, value) in &self.query {
+				pairs.append_pair(name, value);
+			}
+		}
+		Ok(url)
+	}
+
+	pub fn apply_headers(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+		for (name, value) in &self.headers {
+			request = request.header(name, value);
+		}
+		request
+	}
+}
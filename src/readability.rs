@@ -0,0 +1,244 @@
+//! Readability-style extraction of an article page's main content, for feeds
+//! that only publish summaries. Paragraphs score their enclosing elements by
+//! how much prose they hold; the best scoring element, less its links, along
+//! with siblings that score close to it, is taken as the article.
+
+use std::collections::HashMap;
+
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// Elements never part of the article
+const SKIPPED: [&str; 13] = [
+	"head", "script", "style", "noscript", "iframe", "form", "button", "input", "nav", "aside",
+	"footer", "header", "svg",
+];
+/// Class and id words of containers unlikely to hold the article
+const UNLIKELY: [&str; 16] = [
+	"comment", "comments", "sidebar", "footer", "footnote", "menu", "nav", "share", "social",
+	"related", "promo", "sponsor", "ad", "ads", "banner", "popup",
+];
+/// Class and id words of containers likely to hold the article
+const LIKELY: [&str; 8] = [
+	"article", "body", "content", "entry", "main", "page", "post", "text",
+];
+/// Paragraphs shorter than this are ignored
+const MIN_PARAGRAPH_LEN: usize = 25;
+/// Elements with more of their text in links are link lists, not articles
+const MAX_LINK_DENSITY: f64 = 0.5;
+/// Void elements, which have no closing tag
+const VOID: [&str; 6] = ["br", "hr", "img", "source", "track", "wbr"];
+
+/// Lowercased words of the element's class and id
+fn hint_words<'a>(element: ElementRef<'a>) -> impl Iterator<Item = String> + 'a {
+	element
+		.value()
+		.classes()
+		.chain(element.value().id())
+		.flat_map(|hint| hint.split(|c: char| !c.is_alphanumeric()))
+		.filter(|word| !word.is_empty())
+		.map(str::to_lowercase)
+}
+
+/// Score from the kind of element and its class and id
+fn initial_score(element: ElementRef) -> f64 {
+	let tag = match element.value().name() {
+		"article" => 10.0,
+		"div" | "section" | "main" => 5.0,
+		"pre" | "td" | "blockquote" => 3.0,
+		"ol" | "ul" | "dl" | "dd" | "dt" | "li" => -3.0,
+		"h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+		_ => 0.0,
+	};
+	let hints: f64 = hint_words(element)
+		.map(|word| {
+			match (
+				LIKELY.contains(&word.as_str()),
+				UNLIKELY.contains(&word.as_str()),
+			) {
+				(true, _) => 25.0,
+				(_, true) => -25.0,
+				_ => 0.0,
+			}
+		})
+		.sum();
+
+	tag + hints
+}
+
+fn is_skipped(element: ElementRef) -> bool {
+	SKIPPED.contains(&element.value().name())
+		|| hint_words(element).any(|word| UNLIKELY.contains(&word.as_str()))
+}
+
+fn text_len(element: ElementRef) -> usize {
+	element.text().map(|text| text.trim().len()).sum()
+}
+
+/// Share of the element's text that is link text
+fn link_density(element: ElementRef) -> f64 {
+	let links = Selector::parse("a").expect("selector is valid");
+	let total = text_len(element);
+	if total == 0 {
+		return 0.0;
+	}
+	let linked: usize = element.select(&links).map(text_len).sum();
+	linked as f64 / total as f64
+}
+
+fn escape(text: &str, out: &mut String) {
+	for c in text.chars() {
+		match c {
+			'&' => out.push_str("&amp;"),
+			'<' => out.push_str("&lt;"),
+			'>' => out.push_str("&gt;"),
+			'"' => out.push_str("&quot;"),
+			c => out.push(c),
+		}
+	}
+}
+
+/// Serializes the element, leaving out skipped elements and comments
+fn serialize(element: ElementRef, out: &mut String) {
+	if is_skipped(element) {
+		return;
+	}
+	let name = element.value().name();
+	out.push('<');
+	out.push_str(name);
+	for (attr, value) in element.value().attrs() {
+		out.push(' ');
+		out.push_str(attr);
+		out.push_str("=\"");
+		escape(value, out);
+		out.push('"');
+	}
+	out.push('>');
+	if VOID.contains(&name) {
+		return;
+	}
+	for child in element.children() {
+		match child.value() {
+			Node::Text(text) => escape(text, out),
+			Node::Element(_) => {
+				serialize(ElementRef::wrap(child).expect("node is an element"), out)
+			}
+			_ => (),
+		}
+	}
+	out.push_str("</");
+	out.push_str(name);
+	out.push('>');
+}
+
+/// The main content of the page, None if no part of it reads like an article
+pub fn extract(page: &str) -> Option<String> {
+	let html = Html::parse_document(page);
+	let paragraphs = Selector::parse("p, pre, blockquote, td").expect("selector is valid");
+
+	// paragraphs score their parent in full and their grandparent in half
+	let mut scores = HashMap::new();
+	for paragraph in html.select(&paragraphs) {
+		let skipped = paragraph
+			.ancestors()
+			.filter_map(ElementRef::wrap)
+			.any(is_skipped);
+		let text: String = paragraph.text().collect();
+		let len = text.trim().len();
+		if skipped || len < MIN_PARAGRAPH_LEN {
+			continue;
+		}
+
+		let score = 1.0 + text.matches(',').count() as f64 + (len as f64 / 100.0).min(3.0);
+		let parent = paragraph.parent().and_then(ElementRef::wrap);
+		let grandparent = parent.and_then(|parent| parent.parent().and_then(ElementRef::wrap));
+		for (ancestor, share) in [(parent, 1.0), (grandparent, 0.5)] {
+			if let Some(ancestor) = ancestor {
+				scores
+					.entry(ancestor.id())
+					.or_insert_with(|| (ancestor, initial_score(ancestor)))
+					.1 += score * share;
+			}
+		}
+	}
+
+	// link lists aren't prose, however long
+	let (best, best_score) = scores
+		.values()
+		.map(|(element, score)| (*element, *score, link_density(*element)))
+		.filter(|(_, _, density)| *density <= MAX_LINK_DENSITY)
+		.map(|(element, score, density)| (element, score * (1.0 - density)))
+		.max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+	// content split over siblings, e.g. paragraphs next to each other, is kept
+	// together
+	let threshold = (best_score * 0.2).max(10.0);
+	let mut content = String::new();
+	let siblings: Vec<ElementRef> = match best.parent() {
+		Some(parent) => parent.children().filter_map(ElementRef::wrap).collect(),
+		None => vec![best],
+	};
+	for element in siblings {
+		let keep = element == best
+			|| scores
+				.get(&element.id())
+				.is_some_and(|(_, score)| score * (1.0 - link_density(element)) >= threshold)
+			|| (element.value().name() == "p"
+				&& text_len(element) > 80
+				&& link_density(element) < 0.25);
+		if keep {
+			serialize(element, &mut content);
+		}
+	}
+
+	Some(content)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PROSE: &str = "The river rose slowly through the night, and by morning the lower \
+		streets of the town were under water, with boats moving between the houses.";
+
+	#[test]
+	fn only_the_article_is_kept() {
+		let page = format!(
+			r#"<html><body>
+				<nav><a href="/">Home</a></nav>
+				<div class="sidebar"><p>{prose}</p></div>
+				<article><h1>Flood</h1><p>{prose}</p><p>{prose}</p></article>
+				<div id="comments"><p>First! {prose}</p><p>Great post, {prose}</p></div>
+			</body></html>"#,
+			prose = PROSE
+		);
+
+		let content = extract(&page).unwrap();
+		assert!(content.starts_with("<article>"), "{}", content);
+		assert_eq!(content.matches(PROSE).count(), 2);
+		assert!(!content.contains("First!"));
+		assert!(!content.contains("Home"));
+	}
+
+	#[test]
+	fn link_lists_are_not_articles() {
+		let links: String = (1..=20)
+			.map(|n| format!(r#"<p><a href="/{n}">Another story worth reading, number {n}</a></p>"#))
+			.collect();
+		let page = format!("<html><body><div>{}</div></body></html>", links);
+
+		assert_eq!(extract(&page), None);
+	}
+
+	#[test]
+	fn attribute_values_are_escaped() {
+		let html = Html::parse_fragment(r#"<a title='say "hi" &amp; <bye>'>x</a>"#);
+		let link = html
+			.select(&Selector::parse("a").unwrap())
+			.next()
+			.unwrap();
+
+		let mut out = String::new();
+		serialize(link, &mut out);
+		assert_eq!(out, r#"<a title="say &quot;hi&quot; &amp; &lt;bye&gt;">x</a>"#);
+	}
+}
@@ -0,0 +1,376 @@
+//! Follower mode: the instance mirrors an account of a primary instance over
+//! its API and serves it read-only, as a hot spare or a LAN-local mirror of a
+//! remote instance. The follower registers as a device on the primary, so it
+//! receives read and starred changes from the change log; feeds are copied
+//! when their list changes, and articles through the primary's article
+//! revisions, so those added and removed are replayed whatever their publish
+//! time. Restarting without the primary configured promotes the follower to a
+//! regular instance.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use axum::{
+	http::{Method, Request},
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, Feed, NewUser, Page, StateChange, StateFlag},
+	AppState, Error, Result,
+};
+
+/// Articles requested per page while copying
+const PAGE_SIZE: usize = 500;
+const ARTICLE_FIELDS: &str =
+	"id,feed_id,published,first_seen,url,title,summary,content,authors,categories,unread,starred";
+
+/// Routes that are posted to but only read
+const READ_ROUTES: [&str; 3] = ["/api/v1/export", "/api/v1/search", "/api/v1/articles/batch"];
+
+static REPLICA: OnceLock<ReplicaConfig> = OnceLock::new();
+
+pub struct ReplicaConfig {
+	/// Base url of the primary
+	pub primary: Url,
+	/// Account mirrored, with the same credentials locally
+	pub username: String,
+	pub password: String,
+	pub interval: Duration,
+}
+
+/// Makes this instance a follower; must be called at startup
+pub fn configure(config: ReplicaConfig) {
+	let _ = REPLICA.set(config);
+}
+
+pub fn enabled() -> bool {
+	REPLICA.get().is_some()
+}
+
+/// Progress of the replication, kept in the mirrored user's meta tree
+#[derive(Serialize, Deserialize, Default)]
+struct ReplicaState {
+	/// Device registered on the primary
+	device: Option<u64>,
+	/// Of the last copied feed list
+	feeds_etag: Option<String>,
+	/// Articles revision of the primary copied up to, once a full copy
+	/// finished since the device was registered
+	articles_revision: Option<u64>,
+}
+
+impl ReplicaState {
+	fn get(app: &AppUser) -> Result<ReplicaState> {
+		// a state of an older format starts over with a full copy
+		Ok(app
+			.meta
+			.get(AppUser::META_REPLICA)?
+			.and_then(|bytes| bincode::deserialize(&bytes).ok())
+			.unwrap_or_default())
+	}
+
+	fn insert(&self, app: &AppUser) -> Result<()> {
+		app.meta
+			.insert(AppUser::META_REPLICA, bincode::serialize(self)?)?;
+		Ok(())
+	}
+}
+
+#[derive(Deserialize)]
+struct RegisteredDevice {
+	id: u64,
+}
+
+#[derive(Deserialize)]
+struct DeviceChanges {
+	changes: Vec<StateChange>,
+}
+
+#[derive(Deserialize)]
+struct ArticleChanges {
+	revision: u64,
+	changed: Vec<ArticleId>,
+	removed: Vec<ArticleId>,
+}
+
+/// An article as listed by the primary, with its state
+#[derive(Deserialize)]
+struct ReplicatedArticle {
+	#[serde(flatten)]
+	article: Article,
+	unread: bool,
+	starred: bool,
+}
+
+impl ReplicaConfig {
+	fn request(
+		&self,
+		app: &AppUser,
+		method: Method,
+		path: &str,
+	) -> Result<reqwest::RequestBuilder> {
+		Ok(app
+			.client
+			.request(method, self.primary.join(path)?)
+			.basic_auth(&self.username, Some(&self.password)))
+	}
+
+	async fn register_device(&self, app: &AppUser) -> Result<u64> {
+		let device: RegisteredDevice = self
+			.request(app, Method::POST, "api/v1/devices")?
+			.json(&serde_json::json!({ "name": "replica" }))
+			.send()
+			.await?
+			.error_for_status()?
+			.json()
+			.await?;
+		Ok(device.id)
+	}
+
+	/// Changes made on the primary since the last call, or `None` if the device
+	/// is gone, e.g. removed by the user
+	async fn changes(&self, app: &AppUser, device: u64) -> Result<Option<Vec<StateChange>>> {
+		let response = self
+			.request(
+				app,
+				Method::POST,
+				&format!("api/v1/devices/{}/sync", device),
+			)?
+			.json(&serde_json::json!({ "changes": [] }))
+			.send()
+			.await?;
+		if response.status() == reqwest::StatusCode::NOT_FOUND {
+			return Ok(None);
+		}
+		let changes: DeviceChanges = response.error_for_status()?.json().await?;
+		Ok(Some(changes.changes))
+	}
+
+	/// Mirrors the feed list, if it changed since the last copy
+	async fn copy_feeds(&self, app: &AppUser, state: &mut ReplicaState) -> Result<()> {
+		let mut request = self.request(app, Method::GET, "api/v1/feeds")?;
+		if let Some(etag) = &state.feeds_etag {
+			request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+		}
+		let response = request.send().await?.error_for_status()?;
+		if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+			return Ok(());
+		}
+
+		let etag = response
+			.headers()
+			.get(reqwest::header::ETAG)
+			.and_then(|etag| etag.to_str().ok())
+			.map(ToOwned::to_owned);
+		let mut feeds: Vec<Feed> = response.json().await?;
+
+		for local in Feed::get_all(app)? {
+			if !feeds.iter().any(|feed| feed.id == local.id) {
+				Feed::remove(app, local.id)?;
+			}
+		}
+		for feed in &mut feeds {
+			feed.insert(app)?;
+		}
+
+		state.feeds_etag = etag;
+		Ok(())
+	}
+
+	/// Articles changed on the primary since its articles revision `since`, or
+	/// only the current revision without one
+	async fn article_changes(&self, app: &AppUser, since: Option<u64>) -> Result<ArticleChanges> {
+		let mut request = self.request(app, Method::GET, "api/v1/articles/changes")?;
+		if let Some(since) = since {
+			request = request.query(&[("since_revision", since)]);
+		}
+		Ok(request.send().await?.error_for_status()?.json().await?)
+	}
+
+	/// Inserts articles of the primary, taking over the state of those that are
+	/// new here; that of known ones comes from the change log. Returns how many
+	/// were new.
+	fn insert_articles(&self, app: &AppUser, articles: &[ReplicatedArticle]) -> Result<usize> {
+		let mut copied = 0;
+		for replicated in articles {
+			let id = replicated.article.id;
+			let new = !app.articles.contains_key(id.as_bytes())?;
+			replicated.article.insert(app)?;
+			if new {
+				Article::set_read(app, &id, !replicated.unread)?;
+				Article::set_starred(app, &id, replicated.starred)?;
+				copied += 1;
+			}
+		}
+		Ok(copied)
+	}
+
+	/// Copies all articles with their state, returning the articles revision of
+	/// the primary to copy changes from next time, and how many were new
+	async fn copy_all_articles(&self, app: &AppUser) -> Result<(u64, usize)> {
+		// taken first, so articles changed during the copy are copied again next
+		let revision = self.article_changes(app, None).await?.revision;
+
+		let mut copied = 0;
+		let mut cursor = None;
+		loop {
+			let mut request = self
+				.request(app, Method::GET, "api/v1/streams/all/articles")?
+				.query(&[
+					("include_hidden", "true"),
+					("limit", &PAGE_SIZE.to_string()),
+					("fields", ARTICLE_FIELDS),
+				]);
+			if let Some(cursor) = &cursor {
+				request = request.query(&[("cursor", cursor)]);
+			}
+			let page: Page<ReplicatedArticle> =
+				request.send().await?.error_for_status()?.json().await?;
+
+			for replicated in &page.items {
+				let id = replicated.article.id;
+				if app.articles.contains_key(id.as_bytes())? {
+					Article::set_read(app, &id, !replicated.unread)?;
+					Article::set_starred(app, &id, replicated.starred)?;
+				}
+			}
+			copied += self.insert_articles(app, &page.items)?;
+
+			match page.next_cursor {
+				Some(next) => cursor = Some(next.to_string()),
+				None => return Ok((revision, copied)),
+			}
+		}
+	}
+
+	/// Replays the articles added, changed and removed on the primary since its
+	/// articles revision `since`, returning the new revision and how many
+	/// articles changed here
+	async fn copy_article_changes(&self, app: &AppUser, since: u64) -> Result<(u64, usize)> {
+		let changes = self.article_changes(app, Some(since)).await?;
+
+		let mut copied = 0;
+		for id in &changes.removed {
+			// only under that very id, removing by an older one would remove the
+			// article it moved to
+			if !app.articles.contains_key(id.as_bytes())? {
+				continue;
+			}
+			match Article::remove(app, id) {
+				Ok(()) => copied += 1,
+				Err(Error::NotFound(_)) => {}
+				Err(e) => return Err(e),
+			}
+		}
+
+		for ids in changes.changed.chunks(PAGE_SIZE) {
+			let articles: Vec<ReplicatedArticle> = self
+				.request(app, Method::POST, "api/v1/articles/batch")?
+				.json(&serde_json::json!({ "ids": ids, "fields": ARTICLE_FIELDS }))
+				.send()
+				.await?
+				.error_for_status()?
+				.json()
+				.await?;
+			self.insert_articles(app, &articles)?;
+			copied += articles.len();
+		}
+
+		Ok((changes.revision, copied))
+	}
+
+	async fn replicate(&self, app: &AppUser) -> Result<()> {
+		let mut state = ReplicaState::get(app)?;
+
+		// the device goes first, so nothing changed during the copy is missed
+		let changes = match state.device {
+			Some(device) => self.changes(app, device).await?,
+			None => None,
+		};
+		if changes.is_none() {
+			state = ReplicaState {
+				device: Some(self.register_device(app).await?),
+				..Default::default()
+			};
+			state.insert(app)?;
+		}
+
+		self.copy_feeds(app, &mut state).await?;
+		let (revision, copied) = match state.articles_revision {
+			Some(since) => self.copy_article_changes(app, since).await?,
+			None => self.copy_all_articles(app).await?,
+		};
+
+		// in order, so the last change of a flag wins
+		for change in changes.unwrap_or_default() {
+			let result = match change.flag {
+				StateFlag::Read => Article::set_read(app, &change.id, change.value),
+				StateFlag::Starred => Article::set_starred(app, &change.id, change.value),
+			};
+			match result {
+				// removed on the primary since
+				Ok(()) | Err(Error::NotFound(_)) => {}
+				Err(e) => return Err(e),
+			}
+		}
+
+		state.articles_revision = Some(revision);
+		state.insert(app)?;
+		if copied > 0 {
+			log::info!("replicated {} changed articles of {}", copied, app.username);
+			app.update_search_index()?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Keeps replicating the primary's account, creating it locally first if needed
+pub async fn run(state: AppState) {
+	let Some(config) = REPLICA.get()
+	else {
+		return;
+	};
+
+	let new_user = NewUser {
+		username: config.username.clone(),
+		password: config.password.clone(),
+		admin: false,
+		quota: None,
+	};
+	match new_user.insert(&state) {
+		Ok(user) => log::info!("created replicated user {}", user.username),
+		Err(Error::UsernameTaken) => {}
+		Err(e) => log::error!("could not create replicated user: {}", e),
+	}
+
+	let mut interval = tokio::time::interval(config.interval);
+	loop {
+		interval.tick().await;
+
+		let result = match state.open_user(&config.username) {
+			Ok(app) => config.replicate(&app).await,
+			Err(e) => Err(e),
+		};
+		if let Err(e) = result {
+			log::warn!("could not replicate from {}: {}", config.primary, e);
+		}
+	}
+}
+
+/// Rejects requests that would change data, as everything is replicated from
+/// the primary; [reads](READ_ROUTES) that are posted are let through
+pub async fn read_only<B>(req: Request<B>, next: Next<B>) -> Response {
+	let reads = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+		|| READ_ROUTES.contains(&req.uri().path());
+	if !reads {
+		return Error::ReadOnly.into_response();
+	}
+
+	next.run(req).await
+}
@@ -0,0 +1,125 @@
+//! Retention policy, i.e. which articles are old enough to be pruned. Users
+//! preview what their policy would remove before anything is deleted; the
+//! preview only reads.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, Feed},
+	Result,
+};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Retention {
+	/// Articles published longer ago than this are pruned
+	pub max_age_days: Option<u32>,
+	/// Only the newest articles of each feed are kept
+	pub max_per_feed: Option<usize>,
+	pub keep_starred: bool,
+	pub keep_unread: bool,
+}
+
+impl Default for Retention {
+	fn default() -> Self {
+		Self {
+			max_age_days: None,
+			max_per_feed: None,
+			keep_starred: true,
+			keep_unread: true,
+		}
+	}
+}
+
+#[derive(Serialize)]
+pub struct PrunePreview {
+	policy: Retention,
+	articles: usize,
+	/// Storage the articles and the bodies only they use take up
+	bytes: u64,
+	/// Feeds with articles to prune
+	feeds: Vec<FeedPrune>,
+}
+
+#[derive(Serialize)]
+struct FeedPrune {
+	feed_id: u64,
+	name: String,
+	articles: usize,
+	kept: usize,
+}
+
+impl Retention {
+	fn is_enabled(&self) -> bool {
+		self.max_age_days.is_some() || self.max_per_feed.is_some()
+	}
+
+	/// Articles the policy prunes. Only their keys are read, which hold the feed
+	/// and the publish time.
+	fn prunable(&self, app: &AppUser) -> Result<(Vec<ArticleId>, BTreeMap<u64, usize>)> {
+		let mut prunable = vec![];
+		let mut seen = BTreeMap::<u64, usize>::new();
+		if !self.is_enabled() {
+			return Ok((prunable, seen));
+		}
+
+		let cutoff = self
+			.max_age_days
+			.map(|days| Utc::now() - Duration::days(days as i64));
+		// articles are keyed newest-first, so the count so far is the article's rank
+		for key in app.articles.iter().keys() {
+			let id = ArticleId::from_bytes(&key?)?;
+			let rank = seen.entry(id.composite().feed_id).or_default();
+			*rank += 1;
+
+			let too_old = cutoff.is_some_and(|cutoff| id.published() < cutoff);
+			let too_many = self.max_per_feed.is_some_and(|max| *rank > max);
+			if !too_old && !too_many {
+				continue;
+			}
+			if self.keep_starred && Article::is_starred(app, &id)? {
+				continue;
+			}
+			if self.keep_unread && !Article::is_read(app, &id)? {
+				continue;
+			}
+			prunable.push(id);
+		}
+
+		Ok((prunable, seen))
+	}
+
+	pub fn preview(&self, app: &AppUser) -> Result<PrunePreview> {
+		let (prunable, totals) = self.prunable(app)?;
+		let names: HashMap<u64, String> = Feed::get_all(app)?
+			.into_iter()
+			.map(|feed| (feed.id, feed.name))
+			.collect();
+
+		let mut by_feed = BTreeMap::<u64, usize>::new();
+		for id in &prunable {
+			*by_feed.entry(id.composite().feed_id).or_default() += 1;
+		}
+		let mut feeds: Vec<FeedPrune> = by_feed
+			.into_iter()
+			.map(|(feed_id, articles)| FeedPrune {
+				feed_id,
+				name: names.get(&feed_id).cloned().unwrap_or_default(),
+				articles,
+				kept: totals.get(&feed_id).copied().unwrap_or(0) - articles,
+			})
+			.collect();
+		feeds.sort_by_key(|feed| std::cmp::Reverse(feed.articles));
+
+		Ok(PrunePreview {
+			policy: self.clone(),
+			articles: prunable.len(),
+			bytes: Article::stored_bytes(app, &prunable)?,
+			feeds,
+		})
+	}
+}
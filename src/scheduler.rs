@@ -0,0 +1,172 @@
+//! Background refreshes, so feeds update without a client asking for it. A
+//! feed is due once its `refresh_interval`, or the instance's default, passed
+//! since it was last fetched, and for feeds with a [`FetchSchedule`], one of its
+//! times; each tick fetches the due feeds of every user.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::AppUser,
+	db::{Feed, User},
+	sharing, AppState, Result,
+};
+
+/// How often the scheduler looks for feeds due for a refresh
+const TICK: Duration = Duration::from_secs(60);
+/// Shortest interval, so no feed is polled more often than upstreams tolerate
+const MIN_INTERVAL_MINUTES: u32 = 5;
+
+static CONFIG: OnceLock<SchedulerConfig> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct SchedulerConfig {
+	/// Minutes between refreshes of feeds without an interval of their own; 0
+	/// disables scheduled refreshes
+	pub default_interval: u32,
+}
+
+pub fn configure(config: SchedulerConfig) {
+	let _ = CONFIG.set(config);
+}
+
+/// The default interval in minutes, unless scheduled refreshes are disabled
+pub fn default_interval() -> Option<u32> {
+	CONFIG
+		.get()
+		.map(|config| config.default_interval)
+		.filter(|interval| *interval > 0)
+}
+
+/// A cron expression of when a feed is worth polling, in UTC, for sources that
+/// only update at known times, e.g. `0 6 * * MON` for a podcast out on Monday
+/// mornings. The leading seconds field is optional.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct FetchSchedule(String);
+
+impl FetchSchedule {
+	fn parse(expression: &str) -> Result<cron::Schedule, cron::error::Error> {
+		// the cron crate expects seconds, which expressions usually leave out
+		match expression.split_whitespace().count() {
+			5 => format!("0 {}", expression).parse(),
+			_ => expression.parse(),
+		}
+	}
+
+	/// Whether one of its times passed since `last_fetch`
+	fn passed(&self, last_fetch: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+		// cron only knows years from 1970, and new feeds have nothing to wait for
+		if last_fetch == DateTime::<Utc>::MIN_UTC {
+			return true;
+		}
+		Self::parse(&self.0)
+			.expect("validated when deserialized")
+			.after(&last_fetch)
+			.next()
+			.is_some_and(|time| time <= now)
+	}
+}
+
+impl TryFrom<String> for FetchSchedule {
+	type Error = String;
+
+	fn try_from(expression: String) -> Result<Self, String> {
+		match Self::parse(&expression) {
+			Ok(_) => Ok(Self(expression)),
+			Err(e) => Err(format!("invalid fetch schedule {:?}: {}", expression, e)),
+		}
+	}
+}
+
+fn is_due(last_fetch: DateTime<Utc>, interval: u32, now: DateTime<Utc>) -> bool {
+	let interval = interval.max(MIN_INTERVAL_MINUTES);
+	last_fetch + chrono::Duration::minutes(interval as i64) <= now
+}
+
+/// Refreshes the user's due feeds. Feeds shared through subscribed blogrolls
+/// aren't the user's to record fetches on, so `shared_fetched` keeps when they
+/// were last refreshed for the user.
+async fn refresh_due(
+	state: &AppState,
+	app: &AppUser,
+	default_interval: u32,
+	shared_fetched: &mut HashMap<String, DateTime<Utc>>,
+) -> Result<()> {
+	let now = Utc::now();
+	let feeds: Vec<Feed> = Feed::get_all(app)?
+		.into_iter()
+		.filter(|feed| {
+			let interval = feed.refresh_interval.unwrap_or(default_interval);
+			is_due(feed.last_fetch_time, interval, now)
+				&& feed
+					.fetch_schedule
+					.as_ref()
+					.is_none_or(|schedule| schedule.passed(feed.last_fetch_time, now))
+		})
+		.collect();
+
+	let last_shared = shared_fetched
+		.get(&app.username)
+		.copied()
+		.unwrap_or(DateTime::<Utc>::MIN_UTC);
+	let shared_feeds = match is_due(last_shared, default_interval, now) {
+		true => {
+			shared_fetched.insert(app.username.clone(), now);
+			sharing::shared_feeds(state, app)?
+		}
+		false => vec![],
+	};
+
+	if feeds.is_empty() && shared_feeds.is_empty() {
+		return Ok(());
+	}
+	log::info!(
+		"refreshing {} due feeds of {}",
+		feeds.len() + shared_feeds.len(),
+		app.username
+	);
+	state
+		.refreshes
+		.run_feeds(app.clone(), feeds, shared_feeds)
+		.await
+}
+
+/// Periodically refreshes the due feeds of all users, unless disabled
+pub async fn run(state: AppState) {
+	let Some(default_interval) = default_interval()
+	else {
+		return;
+	};
+
+	let mut interval = tokio::time::interval(TICK);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+	let mut shared_fetched = HashMap::new();
+	loop {
+		interval.tick().await;
+
+		let users = match User::get_all(&state) {
+			Ok(users) => users,
+			Err(e) => {
+				log::warn!("could not list users to refresh: {}", e);
+				continue;
+			}
+		};
+		for user in users {
+			let app = match state.open_user(&user.username) {
+				Ok(app) => app,
+				Err(e) => {
+					log::warn!("could not open user {}: {}", user.username, e);
+					continue;
+				}
+			};
+			if let Err(e) = refresh_due(&state, &app, default_interval, &mut shared_fetched).await {
+				log::warn!("scheduled refresh of {} failed: {}", user.username, e);
+			}
+		}
+	}
+}
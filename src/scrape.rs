@@ -0,0 +1,218 @@
+//! Scraper configuration: CSS selectors locating an article's parts on its
+//! page, either given by hand or taken from a named preset. Presets for popular
+//! sites ship with NanoRSS; users can add their own, which take precedence over
+//! built-in ones of the same name.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{app::AppUser, db::Article, Error, Result};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ScraperConfig {
+	Custom(ScraperSelectors),
+	/// Name of a preset, resolved on every fetch so changes to it apply
+	Preset(String),
+}
+
+/// CSS selectors of the parts of an article page; unset parts are taken
+/// from the feed
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ScraperSelectors {
+	pub content: Option<String>,
+	pub title: Option<String>,
+	pub date: Option<String>,
+}
+
+/// Parts of an article scraped from its page
+#[derive(Default)]
+pub struct Scraped {
+	pub content: Option<String>,
+	pub title: Option<String>,
+	pub date: Option<DateTime<Utc>>,
+}
+
+impl Scraped {
+	/// The parts scraped when the article first showed up, as pages are only
+	/// scraped once
+	pub fn kept(article: &Article, selectors: &ScraperSelectors) -> Self {
+		Self {
+			content: selectors.content.as_ref().map(|_| article.content.clone()),
+			title: selectors.title.as_ref().map(|_| article.title.clone()),
+			date: selectors.date.as_ref().map(|_| article.published),
+		}
+	}
+}
+
+/// Text of an element with its whitespace collapsed
+fn text(element: ElementRef) -> String {
+	element
+		.text()
+		.flat_map(str::split_whitespace)
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// Dates are taken from the `datetime` attribute of `<time>` elements, the
+/// `content` attribute of `<meta>` elements, or else the text, in RFC 3339 or
+/// RFC 2822 format
+fn date(element: ElementRef) -> Option<DateTime<Utc>> {
+	let value = element
+		.value()
+		.attr("datetime")
+		.or_else(|| element.value().attr("content"))
+		.map(str::to_owned)
+		.unwrap_or_else(|| text(element));
+
+	DateTime::parse_from_rfc3339(value.trim())
+		.or_else(|_| DateTime::parse_from_rfc2822(value.trim()))
+		.ok()
+		.map(|date| date.with_timezone(&Utc))
+}
+
+impl ScraperSelectors {
+	pub fn validate(&self) -> Result<()> {
+		for selector in [&self.content, &self.title, &self.date]
+			.into_iter()
+			.flatten()
+		{
+			Selector::parse(selector)
+				.map_err(|e| Error::Selector(format!("{} ({:?})", selector, e)))?;
+		}
+		Ok(())
+	}
+
+	/// Scrapes the parts with a selector off the page. Content is the markup of
+	/// all matching elements, title and date come from the first match; parts
+	/// whose selector matches nothing are left out.
+	pub fn scrape(&self, page: &str) -> Scraped {
+		let html = Html::parse_document(page);
+		let select = |selector: &Option<String>| {
+			let selector = Selector::parse(selector.as_deref()?).ok()?;
+			let elements: Vec<ElementRef> = html.select(&selector).collect();
+			(!elements.is_empty()).then_some(elements)
+		};
+
+		Scraped {
+			content: select(&self.content).map(|elements| {
+				elements
+					.iter()
+					.map(|element| element.inner_html())
+					.collect::<Vec<_>>()
+					.join("\n")
+			}),
+			title: select(&self.title)
+				.map(|elements| text(elements[0]))
+				.filter(|title| !title.is_empty()),
+			date: select(&self.date).and_then(|elements| date(elements[0])),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScraperPreset {
+	pub name: String,
+	#[serde(default)]
+	pub description: String,
+	/// Sites the preset is meant for, subdomains included
+	#[serde(default)]
+	pub hosts: Vec<String>,
+	pub selectors: ScraperSelectors,
+}
+
+impl ScraperPreset {
+	/// Built-in presets, from `scraper_presets.json`
+	pub fn builtin() -> &'static [ScraperPreset] {
+		static PRESETS: OnceLock<Vec<ScraperPreset>> = OnceLock::new();
+		PRESETS.get_or_init(|| {
+			serde_json::from_str(include_str!("scraper_presets.json"))
+				.expect("bundled scraper presets are valid")
+		})
+	}
+
+	pub fn matches(&self, url: &Url) -> bool {
+		let Some(host) = url.host_str()
+		else {
+			return false;
+		};
+		self.hosts
+			.iter()
+			.any(|preset_host| host == preset_host || host.ends_with(&format!(".{}", preset_host)))
+	}
+
+	fn get_user(app: &AppUser) -> Result<BTreeMap<String, ScraperPreset>> {
+		Ok(app
+			.meta
+			.get(AppUser::META_SCRAPER_PRESETS)?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()?
+			.unwrap_or_default())
+	}
+
+	fn save_user(app: &AppUser, presets: &BTreeMap<String, ScraperPreset>) -> Result<()> {
+		app.meta
+			.insert(AppUser::META_SCRAPER_PRESETS, bincode::serialize(presets)?)?;
+		Ok(())
+	}
+
+	/// Presets available to the user, their own ones replacing built-in ones
+	pub fn get_all(app: &AppUser) -> Result<Vec<ScraperPreset>> {
+		let mut presets: BTreeMap<String, ScraperPreset> = Self::builtin()
+			.iter()
+			.map(|preset| (preset.name.clone(), preset.clone()))
+			.collect();
+		presets.extend(Self::get_user(app)?);
+
+		Ok(presets.into_values().collect())
+	}
+
+	pub fn get(app: &AppUser, name: &str) -> Result<Option<ScraperPreset>> {
+		if let Some(preset) = Self::get_user(app)?.remove(name) {
+			return Ok(Some(preset));
+		}
+		Ok(Self::builtin()
+			.iter()
+			.find(|preset| preset.name == name)
+			.cloned())
+	}
+
+	/// Adds or replaces one of the user's presets
+	pub fn insert(&self, app: &AppUser) -> Result<()> {
+		self.selectors.validate()?;
+
+		let mut presets = Self::get_user(app)?;
+		presets.insert(self.name.clone(), self.clone());
+		Self::save_user(app, &presets)
+	}
+
+	/// Removes one of the user's presets; built-in ones can only be overridden
+	pub fn remove(app: &AppUser, name: &str) -> Result<()> {
+		let mut presets = Self::get_user(app)?;
+		presets
+			.remove(name)
+			.ok_or(Error::NotFound("scraper preset".into()))?;
+		Self::save_user(app, &presets)
+	}
+}
+
+impl ScraperConfig {
+	/// The selectors to scrape with, looking up the preset if needed
+	pub fn resolve(&self, app: &AppUser) -> Result<ScraperSelectors> {
+		match self {
+			ScraperConfig::Custom(selectors) => Ok(selectors.clone()),
+			ScraperConfig::Preset(name) => ScraperPreset::get(app, name)?
+				.map(|preset| preset.selectors)
+				.ok_or(Error::NotFound(format!("scraper preset {}", name))),
+		}
+	}
+
+	pub fn validate(&self, app: &AppUser) -> Result<()> {
+		self.resolve(app)?.validate()
+	}
+}
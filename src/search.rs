@@ -0,0 +1,288 @@
+//! Full-text search of articles, in a tantivy index kept under the data
+//! directory. One index holds the articles of all users, told apart by
+//! username, and those of [shared feeds](crate::team) once for all of their
+//! subscribers. Matches are ranked by relevance, with title matches counting
+//! double. See [`crate::query`] for the query language.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use tantivy::collector::{DocSetCollector, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{
+	BooleanQuery, BoostQuery, ConstScoreQuery, FuzzyTermQuery, Occur, PhrasePrefixQuery,
+	PhraseQuery, Query, TermQuery,
+};
+use tantivy::schema::{
+	Field, IndexRecordOption, Schema, TantivyDocument, Value, STORED, STRING, TEXT,
+};
+use tantivy::tokenizer::TextAnalyzer;
+use tantivy::{
+	doc, DocAddress, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyError, Term,
+};
+
+use url::Url;
+
+use crate::db::{Article, ArticleId, CompositeId, IndexedArticle};
+use crate::query::{Clause, SearchField};
+use crate::Result;
+
+/// Memory the writer buffers documents in before writing a segment
+const WRITER_MEMORY: usize = 32_000_000;
+const TITLE_BOOST: f32 = 2.0;
+
+#[derive(Clone, Copy)]
+struct Fields {
+	/// Owner of the article, unless shared
+	user: Field,
+	/// Owner or feed, and article, to replace and remove documents by
+	key: Field,
+	/// Article id, unless shared
+	id: Field,
+	/// Url of the feed, if shared
+	feed: Field,
+	/// Entry hash of the article, if shared; subscribers store it under their
+	/// own feed id
+	entry: Field,
+	title: Field,
+	/// Everything else indexed, see [`IndexedArticle`]
+	body: Field,
+}
+
+#[derive(Clone)]
+pub struct SearchIndex {
+	index: Index,
+	reader: IndexReader,
+	/// Changes are staged until [committed](Self::commit)
+	writer: Arc<Mutex<IndexWriter>>,
+	fields: Fields,
+}
+
+fn key(username: &str, id: &ArticleId) -> String {
+	format!("{}/{}", username, id)
+}
+
+fn shared_key(feed: &Url, entry_hash: u64) -> String {
+	format!("shared:{}#{:016x}", feed, entry_hash)
+}
+
+/// A search match, see [`SearchIndex::search`]
+pub enum Hit {
+	Own(ArticleId),
+	/// An article of a shared feed, by the id of the user's feed
+	Shared(CompositeId),
+}
+
+impl SearchIndex {
+	pub fn open(path: &Path) -> Result<Self> {
+		std::fs::create_dir_all(path)?;
+
+		let mut schema = Schema::builder();
+		let fields = Fields {
+			user: schema.add_text_field("user", STRING),
+			key: schema.add_text_field("key", STRING),
+			id: schema.add_bytes_field("id", STORED),
+			feed: schema.add_text_field("feed", STRING | STORED),
+			entry: schema.add_u64_field("entry", STORED),
+			title: schema.add_text_field("title", TEXT),
+			body: schema.add_text_field("body", TEXT),
+		};
+		let schema = schema.build();
+		let open = || {
+			Index::open_or_create(
+				MmapDirectory::open(path).map_err(TantivyError::from)?,
+				schema.clone(),
+			)
+		};
+		// the index of an older format is dropped, and rebuilt from the articles
+		// as the users' index versions are outdated too
+		let index = match open() {
+			Err(TantivyError::SchemaError(e)) => {
+				log::warn!("recreating search index: {}", e);
+				std::fs::remove_dir_all(path)?;
+				std::fs::create_dir_all(path)?;
+				open()?
+			}
+			index => index?,
+		};
+		let reader = index
+			.reader_builder()
+			.reload_policy(ReloadPolicy::Manual)
+			.try_into()?;
+		let writer = index.writer_with_num_threads(1, WRITER_MEMORY)?;
+
+		Ok(Self {
+			index,
+			reader,
+			writer: Arc::new(Mutex::new(writer)),
+			fields,
+		})
+	}
+
+	/// Stages adding the article, replacing it if already indexed
+	pub fn add(&self, username: &str, article: &IndexedArticle) -> Result<()> {
+		let IndexedArticle(Article { id, title, .. }, _) = article;
+		let writer = self.writer.lock().unwrap();
+		writer.delete_term(Term::from_field_text(self.fields.key, &key(username, id)));
+		writer.add_document(doc!(
+			self.fields.user => username,
+			self.fields.key => key(username, id),
+			self.fields.id => id.as_bytes(),
+			self.fields.title => title.as_str(),
+			self.fields.body => article.body(),
+		))?;
+		Ok(())
+	}
+
+	/// Stages adding the article of a shared feed, replacing it if already
+	/// indexed
+	pub fn add_shared(&self, feed: &Url, article: &IndexedArticle) -> Result<()> {
+		let IndexedArticle(Article { id, title, .. }, _) = article;
+		let entry_hash = id.composite().entry_hash();
+		let writer = self.writer.lock().unwrap();
+		writer.delete_term(Term::from_field_text(
+			self.fields.key,
+			&shared_key(feed, entry_hash),
+		));
+		writer.add_document(doc!(
+			self.fields.key => shared_key(feed, entry_hash),
+			self.fields.feed => feed.as_str(),
+			self.fields.entry => entry_hash,
+			self.fields.title => title.as_str(),
+			self.fields.body => article.body(),
+		))?;
+		Ok(())
+	}
+
+	/// Stages removing the article
+	pub fn remove(&self, username: &str, id: &ArticleId) {
+		let writer = self.writer.lock().unwrap();
+		writer.delete_term(Term::from_field_text(self.fields.key, &key(username, id)));
+	}
+
+	/// Stages removing all articles of the user
+	pub fn remove_user(&self, username: &str) {
+		let writer = self.writer.lock().unwrap();
+		writer.delete_term(Term::from_field_text(self.fields.user, username));
+	}
+
+	/// Stages removing all articles of the shared feed
+	pub fn remove_feed(&self, feed: &Url) {
+		let writer = self.writer.lock().unwrap();
+		writer.delete_term(Term::from_field_text(self.fields.feed, feed.as_str()));
+	}
+
+	/// Writes staged changes, of all users, and makes them searchable
+	pub fn commit(&self) -> Result<()> {
+		self.writer.lock().unwrap().commit()?;
+		self.reader.reload()?;
+		Ok(())
+	}
+
+	/// The user's articles matching all clauses: the `limit` best ones, best
+	/// first, or all of them unranked without a limit. `shared` are the urls of
+	/// the user's shared feeds, by feed id.
+	pub fn search(
+		&self,
+		username: &str,
+		shared: &HashMap<u64, Url>,
+		query: &[Clause],
+		limit: Option<usize>,
+	) -> Result<Vec<Hit>> {
+		let mut analyzer: TextAnalyzer = self.index.tokenizer_for_field(self.fields.body)?;
+
+		// the user's own articles, or those of their shared feeds; only matching
+		// words score
+		let term = |field: Field, text: &str| -> Box<dyn Query> {
+			Box::new(TermQuery::new(
+				Term::from_field_text(field, text),
+				IndexRecordOption::Basic,
+			))
+		};
+		let owned = BooleanQuery::union(
+			std::iter::once(term(self.fields.user, username))
+				.chain(
+					shared
+						.values()
+						.map(|url| term(self.fields.feed, url.as_str())),
+				)
+				.collect(),
+		);
+		let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(
+			Occur::Must,
+			Box::new(ConstScoreQuery::new(Box::new(owned), 0.0)),
+		)];
+		for clause in query {
+			let mut words = vec![];
+			analyzer
+				.token_stream(&clause.text)
+				.process(&mut |token| words.push(token.text.clone()));
+			if words.is_empty() {
+				continue;
+			}
+
+			let in_field = |field: Field| -> Box<dyn Query> {
+				let terms: Vec<Term> = words
+					.iter()
+					.map(|word| Term::from_field_text(field, word))
+					.collect();
+				match (terms.len(), clause.prefix) {
+					(1, false) => Box::new(TermQuery::new(
+						terms[0].clone(),
+						IndexRecordOption::WithFreqs,
+					)),
+					(1, true) => Box::new(FuzzyTermQuery::new_prefix(terms[0].clone(), 0, true)),
+					(_, false) => Box::new(PhraseQuery::new(terms)),
+					(_, true) => Box::new(PhrasePrefixQuery::new(terms)),
+				}
+			};
+			let query: Box<dyn Query> = match clause.field {
+				Some(SearchField::Title) => in_field(self.fields.title),
+				None => Box::new(BooleanQuery::union(vec![
+					Box::new(BoostQuery::new(in_field(self.fields.title), TITLE_BOOST)),
+					in_field(self.fields.body),
+				])),
+			};
+			match clause.exclude {
+				true => clauses.push((Occur::MustNot, query)),
+				false => clauses.push((Occur::Must, query)),
+			}
+		}
+
+		let searcher = self.reader.searcher();
+		let query = BooleanQuery::new(clauses);
+		let addresses: Vec<DocAddress> = match limit {
+			Some(limit) => searcher
+				.search(&query, &TopDocs::with_limit(limit.max(1)))?
+				.into_iter()
+				.map(|(_, address)| address)
+				.collect(),
+			None => searcher.search(&query, &DocSetCollector)?.into_iter().collect(),
+		};
+		let feed_ids: HashMap<&str, u64> = shared
+			.iter()
+			.map(|(feed_id, url)| (url.as_str(), *feed_id))
+			.collect();
+		let mut hits = Vec::with_capacity(addresses.len());
+		for address in addresses {
+			let doc: TantivyDocument = searcher.doc(address)?;
+			if let Some(bytes) = doc.get_first(self.fields.id).and_then(|id| id.as_bytes()) {
+				hits.push(Hit::Own(ArticleId::from_bytes(bytes)?));
+				continue;
+			}
+
+			let feed_id = doc
+				.get_first(self.fields.feed)
+				.and_then(|feed| feed.as_str())
+				.and_then(|feed| feed_ids.get(feed));
+			let entry_hash = doc
+				.get_first(self.fields.entry)
+				.and_then(|entry| entry.as_u64());
+			if let (Some(feed_id), Some(entry_hash)) = (feed_id, entry_hash) {
+				hits.push(Hit::Shared(CompositeId::new(*feed_id, entry_hash)));
+			}
+		}
+		Ok(hits)
+	}
+}
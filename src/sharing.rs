@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{app::AppUser, db::Feed, App, Error, Result};
+
+/// A folder published by its owner, which other users of the instance can
+/// subscribe to. Subscribers reference the folder rather than copying its feeds,
+/// so the owner's curation applies to them as well.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Blogroll {
+	pub owner: String,
+	pub folder: String,
+}
+
+impl Blogroll {
+	pub fn key_of(owner: &str, folder: &str) -> String {
+		format!("{}/{}", owner, folder)
+	}
+
+	pub fn key(&self) -> String {
+		Self::key_of(&self.owner, &self.folder)
+	}
+
+	pub fn publish(app: &App, owner: &str, folder: &str) -> Result<Blogroll> {
+		let blogroll = Blogroll {
+			owner: owner.to_owned(),
+			folder: folder.to_owned(),
+		};
+		app.blogrolls.insert(
+			Self::key_of(owner, folder).as_bytes(),
+			bincode::serialize(&blogroll)?,
+		)?;
+
+		Ok(blogroll)
+	}
+
+	pub fn unpublish(app: &App, owner: &str, folder: &str) -> Result<()> {
+		app.blogrolls
+			.remove(Self::key_of(owner, folder).as_bytes())?
+			.ok_or(Error::NotFound("blogroll".into()))?;
+		Ok(())
+	}
+
+	pub fn get(app: &App, owner: &str, folder: &str) -> Result<Option<Blogroll>> {
+		app.blogrolls
+			.get(Self::key_of(owner, folder).as_bytes())?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()
+			.map_err(Into::into)
+	}
+
+	pub fn get_all(app: &App) -> Result<Vec<Blogroll>> {
+		app.blogrolls
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect()
+	}
+
+	/// The owner's feeds currently in the published folder
+	pub fn feeds(&self, app: &App) -> Result<Vec<Feed>> {
+		Ok(Feed::get_all(&app.open_user(&self.owner)?)?
+			.into_iter()
+			.filter(|feed| feed.category.as_deref() == Some(self.folder.as_str()))
+			.collect())
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Subscription {
+	pub owner: String,
+	pub folder: String,
+}
+
+impl Subscription {
+	pub fn subscribe(app: &App, user: &AppUser, owner: &str, folder: &str) -> Result<()> {
+		// only published folders may be subscribed to
+		Blogroll::get(app, owner, folder)?.ok_or(Error::NotFound("blogroll".into()))?;
+
+		let subscription = Subscription {
+			owner: owner.to_owned(),
+			folder: folder.to_owned(),
+		};
+		user.subscriptions.insert(
+			Blogroll::key_of(owner, folder).as_bytes(),
+			bincode::serialize(&subscription)?,
+		)?;
+
+		Ok(())
+	}
+
+	pub fn unsubscribe(user: &AppUser, owner: &str, folder: &str) -> Result<()> {
+		user.subscriptions
+			.remove(Blogroll::key_of(owner, folder).as_bytes())?
+			.ok_or(Error::NotFound("subscription".into()))?;
+		Ok(())
+	}
+
+	pub fn get_all(user: &AppUser) -> Result<Vec<Subscription>> {
+		user.subscriptions
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect()
+	}
+}
+
+/// Resolves the user's subscriptions to the feeds their owners currently share.
+/// Subscriptions to blogrolls that were since unpublished resolve to nothing.
+pub fn shared_feeds(app: &App, user: &AppUser) -> Result<Vec<Feed>> {
+	let mut feeds = vec![];
+	for subscription in Subscription::get_all(user)? {
+		if let Some(blogroll) = Blogroll::get(app, &subscription.owner, &subscription.folder)? {
+			feeds.extend(blogroll.feeds(app)?);
+		}
+	}
+
+	Ok(feeds)
+}
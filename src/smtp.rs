@@ -0,0 +1,266 @@
+//! Minimal SMTP client for email notifications: plain-text messages, with an
+//! optional HTML alternative, sent over implicit TLS or STARTTLS,
+//! authenticating with `AUTH PLAIN`.
+
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+
+use crate::{Error, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Security {
+	/// TLS from the start, usually on port 465
+	#[default]
+	Tls,
+	/// Upgraded to TLS after connecting, usually on port 587
+	StartTls,
+	/// Unencrypted, only for relays on the same host or network
+	None,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Server {
+	pub host: String,
+	pub port: u16,
+	#[serde(default)]
+	pub security: Security,
+	pub username: Option<String>,
+	pub password: Option<String>,
+}
+
+pub struct Message<'a> {
+	pub from: &'a str,
+	pub to: &'a [String],
+	pub subject: &'a str,
+	pub body: &'a str,
+	/// Shown instead of the plain-text body by clients that render HTML
+	pub html: Option<&'a str>,
+}
+
+fn tls_connector() -> tokio_rustls::TlsConnector {
+	static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+	let config = CONFIG.get_or_init(|| {
+		let mut roots = rustls::RootCertStore::empty();
+		roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+			rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+				anchor.subject,
+				anchor.spki,
+				anchor.name_constraints,
+			)
+		}));
+		Arc::new(
+			rustls::ClientConfig::builder()
+				.with_safe_defaults()
+				.with_root_certificates(roots)
+				.with_no_client_auth(),
+		)
+	});
+	tokio_rustls::TlsConnector::from(config.clone())
+}
+
+/// Reads a possibly multiline reply, failing unless its code is `expected`
+async fn expect<S: AsyncRead + AsyncWrite + Unpin>(
+	stream: &mut BufReader<S>,
+	expected: u16,
+) -> Result<()> {
+	let mut reply = String::new();
+	loop {
+		let mut line = String::new();
+		if stream.read_line(&mut line).await? == 0 {
+			return Err(Error::Email("connection closed by server".into()));
+		}
+		reply.push_str(&line);
+		// the last line has a space after the code, the others a dash
+		if line.as_bytes().get(3) != Some(&b'-') {
+			break;
+		}
+	}
+
+	match reply.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+		Some(code) if code == expected => Ok(()),
+		_ => Err(Error::Email(format!("server replied {}", reply.trim()))),
+	}
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+	stream: &mut BufReader<S>,
+	command: &str,
+	expected: u16,
+) -> Result<()> {
+	stream
+		.get_mut()
+		.write_all(format!("{}\r\n", command).as_bytes())
+		.await?;
+	expect(stream, expected).await
+}
+
+/// Encodes a header value as an RFC 2047 encoded word if it isn't plain ASCII,
+/// or has control characters that could end the header line
+pub fn encode_header(value: &str) -> String {
+	match value.is_ascii() && !value.chars().any(char::is_control) {
+		true => value.to_owned(),
+		false => format!("=?UTF-8?B?{}?=", BASE64.encode(value)),
+	}
+}
+
+/// Whether the address can be put in headers and SMTP commands as is
+pub fn valid_address(address: &str) -> bool {
+	address.contains('@')
+		&& !address
+			.chars()
+			.any(|c| c.is_control() || c.is_whitespace() || matches!(c, '<' | '>' | ','))
+}
+
+fn format_message(message: &Message) -> String {
+	let mut data = format!(
+		"From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n",
+		message.from,
+		message.to.join(", "),
+		encode_header(message.subject),
+		Utc::now().to_rfc2822(),
+	);
+	let body = match message.html {
+		None => {
+			data.push_str("Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n");
+			message.body.to_owned()
+		}
+		Some(html) => {
+			let boundary = format!("nanorss-{:016x}", rand::random::<u64>());
+			data.push_str(&format!(
+				"Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n",
+				boundary
+			));
+			let part = |content_type, content| {
+				format!(
+					"--{}\nContent-Type: {}; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\n{}\n",
+					boundary, content_type, content
+				)
+			};
+			format!(
+				"{}{}--{}--",
+				part("text/plain", message.body),
+				part("text/html", html),
+				boundary
+			)
+		}
+	};
+	for line in body.lines() {
+		// lines starting with a dot are escaped, a lone one ends the message
+		if line.starts_with('.') {
+			data.push('.');
+		}
+		data.push_str(line);
+		data.push_str("\r\n");
+	}
+	data.push_str(".\r\n");
+	data
+}
+
+/// Sends the message, past the greeting and TLS negotiation
+async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(
+	stream: &mut BufReader<S>,
+	server: &Server,
+	message: &Message<'_>,
+) -> Result<()> {
+	if let (Some(username), Some(password)) = (&server.username, &server.password) {
+		let credentials = BASE64.encode(format!("\0{}\0{}", username, password));
+		command(stream, &format!("AUTH PLAIN {}", credentials), 235).await?;
+	}
+
+	command(stream, &format!("MAIL FROM:<{}>", message.from), 250).await?;
+	for to in message.to {
+		command(stream, &format!("RCPT TO:<{}>", to), 250).await?;
+	}
+	command(stream, "DATA", 354).await?;
+	stream
+		.get_mut()
+		.write_all(format_message(message).as_bytes())
+		.await?;
+	expect(stream, 250).await?;
+	command(stream, "QUIT", 221).await
+}
+
+async fn session(server: &Server, message: &Message<'_>) -> Result<()> {
+	let tcp = TcpStream::connect((server.host.as_str(), server.port)).await?;
+	let name = rustls::ServerName::try_from(server.host.as_str())
+		.map_err(|_| Error::Email(format!("invalid server name {}", server.host)))?;
+
+	match server.security {
+		Security::Tls => {
+			let tls = tls_connector().connect(name, tcp).await?;
+			let mut stream = BufReader::new(tls);
+			expect(&mut stream, 220).await?;
+			command(&mut stream, "EHLO nanorss", 250).await?;
+			transaction(&mut stream, server, message).await
+		}
+		Security::StartTls => {
+			let mut stream = BufReader::new(tcp);
+			expect(&mut stream, 220).await?;
+			command(&mut stream, "EHLO nanorss", 250).await?;
+			command(&mut stream, "STARTTLS", 220).await?;
+
+			let tls = tls_connector().connect(name, stream.into_inner()).await?;
+			let mut stream = BufReader::new(tls);
+			command(&mut stream, "EHLO nanorss", 250).await?;
+			transaction(&mut stream, server, message).await
+		}
+		Security::None => {
+			let mut stream = BufReader::new(tcp);
+			expect(&mut stream, 220).await?;
+			command(&mut stream, "EHLO nanorss", 250).await?;
+			transaction(&mut stream, server, message).await
+		}
+	}
+}
+
+pub async fn send(server: &Server, message: &Message<'_>) -> Result<()> {
+	let addresses = std::iter::once(message.from).chain(message.to.iter().map(String::as_str));
+	for address in addresses {
+		if !valid_address(address) {
+			return Err(Error::Email(format!("invalid address {:?}", address)));
+		}
+	}
+
+	tokio::time::timeout(TIMEOUT, session(server, message))
+		.await
+		.map_err(|_| Error::Email(format!("{} timed out", server.host)))?
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn headers_cannot_be_injected() {
+		let to = vec!["reader@example.com".to_owned()];
+		let message = Message {
+			from: "nanorss@example.com",
+			to: &to,
+			subject: "New article\r\nBcc: victim@example.com",
+			body: "text",
+			html: None,
+		};
+		let data = format_message(&message);
+		let (headers, _) = data.split_once("\r\n\r\n").unwrap();
+		assert!(headers.lines().all(|line| !line.starts_with("Bcc:")));
+		assert!(headers.contains("Subject: =?UTF-8?B?"));
+	}
+
+	#[test]
+	fn addresses_cannot_carry_commands() {
+		assert!(valid_address("reader@example.com"));
+		assert!(!valid_address("reader@example.com>\r\nRCPT TO:<victim@example.com"));
+		assert!(!valid_address("Reader <reader@example.com>"));
+		assert!(!valid_address("reader"));
+	}
+}
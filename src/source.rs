@@ -0,0 +1,82 @@
+//! Feed sources that take more than a plain GET, e.g. APIs wanting a POST body
+//! or query parameters such as a date range, rendered anew on every fetch
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::oauth::OAuth;
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+	#[default]
+	Get,
+	Post,
+}
+
+/// How a feed's url is requested. Query values and the body are templates,
+/// where `{today}`, `{yesterday}` (as `YYYY-MM-DD`), `{now}` (RFC 3339) and
+/// `{timestamp}` (Unix seconds) are replaced with the time of the fetch, in UTC.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SourceRequest {
+	#[serde(default)]
+	pub method: Method,
+	/// Added to the feed url's query
+	#[serde(default)]
+	pub query: BTreeMap<String, String>,
+	pub body: Option<String>,
+	/// `Content-Type` of the body, `application/json` if unset
+	pub content_type: Option<String>,
+	/// Authorizes requests with an OAuth 2 access token
+	pub oauth: Option<OAuth>,
+}
+
+fn render(template: &str, now: DateTime<Utc>) -> String {
+	template
+		.replace("{today}", &now.format("%Y-%m-%d").to_string())
+		.replace(
+			"{yesterday}",
+			&(now - Duration::days(1)).format("%Y-%m-%d").to_string(),
+		)
+		.replace("{now}", &now.to_rfc3339())
+		.replace("{timestamp}", &now.timestamp().to_string())
+}
+
+impl SourceRequest {
+	pub fn build(&self, client: &reqwest::Client, url: &Url) -> reqwest::RequestBuilder {
+		let now = Utc::now();
+
+		let mut url = url.clone();
+		if !self.query.is_empty() {
+			let mut pairs = url.query_pairs_mut();
+			for (name, value) in &self.query {
+				pairs.append_pair(name, &render(value, now));
+			}
+		}
+
+		let request = match self.method {
+			Method::Get => client.get(url),
+			Method::Post => client.post(url),
+		};
+		let request = match self
+			.oauth
+			.as_ref()
+			.and_then(|oauth| oauth.access_token.as_ref())
+		{
+			Some(token) => request.bearer_auth(token),
+			None => request,
+		};
+		match &self.body {
+			Some(body) => request
+				.header(
+					reqwest::header::CONTENT_TYPE,
+					self.content_type.as_deref().unwrap_or("application/json"),
+				)
+				.body(render(body, now)),
+			None => request,
+		}
+	}
+}
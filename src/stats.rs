@@ -0,0 +1,152 @@
+//! Reading statistics: how much the user read per day and week, of which
+//! feeds, and how long after publishing. Read times come off the state clock,
+//! which keeps when each article was last marked read, so no article is loaded.
+//! The clock doesn't tell who marked an article read, so feeds that mark their
+//! articles read as they arrive are left out.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, Feed, StateFlag},
+	Result,
+};
+
+const DEFAULT_DAYS: u32 = 30;
+const MAX_DAYS: u32 = 366;
+const TOP_FEEDS: usize = 10;
+
+#[derive(Deserialize, Default)]
+pub struct StatsRequest {
+	/// Days back to count reads for, 30 if not set
+	pub days: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct ReadingStats {
+	pub since: DateTime<Utc>,
+	pub read: usize,
+	/// Every day since `since`, in UTC, oldest first
+	pub per_day: Vec<PeriodReads>,
+	/// Weeks starting on Monday, oldest first
+	pub per_week: Vec<PeriodReads>,
+	/// The most read feeds, most read first
+	pub top_feeds: Vec<FeedReadCount>,
+	/// Mean time between an article's publishing and its reading, in hours;
+	/// None without reads
+	pub average_hours_to_read: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct PeriodReads {
+	pub start: NaiveDate,
+	pub read: usize,
+}
+
+#[derive(Serialize)]
+pub struct FeedReadCount {
+	pub feed_id: u64,
+	pub name: String,
+	pub read: usize,
+}
+
+/// Articles currently read, with when they were marked read, except those of
+/// the `skipped` feeds
+fn read_times(app: &AppUser, skipped: &HashSet<u64>) -> Result<Vec<(ArticleId, DateTime<Utc>)>> {
+	let mut times = vec![];
+	for item in app.read.iter() {
+		let (entry_key, _) = item?;
+		// entry keys start with the feed id
+		let feed_id = entry_key
+			.get(..8)
+			.and_then(|bytes| bytes.try_into().ok())
+			.map(u64::from_be_bytes);
+		if feed_id.is_some_and(|feed_id| skipped.contains(&feed_id)) {
+			continue;
+		}
+		let Some(key) = app.article_keys.get(&entry_key)?
+		else {
+			continue;
+		};
+		let id = ArticleId::from_bytes(&key)?;
+		if let Some(read) = Article::flag_changed(app, &id, StateFlag::Read)? {
+			times.push((id, read));
+		}
+	}
+	Ok(times)
+}
+
+impl ReadingStats {
+	pub fn new(app: &AppUser, request: &StatsRequest) -> Result<ReadingStats> {
+		let days = request.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+		let today = Utc::now().date_naive();
+		let first_day = today - Duration::days(days as i64 - 1);
+		let since = first_day.and_time(Default::default()).and_utc();
+
+		let mut per_day: BTreeMap<NaiveDate, usize> = first_day
+			.iter_days()
+			.take_while(|day| *day <= today)
+			.map(|day| (day, 0))
+			.collect();
+		let feeds = Feed::get_all(app)?;
+		// read by the feed rather than the user
+		let auto_read: HashSet<u64> = feeds
+			.iter()
+			.filter(|feed| feed.auto_read)
+			.map(|feed| feed.id)
+			.collect();
+		let mut per_feed: HashMap<u64, usize> = HashMap::new();
+		let mut hours_to_read = vec![];
+		for (id, read) in read_times(app, &auto_read)? {
+			if read < since {
+				continue;
+			}
+			*per_day.entry(read.date_naive()).or_default() += 1;
+			*per_feed.entry(id.composite().feed_id).or_default() += 1;
+			// articles backdated into the future don't count
+			if read >= id.published() {
+				hours_to_read.push((read - id.published()).num_minutes() as f64 / 60.0);
+			}
+		}
+
+		let mut per_week: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+		for (day, read) in &per_day {
+			let monday = *day - Duration::days(day.weekday().num_days_from_monday() as i64);
+			*per_week.entry(monday).or_default() += read;
+		}
+
+		let names: HashMap<u64, String> = feeds
+			.into_iter()
+			.map(|feed| (feed.id, feed.name))
+			.collect();
+		let mut top_feeds: Vec<FeedReadCount> = per_feed
+			.into_iter()
+			.map(|(feed_id, read)| FeedReadCount {
+				feed_id,
+				name: names.get(&feed_id).cloned().unwrap_or_default(),
+				read,
+			})
+			.collect();
+		top_feeds.sort_by(|a, b| b.read.cmp(&a.read).then(a.feed_id.cmp(&b.feed_id)));
+		top_feeds.truncate(TOP_FEEDS);
+
+		let periods = |counts: BTreeMap<NaiveDate, usize>| {
+			counts
+				.into_iter()
+				.map(|(start, read)| PeriodReads { start, read })
+				.collect()
+		};
+		Ok(ReadingStats {
+			since,
+			read: per_day.values().sum(),
+			average_hours_to_read: (!hours_to_read.is_empty())
+				.then(|| hours_to_read.iter().sum::<f64>() / hours_to_read.len() as f64),
+			per_day: periods(per_day),
+			per_week: periods(per_week),
+			top_feeds,
+		})
+	}
+}
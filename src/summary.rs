@@ -0,0 +1,197 @@
+//! Catch-up summaries of what arrived during a recent window, counted and
+//! grouped by feed and by category along with each group's top articles, so a
+//! "what did I miss" view doesn't need the client to crunch the full article
+//! list.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, Feed},
+	mute::Mutes,
+	Result,
+};
+
+const DEFAULT_TOP: usize = 3;
+const MAX_TOP: usize = 20;
+/// Beyond this, a summary is no longer catching up
+const MAX_WINDOW_DAYS: i64 = 90;
+
+/// A duration like `90m`, `24h`, `7d` or `2w`
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "String")]
+pub struct Window(Duration);
+
+impl Default for Window {
+	fn default() -> Self {
+		Self(Duration::hours(24))
+	}
+}
+
+impl TryFrom<String> for Window {
+	type Error = String;
+
+	fn try_from(window: String) -> Result<Self, String> {
+		let invalid = || format!("invalid window {:?}, expected e.g. 24h or 7d", window);
+		let split = window.len().checked_sub(1).ok_or_else(invalid)?;
+		let (amount, unit) = window.split_at(split);
+		let amount: i64 = amount.parse().map_err(|_| invalid())?;
+		let duration = match unit {
+			"m" => Duration::minutes(amount),
+			"h" => Duration::hours(amount),
+			"d" => Duration::days(amount),
+			"w" => Duration::weeks(amount),
+			_ => return Err(invalid()),
+		};
+		if duration <= Duration::zero() || duration > Duration::days(MAX_WINDOW_DAYS) {
+			return Err(format!(
+				"window must be positive and at most {} days",
+				MAX_WINDOW_DAYS
+			));
+		}
+
+		Ok(Self(duration))
+	}
+}
+
+#[derive(Deserialize, Default)]
+pub struct SummaryRequest {
+	#[serde(default)]
+	pub window: Window,
+	/// Top articles listed per group
+	pub top: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+	since: DateTime<Utc>,
+	new_articles: usize,
+	unread: usize,
+	starred: usize,
+	/// Feeds with new articles, the busiest first
+	feeds: Vec<Group>,
+	/// Categories of the feeds with new articles, the busiest first;
+	/// uncategorized feeds are left out
+	categories: Vec<Group>,
+}
+
+#[derive(Serialize)]
+struct Group {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	feed_id: Option<u64>,
+	name: String,
+	new_articles: usize,
+	unread: usize,
+	/// Starred ones first, then unread ones, newest first within each
+	top: Vec<SummaryArticle>,
+}
+
+#[derive(Serialize, Clone)]
+struct SummaryArticle {
+	id: ArticleId,
+	feed_id: u64,
+	title: String,
+	url: Option<String>,
+	published: DateTime<Utc>,
+	unread: bool,
+	starred: bool,
+}
+
+impl Group {
+	fn new(
+		feed_id: Option<u64>,
+		name: String,
+		mut articles: Vec<SummaryArticle>,
+		top: usize,
+	) -> Self {
+		articles.sort_by(|a, b| {
+			(b.starred, b.unread, b.published).cmp(&(a.starred, a.unread, a.published))
+		});
+		let unread = articles.iter().filter(|article| article.unread).count();
+		let new_articles = articles.len();
+		articles.truncate(top);
+
+		Self {
+			feed_id,
+			name,
+			new_articles,
+			unread,
+			top: articles,
+		}
+	}
+}
+
+impl Summary {
+	pub fn new(app: &AppUser, request: &SummaryRequest) -> Result<Summary> {
+		let since = Utc::now() - request.window.0;
+		let top = request.top.unwrap_or(DEFAULT_TOP).min(MAX_TOP);
+		let feeds: HashMap<u64, Feed> = Feed::get_all(app)?
+			.into_iter()
+			.map(|feed| (feed.id, feed))
+			.collect();
+
+		let mutes = Mutes::new(app)?;
+		let mut by_feed: BTreeMap<u64, Vec<SummaryArticle>> = BTreeMap::new();
+		for article in Article::get_seen_since(app, since)? {
+			if mutes.mutes(&article) {
+				continue;
+			}
+			by_feed
+				.entry(article.feed_id)
+				.or_default()
+				.push(SummaryArticle {
+					id: article.id,
+					feed_id: article.feed_id,
+					unread: !Article::is_read(app, &article.id)?,
+					starred: Article::is_starred(app, &article.id)?,
+					title: article.title,
+					url: article.url,
+					published: article.published,
+				});
+		}
+
+		let mut by_category: BTreeMap<String, Vec<SummaryArticle>> = BTreeMap::new();
+		for (feed_id, articles) in &by_feed {
+			if let Some(category) = feeds.get(feed_id).and_then(|feed| feed.category.clone()) {
+				by_category
+					.entry(category)
+					.or_default()
+					.extend(articles.iter().cloned());
+			}
+		}
+
+		let all = by_feed.values().flatten();
+		let new_articles = all.clone().count();
+		let unread = all.clone().filter(|article| article.unread).count();
+		let starred = all.filter(|article| article.starred).count();
+
+		let mut feed_groups: Vec<Group> = by_feed
+			.into_iter()
+			.map(|(feed_id, articles)| {
+				let name = feeds
+					.get(&feed_id)
+					.map(|feed| feed.name.clone())
+					.unwrap_or_default();
+				Group::new(Some(feed_id), name, articles, top)
+			})
+			.collect();
+		feed_groups.sort_by_key(|group| std::cmp::Reverse(group.new_articles));
+		let mut category_groups: Vec<Group> = by_category
+			.into_iter()
+			.map(|(category, articles)| Group::new(None, category, articles, top))
+			.collect();
+		category_groups.sort_by_key(|group| std::cmp::Reverse(group.new_articles));
+
+		Ok(Summary {
+			since,
+			new_articles,
+			unread,
+			starred,
+			feeds: feed_groups,
+			categories: category_groups,
+		})
+	}
+}
@@ -0,0 +1,816 @@
+//! Two-way sync with another reader, Miniflux or another NanoRSS instance, so
+//! both can be used side by side while migrating. Subscriptions missing on
+//! either side are added to it; removals are not mirrored, so nothing is ever
+//! unsubscribed by mistake. Read and starred state of recent articles is
+//! matched by article url and merged against the state agreed on at the last
+//! sync, so whichever side changed since wins.
+//!
+//! Clients working offline register as devices instead. Each device syncs the
+//! changes it made since its last sync and receives those made elsewhere, from
+//! a log of changes kept while devices are registered. Conflicting changes are
+//! settled by their timestamps, the latest one winning.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	crypt,
+	db::{normalize_url, Article, ArticleId, Feed, NewFeed, Page, StateChange, StateFlag, User},
+	AppState, Error, Result,
+};
+
+/// Only articles published this recently are synced
+const SYNC_WINDOW_DAYS: i64 = 30;
+/// How often the scheduler looks for remotes due for a sync
+const SCHEDULER_TICK: Duration = Duration::from_secs(60);
+const DEFAULT_INTERVAL_MINUTES: u32 = 30;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteKind {
+	Miniflux,
+	Nanorss,
+}
+
+#[derive(Deserialize)]
+pub struct NewSyncRemote {
+	pub kind: RemoteKind,
+	pub url: Url,
+	pub username: Option<String>,
+	pub password: Option<String>,
+	pub api_key: Option<String>,
+	pub interval_minutes: Option<u32>,
+}
+
+impl NewSyncRemote {
+	pub fn insert(self, app: &AppUser) -> Result<SyncRemote> {
+		let remote = SyncRemote {
+			id: app.db.generate_id()?,
+			kind: self.kind,
+			url: self.url,
+			username: self.username,
+			password: self.password,
+			api_key: self.api_key,
+			interval_minutes: self
+				.interval_minutes
+				.unwrap_or(DEFAULT_INTERVAL_MINUTES)
+				.max(1),
+			last_sync: None,
+			last_error: None,
+		};
+		remote.insert(app)?;
+
+		Ok(remote)
+	}
+}
+
+/// Another reader the user's subscriptions and article state are synced with
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncRemote {
+	pub id: u64,
+	pub kind: RemoteKind,
+	/// Base url of the instance
+	pub url: Url,
+	pub username: Option<String>,
+	pub password: Option<String>,
+	/// Miniflux API key, instead of a username and password
+	pub api_key: Option<String>,
+	pub interval_minutes: u32,
+
+	pub last_sync: Option<DateTime<Utc>>,
+	pub last_error: Option<String>,
+}
+
+/// Urls of the articles that were read and starred on both sides after the
+/// last sync
+#[derive(Serialize, Deserialize, Default)]
+struct SyncState {
+	read: BTreeSet<String>,
+	starred: BTreeSet<String>,
+}
+
+struct RemoteFeed {
+	url: Url,
+	name: String,
+	category: Option<String>,
+}
+
+/// An article as seen by one side of the sync, keyed by its url
+struct SideArticle<K> {
+	keys: Vec<K>,
+	read: bool,
+	starred: bool,
+}
+
+type Side<K> = BTreeMap<String, SideArticle<K>>;
+
+fn add_article<K>(side: &mut Side<K>, url: String, key: K, read: bool, starred: bool) {
+	let article = side.entry(url).or_insert(SideArticle {
+		keys: vec![],
+		read: true,
+		starred: false,
+	});
+	article.keys.push(key);
+	// the same url in several feeds is read once all copies are
+	article.read &= read;
+	article.starred |= starred;
+}
+
+impl SyncRemote {
+	/// The remote as shown through the API, without its credentials
+	pub fn redacted(mut self) -> SyncRemote {
+		self.password = None;
+		self.api_key = None;
+		self
+	}
+
+	pub fn insert(&self, app: &AppUser) -> Result<()> {
+		app.sync_remotes
+			.insert(bincode::serialize(&self.id)?, crypt::encode(self)?)?;
+		Ok(())
+	}
+
+	pub fn remove(app: &AppUser, id: u64) -> Result<()> {
+		app.sync_remotes
+			.remove(bincode::serialize(&id)?)?
+			.ok_or(Error::NotFound("sync remote".into()))?;
+		app.sync_state.remove(bincode::serialize(&id)?)?;
+		Ok(())
+	}
+
+	pub fn get_all(app: &AppUser) -> Result<Vec<SyncRemote>> {
+		app.sync_remotes
+			.iter()
+			.map(|item| item.map_err(Error::from).and_then(|(_, v)| crypt::decode(&v)))
+			.collect()
+	}
+
+	fn is_due(&self, now: DateTime<Utc>) -> bool {
+		self.last_sync.is_none_or(|last_sync| {
+			now - last_sync >= chrono::Duration::minutes(self.interval_minutes as i64)
+		})
+	}
+
+	fn request(
+		&self,
+		client: &reqwest::Client,
+		method: reqwest::Method,
+		path: &str,
+	) -> Result<reqwest::RequestBuilder> {
+		let request = client.request(method, self.url.join(path)?);
+		Ok(match (&self.api_key, &self.username) {
+			(Some(api_key), _) => request.header("X-Auth-Token", api_key),
+			(None, Some(username)) => request.basic_auth(username, self.password.as_ref()),
+			(None, None) => request,
+		})
+	}
+
+	async fn feeds(&self, client: &reqwest::Client) -> Result<Vec<RemoteFeed>> {
+		match self.kind {
+			RemoteKind::Miniflux => {
+				#[derive(Deserialize)]
+				struct MinifluxFeed {
+					feed_url: Url,
+					title: String,
+					category: Option<MinifluxCategory>,
+				}
+
+				let feeds: Vec<MinifluxFeed> = self
+					.request(client, reqwest::Method::GET, "v1/feeds")?
+					.send()
+					.await?
+					.error_for_status()?
+					.json()
+					.await?;
+				Ok(feeds
+					.into_iter()
+					.map(|feed| RemoteFeed {
+						url: feed.feed_url,
+						name: feed.title,
+						category: feed.category.map(|category| category.title),
+					})
+					.collect())
+			}
+			RemoteKind::Nanorss => {
+				#[derive(Deserialize)]
+				struct NanorssFeed {
+					url: Url,
+					name: String,
+					category: Option<String>,
+				}
+
+				let feeds: Vec<NanorssFeed> = self
+					.request(client, reqwest::Method::GET, "api/v1/feeds")?
+					.send()
+					.await?
+					.error_for_status()?
+					.json()
+					.await?;
+				Ok(feeds
+					.into_iter()
+					.map(|feed| RemoteFeed {
+						url: feed.url,
+						name: feed.name,
+						category: feed.category,
+					})
+					.collect())
+			}
+		}
+	}
+
+	async fn add_feed(&self, client: &reqwest::Client, feed: &Feed) -> Result<()> {
+		match self.kind {
+			RemoteKind::Miniflux => {
+				// miniflux files every feed under a category, the first one being the default
+				let categories: Vec<MinifluxCategory> = self
+					.request(client, reqwest::Method::GET, "v1/categories")?
+					.send()
+					.await?
+					.error_for_status()?
+					.json()
+					.await?;
+				let category = categories
+					.iter()
+					.find(|category| Some(&category.title) == feed.category.as_ref())
+					.or(categories.first())
+					.ok_or_else(|| Error::Sync("miniflux has no categories".into()))?;
+
+				self.request(client, reqwest::Method::POST, "v1/feeds")?
+					.json(&serde_json::json!({
+						"feed_url": feed.url,
+						"category_id": category.id,
+					}))
+					.send()
+					.await?
+					.error_for_status()?;
+			}
+			RemoteKind::Nanorss => {
+				self.request(client, reqwest::Method::POST, "api/v1/feeds")?
+					.json(&serde_json::json!({
+						"url": feed.url,
+						"name": feed.name,
+						"category": feed.category,
+					}))
+					.send()
+					.await?
+					.error_for_status()?;
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn articles(
+		&self,
+		client: &reqwest::Client,
+		cutoff: DateTime<Utc>,
+	) -> Result<Side<String>> {
+		let mut side = Side::new();
+		match self.kind {
+			RemoteKind::Miniflux => {
+				#[derive(Deserialize)]
+				struct Entries {
+					entries: Vec<Entry>,
+				}
+				#[derive(Deserialize)]
+				struct Entry {
+					id: u64,
+					url: String,
+					status: String,
+					starred: bool,
+				}
+
+				let mut offset = 0;
+				loop {
+					let entries: Entries = self
+						.request(client, reqwest::Method::GET, "v1/entries")?
+						.query(&[
+							("after", cutoff.timestamp().to_string()),
+							("limit", "500".into()),
+							("offset", offset.to_string()),
+						])
+						.send()
+						.await?
+						.error_for_status()?
+						.json()
+						.await?;
+					if entries.entries.is_empty() {
+						break;
+					}
+
+					offset += entries.entries.len();
+					for entry in entries.entries {
+						add_article(
+							&mut side,
+							entry.url,
+							entry.id.to_string(),
+							entry.status == "read",
+							entry.starred,
+						);
+					}
+				}
+			}
+			RemoteKind::Nanorss => {
+				let unread = self.nanorss_stream(client, "unread", cutoff).await?;
+				let starred = self.nanorss_stream(client, "starred", cutoff).await?;
+				for (url, ids) in self.nanorss_stream(client, "all", cutoff).await? {
+					for id in ids {
+						let read = !unread.get(&url).is_some_and(|ids| ids.contains(&id));
+						let is_starred = starred.get(&url).is_some_and(|ids| ids.contains(&id));
+						add_article(&mut side, url.clone(), id, read, is_starred);
+					}
+				}
+			}
+		}
+
+		Ok(side)
+	}
+
+	/// Ids of the articles of a NanoRSS stream published after the cutoff, by url
+	async fn nanorss_stream(
+		&self,
+		client: &reqwest::Client,
+		stream: &str,
+		cutoff: DateTime<Utc>,
+	) -> Result<HashMap<String, Vec<String>>> {
+		let mut articles: HashMap<String, Vec<String>> = HashMap::new();
+		let mut cursor: Option<ArticleId> = None;
+		loop {
+			let mut request = self
+				.request(
+					client,
+					reqwest::Method::GET,
+					&format!("api/v1/streams/{}/articles", stream),
+				)?
+				.query(&[("include_hidden", "true"), ("limit", "500")]);
+			if let Some(cursor) = &cursor {
+				request = request.query(&[("cursor", cursor.to_string())]);
+			}
+			let page: Page<NanorssArticle> =
+				request.send().await?.error_for_status()?.json().await?;
+
+			let mut past_cutoff = false;
+			for article in page.items {
+				if article.published < cutoff {
+					past_cutoff = true;
+					break;
+				}
+				if let Some(url) = article.url {
+					articles
+						.entry(url)
+						.or_default()
+						.push(article.id.to_string());
+				}
+			}
+
+			match page.next_cursor {
+				Some(next) if !past_cutoff => cursor = Some(next),
+				_ => break,
+			}
+		}
+
+		Ok(articles)
+	}
+
+	async fn set_read(&self, client: &reqwest::Client, keys: &[String], read: bool) -> Result<()> {
+		match self.kind {
+			RemoteKind::Miniflux => {
+				let ids = keys
+					.iter()
+					.filter_map(|key| key.parse::<u64>().ok())
+					.collect::<Vec<_>>();
+				self.request(client, reqwest::Method::PUT, "v1/entries")?
+					.json(&serde_json::json!({
+						"entry_ids": ids,
+						"status": if read { "read" } else { "unread" },
+					}))
+					.send()
+					.await?
+					.error_for_status()?;
+			}
+			RemoteKind::Nanorss => {
+				let method = match read {
+					true => reqwest::Method::PUT,
+					false => reqwest::Method::DELETE,
+				};
+				for key in keys {
+					self.request(
+						client,
+						method.clone(),
+						&format!("api/v1/articles/{}/read", key),
+					)?
+					.send()
+					.await?
+					.error_for_status()?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn set_starred(
+		&self,
+		client: &reqwest::Client,
+		keys: &[String],
+		starred: bool,
+	) -> Result<()> {
+		for key in keys {
+			let request = match self.kind {
+				// toggles, which is fine since it's only called on a change
+				RemoteKind::Miniflux => self.request(
+					client,
+					reqwest::Method::PUT,
+					&format!("v1/entries/{}/bookmark", key),
+				)?,
+				RemoteKind::Nanorss => self.request(
+					client,
+					match starred {
+						true => reqwest::Method::PUT,
+						false => reqwest::Method::DELETE,
+					},
+					&format!("api/v1/articles/{}/star", key),
+				)?,
+			};
+			request.send().await?.error_for_status()?;
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Deserialize)]
+struct NanorssArticle {
+	id: ArticleId,
+	published: DateTime<Utc>,
+	url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MinifluxCategory {
+	id: u64,
+	title: String,
+}
+
+impl SyncState {
+	fn get(app: &AppUser, id: u64) -> Result<SyncState> {
+		Ok(app
+			.sync_state
+			.get(bincode::serialize(&id)?)?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()?
+			.unwrap_or_default())
+	}
+
+	fn insert(&self, app: &AppUser, id: u64) -> Result<()> {
+		app.sync_state
+			.insert(bincode::serialize(&id)?, bincode::serialize(self)?)?;
+		Ok(())
+	}
+}
+
+/// The user's recent articles, newest first until the cutoff
+fn local_articles(app: &AppUser, cutoff: DateTime<Utc>) -> Result<Side<ArticleId>> {
+	let mut side = Side::new();
+	for article in Article::iter(app) {
+		let article = article?;
+		if article.published < cutoff {
+			break;
+		}
+		if let Some(url) = article.url {
+			let read = Article::is_read(app, &article.id)?;
+			let starred = Article::is_starred(app, &article.id)?;
+			add_article(&mut side, url, article.id, read, starred);
+		}
+	}
+	Ok(side)
+}
+
+/// The merged value of a flag: if the sides disagree, the one that changed
+/// since the last sync wins. Returns whether the local side is to be updated.
+fn merge(local: bool, remote: bool, base: bool) -> (bool, Option<bool>) {
+	match (local == remote, local != base) {
+		(true, _) => (local, None),
+		(false, true) => (local, Some(false)),
+		(false, false) => (remote, Some(true)),
+	}
+}
+
+/// Adds the feeds either side is missing. Feeds that can't be added, e.g. as
+/// they're blocked, are skipped so they don't hold up the rest of the sync;
+/// returns why each was.
+async fn sync_feeds(app: &AppUser, remote: &SyncRemote) -> Result<Vec<String>> {
+	let local = Feed::get_all(app)?;
+	let remote_feeds = remote.feeds(&app.client).await?;
+
+	let local_urls: BTreeSet<Url> = local.iter().map(|feed| feed.url.clone()).collect();
+	let remote_urls: BTreeSet<Url> = remote_feeds
+		.iter()
+		.filter_map(|feed| normalize_url(&feed.url).ok())
+		.collect();
+
+	let mut failed = vec![];
+	for feed in remote_feeds {
+		let added = match normalize_url(&feed.url) {
+			Ok(url) if local_urls.contains(&url) => continue,
+			Ok(_) => {
+				NewFeed::new(feed.url.clone(), Some(feed.name), feed.category)
+					.insert(app)
+					.await
+			}
+			Err(e) => Err(e),
+		};
+		if let Err(e) = added {
+			failed.push(format!("{}: {}", feed.url, e));
+		}
+	}
+	for feed in local {
+		if !remote_urls.contains(&feed.url) {
+			if let Err(e) = remote.add_feed(&app.client, &feed).await {
+				failed.push(format!("{}: {}", feed.url, e));
+			}
+		}
+	}
+
+	Ok(failed)
+}
+
+/// Syncs subscriptions and the state of recent articles with the remote.
+/// Returns why feeds that couldn't be added to either side weren't.
+pub async fn sync(app: &AppUser, remote: &SyncRemote) -> Result<Vec<String>> {
+	let failed_feeds = sync_feeds(app, remote).await?;
+
+	let cutoff = Utc::now() - chrono::Duration::days(SYNC_WINDOW_DAYS);
+	let local = local_articles(app, cutoff)?;
+	let remote_articles = remote.articles(&app.client, cutoff).await?;
+	let base = SyncState::get(app, remote.id)?;
+
+	let mut state = SyncState::default();
+	let (mut read, mut unread) = (vec![], vec![]);
+	for (url, local) in &local {
+		let Some(theirs) = remote_articles.get(url)
+		else {
+			continue;
+		};
+
+		let (is_read, update) = merge(local.read, theirs.read, base.read.contains(url));
+		match update {
+			Some(true) => {
+				for id in &local.keys {
+					Article::set_read(app, id, is_read)?;
+				}
+			}
+			Some(false) if is_read => read.extend(theirs.keys.iter().cloned()),
+			Some(false) => unread.extend(theirs.keys.iter().cloned()),
+			None => {}
+		}
+
+		let (is_starred, update) = merge(local.starred, theirs.starred, base.starred.contains(url));
+		match update {
+			Some(true) => {
+				for id in &local.keys {
+					Article::set_starred(app, id, is_starred)?;
+				}
+			}
+			Some(false) => {
+				remote
+					.set_starred(&app.client, &theirs.keys, is_starred)
+					.await?
+			}
+			None => {}
+		}
+
+		if is_read {
+			state.read.insert(url.clone());
+		}
+		if is_starred {
+			state.starred.insert(url.clone());
+		}
+	}
+	if !read.is_empty() {
+		remote.set_read(&app.client, &read, true).await?;
+	}
+	if !unread.is_empty() {
+		remote.set_read(&app.client, &unread, false).await?;
+	}
+
+	state.insert(app, remote.id)?;
+	Ok(failed_feeds)
+}
+
+/// Syncs one remote, recording the outcome on it
+pub async fn sync_remote(app: &AppUser, mut remote: SyncRemote) -> Result<SyncRemote> {
+	let result = sync(app, &remote).await;
+
+	remote.last_sync = Some(Utc::now());
+	remote.last_error = match result {
+		Ok(failed_feeds) if failed_feeds.is_empty() => None,
+		Ok(failed_feeds) => Some(format!(
+			"could not add {} feeds: {}",
+			failed_feeds.len(),
+			failed_feeds.join("; ")
+		)),
+		Err(e) => {
+			log::warn!("could not sync {} with {}: {}", app.username, remote.url, e);
+			Some(e.to_string())
+		}
+	};
+	remote.insert(app)?;
+
+	Ok(remote)
+}
+
+/// Periodically syncs the remotes of all users that are due
+pub async fn run_scheduler(state: AppState) {
+	let mut interval = tokio::time::interval(SCHEDULER_TICK);
+	loop {
+		interval.tick().await;
+
+		let users = match User::get_all(&state) {
+			Ok(users) => users,
+			Err(e) => {
+				log::warn!("could not list users to sync: {}", e);
+				continue;
+			}
+		};
+		let now = Utc::now();
+		for user in users {
+			let app = match state.open_user(&user.username) {
+				Ok(app) => app,
+				Err(e) => {
+					log::warn!("could not open user {}: {}", user.username, e);
+					continue;
+				}
+			};
+			let remotes = SyncRemote::get_all(&app).unwrap_or_else(|e| {
+				log::warn!("could not get sync remotes of {}: {}", user.username, e);
+				vec![]
+			});
+			for remote in remotes.into_iter().filter(|remote| remote.is_due(now)) {
+				if let Err(e) = sync_remote(&app, remote).await {
+					log::warn!("could not record sync of {}: {}", user.username, e);
+				}
+			}
+		}
+	}
+}
+
+#[derive(Deserialize)]
+pub struct NewDevice {
+	pub name: String,
+}
+
+impl NewDevice {
+	pub fn insert(self, app: &AppUser) -> Result<Device> {
+		let device = Device {
+			id: app.db.generate_id()?,
+			name: self.name,
+			// the device gets the state up to now from listings
+			last_seq: StateChange::last_seq(app)?,
+			last_sync: None,
+		};
+		device.insert(app)?;
+
+		Ok(device)
+	}
+}
+
+/// A client syncing read and starred state through the change log
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Device {
+	pub id: u64,
+	pub name: String,
+	/// Last change of the log the device has
+	pub last_seq: u64,
+	pub last_sync: Option<DateTime<Utc>>,
+}
+
+/// A change made on a device
+#[derive(Deserialize)]
+pub struct DeviceChange {
+	pub id: ArticleId,
+	pub flag: StateFlag,
+	pub value: bool,
+	/// When it was made on the device
+	pub at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceSync {
+	#[serde(default)]
+	pub changes: Vec<DeviceChange>,
+}
+
+/// Current state of a flag, sent back for changes that lost to a later one
+#[derive(Serialize)]
+pub struct FlagState {
+	pub id: ArticleId,
+	pub flag: StateFlag,
+	pub value: bool,
+	pub changed: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct DeviceSyncResult {
+	/// Changes made elsewhere since the last sync, oldest first
+	pub changes: Vec<StateChange>,
+	/// Changes sent that were overridden by later ones
+	pub conflicts: Vec<FlagState>,
+}
+
+impl Device {
+	pub fn insert(&self, app: &AppUser) -> Result<()> {
+		app.devices
+			.insert(bincode::serialize(&self.id)?, bincode::serialize(self)?)?;
+		Ok(())
+	}
+
+	pub fn get(app: &AppUser, id: u64) -> Result<Option<Device>> {
+		app.devices
+			.get(bincode::serialize(&id)?)?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()
+			.map_err(Into::into)
+	}
+
+	pub fn remove(app: &AppUser, id: u64) -> Result<()> {
+		app.devices
+			.remove(bincode::serialize(&id)?)?
+			.ok_or(Error::NotFound("device".into()))?;
+		Self::prune_log(app)
+	}
+
+	pub fn get_all(app: &AppUser) -> Result<Vec<Device>> {
+		app.devices
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect()
+	}
+
+	/// Drops the changes every device already has
+	fn prune_log(app: &AppUser) -> Result<()> {
+		match Self::get_all(app)?
+			.iter()
+			.map(|device| device.last_seq)
+			.min()
+		{
+			Some(seq) => StateChange::prune(app, seq),
+			None => Ok(app.state_changes.clear()?),
+		}
+	}
+
+	/// Applies the device's changes, returning those it has yet to receive
+	pub fn sync(app: &AppUser, id: u64, sync: DeviceSync) -> Result<DeviceSyncResult> {
+		let mut device = Self::get(app, id)?.ok_or(Error::NotFound("device".into()))?;
+
+		let now = Utc::now();
+		let mut conflicts = vec![];
+		for change in sync.changes {
+			// a clock running ahead would win every conflict
+			let at = change.at.min(now);
+			match Article::set_flag(
+				app,
+				&change.id,
+				change.flag,
+				change.value,
+				at,
+				Some(device.id),
+			) {
+				Ok(true) => {}
+				Ok(false) => conflicts.push(FlagState {
+					id: change.id,
+					flag: change.flag,
+					value: Article::get_flag(app, &change.id, change.flag)?,
+					changed: Article::flag_changed(app, &change.id, change.flag)?,
+				}),
+				// removed since, nothing to sync
+				Err(Error::NotFound(_)) => {}
+				Err(e) => return Err(e),
+			}
+		}
+
+		let changes = StateChange::since(app, device.last_seq)?;
+		if let Some(last) = changes.last() {
+			device.last_seq = last.seq;
+		}
+		device.last_sync = Some(now);
+		device.insert(app)?;
+		Self::prune_log(app)?;
+
+		Ok(DeviceSyncResult {
+			changes: changes
+				.into_iter()
+				.filter(|change| change.device != Some(device.id))
+				.collect(),
+			conflicts,
+		})
+	}
+}
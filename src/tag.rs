@@ -0,0 +1,168 @@
+//! Tags the user puts on articles, e.g. `read-later`. Articles are kept by tag
+//! in a tree of their own, keyed by tag and entry key, so listing the tags or
+//! the articles of one doesn't go through every article. The tags of each
+//! article are kept by entry key, for showing and replacing them.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+use sled::Transactional;
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId},
+	Error, Result,
+};
+
+const MAX_TAGS: usize = 32;
+const MAX_TAG_LEN: usize = 64;
+
+#[derive(Serialize)]
+pub struct TagCount {
+	pub name: String,
+	pub articles: usize,
+}
+
+/// Tags are lowercase, without surrounding whitespace
+pub fn normalize(tag: &str) -> Result<String> {
+	let tag = tag.trim().to_lowercase();
+	if tag.is_empty() {
+		return Err(Error::EmptyField("tag"));
+	}
+	if tag.len() > MAX_TAG_LEN {
+		return Err(Error::InvalidTag(format!(
+			"{} is longer than {} bytes",
+			tag, MAX_TAG_LEN
+		)));
+	}
+	// control characters would break up keys
+	if tag.chars().any(char::is_control) {
+		return Err(Error::InvalidTag(format!(
+			"{:?} has control characters",
+			tag
+		)));
+	}
+	Ok(tag)
+}
+
+/// Key of the article in the tag's articles
+fn key(tag: &str, entry_key: &[u8]) -> Vec<u8> {
+	[tag.as_bytes(), &[0], entry_key].concat()
+}
+
+/// Replaces the article's tags, returning them as stored
+pub fn set(app: &AppUser, id: &ArticleId, tags: Vec<String>) -> Result<Vec<String>> {
+	if !Article::exists(app, id)? {
+		return Err(Error::NotFound("article".into()));
+	}
+	let mut tags = tags
+		.iter()
+		.map(|tag| normalize(tag))
+		.collect::<Result<Vec<_>>>()?;
+	tags.sort();
+	tags.dedup();
+	if tags.len() > MAX_TAGS {
+		return Err(Error::InvalidTag(format!(
+			"articles can have up to {} tags",
+			MAX_TAGS
+		)));
+	}
+
+	replace(app, id.entry_key(), &tags)?;
+	Ok(tags)
+}
+
+fn replace(app: &AppUser, entry_key: &[u8], tags: &[String]) -> Result<()> {
+	let encoded = bincode::serialize(tags)?;
+	(&app.tags, &app.article_tags).transaction(|(tagged, article_tags)| {
+		let old: Vec<String> = article_tags
+			.get(entry_key)?
+			.and_then(|bytes| bincode::deserialize(&bytes).ok())
+			.unwrap_or_default();
+		for tag in &old {
+			tagged.remove(key(tag, entry_key))?;
+		}
+		for tag in tags {
+			tagged.insert(key(tag, entry_key), &[])?;
+		}
+		match tags.is_empty() {
+			true => article_tags.remove(entry_key)?,
+			false => article_tags.insert(entry_key, encoded.as_slice())?,
+		};
+		Ok(())
+	})?;
+	Ok(())
+}
+
+pub fn get(app: &AppUser, id: &ArticleId) -> Result<Vec<String>> {
+	app.article_tags
+		.get(id.entry_key())?
+		.map(|bytes| bincode::deserialize(&bytes))
+		.transpose()
+		.map(Option::unwrap_or_default)
+		.map_err(Into::into)
+}
+
+/// Drops the tags of a removed article
+pub fn remove(app: &AppUser, id: &ArticleId) -> Result<()> {
+	replace(app, id.entry_key(), &[])
+}
+
+/// The user's tags, by name, with how many articles have them
+pub fn get_all(app: &AppUser) -> Result<Vec<TagCount>> {
+	let mut counts: Vec<TagCount> = vec![];
+	for key in app.tags.iter().keys() {
+		let key = key?;
+		let Some(end) = key.iter().position(|byte| *byte == 0)
+		else {
+			continue;
+		};
+		let name = String::from_utf8_lossy(&key[..end]);
+		match counts.last_mut() {
+			Some(last) if last.name == name => last.articles += 1,
+			_ => counts.push(TagCount {
+				name: name.into_owned(),
+				articles: 1,
+			}),
+		}
+	}
+	Ok(counts)
+}
+
+/// Entry keys of the articles with the tag
+fn tagged(app: &AppUser, tag: &str) -> Result<HashSet<Vec<u8>>> {
+	app.tags
+		.scan_prefix(key(tag, &[]))
+		.keys()
+		.map(|key| {
+			let key = key?;
+			Ok(key[tag.len() + 1..].to_vec())
+		})
+		.collect()
+}
+
+/// Which articles have all of some tags and none of others
+pub struct TagFilter {
+	all_of: Vec<HashSet<Vec<u8>>>,
+	none_of: Vec<HashSet<Vec<u8>>>,
+}
+
+impl TagFilter {
+	pub fn new(app: &AppUser, all_of: &[String], none_of: &[String]) -> Result<Self> {
+		let tagged = |tags: &[String]| -> Result<Vec<_>> {
+			tags.iter()
+				.map(|tag| tagged(app, &normalize(tag)?))
+				.collect()
+		};
+		Ok(Self {
+			all_of: tagged(all_of)?,
+			none_of: tagged(none_of)?,
+		})
+	}
+
+	pub fn matches(&self, id: &ArticleId) -> bool {
+		let entry_key = id.entry_key();
+		self.all_of.iter().all(|keys| keys.contains(entry_key))
+			&& !self.none_of.iter().any(|keys| keys.contains(entry_key))
+	}
+}
@@ -0,0 +1,130 @@
+//! Feeds shared by the members of a team instance, e.g. work feeds everyone
+//! subscribes to. Administrators opt feeds in; their articles are then indexed
+//! once for all members instead of once per member, and members search them as
+//! their own articles, with their own read state.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	db::{normalize_url, Feed, User},
+	App, Error, Result,
+};
+
+#[derive(Deserialize)]
+pub struct SharedFeedRequest {
+	pub url: Url,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SharedFeed {
+	pub url: Url,
+	pub created_by: String,
+	pub created: DateTime<Utc>,
+}
+
+#[derive(Serialize, Default)]
+pub struct SharedFeedUpdate {
+	/// Members subscribed to the feed
+	pub members: usize,
+	/// Articles of theirs moved to or from the shared index
+	pub reindexed: usize,
+}
+
+impl SharedFeedRequest {
+	/// Shares the feed, moving members' articles of it to the shared index
+	pub fn insert(self, app: &App, created_by: &str) -> Result<SharedFeedUpdate> {
+		let shared = SharedFeed {
+			url: normalize_url(&self.url)?,
+			created_by: created_by.to_owned(),
+			created: Utc::now(),
+		};
+		app.shared_feeds
+			.insert(shared.url.as_str(), bincode::serialize(&shared)?)?;
+
+		let update = reindex(app, &shared.url)?;
+		log::info!(
+			"{} shared {} with {} members",
+			created_by,
+			shared.url,
+			update.members
+		);
+		Ok(update)
+	}
+
+	/// Stops sharing the feed, moving members' articles of it back to their own
+	/// index
+	pub fn remove(self, app: &App) -> Result<SharedFeedUpdate> {
+		let url = normalize_url(&self.url)?;
+		app.shared_feeds
+			.remove(url.as_str())?
+			.ok_or(Error::NotFound("shared feed".into()))?;
+
+		app.search_index.remove_feed(&url);
+		reindex(app, &url)
+	}
+}
+
+impl SharedFeed {
+	pub fn get_all(app: &App) -> Result<Vec<SharedFeed>> {
+		app.shared_feeds
+			.iter()
+			.map(|item| {
+				item.map_err(Error::from)
+					.and_then(|(_, v)| bincode::deserialize(&v).map_err(Error::from))
+			})
+			.collect()
+	}
+}
+
+/// Urls of the shared feeds, for telling which articles go to the shared index
+pub struct SharedFeeds(HashSet<Url>);
+
+impl SharedFeeds {
+	pub fn new(shared_feeds: &sled::Tree) -> Result<Self> {
+		shared_feeds
+			.iter()
+			.keys()
+			.map(|key| {
+				let key = key?;
+				Url::parse(&String::from_utf8_lossy(&key)).map_err(Error::from)
+			})
+			.collect::<Result<_>>()
+			.map(Self)
+	}
+
+	/// Whether `url`, which must be normalized, is shared
+	pub fn contains(&self, url: &Url) -> bool {
+		self.0.contains(url)
+	}
+}
+
+/// Indexes the articles members have of the feed again, which puts them in the
+/// index the feed now belongs in
+fn reindex(app: &App, url: &Url) -> Result<SharedFeedUpdate> {
+	let mut update = SharedFeedUpdate::default();
+	for user in User::get_all(app)? {
+		let user_app = app.open_user(&user.username)?;
+		let feeds: Vec<Feed> = Feed::get_all(&user_app)?
+			.into_iter()
+			.filter(|feed| feed.url == *url)
+			.collect();
+		if feeds.is_empty() {
+			continue;
+		}
+
+		update.members += 1;
+		for feed in feeds {
+			update.reindexed += user_app.queue_feed_for_index(feed.id)?;
+		}
+		user_app.update_search_index()?;
+	}
+	// removing the shared articles is only staged, whether members have the
+	// feed or not
+	app.search_index.commit()?;
+
+	Ok(update)
+}
@@ -0,0 +1,482 @@
+//! Optional Telegram bot, enabled by `TELEGRAM_BOT_TOKEN`. Users link a chat to
+//! their account by sending the bot `/start` with a code from the API; the chat
+//! then receives new articles as messages, each with buttons to mark it read or
+//! star it, which replying `read`, `unread`, `star` or `unstar` to the message
+//! does as well. Sending the bot a feed url subscribes to it, `/stop` unlinks.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	app::AppUser,
+	db::{normalize_url, Article, ArticleId, Feed, NewFeed, User},
+	notify::{BatchingRules, Notification, Notifier, NotifyTarget, TargetFilter, Transport},
+	App, AppState, Error, Result,
+};
+
+const DEFAULT_API: &str = "https://api.telegram.org";
+/// How long a `getUpdates` request waits for updates before returning empty
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+const LINK_VALID_MINUTES: i64 = 15;
+
+static BOT: OnceLock<Bot> = OnceLock::new();
+
+struct Bot {
+	token: String,
+	api: String,
+}
+
+/// Enables the bot, talking to a self-hosted Bot API server at `api` if set.
+/// Must be called before the bot is used, i.e. at startup.
+pub fn configure(token: String, api: Option<String>) {
+	let bot = Bot {
+		token,
+		api: api.unwrap_or_else(|| DEFAULT_API.into()),
+	};
+	if BOT.set(bot).is_err() {
+		log::warn!("telegram bot was already configured");
+	}
+}
+
+fn bot() -> Result<&'static Bot> {
+	BOT.get().ok_or(Error::NotFound("telegram bot".into()))
+}
+
+fn method_url(method: &str) -> Result<String> {
+	let bot = bot()?;
+	Ok(format!(
+		"{}/bot{}/{}",
+		bot.api.trim_end_matches('/'),
+		bot.token,
+		method
+	))
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+	ok: bool,
+	result: Option<T>,
+	description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+	update_id: i64,
+	message: Option<Message>,
+	callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+	id: i64,
+}
+
+#[derive(Deserialize)]
+struct Message {
+	chat: Chat,
+	text: Option<String>,
+	reply_to_message: Option<Box<Message>>,
+	reply_markup: Option<Keyboard>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Keyboard {
+	inline_keyboard: Vec<Vec<Button>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Button {
+	text: String,
+	callback_data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+	id: String,
+	message: Option<Message>,
+	data: Option<String>,
+}
+
+async fn call<T: for<'de> Deserialize<'de>>(
+	client: &reqwest::Client,
+	method: &str,
+	body: &serde_json::Value,
+) -> Result<T> {
+	let response: Response<T> = client
+		.post(method_url(method)?)
+		.json(body)
+		.send()
+		.await?
+		.json()
+		.await?;
+	match (response.ok, response.result) {
+		(true, Some(result)) => Ok(result),
+		_ => Err(Error::Telegram(
+			response
+				.description
+				.unwrap_or_else(|| "request failed".into()),
+		)),
+	}
+}
+
+async fn reply(client: &reqwest::Client, chat_id: i64, text: &str) -> Result<()> {
+	call::<serde_json::Value>(
+		client,
+		"sendMessage",
+		&serde_json::json!({ "chat_id": chat_id, "text": text }),
+	)
+	.await
+	.map(|_| ())
+}
+
+/// Code a user sends the bot to link a chat to their account
+#[derive(Serialize, Deserialize)]
+pub struct LinkCode {
+	pub code: String,
+	pub username: String,
+	pub expires: DateTime<Utc>,
+}
+
+impl LinkCode {
+	pub fn create(app: &App, username: &str) -> Result<LinkCode> {
+		bot()?;
+
+		// deep links only allow these characters in the code
+		let mut bytes = [0u8; 12];
+		rand::Rng::fill(&mut rand::thread_rng(), &mut bytes);
+		let link = LinkCode {
+			code: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+			username: username.to_owned(),
+			expires: Utc::now() + chrono::Duration::minutes(LINK_VALID_MINUTES),
+		};
+		app.telegram_links
+			.insert(link.code.as_bytes(), bincode::serialize(&link)?)?;
+
+		Ok(link)
+	}
+
+	/// Consumes a code, returning whose it was if it's still valid
+	fn redeem(app: &App, code: &str) -> Result<Option<String>> {
+		let link = app
+			.telegram_links
+			.remove(code.as_bytes())?
+			.map(|bytes| bincode::deserialize::<LinkCode>(&bytes))
+			.transpose()?;
+		Ok(link
+			.filter(|link| link.expires > Utc::now())
+			.map(|link| link.username))
+	}
+}
+
+/// Delivers new articles to a linked chat, one message per article so each
+/// can be acted on; only created by linking a chat
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TelegramChat {
+	pub chat_id: i64,
+}
+
+impl Notifier for TelegramChat {
+	fn send<'a>(
+		&'a self,
+		app: &'a AppUser,
+		notification: &'a Notification,
+	) -> BoxFuture<'a, Result<()>> {
+		async move {
+			for article in &notification.articles {
+				let mut text = format!("{}\n{}", article.feed_name, article.title);
+				if let Some(url) = &article.url {
+					text.push('\n');
+					text.push_str(url);
+				}
+				let keyboard = Keyboard {
+					inline_keyboard: vec![vec![
+						Button {
+							text: "Read".into(),
+							callback_data: Some(format!("read:{}", article.id)),
+						},
+						Button {
+							text: "Star".into(),
+							callback_data: Some(format!("star:{}", article.id)),
+						},
+					]],
+				};
+				call::<serde_json::Value>(
+					&app.client,
+					"sendMessage",
+					&serde_json::json!({
+						"chat_id": self.chat_id,
+						"text": text,
+						"reply_markup": keyboard,
+					}),
+				)
+				.await?;
+			}
+			Ok(())
+		}
+		.boxed()
+	}
+}
+
+fn chat_key(chat_id: i64) -> [u8; 8] {
+	chat_id.to_be_bytes()
+}
+
+/// The user a chat is linked to, if they still exist
+fn linked_user(app: &App, chat_id: i64) -> Result<Option<AppUser>> {
+	let Some(username) = app.telegram_chats.get(chat_key(chat_id))?
+	else {
+		return Ok(None);
+	};
+	let username = String::from_utf8_lossy(&username).into_owned();
+	match User::get_user(app, &username)? {
+		Some(_) => app.open_user(&username).map(Some),
+		None => Ok(None),
+	}
+}
+
+fn link(app: &App, chat_id: i64, username: &str) -> Result<()> {
+	unlink(app, chat_id)?;
+	app.telegram_chats
+		.insert(chat_key(chat_id), username.as_bytes())?;
+
+	let user = app.open_user(username)?;
+	NotifyTarget {
+		id: user.db.generate_id()?,
+		transport: Transport::TelegramBot(TelegramChat { chat_id }),
+		batching: BatchingRules {
+			digest: false,
+			..Default::default()
+		},
+		filter: TargetFilter::default(),
+	}
+	.insert(&user)
+}
+
+/// Unlinks a chat, removing the notification target it was linked with
+fn unlink(app: &App, chat_id: i64) -> Result<()> {
+	if let Some(user) = linked_user(app, chat_id)? {
+		for target in NotifyTarget::get_all(&user)? {
+			if matches!(&target.transport, Transport::TelegramBot(chat) if chat.chat_id == chat_id)
+			{
+				NotifyTarget::remove(&user, target.id)?;
+			}
+		}
+	}
+	app.telegram_chats.remove(chat_key(chat_id))?;
+	Ok(())
+}
+
+/// Moves a renamed user's chats to the new name
+pub fn rename_user(app: &App, username: &str, new_username: &str) -> Result<()> {
+	for item in app.telegram_chats.iter() {
+		let (key, value) = item?;
+		if value == username.as_bytes() {
+			app.telegram_chats.insert(key, new_username.as_bytes())?;
+		}
+	}
+	Ok(())
+}
+
+/// Forgets the chats of a deleted user
+pub fn delete_user(app: &App, username: &str) -> Result<()> {
+	for item in app.telegram_chats.iter() {
+		let (key, value) = item?;
+		if value == username.as_bytes() {
+			app.telegram_chats.remove(key)?;
+		}
+	}
+	Ok(())
+}
+
+/// The article a message sent by [`TelegramChat`] is about, from its buttons
+fn article_of(message: &Message) -> Option<ArticleId> {
+	message
+		.reply_markup
+		.as_ref()?
+		.inline_keyboard
+		.iter()
+		.flatten()
+		.filter_map(|button| button.callback_data.as_deref())
+		.find_map(|data| data.split_once(':')?.1.parse().ok())
+}
+
+async fn subscribe(app: &AppUser, url: &Url) -> Result<String> {
+	let url = normalize_url(url)?;
+	if Feed::get_all(app)?.iter().any(|feed| feed.url == url) {
+		return Ok(format!("Already subscribed to {}", url));
+	}
+
+	// only feeds that fetch are added, named after their title
+	let parsed = app.fetch_cache.get(&app.client, &url).await?;
+	let name = parsed
+		.feed
+		.title
+		.map(|title| title.content)
+		.unwrap_or_else(|| url.to_string());
+	NewFeed::new(url, Some(name.clone()), None)
+		.insert(app)
+		.await?;
+
+	Ok(format!("Subscribed to {}", name))
+}
+
+/// Applies a `read`, `unread`, `star` or `unstar` action, or toggles the flag
+/// if the action is just `read` or `star` from a button
+fn apply_action(app: &AppUser, id: &ArticleId, action: &str, toggle: bool) -> Result<String> {
+	if Article::get_id(app, id)?.is_none() {
+		return Ok("That article is gone".into());
+	}
+
+	let (read, value) = match action {
+		"read" if toggle => (true, !Article::is_read(app, id)?),
+		"star" if toggle => (false, !Article::is_starred(app, id)?),
+		"read" => (true, true),
+		"unread" => (true, false),
+		"star" => (false, true),
+		"unstar" => (false, false),
+		_ => return Ok("Reply with read, unread, star or unstar".into()),
+	};
+	match read {
+		true => Article::set_read(app, id, value)?,
+		false => Article::set_starred(app, id, value)?,
+	}
+
+	Ok(match (read, value) {
+		(true, true) => "Marked as read",
+		(true, false) => "Marked as unread",
+		(false, true) => "Starred",
+		(false, false) => "Unstarred",
+	}
+	.into())
+}
+
+async fn handle_message(app: &App, client: &reqwest::Client, message: Message) -> Result<()> {
+	let chat_id = message.chat.id;
+	let text = message.text.as_deref().unwrap_or_default().trim();
+
+	if let Some(code) = text.strip_prefix("/start") {
+		let answer = match LinkCode::redeem(app, code.trim())? {
+			Some(username) => {
+				link(app, chat_id, &username)?;
+				format!("Linked to {}, new articles will be sent here", username)
+			}
+			None => "Send /start with a link code from NanoRSS to link this chat".into(),
+		};
+		return reply(client, chat_id, &answer).await;
+	}
+
+	let Some(user) = linked_user(app, chat_id)?
+	else {
+		return reply(
+			client,
+			chat_id,
+			"This chat isn't linked, send /start with a link code",
+		)
+		.await;
+	};
+
+	let answer = if text == "/stop" {
+		unlink(app, chat_id)?;
+		"Unlinked, no more articles will be sent here".into()
+	}
+	else if let Some(id) = message.reply_to_message.as_deref().and_then(article_of) {
+		apply_action(&user, &id, &text.to_lowercase(), false)?
+	}
+	else if let Some(url) = text
+		.split_whitespace()
+		.filter_map(|word| Url::parse(word).ok())
+		.find(|url| matches!(url.scheme(), "http" | "https"))
+	{
+		subscribe(&user, &url)
+			.await
+			.unwrap_or_else(|e| format!("Could not subscribe: {}", e))
+	}
+	else {
+		"Send a feed url to subscribe, reply to an article to mark it, or /stop to unlink".into()
+	};
+
+	reply(client, chat_id, &answer).await
+}
+
+async fn handle_callback(app: &App, client: &reqwest::Client, query: CallbackQuery) -> Result<()> {
+	let user = match &query.message {
+		Some(message) => linked_user(app, message.chat.id)?,
+		None => None,
+	};
+	let action = query.data.as_deref().and_then(|data| data.split_once(':'));
+
+	let answer = match (user, action) {
+		(Some(user), Some((action, id))) => {
+			let id: ArticleId = id.parse()?;
+			apply_action(&user, &id, action, true)?
+		}
+		_ => "This chat isn't linked".into(),
+	};
+
+	call::<bool>(
+		client,
+		"answerCallbackQuery",
+		&serde_json::json!({ "callback_query_id": query.id, "text": answer }),
+	)
+	.await
+	.map(|_| ())
+}
+
+/// Long-polls the bot's updates and handles them, for as long as the server
+/// runs; does nothing unless the bot was configured
+pub async fn run(state: AppState) {
+	if BOT.get().is_none() {
+		return;
+	}
+	let client = match reqwest::Client::builder()
+		.timeout(POLL_TIMEOUT + Duration::from_secs(10))
+		.build()
+	{
+		Ok(client) => client,
+		Err(e) => {
+			log::error!("could not start telegram bot: {}", e);
+			return;
+		}
+	};
+
+	let mut offset = 0;
+	loop {
+		let updates = call::<Vec<Update>>(
+			&client,
+			"getUpdates",
+			&serde_json::json!({
+				"offset": offset,
+				"timeout": POLL_TIMEOUT.as_secs(),
+				"allowed_updates": ["message", "callback_query"],
+			}),
+		)
+		.await;
+		let updates = match updates {
+			Ok(updates) => updates,
+			Err(e) => {
+				log::warn!("could not get telegram updates: {}", e);
+				tokio::time::sleep(RETRY_AFTER).await;
+				continue;
+			}
+		};
+
+		for update in updates {
+			offset = offset.max(update.update_id + 1);
+			let result = match (update.message, update.callback_query) {
+				(Some(message), _) => handle_message(&state, &client, message).await,
+				(_, Some(query)) => handle_callback(&state, &client, query).await,
+				(None, None) => Ok(()),
+			};
+			if let Err(e) = result {
+				log::warn!("could not handle telegram update: {}", e);
+			}
+		}
+	}
+}
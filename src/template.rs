@@ -0,0 +1,164 @@
+//! Templates users write to format their notifications, e.g. a digest email in
+//! their own layout or a webhook body in the shape a chat service expects.
+//! They're rendered with minijinja and only see the notification being sent:
+//! there's no loader for files, and rendering runs on bounded fuel, so a
+//! template can neither reach other data nor stall the refresh sending it.
+
+use chrono::Utc;
+use minijinja::{Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::AppUser,
+	db::ArticleId,
+	notify::{Notification, NotifiedArticle},
+	Error, Result,
+};
+
+/// Template names; the extension selects escaping, HTML for `.html` and JSON
+/// for `.json`, so variables can't break out of the markup
+const TITLE: &str = "title.txt";
+const TEXT: &str = "text.txt";
+const HTML: &str = "body.html";
+const WEBHOOK: &str = "webhook.json";
+
+/// Roughly the instructions a template may run per notification
+const FUEL: u64 = 100_000;
+const MAX_LEN: usize = 16 * 1024;
+
+/// Templates replacing parts of the built-in notification format; unset ones
+/// keep it. The instance's Telegram bot keeps its own format, as it sends a
+/// message per article with buttons.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Templates {
+	/// Title of notifications and subject of emails, on a single line
+	pub title: Option<String>,
+	/// Plain text body, of emails and of ntfy, Gotify, Telegram and Matrix
+	/// messages
+	pub text: Option<String>,
+	/// HTML body, of emails and Matrix messages
+	pub html: Option<String>,
+	/// Body of webhook requests, sent as JSON
+	pub webhook: Option<String>,
+}
+
+/// Variables available to templates
+#[derive(Serialize)]
+struct Context<'a> {
+	/// The built-in title
+	title: &'a str,
+	/// Whether the notification groups the articles of a refresh
+	digest: bool,
+	/// New articles, including unlisted ones
+	total: usize,
+	/// Feeds the new articles are in
+	feeds: usize,
+	articles: &'a [NotifiedArticle],
+	/// New articles not listed
+	more: usize,
+}
+
+/// Parts of a notification rendered from the user's templates
+#[derive(Default, Debug)]
+pub struct Formatted {
+	pub title: Option<String>,
+	pub text: Option<String>,
+	pub html: Option<String>,
+	pub webhook: Option<String>,
+}
+
+fn template_error(e: minijinja::Error) -> Error {
+	Error::Template(e.to_string())
+}
+
+impl Templates {
+	pub fn get(app: &AppUser) -> Result<Templates> {
+		Ok(app
+			.meta
+			.get(AppUser::META_TEMPLATES)?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()?
+			.unwrap_or_default())
+	}
+
+	/// Saves the templates after trying them on a sample digest, so mistakes
+	/// show up now rather than as notifications silently falling back
+	pub fn save(&self, app: &AppUser) -> Result<()> {
+		let sample = self.render(&Self::sample())?;
+		if let Some(webhook) = sample.webhook {
+			serde_json::from_str::<serde_json::Value>(&webhook)
+				.map_err(|e| Error::Template(format!("webhook body is not JSON: {}", e)))?;
+		}
+		app.meta
+			.insert(AppUser::META_TEMPLATES, bincode::serialize(self)?)?;
+		Ok(())
+	}
+
+	fn sample() -> Notification {
+		let articles = ["First", "Second"]
+			.into_iter()
+			.enumerate()
+			.map(|(i, title)| NotifiedArticle {
+				id: ArticleId::new(Utc::now(), i as u64, title),
+				feed_id: i as u64,
+				feed_name: format!("{} feed", title),
+				category: None,
+				title: format!("{} article", title),
+				url: Some(format!("https://example.org/{}", i)),
+			})
+			.collect();
+		Notification::digest(articles, Some(1))
+	}
+
+	fn environment(&self) -> Result<Environment<'_>> {
+		let mut env = Environment::new();
+		env.set_fuel(Some(FUEL));
+		env.set_undefined_behavior(UndefinedBehavior::Strict);
+		let templates = [
+			(TITLE, &self.title),
+			(TEXT, &self.text),
+			(HTML, &self.html),
+			(WEBHOOK, &self.webhook),
+		];
+		for (name, source) in templates {
+			let Some(source) = source
+			else {
+				continue;
+			};
+			if source.len() > MAX_LEN {
+				return Err(Error::Template(format!(
+					"{} is longer than {} bytes",
+					name, MAX_LEN
+				)));
+			}
+			env.add_template(name, source).map_err(template_error)?;
+		}
+		Ok(env)
+	}
+
+	pub fn render(&self, notification: &Notification) -> Result<Formatted> {
+		let env = self.environment()?;
+		let context = Context {
+			title: &notification.title,
+			digest: notification.digest,
+			total: notification.total,
+			feeds: notification.feeds,
+			articles: &notification.articles,
+			more: notification.total - notification.articles.len(),
+		};
+		let render = |name| match env.get_template(name) {
+			Ok(template) => template.render(&context).map(Some).map_err(template_error),
+			Err(_) => Ok(None),
+		};
+
+		Ok(Formatted {
+			// a line break would end up in the email's headers
+			title: render(TITLE)?
+				.map(|title| title.split_whitespace().collect::<Vec<_>>().join(" ")),
+			text: render(TEXT)?,
+			html: render(HTML)?,
+			webhook: render(WEBHOOK)?,
+		})
+	}
+}
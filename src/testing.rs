@@ -0,0 +1,619 @@
+//! A harness for end-to-end tests, with the `test-util` feature. [`TestApp`]
+//! runs the API in-process on a throwaway data directory, and [`MockServer`]
+//! serves the feeds it subscribes to, as the test programs them.
+//!
+//! ```ignore
+//! let feeds = MockServer::start().await;
+//! feeds.mock("/feed.xml", MockResponse::rss("Blog", &[MockItem::new("1", "Hello")]));
+//!
+//! let app = TestApp::new()?;
+//! app.post("/api/v1/feeds")
+//!     .json(&json!({ "url": feeds.url("/feed.xml") }))
+//!     .send()
+//!     .await
+//!     .expect_status(StatusCode::OK);
+//! ```
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+	body::{Body, Bytes},
+	extract::{ConnectInfo, State},
+	http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri},
+	response::{IntoResponse, Response},
+	Router,
+};
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+use crate::{
+	app::{self, App},
+	crypt,
+	db::NewUser,
+	dns, fetch, invite,
+	network::NetworkConfig,
+	router, Result, ServerConfig,
+};
+
+/// Login of the user requests are made as, unless told otherwise
+pub const USERNAME: &str = "admin";
+pub const PASSWORD: &str = "password";
+
+/// Address requests appear to come from
+const CLIENT: ([u8; 4], u16) = ([127, 0, 0, 1], 40000);
+
+/// Writes to the database before the app opens it
+type Seed = Box<dyn FnOnce(&sled::Db)>;
+
+pub struct TestAppBuilder {
+	/// Username, password and whether they administer the instance
+	users: Vec<(String, String, bool)>,
+	fetch_cache_ttl: Duration,
+	seed: Option<Seed>,
+}
+
+impl TestAppBuilder {
+	/// Adds a user besides the administrator
+	pub fn user(mut self, username: &str, password: &str) -> Self {
+		self.users
+			.push((username.to_owned(), password.to_owned(), false));
+		self
+	}
+
+	/// How long fetched feeds are reused, not at all by default so every refresh
+	/// sees what the mock server serves
+	pub fn fetch_cache_ttl(mut self, ttl: Duration) -> Self {
+		self.fetch_cache_ttl = ttl;
+		self
+	}
+
+	/// Lets anyone register, without a rate limit. Registration is configured
+	/// once per process, so this applies to every app of the test binary.
+	pub fn open_registration(self) -> Self {
+		invite::configure(invite::RegistrationConfig {
+			mode: invite::RegistrationMode::Open,
+			rate_limit: usize::MAX,
+			open_quota: app::Quota::default(),
+		});
+		self
+	}
+
+	/// Writes to the database before the app opens it, e.g. records of an older
+	/// format to upgrade
+	pub fn seed(mut self, seed: impl FnOnce(&sled::Db) + 'static) -> Self {
+		self.seed = Some(Box::new(seed));
+		self
+	}
+
+	pub fn build(self) -> Result<TestApp> {
+		let dir = tempfile::tempdir()?;
+		let seeded = self.seed.is_some();
+		if let Some(seed) = self.seed {
+			let db = sled::open(dir.path().join("db.sled"))?;
+			seed(&db);
+			db.flush()?;
+		}
+		let cfg = config(dir.path(), self.fetch_cache_ttl);
+		let state = match seeded {
+			true => open_seeded(&cfg)?,
+			false => App::new(&cfg)?,
+		};
+		for (username, password, admin) in self.users {
+			NewUser {
+				username,
+				password,
+				admin,
+				quota: None,
+			}
+			.insert(&state)?;
+		}
+
+		Ok(TestApp {
+			router: serve(state),
+			fetch_cache_ttl: self.fetch_cache_ttl,
+			dir,
+		})
+	}
+}
+
+/// Enables encryption at rest with a fixed key. The key is set once per
+/// process, so this applies to every app opened afterwards in the test binary.
+pub fn enable_encryption() {
+	// 32 zero bytes
+	let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+	if !crypt::enabled() {
+		crypt::configure(crypt::KeySource::Value(key)).expect("key is valid");
+	}
+}
+
+fn config(dir: &Path, fetch_cache_ttl: Duration) -> app::Config {
+	app::Config {
+		db_path: dir.join("db.sled"),
+		blobs_path: dir.join("blobs"),
+		search_path: dir.join("search"),
+		fetch_cache_ttl,
+		limits: fetch::SizeLimits {
+			max_feed_size: 20 * 1024 * 1024,
+			max_page_size: 5 * 1024 * 1024,
+			max_content_size: 1024 * 1024,
+		},
+		// the lowest cost bcrypt takes, hashing is slow enough as it is
+		bcrypt_cost: 4,
+		dns: dns::DnsConfig {
+			upstream: dns::Upstream::System,
+			cache_size: 64,
+		},
+		http: app::HttpConfig {
+			pool_max_idle_per_host: None,
+			pool_idle_timeout: None,
+			http2: false,
+			tcp_keepalive: None,
+			extra_root_certs: vec![],
+		},
+	}
+}
+
+fn serve(state: App) -> Router {
+	router(
+		Arc::new(state),
+		ServerConfig {
+			network: NetworkConfig::default(),
+			max_concurrent_requests: 64,
+			queue_timeout: Duration::from_secs(5),
+		},
+	)
+}
+
+/// Opens the app on a database just written to, or just closed. Sled's
+/// background threads release the database's lock a little after it is dropped.
+fn open_seeded(cfg: &app::Config) -> Result<App> {
+	let mut attempts = 0;
+	loop {
+		match App::new(cfg) {
+			Err(crate::Error::Sled(sled::Error::Io(e)))
+				if e.kind() == std::io::ErrorKind::Other && attempts < 100 =>
+			{
+				attempts += 1;
+				std::thread::sleep(Duration::from_millis(10));
+			}
+			result => return result,
+		}
+	}
+}
+
+/// The API on a fresh instance, with an administrator logging in as
+/// [`USERNAME`] and [`PASSWORD`]. Background tasks, such as scheduled
+/// refreshes, don't run; tests trigger what they need through the API.
+pub struct TestApp {
+	router: Router,
+	fetch_cache_ttl: Duration,
+	/// Removed on drop, after the app
+	dir: TempDir,
+}
+
+impl TestApp {
+	pub fn builder() -> TestAppBuilder {
+		TestAppBuilder {
+			users: vec![(USERNAME.to_owned(), PASSWORD.to_owned(), true)],
+			fetch_cache_ttl: Duration::ZERO,
+			seed: None,
+		}
+	}
+
+	pub fn new() -> Result<TestApp> {
+		Self::builder().build()
+	}
+
+	/// Closes the app and opens it again on the same data directory, as a
+	/// restart would
+	pub fn reopen(self) -> Result<TestApp> {
+		let TestApp {
+			router,
+			fetch_cache_ttl,
+			dir,
+		} = self;
+		drop(router);
+
+		let state = open_seeded(&config(dir.path(), fetch_cache_ttl))?;
+		Ok(TestApp {
+			router: serve(state),
+			fetch_cache_ttl,
+			dir,
+		})
+	}
+
+	/// Whether any file of the data directory contains the text, e.g. a secret
+	/// that should only be stored encrypted
+	pub fn stores(&self, text: &str) -> Result<bool> {
+		fn search(dir: &Path, text: &[u8]) -> Result<bool> {
+			for entry in std::fs::read_dir(dir)? {
+				let path = entry?.path();
+				let found = match path.is_dir() {
+					true => search(&path, text)?,
+					false => std::fs::read(&path)?
+						.windows(text.len())
+						.any(|window| window == text),
+				};
+				if found {
+					return Ok(true);
+				}
+			}
+			Ok(false)
+		}
+
+		search(self.dir.path(), text.as_bytes())
+	}
+
+	pub fn request(&self, method: Method, uri: &str) -> TestRequest<'_> {
+		TestRequest {
+			app: self,
+			method,
+			uri: uri.to_owned(),
+			headers: HeaderMap::new(),
+			credentials: Some((USERNAME.to_owned(), PASSWORD.to_owned())),
+			body: vec![],
+		}
+	}
+
+	pub fn get(&self, uri: &str) -> TestRequest<'_> {
+		self.request(Method::GET, uri)
+	}
+
+	pub fn post(&self, uri: &str) -> TestRequest<'_> {
+		self.request(Method::POST, uri)
+	}
+
+	pub fn put(&self, uri: &str) -> TestRequest<'_> {
+		self.request(Method::PUT, uri)
+	}
+
+	pub fn patch(&self, uri: &str) -> TestRequest<'_> {
+		self.request(Method::PATCH, uri)
+	}
+
+	pub fn delete(&self, uri: &str) -> TestRequest<'_> {
+		self.request(Method::DELETE, uri)
+	}
+}
+
+/// A request to a [`TestApp`], made as its administrator unless told
+/// otherwise
+pub struct TestRequest<'a> {
+	app: &'a TestApp,
+	method: Method,
+	uri: String,
+	headers: HeaderMap,
+	credentials: Option<(String, String)>,
+	body: Vec<u8>,
+}
+
+impl TestRequest<'_> {
+	/// Logs in as another user
+	pub fn login(mut self, username: &str, password: &str) -> Self {
+		self.credentials = Some((username.to_owned(), password.to_owned()));
+		self
+	}
+
+	/// Sends no credentials
+	pub fn anonymous(mut self) -> Self {
+		self.credentials = None;
+		self
+	}
+
+	pub fn header(mut self, name: HeaderName, value: &str) -> Self {
+		self.headers
+			.insert(name, HeaderValue::from_str(value).expect("header is valid"));
+		self
+	}
+
+	pub fn json(self, body: &impl Serialize) -> Self {
+		let mut request = self.header(header::CONTENT_TYPE, "application/json");
+		request.body = serde_json::to_vec(body).expect("body serializes");
+		request
+	}
+
+	pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+		self.body = body.into();
+		self
+	}
+
+	pub async fn send(self) -> TestResponse {
+		let mut request = Request::builder()
+			.method(self.method)
+			.uri(self.uri.parse::<Uri>().expect("uri is valid"))
+			.body(Body::from(self.body))
+			.expect("request is valid");
+		*request.headers_mut() = self.headers;
+		if let Some((username, password)) = self.credentials {
+			let credentials = base64::engine::general_purpose::STANDARD
+				.encode(format!("{}:{}", username, password));
+			request.headers_mut().insert(
+				header::AUTHORIZATION,
+				HeaderValue::from_str(&format!("Basic {}", credentials))
+					.expect("credentials are valid"),
+			);
+		}
+		request
+			.extensions_mut()
+			.insert(ConnectInfo(SocketAddr::from(CLIENT)));
+
+		let response = self
+			.app
+			.router
+			.clone()
+			.oneshot(request)
+			.await
+			.expect("routers are infallible");
+		let status = response.status();
+		let headers = response.headers().clone();
+		let body = hyper::body::to_bytes(response.into_body())
+			.await
+			.expect("responses are read in full");
+		TestResponse {
+			status,
+			headers,
+			body,
+		}
+	}
+}
+
+pub struct TestResponse {
+	pub status: StatusCode,
+	pub headers: HeaderMap,
+	pub body: Bytes,
+}
+
+impl TestResponse {
+	/// Fails the test with the body if the status isn't `status`
+	#[track_caller]
+	pub fn expect_status(self, status: StatusCode) -> Self {
+		assert_eq!(
+			self.status,
+			status,
+			"unexpected status, body: {}",
+			self.text()
+		);
+		self
+	}
+
+	pub fn text(&self) -> String {
+		String::from_utf8_lossy(&self.body).into_owned()
+	}
+
+	#[track_caller]
+	pub fn json<T: DeserializeOwned>(&self) -> T {
+		serde_json::from_slice(&self.body)
+			.unwrap_or_else(|e| panic!("body is not the expected json: {}: {}", e, self.text()))
+	}
+}
+
+/// A response of the [`MockServer`]
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+	pub status: StatusCode,
+	pub headers: Vec<(HeaderName, String)>,
+	pub body: String,
+	/// How long the server takes to answer
+	pub delay: Duration,
+}
+
+/// An entry of a feed made with [`MockResponse::rss`]
+#[derive(Clone, Debug)]
+pub struct MockItem {
+	pub guid: String,
+	pub title: String,
+	pub link: Option<String>,
+	pub published: DateTime<Utc>,
+	pub description: String,
+}
+
+impl MockItem {
+	/// An item published on 2024-01-01, without a link or description
+	pub fn new(guid: &str, title: &str) -> Self {
+		Self {
+			guid: guid.to_owned(),
+			title: title.to_owned(),
+			link: None,
+			published: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+			description: String::new(),
+		}
+	}
+
+	pub fn link(mut self, link: &str) -> Self {
+		self.link = Some(link.to_owned());
+		self
+	}
+
+	pub fn published(mut self, published: DateTime<Utc>) -> Self {
+		self.published = published;
+		self
+	}
+
+	pub fn description(mut self, description: &str) -> Self {
+		self.description = description.to_owned();
+		self
+	}
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+impl MockResponse {
+	pub fn new(status: StatusCode, content_type: &str, body: impl Into<String>) -> Self {
+		Self {
+			status,
+			headers: vec![(header::CONTENT_TYPE, content_type.to_owned())],
+			body: body.into(),
+			delay: Duration::ZERO,
+		}
+	}
+
+	/// An empty response with the status, e.g. of a failing feed
+	pub fn status(status: StatusCode) -> Self {
+		Self::new(status, "text/plain", "")
+	}
+
+	/// An RSS 2.0 feed of the items
+	pub fn rss(title: &str, items: &[MockItem]) -> Self {
+		let items: String = items
+			.iter()
+			.map(|item| {
+				format!(
+					"<item><guid>{}</guid><title>{}</title>{}<pubDate>{}</pubDate>\
+					 <description>{}</description></item>",
+					escape(&item.guid),
+					escape(&item.title),
+					item.link
+						.as_ref()
+						.map(|link| format!("<link>{}</link>", escape(link)))
+						.unwrap_or_default(),
+					item.published.to_rfc2822(),
+					escape(&item.description),
+				)
+			})
+			.collect();
+		Self::new(
+			StatusCode::OK,
+			"application/rss+xml",
+			format!(
+				"<?xml version=\"1.0\"?><rss version=\"2.0\"><channel><title>{}</title>{}</channel></rss>",
+				escape(title),
+				items
+			),
+		)
+	}
+
+	pub fn header(mut self, name: HeaderName, value: &str) -> Self {
+		self.headers.push((name, value.to_owned()));
+		self
+	}
+
+	/// Answers only after the delay, e.g. to act on a refresh midway
+	pub fn delay(mut self, delay: Duration) -> Self {
+		self.delay = delay;
+		self
+	}
+}
+
+#[derive(Default)]
+struct MockState {
+	/// By path and query
+	responses: HashMap<String, MockResponse>,
+	hits: HashMap<String, usize>,
+}
+
+/// An HTTP server on a local port, answering with what was mocked for the
+/// requested path and query, or a 404. Responses mocked with an `ETag` are
+/// answered with a 304 when requested with it in `If-None-Match`. Stops when
+/// dropped.
+pub struct MockServer {
+	addr: SocketAddr,
+	state: Arc<Mutex<MockState>>,
+	server: tokio::task::JoinHandle<()>,
+}
+
+async fn serve_mock(
+	State(state): State<Arc<Mutex<MockState>>>,
+	uri: Uri,
+	headers: HeaderMap,
+) -> Response {
+	let path = uri
+		.path_and_query()
+		.map_or(uri.path(), |path| path.as_str())
+		.to_owned();
+	let mock = {
+		let mut state = state.lock().unwrap();
+		*state.hits.entry(path.clone()).or_default() += 1;
+		state.responses.get(&path).cloned()
+	};
+	let Some(mock) = mock
+	else {
+		return StatusCode::NOT_FOUND.into_response();
+	};
+	tokio::time::sleep(mock.delay).await;
+
+	// the mocked ETag stands for the mocked body, whatever it is
+	let etag = mock
+		.headers
+		.iter()
+		.find(|(name, _)| *name == header::ETAG)
+		.map(|(_, etag)| etag.as_str());
+	let if_none_match = headers
+		.get(header::IF_NONE_MATCH)
+		.and_then(|value| value.to_str().ok());
+	let mut response = match etag.is_some() && etag == if_none_match {
+		true => StatusCode::NOT_MODIFIED.into_response(),
+		false => (mock.status, mock.body).into_response(),
+	};
+	for (name, value) in &mock.headers {
+		response.headers_mut().insert(
+			name.clone(),
+			HeaderValue::from_str(value).expect("header is valid"),
+		);
+	}
+	response
+}
+
+impl MockServer {
+	pub async fn start() -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").expect("local ports are free");
+		let addr = listener.local_addr().expect("listener has an address");
+		let state = Arc::new(Mutex::new(MockState::default()));
+		let router = Router::new().fallback(serve_mock).with_state(state.clone());
+		let server = axum::Server::from_tcp(listener).expect("listener is usable");
+		let server = tokio::spawn(async move {
+			server
+				.serve(router.into_make_service())
+				.await
+				.expect("mock server runs")
+		});
+
+		Self {
+			addr,
+			state,
+			server,
+		}
+	}
+
+	/// Url of the path, which may have a query
+	pub fn url(&self, path: &str) -> String {
+		format!("http://{}{}", self.addr, path)
+	}
+
+	/// Answers requests for the path with the response from now on
+	pub fn mock(&self, path: &str, response: MockResponse) {
+		self.state
+			.lock()
+			.unwrap()
+			.responses
+			.insert(path.to_owned(), response);
+	}
+
+	/// Number of requests made for the path
+	pub fn hits(&self, path: &str) -> usize {
+		self.state
+			.lock()
+			.unwrap()
+			.hits
+			.get(path)
+			.copied()
+			.unwrap_or_default()
+	}
+}
+
+impl Drop for MockServer {
+	fn drop(&mut self) {
+		self.server.abort();
+	}
+}
@@ -0,0 +1,167 @@
+//! `/api/v2`: paginated listings, structured errors and composite article ids.
+//! `/api/v1` is frozen, breaking changes to the API go here.
+
+use axum::{
+	extract::{Path, Query},
+	http::header,
+	response::{IntoResponse, Response},
+	routing::get,
+	Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, CompositeId, Page, Visibility},
+	AppState, Error,
+};
+
+pub const VERSIONS: &[&str] = &["v1", "v2"];
+
+/// Features clients can detect via `GET /api/version`
+pub const CAPABILITIES: &[&str] = &[
+	"paginated_articles",
+	"structured_errors",
+	"composite_article_ids",
+	"feeds_etag",
+	"capability_tokens",
+	"blogrolls",
+	"notifications",
+	"starred_feed",
+];
+
+pub fn routes() -> Router<AppState> {
+	Router::new()
+		.route("/articles", get(get_articles))
+		.route("/articles/:id", get(get_article).delete(delete_article))
+}
+
+/// Reports errors as `{"error": {"code": ..., "message": ...}}`
+pub struct ApiError(pub Error);
+
+#[derive(Serialize)]
+struct ErrorBody {
+	error: ErrorDetail,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail {
+	code: &'static str,
+	message: String,
+}
+
+impl From<Error> for ApiError {
+	fn from(e: Error) -> Self {
+		Self(e)
+	}
+}
+
+impl IntoResponse for ApiError {
+	fn into_response(self) -> Response {
+		let body = Json(ErrorBody {
+			error: ErrorDetail {
+				code: self.0.code(),
+				message: self.0.to_string(),
+			},
+		});
+
+		match self.0 {
+			Error::UsernameNotFound | Error::PasswordIncorrect => (
+				self.0.status(),
+				[(
+					header::WWW_AUTHENTICATE,
+					r#"Basic realm="NanoRSS", charset="UTF-8""#,
+				)],
+				body,
+			)
+				.into_response(),
+			_ => (self.0.status(), body).into_response(),
+		}
+	}
+}
+
+type Result<T, E = ApiError> = std::result::Result<T, E>;
+
+#[derive(Serialize)]
+struct ArticleV2 {
+	id: CompositeId,
+	feed_id: u64,
+	published: DateTime<Utc>,
+	first_seen: DateTime<Utc>,
+	url: Option<String>,
+	title: String,
+	summary: String,
+	content: String,
+	authors: Vec<String>,
+	categories: Vec<String>,
+}
+
+impl From<Article> for ArticleV2 {
+	fn from(article: Article) -> Self {
+		Self {
+			id: article.id.composite(),
+			feed_id: article.feed_id,
+			published: article.published,
+			first_seen: article.first_seen,
+			url: article.url,
+			title: article.title,
+			summary: article.summary,
+			content: article.content,
+			authors: article.authors,
+			categories: article.categories,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct ArticlesRequest {
+	feed_id: Option<u64>,
+	limit: Option<usize>,
+	cursor: Option<ArticleId>,
+	/// Include articles outside their feed's display window or muted
+	#[serde(default)]
+	include_hidden: bool,
+}
+
+async fn get_articles(
+	Extension(app): Extension<AppUser>,
+	Query(query): Query<ArticlesRequest>,
+) -> Result<Json<Page<ArticleV2>>> {
+	let visibility = match query.include_hidden {
+		true => None,
+		false => Some(Visibility::new(&app)?),
+	};
+	let page = Article::page(
+		&app,
+		query.cursor.as_ref(),
+		app.page_size(query.limit),
+		|article| {
+			Ok(query
+				.feed_id
+				.is_none_or(|feed_id| feed_id == article.feed_id)
+				&& visibility
+					.as_ref()
+					.is_none_or(|visibility| visibility.is_visible(article)))
+		},
+	)?;
+
+	Ok(Json(page.map(ArticleV2::from)))
+}
+
+async fn get_article(
+	Extension(app): Extension<AppUser>,
+	Path(id): Path<String>,
+) -> Result<Json<ArticleV2>> {
+	let id: CompositeId = id.parse()?;
+	Article::get_composite(&app, &id)?
+		.map(|article| Json(article.into()))
+		.ok_or(ApiError(Error::NotFound("article".into())))
+}
+
+async fn delete_article(Extension(app): Extension<AppUser>, Path(id): Path<String>) -> Result<()> {
+	let id: CompositeId = id.parse()?;
+	let article =
+		Article::get_composite(&app, &id)?.ok_or(ApiError(Error::NotFound("article".into())))?;
+	Ok(Article::remove(&app, &article.id)?)
+}
@@ -0,0 +1,185 @@
+//! Page watch feeds, for sites without a feed: part of a page is selected with a
+//! CSS selector, and every change to it becomes an article showing the diff
+
+use chrono::Utc;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use similar::{ChangeTag, TextDiff};
+
+use crate::{
+	app::AppUser,
+	db::{Article, ArticleId, Feed, FeedMeta},
+	fetch::{self, Fetched},
+	Error, Result,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PageWatch {
+	/// Selects the watched content; if it matches several elements, all of them
+	/// are watched
+	pub selector: String,
+}
+
+impl PageWatch {
+	pub fn parse_selector(&self) -> Result<Selector> {
+		Selector::parse(&self.selector)
+			.map_err(|e| Error::Selector(format!("{} ({:?})", self.selector, e)))
+	}
+
+	/// Text of the selected elements, one line per text node, along with the
+	/// page title
+	fn select(&self, page: &str) -> Result<(Option<String>, String)> {
+		let selector = self.parse_selector()?;
+		let html = Html::parse_document(page);
+
+		let title = Selector::parse("title")
+			.ok()
+			.and_then(|title| html.select(&title).next())
+			.map(|title| title.text().collect::<String>().trim().to_owned());
+		let text = html
+			.select(&selector)
+			.flat_map(|element| element.text())
+			.flat_map(str::lines)
+			.map(str::trim)
+			.filter(|line| !line.is_empty())
+			.collect::<Vec<_>>()
+			.join("\n");
+
+		Ok((title, text))
+	}
+}
+
+/// Watched content as of the last fetch
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+	hash: [u8; 32],
+	text: String,
+}
+
+impl Snapshot {
+	fn get(app: &AppUser, feed_id: u64) -> Result<Option<Snapshot>> {
+		app.snapshots
+			.get(feed_id.to_be_bytes())?
+			.map(|bytes| bincode::deserialize(&bytes))
+			.transpose()
+			.map_err(Into::into)
+	}
+
+	fn insert(&self, app: &AppUser, feed_id: u64) -> Result<()> {
+		app.snapshots
+			.insert(feed_id.to_be_bytes(), bincode::serialize(self)?)?;
+		Ok(())
+	}
+}
+
+/// Forgets the watched content, so the next fetch starts over without
+/// reporting a change, e.g. after the selector was changed
+pub fn reset(app: &AppUser, feed_id: u64) -> Result<()> {
+	app.snapshots.remove(feed_id.to_be_bytes())?;
+	Ok(())
+}
+
+pub fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+/// Renders the changed lines with a bit of context as HTML
+fn render_diff(old: &str, new: &str) -> (String, usize, usize) {
+	let diff = TextDiff::from_lines(old, new);
+	let (mut added, mut removed) = (0, 0);
+
+	let mut content = String::from("<pre>");
+	for (i, group) in diff.grouped_ops(2).iter().enumerate() {
+		if i > 0 {
+			content.push_str("…\n");
+		}
+		for op in group {
+			for change in diff.iter_changes(op) {
+				let line = escape(change.value().trim_end_matches('\n'));
+				match change.tag() {
+					ChangeTag::Equal => content.push_str(&format!("  {}\n", line)),
+					ChangeTag::Delete => {
+						removed += 1;
+						content.push_str(&format!("<del>- {}</del>\n", line));
+					}
+					ChangeTag::Insert => {
+						added += 1;
+						content.push_str(&format!("<ins>+ {}</ins>\n", line));
+					}
+				}
+			}
+		}
+	}
+	content.push_str("</pre>");
+
+	(content, added, removed)
+}
+
+/// Fetches a page watch feed, returning the id of the article reporting the
+/// change if the watched content changed. The first fetch only takes a snapshot.
+pub async fn fetch_watched(app: &AppUser, feed: &mut Feed, watch: &PageWatch) -> Result<Fetched> {
+	let page = fetch::fetch_page(
+		app.client_for(feed),
+		feed.url.as_str(),
+		Some(&mut feed.cert_pins),
+		app.limits.max_page_size,
+	)
+	.await?;
+	let bytes = page.len();
+	let (title, text) = watch.select(&page)?;
+
+	feed.meta = FeedMeta {
+		title: title.clone(),
+		site_url: Some(feed.url.to_string()),
+		..Default::default()
+	};
+
+	let hash: [u8; 32] = Sha256::digest(text.as_bytes()).into();
+	let prev = Snapshot::get(app, feed.id)?;
+	let snapshot = Snapshot { hash, text };
+	let prev = match prev {
+		Some(prev) if prev.hash == snapshot.hash => return Ok(Fetched::nothing_new(bytes)),
+		Some(prev) => prev,
+		None => {
+			snapshot.insert(app, feed.id)?;
+			return Ok(Fetched::nothing_new(bytes));
+		}
+	};
+
+	let (content, added, removed) = render_diff(&prev.text, &snapshot.text);
+	let now = Utc::now();
+	// content may well change back, so it can't identify the change
+	let id = ArticleId::new(now, feed.id, &now.to_rfc3339());
+	Article {
+		id,
+		feed_id: feed.id,
+		published: now,
+		first_seen: now,
+		url: Some(feed.url.to_string()),
+		title: format!("{} changed", title.unwrap_or_else(|| feed.name.clone())),
+		summary: format!(
+			"{} line{} added, {} removed",
+			added,
+			if added == 1 { "" } else { "s" },
+			removed
+		),
+		content,
+		authors: vec![],
+		categories: vec![],
+	}
+	.insert(app)?;
+	snapshot.insert(app, feed.id)?;
+
+	if feed.auto_read {
+		Article::set_read(app, &id, true)?;
+	}
+
+	Ok(Fetched {
+		new_articles: vec![id],
+		bytes,
+	})
+}
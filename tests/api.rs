@@ -0,0 +1,1131 @@
+//! End-to-end tests of the API, against feeds served by a mock server
+
+use std::time::Duration;
+
+use axum::http::{header, StatusCode};
+use nanorss::testing::{MockItem, MockResponse, MockServer, TestApp};
+use serde_json::{json, Value};
+
+/// Subscribes to the feed at `path` and refreshes
+async fn subscribe(app: &TestApp, feeds: &MockServer, path: &str) {
+	app.post("/api/v1/feeds")
+		.json(&json!({ "url": feeds.url(path) }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	refresh(app).await;
+}
+
+async fn refresh(app: &TestApp) {
+	app.post("/api/v1/refresh")
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+}
+
+/// Titles of the listed articles, newest first
+async fn titles(app: &TestApp, query: &str) -> Vec<String> {
+	let articles: Vec<Value> = app
+		.get(&format!("/api/v1/articles?fields=title{}", query))
+		.send()
+		.await
+		.json();
+	articles
+		.iter()
+		.map(|article| article["title"].as_str().unwrap_or_default().to_owned())
+		.collect()
+}
+
+fn blog(items: &[MockItem]) -> MockResponse {
+	MockResponse::rss("Blog", items)
+}
+
+fn item(guid: &str, title: &str, day: u32) -> MockItem {
+	MockItem::new(guid, title).published(
+		chrono::NaiveDate::from_ymd_opt(2024, 1, day)
+			.unwrap()
+			.and_hms_opt(12, 0, 0)
+			.unwrap()
+			.and_utc(),
+	)
+}
+
+#[tokio::test]
+async fn requests_need_credentials() {
+	let app = TestApp::new().unwrap();
+
+	app.get("/api/v1/feeds")
+		.anonymous()
+		.send()
+		.await
+		.expect_status(StatusCode::UNAUTHORIZED);
+	app.get("/api/v1/feeds")
+		.login("admin", "wrong")
+		.send()
+		.await
+		.expect_status(StatusCode::UNAUTHORIZED);
+	app.get("/api/v1/feeds")
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.get("/api/v1/meta")
+		.anonymous()
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn refresh_stores_articles() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 1), item("2", "Second", 2)]),
+	);
+	let app = TestApp::new().unwrap();
+
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	assert!(feeds.hits("/feed.xml") >= 1);
+	assert_eq!(titles(&app, "").await, ["Second", "First"]);
+}
+
+#[tokio::test]
+async fn refresh_adds_new_entries_once() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/feed.xml", blog(&[item("1", "First", 1)]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	// an entry is added and the first one retitled, which updates it in place
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First, edited", 1), item("2", "Second", 2)]),
+	);
+	refresh(&app).await;
+	refresh(&app).await;
+
+	assert_eq!(titles(&app, "").await, ["Second", "First, edited"]);
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_answered_with_a_304() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 1)]).header(header::ETAG, "\"v1\""),
+	);
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	// the server says it's unchanged, so the added entry is not seen
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 1), item("2", "Second", 2)])
+			.header(header::ETAG, "\"v1\""),
+	);
+	refresh(&app).await;
+	assert_eq!(titles(&app, "").await, ["First"]);
+
+	// other content may come of the same version, it's fetched in full again
+	let feed: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	app.patch(&format!("/api/v1/feeds/{}", feed[0]["id"]))
+		.json(&json!({ "content_mode": "summary_only" }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	refresh(&app).await;
+	assert_eq!(titles(&app, "").await, ["Second", "First"]);
+}
+
+#[tokio::test]
+async fn refreshes_finish_when_the_client_leaves() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/feed.xml", blog(&[]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 1)]).delay(Duration::from_millis(300)),
+	);
+	let left = tokio::time::timeout(Duration::from_millis(50), refresh(&app)).await;
+	assert!(left.is_err());
+
+	tokio::time::sleep(Duration::from_millis(800)).await;
+	assert_eq!(titles(&app, "").await, ["First"]);
+}
+
+#[tokio::test]
+async fn read_state_survives_refresh() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 1), item("2", "Second", 2)]),
+	);
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	let articles: Vec<Value> = app
+		.get("/api/v1/articles?fields=id,title")
+		.send()
+		.await
+		.json();
+	let first = articles
+		.iter()
+		.find(|article| article["title"] == "First")
+		.and_then(|article| article["id"].as_str())
+		.unwrap();
+	app.patch(&format!("/api/v1/articles/{}", first))
+		.json(&json!({ "read": true }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	refresh(&app).await;
+
+	let articles: Vec<Value> = app
+		.get("/api/v1/articles?fields=title,unread")
+		.send()
+		.await
+		.json();
+	let unread: Vec<(&str, bool)> = articles
+		.iter()
+		.map(|article| {
+			(
+				article["title"].as_str().unwrap(),
+				article["unread"].as_bool().unwrap(),
+			)
+		})
+		.collect();
+	assert_eq!(unread, [("Second", true), ("First", false)]);
+}
+
+#[tokio::test]
+async fn ids_outlive_changes_of_the_publish_time() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/feed.xml", blog(&[item("1", "First", 1)]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+	let articles: Vec<Value> = app.get("/api/v1/articles?fields=id").send().await.json();
+	let old_id = articles[0]["id"].as_str().unwrap().to_owned();
+
+	feeds.mock("/feed.xml", blog(&[item("1", "First", 3)]));
+	refresh(&app).await;
+	let articles: Vec<Value> = app.get("/api/v1/articles?fields=id").send().await.json();
+	assert_eq!(articles.len(), 1);
+	let new_id = articles[0]["id"].as_str().unwrap();
+	assert_ne!(new_id, old_id);
+
+	let article: Value = app
+		.get(&format!("/api/v1/articles/{}", old_id))
+		.send()
+		.await
+		.expect_status(StatusCode::OK)
+		.json();
+	assert_eq!(article["id"], new_id);
+	app.patch(&format!("/api/v1/articles/{}", old_id))
+		.json(&json!({ "read": true }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.delete(&format!("/api/v1/articles/{}", old_id))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	assert!(titles(&app, "").await.is_empty());
+}
+
+#[tokio::test]
+async fn listings_are_paged_by_the_page_size_setting() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[
+			item("1", "First", 1),
+			item("2", "Second", 2),
+			item("3", "Third", 3),
+		]),
+	);
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+	assert_eq!(titles(&app, "&limit=1").await, ["Third"]);
+	assert_eq!(titles(&app, "").await, ["Third", "Second", "First"]);
+
+	app.put("/api/v1/account/settings")
+		.json(&json!({ "page_size": 2 }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	assert_eq!(titles(&app, "").await, ["Third", "Second"]);
+	assert_eq!(titles(&app, "&limit=3").await, ["Third", "Second", "First"]);
+
+	let feed: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	let page: Vec<Value> = app
+		.get(&format!("/api/v1/feeds/{}/articles", feed[0]["id"]))
+		.send()
+		.await
+		.json();
+	assert_eq!(page.len(), 2);
+	let rest: Vec<Value> = app
+		.get(&format!(
+			"/api/v1/feeds/{}/articles?cursor={}",
+			feed[0]["id"],
+			page[1]["id"].as_str().unwrap()
+		))
+		.send()
+		.await
+		.json();
+	assert_eq!(rest.len(), 1);
+	assert_eq!(rest[0]["title"], "First");
+}
+
+#[tokio::test]
+async fn listings_default_to_the_unread_only_setting() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 1), item("2", "Second", 2)]),
+	);
+	let app = TestApp::new().unwrap();
+	app.post("/api/v1/feeds")
+		.json(&json!({ "url": feeds.url("/feed.xml"), "category": "News" }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	refresh(&app).await;
+	let articles: Vec<Value> = app.get("/api/v1/articles?fields=id").send().await.json();
+	let (second, first) = (&articles[0]["id"], &articles[1]["id"]);
+	app.put(&format!("/api/v1/articles/{}/read", first.as_str().unwrap()))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.put("/api/v1/account/settings")
+		.json(&json!({ "unread_only": true }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	assert_eq!(titles(&app, "").await, ["Second"]);
+	assert_eq!(titles(&app, "&unread_only=false").await, ["Second", "First"]);
+	let feed: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	let listed: Vec<Value> = app
+		.get(&format!("/api/v1/feeds/{}/articles", feed[0]["id"]))
+		.send()
+		.await
+		.json();
+	assert_eq!(listed.len(), 1);
+	let page: Value = app
+		.get("/api/v1/categories/News/articles")
+		.send()
+		.await
+		.json();
+	assert_eq!(page["items"].as_array().unwrap().len(), 1);
+	let found: Vec<Value> = app.post("/api/v1/search").send().await.json();
+	assert_eq!(found.len(), 1);
+	assert_eq!(&found[0], second);
+}
+
+#[tokio::test]
+async fn reading_stats_leave_out_auto_read_feeds() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/read.xml", blog(&[item("1", "Read", 1)]));
+	feeds.mock("/auto.xml", blog(&[item("1", "Auto", 1)]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/read.xml").await;
+	app.post("/api/v1/feeds")
+		.json(&json!({ "url": feeds.url("/auto.xml"), "auto_read": true }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	refresh(&app).await;
+	let articles: Vec<Value> = app
+		.get("/api/v1/articles?fields=id,title,unread")
+		.send()
+		.await
+		.json();
+	assert_eq!(articles.len(), 2);
+	let read = articles
+		.iter()
+		.find(|article| article["title"] == "Read")
+		.unwrap();
+	app.put(&format!("/api/v1/articles/{}/read", read["id"].as_str().unwrap()))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	let stats: Value = app.get("/api/v1/stats/reading").send().await.json();
+	assert_eq!(stats["read"], 1);
+	assert_eq!(stats["top_feeds"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn failed_fetches_are_recorded() {
+	let feeds = MockServer::start().await;
+	// server errors are retried with a backoff, which tests shouldn't wait for
+	feeds.mock("/feed.xml", MockResponse::status(StatusCode::GONE));
+	feeds.mock(
+		"/broken.xml",
+		MockResponse::new(StatusCode::OK, "application/rss+xml", "<rss><channel>"),
+	);
+	let app = TestApp::new().unwrap();
+
+	subscribe(&app, &feeds, "/feed.xml").await;
+	subscribe(&app, &feeds, "/broken.xml").await;
+
+	let listed: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	let error_of = |path: &str| {
+		listed
+			.iter()
+			.find(|feed| feed["url"] == feeds.url(path))
+			.map(|feed| feed["last_error"].clone())
+			.unwrap()
+	};
+	assert_eq!(error_of("/feed.xml")["kind"], "http");
+	assert_eq!(error_of("/feed.xml")["status"], 410);
+	assert_eq!(error_of("/broken.xml")["kind"], "parse");
+	assert!(titles(&app, "").await.is_empty());
+}
+
+#[tokio::test]
+async fn search_finds_refreshed_articles() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[
+			item("1", "Rust async runtimes", 1).description("A tour of tokio"),
+			item("2", "Cooking pasta", 2).description("Water, salt and time"),
+		]),
+	);
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	let search = |q: &'static str| {
+		let app = &app;
+		async move {
+			app.post(&format!("/api/v1/search?q={}", q))
+				.send()
+				.await
+				.expect_status(StatusCode::OK)
+				.json::<Vec<String>>()
+				.len()
+		}
+	};
+	assert_eq!(search("tokio").await, 1);
+	assert_eq!(search("rust*").await, 1);
+	assert_eq!(search("title:salt").await, 0);
+	assert_eq!(search("-pasta").await, 1);
+
+	app.post("/api/v1/search?q=after:yesterday")
+		.send()
+		.await
+		.expect_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn exclusions_alone_filter_every_article() {
+	let feeds = MockServer::start().await;
+	let mut items: Vec<MockItem> = (1..=1100)
+		.map(|n| MockItem::new(&n.to_string(), &format!("Post {}", n)))
+		.collect();
+	items.push(MockItem::new("pasta", "Cooking pasta"));
+	feeds.mock("/feed.xml", blog(&items));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	// more than a search goes through
+	let found: Vec<String> = app.post("/api/v1/search?q=-pasta").send().await.json();
+	assert_eq!(found.len(), 1100);
+	let found: Vec<String> = app
+		.post("/api/v1/search?q=-pasta%20-post")
+		.send()
+		.await
+		.json();
+	assert!(found.is_empty());
+}
+
+#[tokio::test]
+async fn search_matches_are_ordered_by_publish_time() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[
+			item("1", "Rust in 2023", 1),
+			item("2", "Cooking pasta", 2),
+			item("3", "Rust in 2024", 3),
+		]),
+	);
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	let listed: Vec<Value> = app
+		.get("/api/v1/articles?fields=id,title")
+		.send()
+		.await
+		.json();
+	let id = |title: &str| {
+		listed
+			.iter()
+			.find(|article| article["title"] == title)
+			.unwrap()["id"]
+			.clone()
+	};
+	let search = |order: &'static str| {
+		let app = &app;
+		async move {
+			app.post(&format!(
+				"/api/v1/search?q=rust&order_by=published&order={}",
+				order
+			))
+			.send()
+			.await
+			.expect_status(StatusCode::OK)
+			.json::<Vec<Value>>()
+		}
+	};
+	assert_eq!(
+		search("desc").await,
+		[id("Rust in 2024"), id("Rust in 2023")]
+	);
+	assert_eq!(
+		search("asc").await,
+		[id("Rust in 2023"), id("Rust in 2024")]
+	);
+}
+
+#[tokio::test]
+async fn articles_are_filtered_by_tag() {
+	let feeds = MockServer::start().await;
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 1), item("2", "Second", 2)]),
+	);
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	let articles: Vec<Value> = app
+		.get("/api/v1/articles?fields=id,title")
+		.send()
+		.await
+		.json();
+	let first = articles
+		.iter()
+		.find(|article| article["title"] == "First")
+		.and_then(|article| article["id"].as_str())
+		.unwrap();
+	let state: Value = app
+		.patch(&format!("/api/v1/articles/{}", first))
+		.json(&json!({ "tags": ["Read-Later", "rust", "rust"] }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK)
+		.json();
+	assert_eq!(state["tags"], json!(["read-later", "rust"]));
+
+	let tags: Value = app.get("/api/v1/tags").send().await.json();
+	assert_eq!(
+		tags,
+		json!([
+			{ "name": "read-later", "articles": 1 },
+			{ "name": "rust", "articles": 1 },
+		])
+	);
+	assert_eq!(titles(&app, "&tag=read-later").await, ["First"]);
+
+	let found: Vec<String> = app.post("/api/v1/search?q=-tag:rust").send().await.json();
+	assert_eq!(found.len(), 1);
+	assert_ne!(found[0], first);
+}
+
+#[tokio::test]
+async fn feeds_are_subscribed_to_once() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/feed.xml", blog(&[]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	// the same feed once normalized
+	let url = format!("feed:{}#latest", feeds.url("/feed.xml"));
+	app.post("/api/v1/feeds")
+		.json(&json!({ "url": url }))
+		.send()
+		.await
+		.expect_status(StatusCode::BAD_REQUEST);
+	let listed: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	assert_eq!(listed.len(), 1);
+}
+
+#[tokio::test]
+async fn feeds_are_not_moved_onto_another_subscription() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/a.xml", blog(&[]));
+	feeds.mock("/b.xml", blog(&[]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/a.xml").await;
+	subscribe(&app, &feeds, "/b.xml").await;
+
+	let listed: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	let id_of = |path: &str| {
+		let url = feeds.url(path);
+		listed.iter().find(|feed| feed["url"] == url).unwrap()["id"].clone()
+	};
+	let url = format!("feed:{}#latest", feeds.url("/a.xml"));
+	app.patch(&format!("/api/v1/feeds/{}", id_of("/b.xml")))
+		.json(&json!({ "url": url }))
+		.send()
+		.await
+		.expect_status(StatusCode::BAD_REQUEST);
+
+	// a feed may still be set to its own url
+	app.patch(&format!("/api/v1/feeds/{}", id_of("/a.xml")))
+		.json(&json!({ "url": url }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn feed_deltas_report_deleted_feeds() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/a.xml", blog(&[]));
+	feeds.mock("/b.xml", blog(&[]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/a.xml").await;
+	subscribe(&app, &feeds, "/b.xml").await;
+
+	let delta: Value = app
+		.get("/api/v1/feeds?since_revision=0")
+		.send()
+		.await
+		.json();
+	assert_eq!(delta["feeds"].as_array().unwrap().len(), 2);
+	assert_eq!(delta["deleted"], json!([]));
+	let id = delta["feeds"][0]["id"].as_u64().unwrap();
+
+	app.delete(&format!("/api/v1/feeds/{}", id))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	let since: Value = app
+		.get(&format!(
+			"/api/v1/feeds?since_revision={}",
+			delta["revision"]
+		))
+		.send()
+		.await
+		.json();
+	assert_eq!(since["feeds"], json!([]));
+	assert_eq!(since["deleted"], json!([id]));
+}
+
+#[tokio::test]
+async fn article_changes_report_late_and_removed_articles() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/feed.xml", blog(&[item("1", "First", 5)]));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+	let start: Value = app.get("/api/v1/articles/changes").send().await.json();
+	assert_eq!(start["changed"], json!([]));
+
+	// published before every known article, still reported
+	feeds.mock(
+		"/feed.xml",
+		blog(&[item("1", "First", 5), item("2", "Older", 1)]),
+	);
+	refresh(&app).await;
+	let articles: Vec<Value> = app.get("/api/v1/articles?fields=id").send().await.json();
+	let (first, older) = (&articles[0]["id"], &articles[1]["id"]);
+	app.delete(&format!("/api/v1/articles/{}", first.as_str().unwrap()))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	let changes: Value = app
+		.get(&format!(
+			"/api/v1/articles/changes?since_revision={}",
+			start["revision"]
+		))
+		.send()
+		.await
+		.json();
+	assert_eq!(changes["changed"], json!([older]));
+	assert_eq!(changes["removed"], json!([first]));
+	assert!(changes["revision"].as_u64() > start["revision"].as_u64());
+}
+
+#[tokio::test]
+async fn users_only_see_their_own_feeds() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/feed.xml", blog(&[item("1", "First", 1)]));
+	let app = TestApp::builder().user("bob", "hunter2").build().unwrap();
+	subscribe(&app, &feeds, "/feed.xml").await;
+
+	let listed: Vec<Value> = app
+		.get("/api/v1/feeds")
+		.login("bob", "hunter2")
+		.send()
+		.await
+		.json();
+	assert!(listed.is_empty());
+	let articles: Vec<Value> = app
+		.get("/api/v1/articles")
+		.login("bob", "hunter2")
+		.send()
+		.await
+		.json();
+	assert!(articles.is_empty());
+}
+
+#[tokio::test]
+async fn logins_are_recorded_when_they_change() {
+	let app = TestApp::new().unwrap();
+	let account = |user_agent: &'static str| {
+		let app = &app;
+		async move {
+			app.get("/api/v1/account")
+				.header(header::USER_AGENT, user_agent)
+				.send()
+				.await
+				.expect_status(StatusCode::OK)
+				.json::<Value>()
+		}
+	};
+
+	let first = account("reader/1").await;
+	assert_eq!(first["last_user_agent"], "reader/1");
+	let again = account("reader/1").await;
+	assert_eq!(again["last_login"], first["last_login"]);
+
+	let other = account("reader/2").await;
+	assert_eq!(other["last_user_agent"], "reader/2");
+	assert_ne!(other["last_login"], first["last_login"]);
+}
+
+#[tokio::test]
+async fn concurrent_registrations_take_a_name_once() {
+	let app = TestApp::builder().open_registration().build().unwrap();
+	let register = |password: &str| {
+		app.post("/api/v1/register")
+			.anonymous()
+			.json(&json!({ "username": "carol", "password": password }))
+			.send()
+	};
+
+	let (first, second) = tokio::join!(register("first"), register("second"));
+	let mut statuses = [first.status, second.status];
+	statuses.sort();
+	assert_eq!(statuses, [StatusCode::OK, StatusCode::BAD_REQUEST]);
+
+	register("third")
+		.await
+		.expect_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn renames_revoke_tokens_and_reject_invalid_names() {
+	let app = TestApp::builder().user("bob", "hunter2").build().unwrap();
+	let token: Value = app
+		.post("/api/v1/tokens")
+		.login("bob", "hunter2")
+		.json(&json!({ "label": "reader", "scope": "feed" }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK)
+		.json();
+	let published = format!("/api/v1/publish/{}", token["token"].as_str().unwrap());
+	app.get(&published)
+		.anonymous()
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	for invalid in ["", "bob/feeds", "bob:x", &"b".repeat(65)] {
+		app.post("/api/v1/admin/users/rename")
+			.login("admin", "password")
+			.json(&json!({ "username": "bob", "new_username": invalid }))
+			.send()
+			.await
+			.expect_status(StatusCode::BAD_REQUEST);
+	}
+	app.post("/api/v1/admin/users/rename")
+		.login("admin", "password")
+		.json(&json!({ "username": "bob", "new_username": "admin" }))
+		.send()
+		.await
+		.expect_status(StatusCode::BAD_REQUEST);
+
+	app.post("/api/v1/admin/users/rename")
+		.login("admin", "password")
+		.json(&json!({ "username": "bob", "new_username": "robert" }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.get("/api/v1/feeds")
+		.login("robert", "hunter2")
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.get(&published)
+		.anonymous()
+		.send()
+		.await
+		.expect_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn feed_secrets_stay_with_their_owner() {
+	let app = TestApp::builder().user("bob", "hunter2").build().unwrap();
+	app.post("/api/v1/feeds")
+		.json(&json!({
+			"url": "https://api.example.com/feed",
+			"category": "Private",
+			"request": {
+				"oauth": {
+					"token_url": "https://api.example.com/token",
+					"client_id": "reader",
+					"client_secret": "client-secret",
+					"refresh_token": "refresh-secret",
+				},
+			},
+			"downloader": {
+				"kind": "webhook",
+				"url": "https://hooks.example.com/",
+				"password": "hook-secret",
+			},
+		}))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.post("/api/v1/blogrolls")
+		.json(&json!({ "folder": "Private" }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.post("/api/v1/blogrolls/subscriptions")
+		.login("bob", "hunter2")
+		.json(&json!({ "owner": "admin", "folder": "Private" }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	let listed = app.get("/api/v1/feeds").send().await.text();
+	let feeds: Vec<Value> = serde_json::from_str(&listed).unwrap();
+	let feed = app
+		.get(&format!("/api/v1/feeds/{}", feeds[0]["id"]))
+		.send()
+		.await
+		.text();
+	for body in [&listed, &feed] {
+		assert!(body.contains("\"client_id\":\"reader\""), "{}", body);
+		assert!(!body.contains("-secret"), "{}", body);
+	}
+
+	let subscriptions: Vec<Value> = app
+		.get("/api/v1/blogrolls/subscriptions")
+		.login("bob", "hunter2")
+		.send()
+		.await
+		.json();
+	let shared = &subscriptions[0]["feeds"][0];
+	assert_eq!(shared["url"], "https://api.example.com/feed");
+	assert_eq!(shared["request"], Value::Null);
+	assert_eq!(shared["downloader"], Value::Null);
+}
+
+#[tokio::test]
+async fn syncs_skip_remote_feeds_that_cannot_be_added() {
+	let remote = MockServer::start().await;
+	remote.mock(
+		"/api/v1/feeds",
+		MockResponse::new(
+			StatusCode::OK,
+			"application/json",
+			json!([
+				{ "url": "feed:not a url", "name": "Broken" },
+				{ "url": remote.url("/feed.xml"), "name": "Blog" },
+			])
+			.to_string(),
+		),
+	);
+	let app = TestApp::new().unwrap();
+	app.post("/api/v1/sync/remotes")
+		.json(&json!({
+			"kind": "nanorss",
+			"url": remote.url("/"),
+			"username": "admin",
+			"password": "remote-secret",
+		}))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	let synced = app.post("/api/v1/sync").send().await.text();
+	assert!(!synced.contains("remote-secret"), "{}", synced);
+	let remotes = app.get("/api/v1/sync/remotes").send().await.text();
+	assert!(!remotes.contains("remote-secret"), "{}", remotes);
+
+	let feeds: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	assert_eq!(feeds.len(), 1);
+	assert_eq!(feeds[0]["name"], "Blog");
+}
+
+#[tokio::test]
+async fn notification_targets_are_listed_without_credentials() {
+	let app = TestApp::new().unwrap();
+	let transports = [
+		json!({ "kind": "gotify", "server": "https://gotify.example.com/", "token": "gotify-secret" }),
+		json!({ "kind": "telegram", "bot_token": "telegram-secret", "chat_id": "42" }),
+		json!({ "kind": "ntfy", "server": "https://ntfy.sh/", "topic": "news", "token": "ntfy-secret" }),
+		json!({
+			"kind": "matrix",
+			"homeserver": "https://matrix.example.org",
+			"access_token": "matrix-secret",
+			"room_id": "!abcdef:example.org",
+		}),
+	];
+	for transport in transports {
+		let created = app
+			.post("/api/v1/notifications/targets")
+			.json(&json!({ "transport": transport }))
+			.send()
+			.await
+			.expect_status(StatusCode::OK)
+			.text();
+		assert!(!created.contains("-secret"), "{}", created);
+	}
+
+	let listed = app.get("/api/v1/notifications/targets").send().await.text();
+	assert!(listed.contains("gotify.example.com"), "{}", listed);
+	assert!(!listed.contains("-secret"), "{}", listed);
+}
+
+#[tokio::test]
+async fn opml_round_trips_nested_folders() {
+	let feeds = MockServer::start().await;
+	let opml = format!(
+		r#"<?xml version="1.0"?>
+<opml version="2.0"><head><title>Feeds</title></head><body>
+<outline text="Work"><outline text="Rust"><outline text="Blog" type="rss" xmlUrl="{}"/></outline></outline>
+<outline text="Other" type="rss" xmlUrl="{}"/>
+</body></opml>"#,
+		feeds.url("/blog.xml"),
+		feeds.url("/other.xml"),
+	);
+	let app = TestApp::new().unwrap();
+	app.post("/api/v1/import")
+		.body(opml)
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	let exported = app
+		.post("/api/v1/export?kind=opml")
+		.send()
+		.await
+		.expect_status(StatusCode::OK)
+		.text();
+	let copy = TestApp::new().unwrap();
+	copy.post("/api/v1/import")
+		.body(exported)
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+
+	let listed: Vec<Value> = copy.get("/api/v1/feeds").send().await.json();
+	let category_of = |path: &str| {
+		listed
+			.iter()
+			.find(|feed| feed["url"] == feeds.url(path))
+			.map(|feed| feed["category"].clone())
+			.unwrap()
+	};
+	assert_eq!(category_of("/blog.xml"), "Work/Rust");
+	assert_eq!(category_of("/other.xml"), Value::Null);
+}
+
+/// Records as stored before the database was versioned
+mod v0 {
+	use chrono::{DateTime, Utc};
+	use serde::Serialize;
+
+	#[derive(Serialize)]
+	pub struct User {
+		pub username: String,
+		pub pass_hash: String,
+	}
+
+	#[derive(Serialize)]
+	pub struct Feed {
+		pub id: u64,
+		pub url: String,
+		pub name: String,
+		pub scraper: Option<()>,
+		pub last_fetch_time: DateTime<Utc>,
+		pub last_error: Option<String>,
+	}
+
+	#[derive(Serialize)]
+	pub struct Article {
+		pub id: String,
+		pub feed_id: u64,
+		pub published: DateTime<Utc>,
+		pub url: Option<String>,
+		pub title: String,
+		pub summary: String,
+		pub content: String,
+	}
+}
+
+#[tokio::test]
+async fn databases_from_before_versioning_are_upgraded() {
+	let app = TestApp::builder()
+		.seed(|db| {
+			let user = v0::User {
+				username: "alice".into(),
+				pass_hash: bcrypt::hash("secret", 4).unwrap(),
+			};
+			let feed = v0::Feed {
+				id: 7,
+				url: "https://example.org/feed.xml".into(),
+				name: "Example".into(),
+				scraper: None,
+				last_fetch_time: chrono::Utc::now(),
+				last_error: Some("connection refused".into()),
+			};
+			let article = v0::Article {
+				id: "https://example.org/hello".into(),
+				feed_id: 7,
+				published: chrono::Utc::now(),
+				url: Some("https://example.org/hello".into()),
+				title: "Hello".into(),
+				summary: "A tour of tokio".into(),
+				content: String::new(),
+			};
+			db.open_tree("users")
+				.unwrap()
+				.insert("alice", bincode::serialize(&user).unwrap())
+				.unwrap();
+			db.open_tree("alice/feeds")
+				.unwrap()
+				.insert(
+					bincode::serialize(&feed.id).unwrap(),
+					bincode::serialize(&feed).unwrap(),
+				)
+				.unwrap();
+			db.open_tree("alice/articles")
+				.unwrap()
+				.insert(article.id.as_bytes(), bincode::serialize(&article).unwrap())
+				.unwrap();
+		})
+		.build()
+		.unwrap();
+	let alice = |uri: &str| app.get(uri).login("alice", "secret");
+
+	let listed: Vec<Value> = alice("/api/v1/feeds").send().await.json();
+	assert_eq!(listed.len(), 1);
+	assert_eq!(listed[0]["name"], "Example");
+	assert_eq!(listed[0]["last_error"]["message"], "connection refused");
+	let articles: Vec<Value> = alice("/api/v1/articles?fields=title,feed_id")
+		.send()
+		.await
+		.json();
+	assert_eq!(articles, [json!({ "title": "Hello", "feed_id": 7 })]);
+	let found: Vec<String> = app
+		.post("/api/v1/search?q=tokio")
+		.login("alice", "secret")
+		.send()
+		.await
+		.json();
+	assert_eq!(found.len(), 1);
+	// only the operator could set up users then
+	alice("/api/v1/admin/users")
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn report_applies_only_unblocked_replacements() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/old.xml", blog(&[item("1", "First", 1)]));
+	feeds.mock("/new.xml", blog(&[item("1", "First", 1)]));
+	feeds.mock(
+		"/",
+		MockResponse::new(
+			StatusCode::OK,
+			"text/html",
+			r#"<html><head>
+				<link rel="alternate" type="application/rss+xml" href="feed:nowhere">
+				<link rel="alternate" type="application/rss+xml" href="/new.xml">
+			</head></html>"#,
+		),
+	);
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/old.xml").await;
+	feeds.mock("/old.xml", MockResponse::status(StatusCode::NOT_FOUND));
+	refresh(&app).await;
+
+	app.post("/api/v1/admin/blocklist")
+		.json(&json!({ "target": { "url": feeds.url("/new.xml") } }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	let report: Value = app
+		.get("/api/v1/feeds/report?apply=true")
+		.send()
+		.await
+		.expect_status(StatusCode::OK)
+		.json();
+	let entry = &report["erroring"][0];
+	assert_eq!(entry["suggested_url"], feeds.url("/new.xml"));
+	assert_eq!(entry["applied"], false);
+	assert_eq!(entry["url"], feeds.url("/old.xml"));
+
+	app.delete("/api/v1/admin/blocklist")
+		.json(&json!({ "url": feeds.url("/new.xml") }))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	let report: Value = app
+		.get("/api/v1/feeds/report?apply=true")
+		.send()
+		.await
+		.expect_status(StatusCode::OK)
+		.json();
+	let entry = &report["erroring"][0];
+	assert_eq!(entry["applied"], true);
+	assert_eq!(entry["url"], feeds.url("/new.xml"));
+}
+
+#[tokio::test]
+async fn report_applies_only_permanent_redirects() {
+	let feeds = MockServer::start().await;
+	feeds.mock("/moved.xml", blog(&[item("1", "First", 1)]));
+	feeds.mock("/for-now.xml", blog(&[item("1", "First", 1)]));
+	let redirect = |status, to: &str| {
+		MockResponse::status(status).header(header::LOCATION, &feeds.url(to))
+	};
+	feeds.mock("/old.xml", redirect(StatusCode::MOVED_PERMANENTLY, "/moved.xml"));
+	feeds.mock("/busy.xml", redirect(StatusCode::FOUND, "/for-now.xml"));
+	let app = TestApp::new().unwrap();
+	subscribe(&app, &feeds, "/old.xml").await;
+	subscribe(&app, &feeds, "/busy.xml").await;
+
+	let report: Value = app
+		.get("/api/v1/feeds/report?apply=true")
+		.send()
+		.await
+		.expect_status(StatusCode::OK)
+		.json();
+	let redirected = report["redirected"].as_array().unwrap();
+	assert_eq!(redirected.len(), 2);
+	let entry = |url: &str| {
+		redirected
+			.iter()
+			.find(|entry| entry["suggested_url"] == url || entry["url"] == url)
+			.unwrap()
+	};
+	assert_eq!(entry(&feeds.url("/moved.xml"))["applied"], true);
+	let temporary = entry(&feeds.url("/busy.xml"));
+	assert_eq!(temporary["applied"], false);
+	assert_eq!(temporary["suggested_url"], Value::Null);
+}
@@ -0,0 +1,68 @@
+//! Encryption at rest, in a test binary of its own, as the key is set once per
+//! process for every app opened afterwards
+
+use axum::http::StatusCode;
+use nanorss::testing::{self, TestApp};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn credentials_are_sealed_once_encryption_is_enabled() {
+	let app = TestApp::new().unwrap();
+	app.post("/api/v1/feeds")
+		.json(&json!({
+			"url": "https://example.com/feed.xml",
+			"downloader": {
+				"kind": "webhook",
+				"url": "https://hooks.example.com/",
+				"password": "hook-secret",
+			},
+		}))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.post("/api/v1/sync/remotes")
+		.json(&json!({
+			"kind": "miniflux",
+			"url": "https://miniflux.example.com/",
+			"api_key": "remote-secret",
+		}))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	app.post("/api/v1/notifications/targets")
+		.json(&json!({
+			"transport": {
+				"kind": "gotify",
+				"server": "https://gotify.example.com/",
+				"token": "gotify-secret",
+			},
+		}))
+		.send()
+		.await
+		.expect_status(StatusCode::OK);
+	// closed, so everything is written out
+	let app = app.reopen().unwrap();
+	let secrets = ["hook-secret", "remote-secret", "gotify-secret"];
+	for secret in secrets {
+		assert!(app.stores(secret).unwrap(), "{}", secret);
+	}
+
+	// existing values are sealed on the first start with a key, and their plain
+	// copies compacted away on the next
+	testing::enable_encryption();
+	let app = app.reopen().unwrap().reopen().unwrap();
+	for secret in secrets {
+		assert!(!app.stores(secret).unwrap(), "{}", secret);
+	}
+
+	let feeds: Vec<Value> = app.get("/api/v1/feeds").send().await.json();
+	assert_eq!(feeds.len(), 1);
+	let remotes: Vec<Value> = app.get("/api/v1/sync/remotes").send().await.json();
+	assert_eq!(remotes[0]["url"], "https://miniflux.example.com/");
+	let targets: Vec<Value> = app
+		.get("/api/v1/notifications/targets")
+		.send()
+		.await
+		.json();
+	assert_eq!(targets[0]["transport"]["server"], "https://gotify.example.com/");
+}